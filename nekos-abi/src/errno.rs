@@ -0,0 +1,106 @@
+//! Error numbers returned by system calls.
+//!
+//! The values match the ones used by Linux so that existing C libraries can be ported without
+//! translating them.
+
+#[repr(isize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Errno {
+    EPERM = 1,
+    ENOENT = 2,
+    ESRCH = 3,
+    EINTR = 4,
+    EIO = 5,
+    ENXIO = 6,
+    E2BIG = 7,
+    ENOEXEC = 8,
+    EBADF = 9,
+    ECHILD = 10,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EACCES = 13,
+    EFAULT = 14,
+    EBUSY = 16,
+    EEXIST = 17,
+    EXDEV = 18,
+    ENODEV = 19,
+    ENOTDIR = 20,
+    EISDIR = 21,
+    EINVAL = 22,
+    ENFILE = 23,
+    EMFILE = 24,
+    ENOTTY = 25,
+    EFBIG = 27,
+    ENOSPC = 28,
+    ESPIPE = 29,
+    EROFS = 30,
+    EMLINK = 31,
+    EPIPE = 32,
+    ERANGE = 34,
+    ENAMETOOLONG = 36,
+    ENOSYS = 38,
+    ENOTEMPTY = 39,
+    ELOOP = 40,
+    EOPNOTSUPP = 95,
+}
+
+impl Errno {
+    /// Decodes a raw system call result, returning the error if it is one.
+    pub const fn from_result(result: isize) -> Result<usize, Errno> {
+        if result >= 0 || result < -4095 {
+            return Ok(result as usize);
+        }
+
+        match Self::from_raw(-result) {
+            Some(errno) => Err(errno),
+            None => Err(Errno::EINVAL),
+        }
+    }
+
+    pub const fn from_raw(value: isize) -> Option<Self> {
+        Some(match value {
+            1 => Errno::EPERM,
+            2 => Errno::ENOENT,
+            3 => Errno::ESRCH,
+            4 => Errno::EINTR,
+            5 => Errno::EIO,
+            6 => Errno::ENXIO,
+            7 => Errno::E2BIG,
+            8 => Errno::ENOEXEC,
+            9 => Errno::EBADF,
+            10 => Errno::ECHILD,
+            11 => Errno::EAGAIN,
+            12 => Errno::ENOMEM,
+            13 => Errno::EACCES,
+            14 => Errno::EFAULT,
+            16 => Errno::EBUSY,
+            17 => Errno::EEXIST,
+            18 => Errno::EXDEV,
+            19 => Errno::ENODEV,
+            20 => Errno::ENOTDIR,
+            21 => Errno::EISDIR,
+            22 => Errno::EINVAL,
+            23 => Errno::ENFILE,
+            24 => Errno::EMFILE,
+            25 => Errno::ENOTTY,
+            27 => Errno::EFBIG,
+            28 => Errno::ENOSPC,
+            29 => Errno::ESPIPE,
+            30 => Errno::EROFS,
+            31 => Errno::EMLINK,
+            32 => Errno::EPIPE,
+            34 => Errno::ERANGE,
+            36 => Errno::ENAMETOOLONG,
+            38 => Errno::ENOSYS,
+            39 => Errno::ENOTEMPTY,
+            40 => Errno::ELOOP,
+            95 => Errno::EOPNOTSUPP,
+            _ => return None,
+        })
+    }
+
+    /// The value placed in `a0` when a system call fails.
+    pub const fn as_result(self) -> isize {
+        -(self as isize)
+    }
+}
@@ -0,0 +1,107 @@
+//! Types and flags used by the file system calls.
+
+use bitflags::bitflags;
+
+use crate::time::Timespec;
+
+bitflags! {
+    /// Flags accepted by `openat`.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct OpenFlags: usize {
+        const WRONLY = 0o1;
+        const RDWR = 0o2;
+        const CREAT = 0o100;
+        const EXCL = 0o200;
+        const TRUNC = 0o1000;
+        const APPEND = 0o2000;
+        const NONBLOCK = 0o4000;
+        const DIRECTORY = 0o200000;
+        const NOFOLLOW = 0o400000;
+        const CLOEXEC = 0o2000000;
+    }
+}
+
+impl OpenFlags {
+    pub const RDONLY: OpenFlags = OpenFlags::empty();
+    pub const ACCMODE: usize = 0o3;
+
+    pub const fn readable(&self) -> bool {
+        self.bits() & Self::ACCMODE != OpenFlags::WRONLY.bits()
+    }
+
+    pub const fn writable(&self) -> bool {
+        self.bits() & Self::ACCMODE != OpenFlags::RDONLY.bits()
+    }
+}
+
+/// Makes `*at` system calls resolve relative paths from the current working directory.
+pub const AT_FDCWD: isize = -100;
+/// Makes `fstatat` not follow a trailing symlink.
+pub const AT_SYMLINK_NOFOLLOW: usize = 0x100;
+/// Makes `fstatat` operate on the file descriptor itself when the path is empty.
+pub const AT_EMPTY_PATH: usize = 0x1000;
+/// Makes `unlinkat` remove an empty directory instead of a file.
+pub const AT_REMOVEDIR: usize = 0x200;
+
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+/// The longest path accepted by the kernel, including the terminating null byte.
+pub const PATH_MAX: usize = 4096;
+/// The longest file name accepted by the kernel.
+pub const NAME_MAX: usize = 255;
+
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFSOCK: u32 = 0o140000;
+pub const S_IFLNK: u32 = 0o120000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFBLK: u32 = 0o060000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFIFO: u32 = 0o010000;
+
+/// The layout of `struct stat` on 64-bit RISC-V.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stat {
+    pub st_dev: u64,
+    pub st_ino: u64,
+    pub st_mode: u32,
+    pub st_nlink: u32,
+    pub st_uid: u32,
+    pub st_gid: u32,
+    pub st_rdev: u64,
+    pub __pad1: u64,
+    pub st_size: i64,
+    pub st_blksize: i32,
+    pub __pad2: i32,
+    pub st_blocks: i64,
+    pub st_atim: Timespec,
+    pub st_mtim: Timespec,
+    pub st_ctim: Timespec,
+    pub __unused: [u32; 2],
+}
+
+pub const DT_UNKNOWN: u8 = 0;
+pub const DT_FIFO: u8 = 1;
+pub const DT_CHR: u8 = 2;
+pub const DT_DIR: u8 = 4;
+pub const DT_BLK: u8 = 6;
+pub const DT_REG: u8 = 8;
+pub const DT_LNK: u8 = 10;
+pub const DT_SOCK: u8 = 12;
+
+/// The fixed part of a `struct linux_dirent64` written by `getdents64`. It is followed by the
+/// null terminated name, and padded so that the next record is 8-byte aligned.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Dirent64 {
+    pub d_ino: u64,
+    pub d_off: i64,
+    pub d_reclen: u16,
+    pub d_type: u8,
+}
+
+/// The offset of the name in a `linux_dirent64` record.
+pub const DIRENT64_NAME_OFFSET: usize = 19;
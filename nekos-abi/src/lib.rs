@@ -0,0 +1,17 @@
+//! The interface between the kernel and userland.
+//!
+//! Both sides depend on this crate, so that they agree on system call numbers, error numbers and
+//! the layout of the structures passed through system calls.
+
+#![no_std]
+
+pub mod errno;
+pub mod fs;
+pub mod mman;
+pub mod sched;
+pub mod syscall;
+pub mod syslog;
+pub mod time;
+
+pub use errno::Errno;
+pub use syscall::Syscall;
@@ -0,0 +1,33 @@
+//! Flags used by `mmap`, `munmap` and `mprotect`.
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// The protection of a mapping.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Prot: usize {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXEC = 1 << 2;
+    }
+}
+
+bitflags! {
+    /// How a mapping is created.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct MapFlags: usize {
+        const SHARED = 0x01;
+        const PRIVATE = 0x02;
+        const FIXED = 0x10;
+        const ANONYMOUS = 0x20;
+        /// The mapping is a stack that grows down when the page below it is touched.
+        const GROWSDOWN = 0x0100;
+        /// Do not check that there is memory for the whole mapping up front.
+        const NORESERVE = 0x4000;
+        /// Accepted for compatibility, stacks need no special treatment.
+        const STACK = 0x20000;
+    }
+}
+
+/// The value returned by `mmap` on failure when viewed as a pointer.
+pub const MAP_FAILED: usize = usize::MAX;
@@ -0,0 +1,5 @@
+//! Flags used by `clone`.
+
+/// The signal sent to the parent when a child exits, which is passed in the lowest byte of the
+/// `clone` flags. `fork` is a `clone` with only this set.
+pub const SIGCHLD: usize = 17;
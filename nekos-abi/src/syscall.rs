@@ -0,0 +1,110 @@
+//! System call numbers and the raw calling convention.
+//!
+//! A system call is made with `ecall` from U-mode. The system call number is passed in `a7`, up to
+//! six arguments in `a0`-`a5`, and the result is returned in `a0`. A result in the range
+//! `-4095..0` is a negated [`Errno`](crate::Errno).
+//!
+//! The numbers follow the generic Linux system call table.
+
+/// The system calls implemented by the kernel.
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syscall {
+    /// `unlinkat(dirfd: i32, path: *const u8, flags: usize) -> i32`
+    UnlinkAt = 35,
+    /// `openat(dirfd: i32, path: *const u8, flags: OpenFlags, mode: u32) -> i32`
+    OpenAt = 56,
+    /// `close(fd: i32) -> i32`
+    Close = 57,
+    /// `getdents64(fd: i32, dirp: *mut u8, count: usize) -> isize`
+    Getdents64 = 61,
+    /// `lseek(fd: i32, offset: i64, whence: usize) -> i64`
+    Lseek = 62,
+    /// `read(fd: i32, buf: *mut u8, count: usize) -> isize`
+    Read = 63,
+    /// `write(fd: i32, buf: *const u8, count: usize) -> isize`
+    Write = 64,
+    /// `newfstatat(dirfd: i32, path: *const u8, statbuf: *mut Stat, flags: usize) -> i32`
+    NewFstatAt = 79,
+    /// `fstat(fd: i32, statbuf: *mut Stat) -> i32`
+    Fstat = 80,
+    /// `sync()`, writes back the cached state of every file system.
+    Sync = 81,
+    /// `exit(status: i32) -> !`
+    Exit = 93,
+    /// `clock_gettime(clock: ClockId, tp: *mut Timespec) -> i32`
+    ClockGettime = 113,
+    /// `syslog(action: SyslogAction, buf: *mut u8, len: usize) -> i32`
+    Syslog = 116,
+    /// `sched_yield() -> i32`
+    Yield = 124,
+    /// `getpid() -> i32`
+    GetPid = 172,
+    /// `brk(addr: *mut u8) -> *mut u8`, returns the new program break, or the old one on failure.
+    Brk = 214,
+    /// `munmap(addr: *mut u8, length: usize) -> i32`
+    Munmap = 215,
+    /// `clone(flags: usize, stack: *mut u8, parent_tid: *mut i32, tls: usize, child_tid: *mut i32)`
+    Clone = 220,
+    /// `mmap(addr: *mut u8, length: usize, prot: Prot, flags: MapFlags, fd: i32, offset: i64)`
+    Mmap = 222,
+    /// `mprotect(addr: *mut u8, length: usize, prot: Prot) -> i32`
+    Mprotect = 226,
+}
+
+impl Syscall {
+    /// One past the highest system call number.
+    pub const MAX: usize = 227;
+
+    pub const fn from_raw(number: usize) -> Option<Self> {
+        match number {
+            35 => Some(Syscall::UnlinkAt),
+            56 => Some(Syscall::OpenAt),
+            57 => Some(Syscall::Close),
+            61 => Some(Syscall::Getdents64),
+            62 => Some(Syscall::Lseek),
+            63 => Some(Syscall::Read),
+            64 => Some(Syscall::Write),
+            79 => Some(Syscall::NewFstatAt),
+            80 => Some(Syscall::Fstat),
+            81 => Some(Syscall::Sync),
+            93 => Some(Syscall::Exit),
+            113 => Some(Syscall::ClockGettime),
+            116 => Some(Syscall::Syslog),
+            124 => Some(Syscall::Yield),
+            172 => Some(Syscall::GetPid),
+            214 => Some(Syscall::Brk),
+            215 => Some(Syscall::Munmap),
+            220 => Some(Syscall::Clone),
+            222 => Some(Syscall::Mmap),
+            226 => Some(Syscall::Mprotect),
+            _ => None,
+        }
+    }
+}
+
+/// Makes a raw system call. The result is the raw value of `a0`.
+///
+/// # Safety
+///
+/// The arguments must be valid for the given system call.
+#[cfg(target_arch = "riscv64")]
+#[inline]
+pub unsafe fn syscall6(number: Syscall, args: [usize; 6]) -> isize {
+    let result;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") args[0] => result,
+            in("a1") args[1],
+            in("a2") args[2],
+            in("a3") args[3],
+            in("a4") args[4],
+            in("a5") args[5],
+            in("a7") number as usize,
+        );
+    }
+
+    result
+}
@@ -0,0 +1,29 @@
+//! Actions of `syslog`, which reads and controls the kernel log.
+
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyslogAction {
+    /// Copies the most recent `len` bytes of the log into `buf`.
+    ReadAll = 3,
+    /// Like `ReadAll`, then clears the log.
+    ReadClear = 4,
+    /// Makes later reads skip what is in the log now.
+    Clear = 5,
+    /// Sets the level of the messages the kernel records to `len`.
+    ConsoleLevel = 8,
+    /// Returns the size of the log buffer.
+    SizeBuffer = 10,
+}
+
+impl SyslogAction {
+    pub const fn from_raw(value: usize) -> Option<Self> {
+        match value {
+            3 => Some(SyslogAction::ReadAll),
+            4 => Some(SyslogAction::ReadClear),
+            5 => Some(SyslogAction::Clear),
+            8 => Some(SyslogAction::ConsoleLevel),
+            10 => Some(SyslogAction::SizeBuffer),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Types used by `clock_gettime`.
+
+use core::time::Duration;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+impl From<Duration> for Timespec {
+    fn from(duration: Duration) -> Self {
+        Self {
+            tv_sec: duration.as_secs() as i64,
+            tv_nsec: duration.subsec_nanos() as i64,
+        }
+    }
+}
+
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockId {
+    Realtime = 0,
+    Monotonic = 1,
+    Boottime = 7,
+}
+
+impl ClockId {
+    pub const fn from_raw(value: usize) -> Option<Self> {
+        match value {
+            0 => Some(ClockId::Realtime),
+            1 => Some(ClockId::Monotonic),
+            7 => Some(ClockId::Boottime),
+            _ => None,
+        }
+    }
+}
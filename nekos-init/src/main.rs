@@ -0,0 +1,93 @@
+//! The first user program started by the kernel.
+
+#![no_std]
+#![no_main]
+
+use core::fmt::{self, Write};
+
+use nekos_abi::mman::{MapFlags, Prot};
+use nekos_abi::syscall::syscall6;
+use nekos_abi::time::{ClockId, Timespec};
+use nekos_abi::{Errno, Syscall};
+
+fn syscall(number: Syscall, args: [usize; 6]) -> Result<usize, Errno> {
+    Errno::from_result(unsafe { syscall6(number, args) })
+}
+
+fn exit(status: i32) -> ! {
+    let _ = syscall(Syscall::Exit, [status as usize, 0, 0, 0, 0, 0]);
+    unreachable!("exit returned");
+}
+
+struct Stdout;
+
+impl Write for Stdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        syscall(Syscall::Write, [1, s.as_ptr() as usize, s.len(), 0, 0, 0])
+            .map(|_| ())
+            .map_err(|_| fmt::Error)
+    }
+}
+
+macro_rules! println {
+    ($($arg:tt)*) => {
+        let _ = writeln!(Stdout, $($arg)*);
+    };
+}
+
+fn main() -> Result<(), Errno> {
+    let pid = syscall(Syscall::GetPid, [0; 6])?;
+    println!("Hello from init, running as process {}!", pid);
+
+    let mut now = Timespec::default();
+    syscall(
+        Syscall::ClockGettime,
+        [
+            ClockId::Monotonic as usize,
+            &raw mut now as usize,
+            0,
+            0,
+            0,
+            0,
+        ],
+    )?;
+    println!("Booted {}.{:09}s ago.", now.tv_sec, now.tv_nsec);
+
+    let length = 4096;
+    let page = syscall(
+        Syscall::Mmap,
+        [
+            0,
+            length,
+            (Prot::READ | Prot::WRITE).bits(),
+            (MapFlags::PRIVATE | MapFlags::ANONYMOUS).bits(),
+            usize::MAX,
+            0,
+        ],
+    )?;
+
+    unsafe { (page as *mut u64).write_volatile(0xCA7) };
+    println!("Mapped a page at {:#x}.", page);
+
+    syscall(Syscall::Munmap, [page, length, 0, 0, 0, 0])?;
+    syscall(Syscall::Yield, [0; 6])?;
+
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn _start() -> ! {
+    match main() {
+        Ok(()) => exit(0),
+        Err(errno) => {
+            println!("init failed with {:?}", errno);
+            exit(1)
+        }
+    }
+}
+
+#[panic_handler]
+fn panic_handler(info: &core::panic::PanicInfo) -> ! {
+    println!("init panicked: {}", info);
+    exit(101)
+}
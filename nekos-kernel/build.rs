@@ -1,3 +1,7 @@
 fn main() {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+
+    // Only the kernel is linked with its own script, user programs use the default layout.
+    println!("cargo::rustc-link-arg-bins=-T{manifest_dir}/riscv64.ld");
     println!("cargo::rerun-if-changed=riscv64.ld")
 }
@@ -1,7 +1,9 @@
 #[cfg(target_arch = "riscv64")]
 pub mod riscv64;
 
-use crate::mem::{PageMapErr, PhysicalAddr, VirtualAddr, VirtualMemoryFlags};
+use crate::mem::{
+    Mapping, PageMapErr, PhysicalAddr, UserAccessErr, VirtualAddr, VirtualMemoryFlags,
+};
 use core::arch::asm;
 
 #[inline]
@@ -30,7 +32,128 @@ pub fn map_page(
 ) -> Result<(), PageMapErr> {
     #[cfg(target_arch = "riscv64")]
     {
-        return riscv64::map_page(root_page_table, virtual_addr, physical_addr, size, flags);
+        riscv64::map_page(root_page_table, virtual_addr, physical_addr, size, flags)
+    }
+    #[cfg(not(target_arch = "riscv64"))]
+    {
+        compile_error!("Unsupported architecture - only riscv64 is supported");
+    }
+}
+
+#[inline]
+pub fn translate(root_page_table: PhysicalAddr, virtual_addr: VirtualAddr) -> Option<Mapping> {
+    #[cfg(target_arch = "riscv64")]
+    {
+        riscv64::translate(root_page_table, virtual_addr)
+    }
+    #[cfg(not(target_arch = "riscv64"))]
+    {
+        compile_error!("Unsupported architecture - only riscv64 is supported");
+    }
+}
+
+/// Makes the kernel half of `src` visible in the root page table `dst`.
+#[inline]
+pub fn copy_kernel_mappings(src: PhysicalAddr, dst: PhysicalAddr) {
+    #[cfg(target_arch = "riscv64")]
+    riscv64::copy_kernel_mappings(src, dst);
+}
+
+/// Frees the root page table along with every page table and frame mapped in its user half, except
+/// for frames a fork still shares.
+#[inline]
+pub fn free_user_mappings(root_page_table: PhysicalAddr) {
+    #[cfg(target_arch = "riscv64")]
+    riscv64::free_user_mappings(root_page_table);
+}
+
+/// Maps the user half of `src` into the root page table `dst` as well, sharing the frames and
+/// turning writable pages copy-on-write in both.
+#[inline]
+pub fn share_user_mappings(src: PhysicalAddr, dst: PhysicalAddr) -> Result<(), PageMapErr> {
+    #[cfg(target_arch = "riscv64")]
+    {
+        riscv64::share_user_mappings(src, dst)
+    }
+    #[cfg(not(target_arch = "riscv64"))]
+    {
+        compile_error!("Unsupported architecture - only riscv64 is supported");
+    }
+}
+
+/// Flushes the range from the TLBs of all harts, after its mappings were changed or removed.
+#[inline]
+pub fn shootdown_tlb(virtual_addr: VirtualAddr, size: usize) {
+    #[cfg(target_arch = "riscv64")]
+    riscv64::shootdown_tlb(virtual_addr, size);
+}
+
+#[inline]
+pub fn user_space_end() -> VirtualAddr {
+    #[cfg(target_arch = "riscv64")]
+    {
+        riscv64::user_space_end()
+    }
+    #[cfg(not(target_arch = "riscv64"))]
+    {
+        compile_error!("Unsupported architecture - only riscv64 is supported");
+    }
+}
+
+#[inline]
+pub fn unmap_page(root_page_table: PhysicalAddr, virtual_addr: VirtualAddr) -> Option<Mapping> {
+    #[cfg(target_arch = "riscv64")]
+    {
+        riscv64::unmap_page(root_page_table, virtual_addr)
+    }
+    #[cfg(not(target_arch = "riscv64"))]
+    {
+        compile_error!("Unsupported architecture - only riscv64 is supported");
+    }
+}
+
+#[inline]
+pub fn enter_user(entry: VirtualAddr, stack: VirtualAddr) -> ! {
+    #[cfg(target_arch = "riscv64")]
+    riscv64::enter_user(entry, stack);
+}
+
+/// Returns to the user context saved in `context`.
+#[inline]
+pub fn resume_user(context: TrapFrame) -> ! {
+    #[cfg(target_arch = "riscv64")]
+    riscv64::resume(context);
+}
+
+#[inline]
+pub fn copy_from_user(dst: &mut [u8], src: VirtualAddr) -> Result<(), UserAccessErr> {
+    #[cfg(target_arch = "riscv64")]
+    {
+        riscv64::copy_from_user(dst, src)
+    }
+    #[cfg(not(target_arch = "riscv64"))]
+    {
+        compile_error!("Unsupported architecture - only riscv64 is supported");
+    }
+}
+
+#[inline]
+pub fn copy_cstr_from_user(dst: &mut [u8], src: VirtualAddr) -> Result<usize, UserAccessErr> {
+    #[cfg(target_arch = "riscv64")]
+    {
+        riscv64::copy_cstr_from_user(dst, src)
+    }
+    #[cfg(not(target_arch = "riscv64"))]
+    {
+        compile_error!("Unsupported architecture - only riscv64 is supported");
+    }
+}
+
+#[inline]
+pub fn copy_to_user(dst: VirtualAddr, src: &[u8]) -> Result<(), UserAccessErr> {
+    #[cfg(target_arch = "riscv64")]
+    {
+        riscv64::copy_to_user(dst, src)
     }
     #[cfg(not(target_arch = "riscv64"))]
     {
@@ -44,7 +167,7 @@ pub fn root_page_table() -> PhysicalAddr {
     {
         use crate::arch::riscv64::csr::CsrRead;
         let satp = riscv64::csr::satp::read();
-        return satp.ppn().as_physical_addr();
+        satp.ppn().as_physical_addr()
     }
     #[cfg(not(target_arch = "riscv64"))]
     {
@@ -82,8 +205,129 @@ pub fn flush_tlb() {
     }
 }
 
+/// Sets up the interrupt controller, which needs device registers to be mappable.
+#[inline]
+pub fn init_irq() {
+    #[cfg(target_arch = "riscv64")]
+    riscv64::init_irq();
+}
+
+/// Lets the external interrupt `irq` through to the kernel.
+#[inline]
+pub fn enable_irq(irq: u32) {
+    #[cfg(target_arch = "riscv64")]
+    riscv64::enable_irq(irq);
+}
+
+#[inline]
+pub fn monotonic_time() -> core::time::Duration {
+    #[cfg(target_arch = "riscv64")]
+    {
+        riscv64::monotonic_time()
+    }
+    #[cfg(not(target_arch = "riscv64"))]
+    {
+        compile_error!("Unsupported architecture - only riscv64 is supported");
+    }
+}
+
+/// The id of the hart this is running on.
+#[inline]
+pub fn current_hart_id() -> u64 {
+    #[cfg(target_arch = "riscv64")]
+    {
+        riscv64::current_hart_id()
+    }
+    #[cfg(not(target_arch = "riscv64"))]
+    {
+        compile_error!("Unsupported architecture - only riscv64 is supported");
+    }
+}
+
+/// The ids of the harts that run the kernel, including the current one.
+#[inline]
+pub fn online_harts() -> impl Iterator<Item = u64> {
+    #[cfg(target_arch = "riscv64")]
+    {
+        riscv64::online_harts()
+    }
+    #[cfg(not(target_arch = "riscv64"))]
+    {
+        compile_error!("Unsupported architecture - only riscv64 is supported");
+    }
+}
+
+/// Interrupts the hart `hart_id`.
+#[inline]
+pub fn send_ipi(hart_id: u64) {
+    #[cfg(target_arch = "riscv64")]
+    riscv64::send_ipi(hart_id);
+}
+
+/// Reboots the machine, halting if that is not possible.
+#[inline]
+pub fn reboot() -> ! {
+    #[cfg(target_arch = "riscv64")]
+    riscv64::reset(true);
+    #[cfg(not(target_arch = "riscv64"))]
+    compile_error!("Unsupported architecture - only riscv64 is supported");
+}
+
+/// Powers the machine off, halting if that is not possible.
+#[inline]
+pub fn poweroff() -> ! {
+    #[cfg(target_arch = "riscv64")]
+    riscv64::reset(false);
+    #[cfg(not(target_arch = "riscv64"))]
+    compile_error!("Unsupported architecture - only riscv64 is supported");
+}
+
+/// Stops the machine with the exit status `code`, which the emulator running it passes on.
+#[inline]
+pub fn exit(code: u32) -> ! {
+    #[cfg(target_arch = "riscv64")]
+    riscv64::exit(code);
+    #[cfg(not(target_arch = "riscv64"))]
+    compile_error!("Unsupported architecture - only riscv64 is supported");
+}
+
+/// The frame pointer of the calling function, where a backtrace starts.
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    #[cfg(target_arch = "riscv64")]
+    {
+        riscv64::frame_pointer()
+    }
+    #[cfg(not(target_arch = "riscv64"))]
+    {
+        compile_error!("Unsupported architecture - only riscv64 is supported");
+    }
+}
+
+/// Follows the frame pointer `fp` to the return address and frame pointer of its caller.
+#[inline]
+pub fn unwind_frame(fp: u64) -> Option<(u64, u64)> {
+    #[cfg(target_arch = "riscv64")]
+    {
+        riscv64::unwind_frame(fp)
+    }
+    #[cfg(not(target_arch = "riscv64"))]
+    {
+        compile_error!("Unsupported architecture - only riscv64 is supported");
+    }
+}
+
+#[inline]
+pub fn console_write(bytes: &[u8]) {
+    #[cfg(target_arch = "riscv64")]
+    riscv64::console_write(bytes);
+}
+
 pub const PAGE_SIZE: u64 = 4096;
 
+#[cfg(target_arch = "riscv64")]
+pub use riscv64::TrapFrame;
+
 macro_rules! print {
     ($($arg:tt)*) => (
         #[cfg(target_arch = "riscv64")]
@@ -49,6 +49,9 @@ impl_csr!(sepc);
 impl_csr!(sstatus);
 impl_csr!(stvec);
 impl_csr!(satp);
+impl_csr!(sscratch);
+impl_csr!(sie);
+impl_csr!(sip);
 
 impl scause {
     pub fn interrupt_code(&self) -> InterruptCode {
@@ -123,3 +126,87 @@ impl satp {
         self.0 = (self.0 & mask) | ppn.value();
     }
 }
+
+/// The privilege mode a hart was in before it took a trap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PrivilegeMode {
+    User,
+    Supervisor,
+}
+
+impl sstatus {
+    pub const SIE: u64 = 1 << 1;
+    pub const SPIE: u64 = 1 << 5;
+    pub const SPP: u64 = 1 << 8;
+    pub const SUM: u64 = 1 << 18;
+
+    /// The privilege mode that `sret` returns to.
+    pub const fn spp(&self) -> PrivilegeMode {
+        if self.0 & Self::SPP == 0 {
+            PrivilegeMode::User
+        } else {
+            PrivilegeMode::Supervisor
+        }
+    }
+
+    pub const fn set_spp(&mut self, mode: PrivilegeMode) {
+        match mode {
+            PrivilegeMode::User => self.0 &= !Self::SPP,
+            PrivilegeMode::Supervisor => self.0 |= Self::SPP,
+        }
+    }
+
+    /// Whether interrupts get enabled after `sret`.
+    pub const fn set_spie(&mut self, enabled: bool) {
+        self.set_bit(Self::SPIE, enabled);
+    }
+
+    /// Whether supervisor mode is permitted to access pages marked with `U`.
+    pub const fn sum(&self) -> bool {
+        self.0 & Self::SUM != 0
+    }
+
+    pub const fn set_sum(&mut self, enabled: bool) {
+        self.set_bit(Self::SUM, enabled);
+    }
+
+    const fn set_bit(&mut self, bit: u64, enabled: bool) {
+        if enabled {
+            self.0 |= bit;
+        } else {
+            self.0 &= !bit;
+        }
+    }
+}
+
+impl sie {
+    pub const SSIE: u64 = 1 << 1;
+    pub const SEIE: u64 = 1 << 9;
+
+    /// Whether software interrupts, which other harts send, are delivered to supervisor mode.
+    pub const fn set_ssie(&mut self, enabled: bool) {
+        if enabled {
+            self.0 |= Self::SSIE;
+        } else {
+            self.0 &= !Self::SSIE;
+        }
+    }
+
+    /// Whether external interrupts are delivered to supervisor mode.
+    pub const fn set_seie(&mut self, enabled: bool) {
+        if enabled {
+            self.0 |= Self::SEIE;
+        } else {
+            self.0 &= !Self::SEIE;
+        }
+    }
+}
+
+impl sip {
+    pub const SSIP: u64 = 1 << 1;
+
+    /// Acknowledges a pending software interrupt.
+    pub const fn clear_ssip(&mut self) {
+        self.0 &= !Self::SSIP;
+    }
+}
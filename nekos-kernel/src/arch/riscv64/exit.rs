@@ -0,0 +1,43 @@
+//! Stopping an emulated machine with an exit status, which is how kernel tests report back.
+//!
+//! QEMU `virt` has a `sifive,test0` device that exits the emulator with a given status. Without
+//! it, the machine is shut down through the SBI, which only tells success from failure.
+
+use super::sbi::{self, ResetReason, ResetType};
+use crate::mem::{self, PhysicalAddr, VirtualAddr};
+use crate::{boot, log};
+
+const COMPATIBLE: [&str; 2] = ["sifive,test0", "sifive,test1"];
+
+const PASS: u32 = 0x5555;
+const FAIL: u32 = 0x3333;
+
+/// Stops the machine with the exit status `code`, which must fit into 16 bits.
+pub fn exit(code: u32) -> ! {
+    if let Some(device) = find_device() {
+        let value = if code == 0 { PASS } else { code << 16 | FAIL };
+        unsafe { device.as_mut_ptr::<u32>().write_volatile(value) }
+    }
+
+    let reason = if code == 0 {
+        ResetReason::NoReason
+    } else {
+        ResetReason::SystemFailure
+    };
+    sbi::system_reset(ResetType::Shutdown, reason);
+
+    log::error!("Failed to stop the machine with exit status {}.", code);
+    crate::arch::halt();
+}
+
+fn find_device() -> Option<VirtualAddr> {
+    let device_tree = boot::device_tree()?;
+    let node = device_tree.nodes().find(|node| {
+        COMPATIBLE
+            .iter()
+            .any(|&compatible| node.is_compatible(compatible))
+    })?;
+    let (addr, size) = node.reg().next()?;
+
+    mem::map_mmio(PhysicalAddr::new(addr), size as usize).ok()
+}
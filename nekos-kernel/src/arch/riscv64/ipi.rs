@@ -0,0 +1,36 @@
+//! Inter-processor interrupts, sent through the SBI as supervisor software interrupts.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::csr::{self, CsrRead, CsrWrite};
+use super::oops;
+use super::trap::TrapFrame;
+
+/// A bit for each hart that runs the kernel. Hart ids past 63 are not supported.
+static ONLINE_HARTS: AtomicU64 = AtomicU64::new(0);
+
+/// Lets the current hart receive software interrupts and marks it as running the kernel.
+pub fn init() {
+    let mut sie = csr::sie::read();
+    sie.set_ssie(true);
+    unsafe { csr::sie::write(sie) }
+
+    ONLINE_HARTS.fetch_or(1 << super::current_hart_id(), Ordering::AcqRel);
+}
+
+/// The ids of the harts that run the kernel, including the current one.
+pub fn online_harts() -> impl Iterator<Item = u64> {
+    let harts = ONLINE_HARTS.load(Ordering::Acquire);
+    (0..64).filter(move |hart_id| harts & (1 << hart_id) != 0)
+}
+
+pub fn handle_interrupt(frame: &TrapFrame) {
+    let mut sip = csr::sip::read();
+    sip.clear_ssip();
+    unsafe { csr::sip::write(sip) }
+
+    // Software interrupts are only sent to stop harts when another one panics so far.
+    if crate::panic::in_progress() {
+        crate::panic::stop_hart(&|| oops::log_registers(frame));
+    }
+}
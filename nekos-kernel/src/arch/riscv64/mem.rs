@@ -1,7 +1,7 @@
 use crate::arch::{PAGE_SIZE, PageMapErr};
-use crate::mem::{PhysicalAddr, VirtualAddr, VirtualMemoryFlags};
+use crate::mem::{Mapping, PhysicalAddr, Usage, VirtualAddr, VirtualMemoryFlags};
 
-use crate::{boot, log};
+use crate::{boot, log, misc};
 use core::usize;
 use limine::paging::Mode;
 
@@ -83,20 +83,10 @@ fn map_page_impl(
     debug_assert!(virtual_addr.is_aligned_with(alignment));
     debug_assert!(physical_addr.is_aligned_with(alignment));
 
-    let mut indices = [0u16; 5];
-    for (i, shift) in [12u16, 21, 30, 39, 48].iter().enumerate() {
-        let addr = virtual_addr.addr();
-        indices[i] = ((addr >> shift) & 0x1FF) as u16;
-    }
+    let indices = vpn_indices(virtual_addr);
 
     let boot_info = boot::BOOT_INFO.get().unwrap();
-
-    let top_level = match boot_info.paging_mode {
-        Mode::SV57 => 4,
-        Mode::SV48 => 3,
-        Mode::SV39 => 2,
-        _ => unreachable!(),
-    };
+    let top_level = top_level();
 
     let leaf_level = match page_type {
         PageType::FourKiB => 0,
@@ -112,7 +102,7 @@ fn map_page_impl(
         let pte = &mut page_table.entries[vpn];
 
         if !pte.flags().contains(PageTableFlags::VALID) {
-            let new_page = crate::mem::allocate_pages(1, /*zeroed=*/ true)
+            let new_page = crate::mem::allocate_pages(1, /*zeroed=*/ true, Usage::PageTable)
                 .map_err(|_| PageMapErr::PageFrameAllocError)?;
             let ppn = PPN::from_physical_addr(new_page);
             *pte = PageTableEntry::new(ppn, PageTableFlags::VALID);
@@ -134,6 +124,255 @@ fn map_page_impl(
     Ok(())
 }
 
+/// Walks the page table rooted at `root_page_table_addr` and returns the leaf mapping that covers
+/// `virtual_addr`, if any.
+pub fn translate(root_page_table_addr: PhysicalAddr, virtual_addr: VirtualAddr) -> Option<Mapping> {
+    let indices = vpn_indices(virtual_addr);
+    let boot_info = boot::BOOT_INFO.get().unwrap();
+
+    let root_page_table = root_page_table_addr.as_virtual_by_offset(boot_info.hhdm_offset);
+    let mut page_table = PageTable::from_addr(root_page_table);
+
+    for level in (0..=top_level()).rev() {
+        let pte = page_table.entries[indices[level] as usize];
+
+        if !pte.has_flag(PageTableFlags::VALID) {
+            return None;
+        }
+
+        if pte.is_leaf() {
+            let size = PAGE_SIZE << (9 * level);
+            let base = misc::align_down(virtual_addr.addr(), size);
+
+            return Some(Mapping {
+                virtual_addr: VirtualAddr::new(base),
+                physical_addr: pte.ppn().as_physical_addr(),
+                size,
+                flags: pte.flags().as_virtual_memory_flags(),
+            });
+        }
+
+        let next_table_vaddr = pte
+            .ppn()
+            .as_physical_addr()
+            .as_virtual_by_offset(boot_info.hhdm_offset);
+        page_table = PageTable::from_addr(next_table_vaddr);
+    }
+
+    None
+}
+
+/// Removes the leaf mapping that covers `virtual_addr` and returns it. The mapped frames are not
+/// freed.
+pub fn unmap_page(
+    root_page_table_addr: PhysicalAddr,
+    virtual_addr: VirtualAddr,
+) -> Option<Mapping> {
+    let indices = vpn_indices(virtual_addr);
+    let boot_info = boot::BOOT_INFO.get().unwrap();
+
+    let root_page_table = root_page_table_addr.as_virtual_by_offset(boot_info.hhdm_offset);
+    let mut page_table = PageTable::from_addr(root_page_table);
+
+    for level in (0..=top_level()).rev() {
+        let pte = &mut page_table.entries[indices[level] as usize];
+
+        if !pte.has_flag(PageTableFlags::VALID) {
+            return None;
+        }
+
+        if pte.is_leaf() {
+            let size = PAGE_SIZE << (9 * level);
+            let base = misc::align_down(virtual_addr.addr(), size);
+
+            let mapping = Mapping {
+                virtual_addr: VirtualAddr::new(base),
+                physical_addr: pte.ppn().as_physical_addr(),
+                size,
+                flags: pte.flags().as_virtual_memory_flags(),
+            };
+
+            *pte = PageTableEntry(0);
+
+            unsafe { core::arch::asm!("sfence.vma {}, zero", in(reg) base) };
+
+            return Some(mapping);
+        }
+
+        let next_table_vaddr = pte
+            .ppn()
+            .as_physical_addr()
+            .as_virtual_by_offset(boot_info.hhdm_offset);
+        page_table = PageTable::from_addr(next_table_vaddr);
+    }
+
+    None
+}
+
+/// Copies the upper half of the root page table at `src` into the root page table at `dst`.
+///
+/// Only the top-level entries are copied, so the lower level page tables of the kernel half are
+/// shared between both page tables.
+pub fn copy_kernel_mappings(src: PhysicalAddr, dst: PhysicalAddr) {
+    let boot_info = boot::BOOT_INFO.get().unwrap();
+
+    let src = PageTable::from_addr(src.as_virtual_by_offset(boot_info.hhdm_offset));
+    let dst = PageTable::from_addr(dst.as_virtual_by_offset(boot_info.hhdm_offset));
+
+    dst.entries[256..].copy_from_slice(&src.entries[256..]);
+}
+
+/// Maps every user page of the root page table at `src` into the one at `dst` as well. Both share
+/// the frames from now on, and writable pages that are not `SHARED` become copy-on-write in both.
+///
+/// The TLBs of the harts running with `src` still allow writes to these pages until they are
+/// flushed.
+pub fn share_user_mappings(src: PhysicalAddr, dst: PhysicalAddr) -> Result<(), PageMapErr> {
+    fn share_table(
+        table_addr: PhysicalAddr,
+        level: usize,
+        entries: core::ops::Range<usize>,
+        base: u64,
+        dst: PhysicalAddr,
+    ) -> Result<(), PageMapErr> {
+        let boot_info = boot::BOOT_INFO.get().unwrap();
+        let page_table =
+            PageTable::from_addr(table_addr.as_virtual_by_offset(boot_info.hhdm_offset));
+
+        for index in entries {
+            let pte = &mut page_table.entries[index];
+            if !pte.has_flag(PageTableFlags::VALID) {
+                continue;
+            }
+
+            let virtual_addr = base + ((index as u64) << (12 + 9 * level));
+
+            if !pte.is_leaf() {
+                share_table(
+                    pte.ppn().as_physical_addr(),
+                    level - 1,
+                    0..512,
+                    virtual_addr,
+                    dst,
+                )?;
+                continue;
+            }
+
+            // Shared pages stay writable in both address spaces.
+            if pte.has_flag(PageTableFlags::WRITABLE) && !pte.has_flag(PageTableFlags::SHARED) {
+                let mut flags = pte.flags();
+                flags.remove(PageTableFlags::WRITABLE);
+                flags.insert(PageTableFlags::COPY_ON_WRITE);
+                pte.set_flags(flags);
+            }
+
+            // Large pages stay large, `map_page` picks the same size for the same alignment.
+            let size = PAGE_SIZE << (9 * level);
+            map_page(
+                dst,
+                VirtualAddr::new(virtual_addr),
+                pte.ppn().as_physical_addr(),
+                size as usize,
+                pte.flags().as_virtual_memory_flags(),
+            )?;
+
+            crate::mem::share_pages(pte.ppn().as_physical_addr(), (size / PAGE_SIZE) as usize);
+        }
+
+        Ok(())
+    }
+
+    share_table(src, top_level(), 0..256, 0, dst)
+}
+
+/// Above this many pages a range is flushed from the TLB as a whole rather than page by page.
+const MAX_FLUSH_PAGES: u64 = 64;
+
+/// Flushes `[virtual_addr, virtual_addr + size)` from the TLBs of all harts running the kernel,
+/// after its mappings changed.
+pub fn shootdown_tlb(virtual_addr: VirtualAddr, size: usize) {
+    let num_pages = (size as u64).div_ceil(PAGE_SIZE);
+
+    if num_pages > MAX_FLUSH_PAGES {
+        unsafe { core::arch::asm!("sfence.vma") };
+    } else {
+        for page in 0..num_pages {
+            let addr = virtual_addr.addr() + page * PAGE_SIZE;
+            unsafe { core::arch::asm!("sfence.vma {}, zero", in(reg) addr) };
+        }
+    }
+
+    let current = super::current_hart_id();
+    let others = super::online_harts()
+        .filter(|&hart_id| hart_id != current)
+        .fold(0, |mask, hart_id| mask | 1 << hart_id);
+
+    if others != 0 {
+        super::sbi::remote_sfence_vma(others, virtual_addr.addr(), size as u64);
+    }
+}
+
+/// Frees every page table reachable from the lower half of the root page table at
+/// `root_page_table_addr`, and the leaf frames no other page table shares, then frees the root
+/// page table itself.
+pub fn free_user_mappings(root_page_table_addr: PhysicalAddr) {
+    fn free_table(table_addr: PhysicalAddr, level: usize, entries: core::ops::Range<usize>) {
+        let boot_info = boot::BOOT_INFO.get().unwrap();
+        let page_table =
+            PageTable::from_addr(table_addr.as_virtual_by_offset(boot_info.hhdm_offset));
+
+        for pte in &mut page_table.entries[entries] {
+            if !pte.has_flag(PageTableFlags::VALID) {
+                continue;
+            }
+
+            if pte.is_leaf() {
+                crate::mem::release_pages(
+                    pte.ppn().as_physical_addr(),
+                    1 << (9 * level),
+                    Usage::User,
+                );
+            } else {
+                free_table(pte.ppn().as_physical_addr(), level - 1, 0..512);
+                crate::mem::deallocate_pages(pte.ppn().as_physical_addr(), 1, Usage::PageTable);
+            }
+
+            *pte = PageTableEntry(0);
+        }
+    }
+
+    free_table(root_page_table_addr, top_level(), 0..256);
+    crate::mem::deallocate_pages(root_page_table_addr, 1, Usage::PageTable);
+}
+
+/// Returns the first address past the lower half of the address space, which is reserved for
+/// user mappings.
+pub fn user_space_end() -> VirtualAddr {
+    // The lower half spans everything below the sign-extension bit of the virtual address.
+    let bits = 12 + 9 * (top_level() + 1);
+    VirtualAddr::new(1 << (bits - 1))
+}
+
+fn vpn_indices(virtual_addr: VirtualAddr) -> [u16; 5] {
+    let mut indices = [0u16; 5];
+    for (i, shift) in [12u16, 21, 30, 39, 48].iter().enumerate() {
+        let addr = virtual_addr.addr();
+        indices[i] = ((addr >> shift) & 0x1FF) as u16;
+    }
+    indices
+}
+
+fn top_level() -> usize {
+    let boot_info = boot::BOOT_INFO.get().unwrap();
+
+    match boot_info.paging_mode {
+        Mode::SV57 => 4,
+        Mode::SV48 => 3,
+        Mode::SV39 => 2,
+        _ => unreachable!(),
+    }
+}
+
 use bitflags::bitflags;
 use ubyte::ToByteUnit;
 
@@ -148,6 +387,11 @@ bitflags! {
         const GLOBAL     = 1 << 5;
         const ACCESSED   = 1 << 6;
         const DIRTY      = 1 << 7;
+        /// One of the bits reserved for software, marks read-only pages of a forked address
+        /// space that used to be writable.
+        const COPY_ON_WRITE = 1 << 8;
+        /// The other software bit, marks pages that forks share instead of copying.
+        const SHARED = 1 << 9;
     }
 }
 
@@ -163,10 +407,49 @@ impl PageTableFlags {
             flags |= PageTableFlags::EXECUTABLE;
         }
 
-        if virtual_memory_flags.contains(VirtualMemoryFlags::UserAccessible) {
+        // A leaf has to stay readable, without any of `R`, `W` and `X` it points to the next
+        // level. Leaving out `U` is what keeps the process from touching the page.
+        if virtual_memory_flags.contains(VirtualMemoryFlags::UserAccessible)
+            && !virtual_memory_flags.contains(VirtualMemoryFlags::NoAccess)
+        {
             flags |= PageTableFlags::USER;
         }
 
+        if virtual_memory_flags.contains(VirtualMemoryFlags::CopyOnWrite) {
+            debug_assert!(!flags.contains(PageTableFlags::WRITABLE));
+            flags |= PageTableFlags::COPY_ON_WRITE;
+        }
+
+        if virtual_memory_flags.contains(VirtualMemoryFlags::Shared) {
+            flags |= PageTableFlags::SHARED;
+        }
+
+        flags
+    }
+
+    pub fn as_virtual_memory_flags(&self) -> VirtualMemoryFlags {
+        let mut flags = VirtualMemoryFlags::empty();
+
+        if self.contains(PageTableFlags::WRITABLE) {
+            flags |= VirtualMemoryFlags::Writeable;
+        }
+
+        if self.contains(PageTableFlags::EXECUTABLE) {
+            flags |= VirtualMemoryFlags::Executable;
+        }
+
+        if self.contains(PageTableFlags::USER) {
+            flags |= VirtualMemoryFlags::UserAccessible;
+        }
+
+        if self.contains(PageTableFlags::COPY_ON_WRITE) {
+            flags |= VirtualMemoryFlags::CopyOnWrite;
+        }
+
+        if self.contains(PageTableFlags::SHARED) {
+            flags |= VirtualMemoryFlags::Shared;
+        }
+
         flags
     }
 }
@@ -203,12 +486,20 @@ impl PageTableEntry {
     }
 
     pub fn set_flags(&mut self, flags: PageTableFlags) {
-        self.0 = (self.0 & !0xFF) | flags.bits();
+        self.0 = (self.0 & !0x3FF) | flags.bits();
     }
 
     pub fn has_flag(&self, flag: PageTableFlags) -> bool {
         self.flags().contains(flag)
     }
+
+    /// A valid entry is a leaf if any of the R, W or X bits are set, otherwise it points to the
+    /// next level of the page table.
+    pub fn is_leaf(&self) -> bool {
+        self.flags().intersects(
+            PageTableFlags::READABLE | PageTableFlags::WRITABLE | PageTableFlags::EXECUTABLE,
+        )
+    }
 }
 
 pub struct PPN(u64);
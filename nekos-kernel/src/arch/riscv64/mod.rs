@@ -1,16 +1,56 @@
+mod exit;
+mod ipi;
+mod oops;
+mod plic;
 mod sbi;
+mod stack;
+mod time;
 mod trap;
+mod user;
 
 pub mod csr;
 pub mod mem;
-pub use sbi::print;
+pub use sbi::{ResetReason, ResetType, console_write, print, send_ipi, system_reset};
 
-use crate::log;
+use crate::{boot, log};
 
 pub fn init() {
     log::debug!("Initializing arch");
 
     trap::init();
+    time::init();
+    ipi::init();
 }
 
-pub use mem::map_page;
+/// Only the boot hart runs the kernel so far, the others are never started.
+pub fn current_hart_id() -> u64 {
+    boot::BOOT_INFO
+        .get()
+        .map_or(0, |boot_info| boot_info.bsp_hart_id)
+}
+
+/// Shuts down the machine, or reboots it if `reboot` is set, through the SBI.
+pub fn reset(reboot: bool) -> ! {
+    let kind = if reboot {
+        ResetType::ColdReboot
+    } else {
+        ResetType::Shutdown
+    };
+
+    system_reset(kind, ResetReason::SystemFailure);
+
+    log::error!("The firmware does not support resetting the machine.");
+    crate::arch::halt();
+}
+
+pub use exit::exit;
+pub use ipi::online_harts;
+pub use mem::{
+    copy_kernel_mappings, free_user_mappings, map_page, share_user_mappings, shootdown_tlb,
+    translate, unmap_page, user_space_end,
+};
+pub use plic::{enable_irq, init as init_irq};
+pub use stack::{frame_pointer, unwind_frame};
+pub use time::monotonic_time;
+pub use trap::{TrapFrame, enter_user, resume};
+pub use user::{copy_cstr_from_user, copy_from_user, copy_to_user};
@@ -0,0 +1,154 @@
+//! Reports of exceptions the kernel does not handle.
+//!
+//! An oops report holds everything needed to make sense of a crash from a serial log: the trap
+//! CSRs, every saved register, the faulting instruction, how the faulting address is mapped and,
+//! for faults in the kernel, a backtrace.
+
+use core::fmt;
+
+use super::csr::{self, PrivilegeMode};
+use super::trap::TrapFrame;
+use crate::arch;
+use crate::backtrace;
+use crate::boot;
+use crate::log::{self, Level};
+use crate::mem::VirtualAddr;
+
+/// Logs the oops report of the exception `scause` with the trap value `stval` taken in `frame`.
+pub fn report(frame: &TrapFrame, scause: csr::scause, stval: u64) {
+    // `TrapFrame` is packed, so its fields are copied out before being formatted.
+    let (sepc, sstatus) = (frame.sepc, frame.sstatus);
+    let mode = csr::sstatus::new(sstatus).spp();
+
+    log::error!("Oops: `{:?}` in {:?} mode", scause.exception_code(), mode);
+    log::error!(
+        "sepc {:016x} stval {:016x} scause {:016x} sstatus {:016x}",
+        sepc,
+        stval,
+        scause.value(),
+        sstatus
+    );
+
+    if mode == PrivilegeMode::Supervisor
+        && let Some((name, offset)) = backtrace::lookup(sepc)
+    {
+        log::error!("sepc is at {}+{:#x}", name, offset);
+    }
+
+    log_registers(frame);
+    log_instruction(sepc);
+    log_mapping(stval);
+
+    // The frame pointer of a user program means nothing to the kernel.
+    if mode == PrivilegeMode::Supervisor {
+        backtrace::log(Level::Error, Some(sepc), frame.s0);
+    }
+}
+
+pub fn log_registers(frame: &TrapFrame) {
+    let registers = [
+        ("ra", frame.ra),
+        ("sp", frame.sp),
+        ("gp", frame.gp),
+        ("tp", frame.tp),
+        ("t0", frame.t0),
+        ("t1", frame.t1),
+        ("t2", frame.t2),
+        ("s0", frame.s0),
+        ("s1", frame.s1),
+        ("a0", frame.a0),
+        ("a1", frame.a1),
+        ("a2", frame.a2),
+        ("a3", frame.a3),
+        ("a4", frame.a4),
+        ("a5", frame.a5),
+        ("a6", frame.a6),
+        ("a7", frame.a7),
+        ("s2", frame.s2),
+        ("s3", frame.s3),
+        ("s4", frame.s4),
+        ("s5", frame.s5),
+        ("s6", frame.s6),
+        ("s7", frame.s7),
+        ("s8", frame.s8),
+        ("s9", frame.s9),
+        ("s10", frame.s10),
+        ("s11", frame.s11),
+        ("t3", frame.t3),
+        ("t4", frame.t4),
+        ("t5", frame.t5),
+        ("t6", frame.t6),
+    ];
+
+    for row in registers.chunks(4) {
+        log::error!("{}", Registers(row));
+    }
+}
+
+/// A row of the register dump.
+struct Registers<'a>(&'a [(&'static str, u64)]);
+
+impl fmt::Display for Registers<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (name, value)) in self.0.iter().enumerate() {
+            if index != 0 {
+                f.write_str(" ")?;
+            }
+
+            write!(f, "{:>3} {:016x}", name, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Logs the instruction at `sepc`, read through the direct map so that a bad `sepc` can not fault
+/// again.
+fn log_instruction(sepc: u64) {
+    let Some(low) = read_parcel(sepc) else {
+        log::error!("Instruction: (unreadable)");
+        return;
+    };
+
+    // Instructions with the lowest two bits set are 32 bits long, the others are compressed.
+    if low & 0b11 != 0b11 {
+        log::error!("Instruction: {:04x}", low);
+        return;
+    }
+
+    match read_parcel(sepc + 2) {
+        Some(high) => log::error!("Instruction: {:08x}", (high as u32) << 16 | low as u32),
+        None => log::error!("Instruction: {:04x} (unreadable)", low),
+    }
+}
+
+/// Reads the 16-bit parcel of an instruction at `addr` in the current page table.
+fn read_parcel(addr: u64) -> Option<u16> {
+    let root_page_table = arch::root_page_table();
+    let mapping = arch::translate(root_page_table, VirtualAddr::new(addr))?;
+    let hhdm_offset = boot::BOOT_INFO.get()?.hhdm_offset;
+    let physical = mapping.physical_addr.addr() + (addr - mapping.virtual_addr.addr());
+
+    // The direct map only covers the regions of the memory map. A misaligned `sepc` is what some
+    // of the exceptions are about, so the parcel may reach into the next page.
+    let direct = physical.checked_add(hhdm_offset)?;
+    for byte in [direct, direct.checked_add(1)?] {
+        arch::translate(root_page_table, VirtualAddr::new(byte))?;
+    }
+
+    // SAFETY: Both bytes of the parcel are mapped in the direct map of the current page table.
+    Some(unsafe { (direct as *const u16).read_unaligned() })
+}
+
+fn log_mapping(stval: u64) {
+    match arch::translate(arch::root_page_table(), VirtualAddr::new(stval)) {
+        Some(mapping) => log::error!(
+            "stval is in the {:#x} byte page at {} mapped to {} with {:?}",
+            mapping.size,
+            mapping.virtual_addr,
+            mapping.physical_addr,
+            mapping.flags
+        ),
+        None => log::error!("stval is not mapped."),
+    }
+}
@@ -0,0 +1,145 @@
+//! The platform-level interrupt controller, which routes the interrupts of devices to the harts.
+
+use spin::Once;
+
+use super::csr::{self, CsrRead, CsrWrite};
+use crate::fdt::{DeviceTree, Node};
+use crate::mem::{self, PhysicalAddr, VirtualAddr};
+use crate::{boot, log};
+
+const PRIORITY_OFFSET: u64 = 0x0;
+const ENABLE_OFFSET: u64 = 0x2000;
+const ENABLE_STRIDE: u64 = 0x80;
+const CONTEXT_OFFSET: u64 = 0x20_0000;
+const CONTEXT_STRIDE: u64 = 0x1000;
+
+const THRESHOLD: u64 = 0x0;
+const CLAIM: u64 = 0x4;
+
+/// The cause of supervisor external interrupts, as found in `interrupts-extended`.
+const SUPERVISOR_EXTERNAL_INTERRUPT: u32 = 9;
+
+const COMPATIBLE: [&str; 2] = ["riscv,plic0", "sifive,plic-1.0.0"];
+
+struct Plic {
+    base: VirtualAddr,
+    /// The context that delivers interrupts to supervisor mode on the boot hart.
+    context: u64,
+}
+
+static PLIC: Once<Plic> = Once::new();
+
+pub fn init() {
+    let Some(device_tree) = boot::device_tree() else {
+        log::warn!("No device tree, external interrupts are disabled.");
+        return;
+    };
+
+    let Some(node) = device_tree.nodes().find(|node| {
+        COMPATIBLE
+            .iter()
+            .any(|&compatible| node.is_compatible(compatible))
+    }) else {
+        log::warn!("No PLIC found, external interrupts are disabled.");
+        return;
+    };
+
+    let Some((addr, size)) = node.reg().next() else {
+        log::warn!("The PLIC has no registers, external interrupts are disabled.");
+        return;
+    };
+
+    let hart_id = boot::BOOT_INFO.get().unwrap().bsp_hart_id;
+    let context = find_context(&device_tree, &node, hart_id).unwrap_or_else(|| {
+        // This is how QEMU `virt` numbers the contexts, machine mode comes first.
+        2 * hart_id + 1
+    });
+
+    let base =
+        mem::map_mmio(PhysicalAddr::new(addr), size as usize).expect("Failed to map the PLIC");
+
+    let plic = PLIC.call_once(|| Plic { base, context });
+
+    // Let every interrupt with a non-zero priority through.
+    plic.write(CONTEXT_OFFSET + CONTEXT_STRIDE * context + THRESHOLD, 0);
+
+    let mut sie = csr::sie::read();
+    sie.set_seie(true);
+    unsafe { csr::sie::write(sie) }
+
+    log::info!(
+        "Initialized PLIC at {} for context {}.",
+        PhysicalAddr::new(addr),
+        context
+    );
+}
+
+/// Unmasks the interrupt `irq` for the boot hart.
+pub fn enable_irq(irq: u32) {
+    let Some(plic) = PLIC.get() else {
+        return;
+    };
+
+    plic.write(PRIORITY_OFFSET + 4 * irq as u64, 1);
+
+    let enable = ENABLE_OFFSET + ENABLE_STRIDE * plic.context + 4 * (irq as u64 / 32);
+    plic.write(enable, plic.read(enable) | 1 << (irq % 32));
+}
+
+/// Dispatches every pending external interrupt.
+pub fn handle_interrupt() {
+    let Some(plic) = PLIC.get() else {
+        return;
+    };
+
+    let claim = CONTEXT_OFFSET + CONTEXT_STRIDE * plic.context + CLAIM;
+
+    loop {
+        let irq = plic.read(claim);
+        if irq == 0 {
+            break;
+        }
+
+        crate::irq::handle(irq);
+        plic.write(claim, irq);
+    }
+}
+
+impl Plic {
+    fn read(&self, offset: u64) -> u32 {
+        let register = VirtualAddr::new(self.base.addr() + offset);
+        unsafe { register.as_ptr::<u32>().read_volatile() }
+    }
+
+    fn write(&self, offset: u64, value: u32) {
+        let register = VirtualAddr::new(self.base.addr() + offset);
+        unsafe { register.as_mut_ptr::<u32>().write_volatile(value) }
+    }
+}
+
+/// Finds the context of the PLIC that targets supervisor mode on the hart `hart_id`.
+///
+/// Every context is listed in `interrupts-extended` as the interrupt controller of a hart together
+/// with the interrupt it raises there.
+fn find_context(device_tree: &DeviceTree<'_>, plic: &Node<'_>, hart_id: u64) -> Option<u64> {
+    let hart_controller = device_tree
+        .find_node("/cpus")?
+        .children()
+        .find(|cpu| cpu.reg().next().map(|(reg, _)| reg) == Some(hart_id))?
+        .children()
+        .find(|child| child.property("interrupt-controller").is_some())?
+        .phandle()?;
+
+    let mut cells = plic.property("interrupts-extended")?.cells();
+    let mut context = 0;
+
+    while let (Some(phandle), Some(cause)) = (cells.next(), cells.next()) {
+        if phandle == hart_controller && cause == SUPERVISOR_EXTERNAL_INTERRUPT {
+            return Some(context);
+        }
+
+        context += 1;
+    }
+
+    None
+}
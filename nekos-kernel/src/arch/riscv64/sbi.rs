@@ -1,16 +1,16 @@
 #[repr(C)]
 struct SibReturn {
-    error: i32,
-    value: i32,
+    error: i64,
+    value: i64,
 }
 
 unsafe fn call(
-    arg0: i32,
-    arg1: i32,
-    arg2: i32,
-    arg3: i32,
-    arg4: i32,
-    arg5: i32,
+    arg0: u64,
+    arg1: u64,
+    arg2: u64,
+    arg3: u64,
+    arg4: u64,
+    arg5: u64,
     fid: i32,
     eid: i32,
 ) -> SibReturn {
@@ -34,6 +34,45 @@ unsafe fn call(
     SibReturn { error, value }
 }
 
+/// The IPI extension, `sPI` in ASCII.
+const IPI_EXTENSION: i32 = 0x735049;
+
+/// The system reset extension, `SRST` in ASCII.
+const SRST_EXTENSION: i32 = 0x53525354;
+
+/// The remote fence extension, `RFNC` in ASCII.
+const RFENCE_EXTENSION: i32 = 0x52464E43;
+
+/// Raises a supervisor software interrupt on the hart `hart_id`.
+pub fn send_ipi(hart_id: u64) {
+    // The hart mask holds only the lowest hart, which is `hart_id` as the base.
+    unsafe { call(1, hart_id, 0, 0, 0, 0, 0, IPI_EXTENSION) };
+}
+
+#[repr(i32)]
+#[derive(Clone, Copy)]
+pub enum ResetType {
+    Shutdown = 0,
+    ColdReboot = 1,
+}
+
+#[repr(i32)]
+#[derive(Clone, Copy)]
+pub enum ResetReason {
+    NoReason = 0,
+    SystemFailure = 1,
+}
+
+/// Shuts down or reboots the machine. Only returns if the firmware does not support it.
+pub fn system_reset(kind: ResetType, reason: ResetReason) {
+    unsafe { call(kind as u64, reason as u64, 0, 0, 0, 0, 0, SRST_EXTENSION) };
+}
+
+/// Makes the harts in `hart_mask` execute `sfence.vma` for `[start, start + size)`.
+pub fn remote_sfence_vma(hart_mask: u64, start: u64, size: u64) {
+    unsafe { call(hart_mask, 0, start, size, 0, 0, 1, RFENCE_EXTENSION) };
+}
+
 pub struct SbiWriter;
 
 use core::fmt;
@@ -41,13 +80,20 @@ use core::fmt;
 impl fmt::Write for SbiWriter {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         for c in s.bytes() {
-            unsafe { call(c as i32, 0, 0, 0, 0, 0, 0, 1) };
+            unsafe { call(c as u64, 0, 0, 0, 0, 0, 0, 1) };
         }
 
         Ok(())
     }
 }
 
+/// Writes raw bytes to the console, without requiring them to be valid UTF-8.
+pub fn console_write(bytes: &[u8]) {
+    for &c in bytes {
+        unsafe { call(c as u64, 0, 0, 0, 0, 0, 0, 1) };
+    }
+}
+
 pub fn print(args: fmt::Arguments) {
     use core::fmt::Write;
     let mut writer = SbiWriter {};
@@ -0,0 +1,49 @@
+//! Walking the stack through frame pointers.
+//!
+//! Functions built with frame pointers save the return address and the frame pointer of their
+//! caller at the top of their frame, right below the address `s0` points to:
+//!
+//! ```text
+//! s0 - 8:  return address
+//! s0 - 16: frame pointer of the caller
+//! ```
+
+use super::csr::{self, CsrRead};
+use super::mem::{translate, user_space_end};
+use crate::mem::VirtualAddr;
+
+/// The frame pointer of the calling function.
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    let fp: u64;
+    unsafe { core::arch::asm!("mv {}, s0", out(reg) fp) };
+    fp
+}
+
+/// Returns the return address and the caller's frame pointer saved in the frame at `fp`.
+///
+/// A corrupt `fp` is expected while a crash is being reported, so it is checked against the
+/// current page table instead of being trusted. It is up to the caller to stop once the caller's
+/// frame pointer is not above `fp`.
+pub fn unwind_frame(fp: u64) -> Option<(u64, u64)> {
+    // Kernel stacks live in the upper half, anything else is a broken chain or a user frame.
+    if !fp.is_multiple_of(8) || fp <= user_space_end().addr() + 16 {
+        return None;
+    }
+
+    // The two words may lie on either side of a page boundary.
+    let root_page_table = csr::satp::read().ppn().as_physical_addr();
+    let mapped = |addr: u64| translate(root_page_table, VirtualAddr::new(addr)).is_some();
+    if !mapped(fp - 16) || !mapped(fp - 8) {
+        return None;
+    }
+
+    // SAFETY: Both words are aligned and mapped in the current page table, in the upper half
+    // where every mapping is readable by the kernel. They may hold garbage if `fp` was not a frame
+    // pointer, which the caller has to expect.
+    unsafe {
+        let ra = ((fp - 8) as *const u64).read();
+        let caller_fp = ((fp - 16) as *const u64).read();
+        Some((ra, caller_fp))
+    }
+}
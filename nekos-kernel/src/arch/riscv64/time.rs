@@ -0,0 +1,35 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use crate::{boot, log};
+
+/// The frequency of the `time` CSR, which defaults to the one of the QEMU `virt` machine until it
+/// is read from the device tree.
+static TIMEBASE_FREQUENCY: AtomicU64 = AtomicU64::new(10_000_000);
+
+pub fn init() {
+    let frequency = boot::device_tree()
+        .and_then(|device_tree| device_tree.find_node("/cpus"))
+        .and_then(|cpus| cpus.property("timebase-frequency"))
+        .and_then(|property| property.as_u64());
+
+    match frequency {
+        Some(frequency) if frequency != 0 => {
+            TIMEBASE_FREQUENCY.store(frequency, Ordering::Relaxed);
+            log::debug!("Timebase frequency is {} Hz", frequency);
+        }
+        _ => log::warn!("No timebase frequency in the device tree, assuming the default."),
+    }
+}
+
+/// Returns the time elapsed since the hart was reset.
+pub fn monotonic_time() -> Duration {
+    let ticks: u64;
+    unsafe { core::arch::asm!("rdtime {}", out(reg) ticks) };
+
+    let frequency = TIMEBASE_FREQUENCY.load(Ordering::Relaxed);
+    let secs = ticks / frequency;
+    let nanos = (ticks % frequency) * 1_000_000_000 / frequency;
+
+    Duration::new(secs, nanos as u32)
+}
@@ -48,6 +48,7 @@ pub enum ExceptionCode {
 }
 
 #[repr(C, packed)]
+#[derive(Clone, Copy)]
 pub struct TrapFrame {
     pub ra: u64,
     pub gp: u64,
@@ -80,17 +81,26 @@ pub struct TrapFrame {
     pub s10: u64,
     pub s11: u64,
     pub sp: u64,
+    pub sepc: u64,
+    pub sstatus: u64,
 }
 
-use crate::log;
+/// The size reserved on the stack for a `TrapFrame`, the stack pointer must stay 16-byte aligned.
+const TRAP_FRAME_SIZE: usize = misc::align_up(size_of::<TrapFrame>() as u64, 16) as usize;
+
+use crate::{log, misc};
 use core::arch;
 
-use super::csr::{self, CsrRead, CsrWrite};
-use crate::mem::VirtualAddr;
+use super::csr::{self, CsrRead, CsrWrite, PrivilegeMode};
+use super::oops;
+use crate::mem::{Access, VirtualAddr};
 
 pub fn init() {
     let stvec = csr::stvec::new(handler as u64);
 
+    // `sscratch` is zero while the hart runs in S-mode, and holds the kernel stack while it runs in
+    // U-mode. This is how the trap handler tells where it came from.
+    unsafe { csr::sscratch::write(csr::sscratch::new(0)) }
     unsafe { csr::stvec::write(stvec) }
 
     log::info!(
@@ -109,24 +119,151 @@ extern "C" fn handle_trap(frame: &mut TrapFrame) {
     }
 }
 
-fn handle_interrupt(_frame: &mut TrapFrame) {
+fn handle_interrupt(frame: &mut TrapFrame) {
     let scause = csr::scause::read();
 
-    panic!("Unhandled interrupt: `{:?}`.", scause.interrupt_code());
+    match scause.interrupt_code() {
+        InterruptCode::SupervisorSoftwareInterrupt => super::ipi::handle_interrupt(frame),
+        InterruptCode::SupervisorExternalInterrupt => super::plic::handle_interrupt(),
+        code => panic!("Unhandled interrupt: `{:?}`.", code),
+    }
 }
 
-fn handle_exception(_frame: &mut TrapFrame) {
+fn handle_exception(frame: &mut TrapFrame) {
     let scause = csr::scause::read();
 
-    panic!("Unhandled exception: `{:?}`.", scause.exception_code());
+    if let ExceptionCode::EnvironmentCallFromUserMode = scause.exception_code() {
+        // Return to the instruction after `ecall`.
+        frame.sepc += 4;
+
+        let args = [frame.a0, frame.a1, frame.a2, frame.a3, frame.a4, frame.a5];
+        frame.a0 = crate::syscall::dispatch(frame.a7, args) as u64;
+
+        crate::process::schedule(frame);
+        return;
+    }
+
+    let sepc = frame.sepc;
+    let mode = csr::sstatus::new(frame.sstatus).spp();
+    let stval = csr::stval::read().value();
+
+    #[cfg(feature = "kernel-tests")]
+    if mode == PrivilegeMode::Supervisor
+        && let ExceptionCode::IllegalInstruction = scause.exception_code()
+        && tests::CATCH_ILLEGAL.swap(false, core::sync::atomic::Ordering::AcqRel)
+    {
+        // Skip the 32-bit instruction that the test planted.
+        frame.sepc += 4;
+        return;
+    }
+
+    // Page faults of a process on memory it mapped are retried once the page is populated, or
+    // copied if it was shared with a fork.
+    let access = match scause.exception_code() {
+        ExceptionCode::LoadPageFault => Some(Access::Read),
+        ExceptionCode::StoreAmoPageFault => Some(Access::Write),
+        ExceptionCode::InstructionPageFault => Some(Access::Execute),
+        _ => None,
+    };
+
+    if mode == PrivilegeMode::User
+        && let Some(access) = access
+    {
+        match crate::process::handle_page_fault(VirtualAddr::new(stval), access) {
+            Ok(true) => return,
+            Ok(false) => {}
+            Err(err) => log::error!(
+                "Failed to handle the page fault at {}: {:?}",
+                VirtualAddr::new(stval),
+                err
+            ),
+        }
+    }
+
+    oops::report(frame, scause, stval);
+
+    // A faulting user program only takes itself down, the kernel can not be trusted after a fault
+    // of its own.
+    if mode == PrivilegeMode::User {
+        let signal = signal(scause.exception_code());
+        let pid = crate::process::with_current(|process| process.pid);
+        log::error!("Killing process {} with signal {}.", pid, signal);
+
+        crate::process::exit(128 + signal);
+        crate::process::schedule(frame);
+        return;
+    }
+
+    panic!(
+        "Unhandled exception: `{:?}` from {:?} mode at {}.",
+        scause.exception_code(),
+        mode,
+        VirtualAddr::new(sepc)
+    );
+}
+
+/// The number of the POSIX signal that a process is killed with for an exception.
+fn signal(code: ExceptionCode) -> i32 {
+    const SIGILL: i32 = 4;
+    const SIGTRAP: i32 = 5;
+    const SIGBUS: i32 = 7;
+    const SIGSEGV: i32 = 11;
+
+    match code {
+        ExceptionCode::IllegalInstruction => SIGILL,
+        ExceptionCode::Breakpoint => SIGTRAP,
+        ExceptionCode::InstructionAddressMisaligned
+        | ExceptionCode::LoadAddressMisaligned
+        | ExceptionCode::StoreAmoAddressMisaligned => SIGBUS,
+        _ => SIGSEGV,
+    }
+}
+
+impl TrapFrame {
+    /// Sets the value that the interrupted system call returns.
+    pub fn set_return_value(&mut self, value: u64) {
+        self.a0 = value;
+    }
+
+    /// Creates the initial state of a U-mode context that starts at `entry` with the stack
+    /// pointer set to `stack`.
+    pub fn new_user(entry: VirtualAddr, stack: VirtualAddr) -> Self {
+        let mut sstatus = csr::sstatus::read();
+        sstatus.set_spp(PrivilegeMode::User);
+        sstatus.set_spie(true);
+        sstatus.set_sum(false);
+
+        // SAFETY: `TrapFrame` is plain old data, all zeroes is a valid state for the user registers.
+        let mut frame: TrapFrame = unsafe { core::mem::zeroed() };
+        frame.sp = stack.addr();
+        frame.sepc = entry.addr();
+        frame.sstatus = sstatus.value();
+        frame
+    }
+}
+
+/// Drops the hart into U-mode at `entry` with the stack pointer set to `stack`.
+///
+/// Traps taken from U-mode reuse the kernel stack from the point where this function was called.
+pub fn enter_user(entry: VirtualAddr, stack: VirtualAddr) -> ! {
+    resume(TrapFrame::new_user(entry, stack))
+}
+
+/// Returns to the context saved in `frame`.
+pub fn resume(frame: TrapFrame) -> ! {
+    unsafe { trap_return(&frame) }
 }
 
 #[unsafe(naked)]
 extern "C" fn handler() {
     arch::naked_asm!(
-        // save `sp` to `sscratch` because it will be passed to the trap frame.
-        "csrw sscratch, sp",
-        "addi sp, sp, -8 * 31",
+        // Swap `sp` with `sscratch`, if we came from S-mode `sscratch` is zero, so we restore `sp`
+        // and keep the interrupted stack pointer in `sscratch` for the trap frame.
+        "csrrw sp, sscratch, sp",
+        "bnez sp, 1f",
+        "csrr sp, sscratch",
+        "1:",
+        "addi sp, sp, -{frame_size}",
 
         "sd ra,  8 * 0(sp)",
         "sd gp,  8 * 1(sp)",
@@ -159,12 +296,47 @@ extern "C" fn handler() {
         "sd s10, 8 * 28(sp)",
         "sd s11, 8 * 29(sp)",
 
-        "csrr a0, sscratch",
-        "sd a0, 8 * 30(sp)",
-        "mv a0, sp",
+        "csrr t0, sscratch",
+        "sd t0, 8 * 30(sp)",
+        "csrw sscratch, zero",
+
+        "csrr t0, sepc",
+        "sd t0, 8 * 31(sp)",
+        "csrr t0, sstatus",
+        "sd t0, 8 * 32(sp)",
 
+        "mv a0, sp",
         "call {handle_trap}",
 
+        "mv a0, sp",
+        "tail {trap_return}",
+
+        handle_trap = sym handle_trap,
+        trap_return = sym trap_return,
+        frame_size = const TRAP_FRAME_SIZE,
+    );
+}
+
+/// Restores the state saved in `frame` and returns from the trap with `sret`.
+///
+/// `frame` must be at the top of the kernel stack that should be used for the next trap.
+#[unsafe(naked)]
+unsafe extern "C" fn trap_return(frame: *const TrapFrame) -> ! {
+    arch::naked_asm!(
+        "mv sp, a0",
+
+        "ld t0, 8 * 31(sp)",
+        "csrw sepc, t0",
+        "ld t0, 8 * 32(sp)",
+        "csrw sstatus, t0",
+
+        // When returning to U-mode, the next trap starts at the top of this trap frame.
+        "andi t0, t0, {spp}",
+        "bnez t0, 1f",
+        "addi t0, sp, {frame_size}",
+        "csrw sscratch, t0",
+        "1:",
+
         "ld ra,  8 * 0(sp)",
         "ld gp,  8 * 1(sp)",
         "ld tp,  8 * 2(sp)",
@@ -199,6 +371,37 @@ extern "C" fn handler() {
 
         "sret",
 
-        handle_trap = sym handle_trap,
+        spp = const csr::sstatus::SPP,
+        frame_size = const TRAP_FRAME_SIZE,
     );
 }
+
+#[cfg(feature = "kernel-tests")]
+mod tests {
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    use crate::test::kernel_test;
+
+    /// Set by a test that is about to execute an illegal instruction, the trap handler skips the
+    /// instruction instead of panicking.
+    pub(super) static CATCH_ILLEGAL: AtomicBool = AtomicBool::new(false);
+
+    #[kernel_test]
+    fn returns_from_illegal_instruction() {
+        let value: u64;
+        CATCH_ILLEGAL.store(true, Ordering::Release);
+
+        // `csrw cycle, zero` writes a read-only CSR, which is the canonical 32-bit illegal
+        // instruction. The registers must be the same after the handler returns.
+        unsafe {
+            core::arch::asm!(
+                "li {value}, 0x5a5a",
+                ".4byte 0xc0001073",
+                value = out(reg) value,
+            );
+        }
+
+        assert!(!CATCH_ILLEGAL.load(Ordering::Acquire));
+        assert_eq!(value, 0x5a5a);
+    }
+}
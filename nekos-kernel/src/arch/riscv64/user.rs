@@ -0,0 +1,121 @@
+use super::csr::{self, CsrRead, CsrWrite};
+use super::mem::{translate, user_space_end};
+
+use crate::arch::{self, PAGE_SIZE};
+use crate::mem::{Access, UserAccessErr, VirtualAddr, VirtualMemoryFlags};
+use crate::misc;
+
+/// Copies `dst.len()` bytes from user memory at `src` into `dst`.
+pub fn copy_from_user(dst: &mut [u8], src: VirtualAddr) -> Result<(), UserAccessErr> {
+    check_user_range(src, dst.len(), VirtualMemoryFlags::empty())?;
+
+    with_user_access(|| unsafe {
+        core::ptr::copy_nonoverlapping(src.as_ptr::<u8>(), dst.as_mut_ptr(), dst.len());
+    });
+
+    Ok(())
+}
+
+/// Copies `src` into user memory at `dst`.
+pub fn copy_to_user(dst: VirtualAddr, src: &[u8]) -> Result<(), UserAccessErr> {
+    check_user_range(dst, src.len(), VirtualMemoryFlags::Writeable)?;
+
+    with_user_access(|| unsafe {
+        core::ptr::copy_nonoverlapping(src.as_ptr(), dst.as_mut_ptr::<u8>(), src.len());
+    });
+
+    Ok(())
+}
+
+/// Copies the null terminated string at `src` into `dst` and returns its length, without the
+/// terminator.
+pub fn copy_cstr_from_user(dst: &mut [u8], src: VirtualAddr) -> Result<usize, UserAccessErr> {
+    let mut len = 0;
+
+    while len < dst.len() {
+        // Only check one page at a time, the string may end before the next one.
+        let addr = src.addr() + len as u64;
+        let page_end = misc::align_down_page(addr) + PAGE_SIZE;
+        let chunk = ((page_end - addr) as usize).min(dst.len() - len);
+
+        check_user_range(VirtualAddr::new(addr), chunk, VirtualMemoryFlags::empty())?;
+
+        let dst = &mut dst[len..len + chunk];
+        with_user_access(|| unsafe {
+            core::ptr::copy_nonoverlapping(addr as *const u8, dst.as_mut_ptr(), chunk);
+        });
+
+        if let Some(end) = dst.iter().position(|&c| c == 0) {
+            return Ok(len + end);
+        }
+
+        len += chunk;
+    }
+
+    Err(UserAccessErr::TooLong)
+}
+
+/// Checks that every page in `[addr, addr + len)` is mapped in the current page table as user
+/// accessible with at least `flags`, populating pages the current process did not touch yet.
+fn check_user_range(
+    addr: VirtualAddr,
+    len: usize,
+    flags: VirtualMemoryFlags,
+) -> Result<(), UserAccessErr> {
+    let end = addr
+        .addr()
+        .checked_add(len as u64)
+        .ok_or(UserAccessErr::InvalidAddr)?;
+
+    if end > user_space_end().addr() {
+        return Err(UserAccessErr::InvalidAddr);
+    }
+
+    let root_page_table = arch::root_page_table();
+    let required = flags | VirtualMemoryFlags::UserAccessible;
+    let access = if flags.contains(VirtualMemoryFlags::Writeable) {
+        Access::Write
+    } else {
+        Access::Read
+    };
+
+    let mut page = misc::align_down_page(addr.addr());
+    while page < end {
+        let virtual_addr = VirtualAddr::new(page);
+
+        // The kernel touching a page faults it in like the process touching it would, which also
+        // copies copy-on-write pages it writes to.
+        let permitted = translate(root_page_table, virtual_addr)
+            .is_some_and(|mapping| mapping.flags.contains(required));
+
+        if !permitted {
+            let resolved = crate::process::handle_page_fault(virtual_addr, access)
+                .map_err(|_| UserAccessErr::PermissionDenied)?;
+
+            let mapping =
+                translate(root_page_table, virtual_addr).ok_or(UserAccessErr::NotMapped)?;
+            if !resolved || !mapping.flags.contains(required) {
+                return Err(UserAccessErr::PermissionDenied);
+            }
+        }
+
+        page += PAGE_SIZE;
+    }
+
+    Ok(())
+}
+
+/// Runs `f` with `sstatus.SUM` set, so that it may touch pages mapped with `U`.
+fn with_user_access<T>(f: impl FnOnce() -> T) -> T {
+    let mut sstatus = csr::sstatus::read();
+    sstatus.set_sum(true);
+    unsafe { csr::sstatus::write(sstatus) };
+
+    let result = f();
+
+    let mut sstatus = csr::sstatus::read();
+    sstatus.set_sum(false);
+    unsafe { csr::sstatus::write(sstatus) };
+
+    result
+}
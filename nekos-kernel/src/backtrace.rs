@@ -0,0 +1,183 @@
+//! Stack backtraces with function names.
+//!
+//! The kernel is built with frame pointers, so the frames of the stack form a linked list that
+//! can be followed without unwinding tables. Return addresses are turned into names with a symbol
+//! table that `nekos-symbols` writes into the `.symbols` section after linking. Kernels that were
+//! not patched, like the one `cargo build` produces on its own, print bare addresses.
+
+use crate::arch;
+use crate::log::{self, Level};
+
+/// The space reserved for the symbol table.
+const SYMBOLS_SIZE: usize = 512 * 1024;
+
+const MAGIC: &[u8; 4] = b"NKSY";
+
+/// Stops following frame pointers at some point, in case the stack is corrupted.
+const MAX_FRAMES: usize = 64;
+
+#[used]
+#[unsafe(link_section = ".symbols")]
+static SYMBOL_SPACE: [u8; SYMBOLS_SIZE] = [0; SYMBOLS_SIZE];
+
+unsafe extern "C" {
+    #[link_name = "__symbols_begin"]
+    static SYMBOLS_BEGIN: u8;
+
+    #[link_name = "__symbols_end"]
+    static SYMBOLS_END: u8;
+}
+
+/// Logs a backtrace of the caller at `Level::Info`. Nothing calls it, it is meant to be dropped
+/// into code while debugging.
+#[allow(unused_macros)]
+macro_rules! backtrace {
+    () => {
+        $crate::backtrace::log(
+            $crate::log::Level::Info,
+            None,
+            $crate::arch::frame_pointer(),
+        )
+    };
+}
+
+#[allow(unused_imports)]
+pub(crate) use backtrace;
+
+/// The symbol table as patched in after linking. The contents are read through the linker symbols
+/// rather than `SYMBOL_SPACE`, which the compiler knows to be all zeroes.
+fn table() -> Option<&'static [u8]> {
+    // SAFETY: The linker script puts the `.symbols` section between the two symbols.
+    let table = unsafe {
+        let begin = &raw const SYMBOLS_BEGIN;
+        let end = &raw const SYMBOLS_END;
+        core::slice::from_raw_parts(begin, end.offset_from(begin) as usize)
+    };
+
+    (table.get(..4)? == MAGIC).then_some(table)
+}
+
+fn u32_at(table: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        table.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn u64_at(table: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        table.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// Finds the function containing `addr`, returning its name and the offset of `addr` into it.
+pub fn lookup(addr: u64) -> Option<(&'static str, u64)> {
+    let table = table()?;
+    let count = u32_at(table, 4)? as usize;
+    let entry = |index: usize| 8 + index * 16;
+
+    // The first function starting after `addr`, the one before it is the candidate.
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let middle = (low + high) / 2;
+        if u64_at(table, entry(middle))? <= addr {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+
+    let entry = entry(low.checked_sub(1)?);
+    let start = u64_at(table, entry)?;
+    let size = u32_at(table, entry + 8)? as u64;
+
+    if size != 0 && addr >= start + size {
+        return None;
+    }
+
+    let name = u32_at(table, entry + 12)? as usize;
+    let len = u16::from_le_bytes(table.get(name..name + 2)?.try_into().ok()?) as usize;
+    let name = core::str::from_utf8(table.get(name + 2..name + 2 + len)?).ok()?;
+
+    Some((name, addr - start))
+}
+
+/// Logs the call chain starting at `pc`, if given, followed by the return addresses found by
+/// following the frame pointer `fp`.
+pub fn log(level: Level, pc: Option<u64>, mut fp: u64) {
+    log::log(level, module_path!(), format_args!("Backtrace:"));
+
+    let mut index = 0;
+    if let Some(pc) = pc {
+        log_frame(level, index, pc, pc);
+        index += 1;
+    }
+
+    while index < MAX_FRAMES {
+        let Some((ra, caller_fp)) = arch::unwind_frame(fp) else {
+            break;
+        };
+
+        if ra == 0 {
+            break;
+        }
+
+        // The return address may already be past the end of a function that ends in a call.
+        log_frame(level, index, ra, ra - 1);
+        index += 1;
+
+        // Callers live further up the stack, anything else means the chain is broken.
+        if caller_fp <= fp {
+            break;
+        }
+
+        fp = caller_fp;
+    }
+}
+
+fn log_frame(level: Level, index: usize, addr: u64, lookup_addr: u64) {
+    match lookup(lookup_addr) {
+        Some((name, offset)) => log::log(
+            level,
+            module_path!(),
+            format_args!(
+                "  #{:<2} {:016x} {}+{:#x}",
+                index,
+                addr,
+                name,
+                offset + (addr - lookup_addr)
+            ),
+        ),
+        None => log::log(
+            level,
+            module_path!(),
+            format_args!("  #{:<2} {:016x} <unknown>", index, addr),
+        ),
+    }
+}
+
+#[cfg(feature = "kernel-tests")]
+mod tests {
+    use super::*;
+    use crate::test::kernel_test;
+
+    #[kernel_test]
+    fn unwinds_to_caller() {
+        let (ra, caller_fp) = arch::unwind_frame(arch::frame_pointer()).unwrap();
+        assert_ne!(ra, 0);
+        assert!(caller_fp > arch::frame_pointer());
+    }
+
+    #[kernel_test]
+    fn looks_up_functions() {
+        // Kernels that were not patched by `nekos-symbols` have no names.
+        if table().is_none() {
+            return;
+        }
+
+        let addr = lookup as *const () as u64;
+        assert_eq!(
+            lookup(addr + 4),
+            Some(("nekos_kernel::backtrace::lookup", 4))
+        );
+    }
+}
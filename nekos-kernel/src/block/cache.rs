@@ -0,0 +1,139 @@
+use alloc::collections::BTreeMap;
+use alloc::collections::btree_map::Entry;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use super::{BlockError, SECTOR_SIZE};
+use crate::arch::PAGE_SIZE;
+use crate::drivers::dma::DmaBuffer;
+use crate::mem::PhysicalAddr;
+use crate::misc;
+
+pub const SECTORS_PER_PAGE: u64 = PAGE_SIZE / SECTOR_SIZE;
+
+// The state of every sector in a page is tracked in a byte.
+misc::const_assert!(SECTORS_PER_PAGE <= 8);
+
+/// A page frame that caches the sectors of an aligned run of `SECTORS_PER_PAGE` sectors.
+pub struct CachePage {
+    frame: DmaBuffer,
+    /// The sectors that hold what is on the disk, or something newer.
+    pub valid: u8,
+    /// The sectors that are newer than what is on the disk.
+    pub dirty: u8,
+    last_used: u64,
+}
+
+/// The cached sectors of a disk, by the index of the page they are in.
+pub struct Cache {
+    pages: BTreeMap<u64, CachePage>,
+    max_pages: usize,
+    clock: u64,
+}
+
+impl CachePage {
+    /// The address of the `sector`th sector of the page, for requests to the device.
+    pub fn sector_addr(&self, sector: u64) -> PhysicalAddr {
+        PhysicalAddr::new(self.frame.physical_addr().addr() + sector * SECTOR_SIZE)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.frame.as_slice()[..PAGE_SIZE as usize]
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.frame.as_mut_slice()[..PAGE_SIZE as usize]
+    }
+}
+
+impl Cache {
+    pub const fn new(max_pages: usize) -> Self {
+        Self {
+            pages: BTreeMap::new(),
+            max_pages,
+            clock: 0,
+        }
+    }
+
+    pub fn max_pages(&self) -> usize {
+        self.max_pages
+    }
+
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn contains(&self, index: u64) -> bool {
+        self.pages.contains_key(&index)
+    }
+
+    /// Returns the page at `index`, adding an empty one if it is not cached yet.
+    pub fn get_or_insert(&mut self, index: u64) -> Result<&mut CachePage, BlockError> {
+        self.clock += 1;
+
+        let page = match self.pages.entry(index) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(CachePage {
+                frame: DmaBuffer::new(PAGE_SIZE as usize).ok_or(BlockError::OutOfMemory)?,
+                valid: 0,
+                dirty: 0,
+                last_used: 0,
+            }),
+        };
+
+        page.last_used = self.clock;
+        Ok(page)
+    }
+
+    pub fn get_mut(&mut self, index: u64) -> Option<&mut CachePage> {
+        self.pages.get_mut(&index)
+    }
+
+    pub fn remove(&mut self, index: u64) -> Option<CachePage> {
+        self.pages.remove(&index)
+    }
+
+    /// The least recently used page, leaving out the pages in `keep`.
+    pub fn least_recently_used(&self, keep: &Range<u64>) -> Option<u64> {
+        self.pages
+            .iter()
+            .filter(|&(index, _)| !keep.contains(index))
+            .min_by_key(|(_, page)| page.last_used)
+            .map(|(&index, _)| index)
+    }
+
+    pub fn dirty_pages(&self) -> Vec<u64> {
+        self.pages
+            .iter()
+            .filter(|(_, page)| page.dirty != 0)
+            .map(|(&index, _)| index)
+            .collect()
+    }
+}
+
+/// The runs of set bits in `mask` as ranges of bit indices.
+pub fn runs(mask: u8) -> impl Iterator<Item = Range<u64>> {
+    let mut bit = 0;
+
+    core::iter::from_fn(move || {
+        while bit < SECTORS_PER_PAGE && mask & (1 << bit) == 0 {
+            bit += 1;
+        }
+
+        let start = bit;
+        while bit < SECTORS_PER_PAGE && mask & (1 << bit) != 0 {
+            bit += 1;
+        }
+
+        (start < bit).then_some(start..bit)
+    })
+}
+
+/// The mask of the sectors of the page at `index` that fall into `sectors`.
+pub fn mask(index: u64, sectors: &Range<u64>) -> u8 {
+    let first = index * SECTORS_PER_PAGE;
+
+    (0..SECTORS_PER_PAGE)
+        .filter(|sector| sectors.contains(&(first + sector)))
+        .fold(0, |mask, sector| mask | (1 << sector))
+}
@@ -0,0 +1,489 @@
+//! The block layer, which sits between file systems and the drivers of block devices.
+//!
+//! Every registered device is wrapped in a [`Disk`]. Requests submitted to a disk are held back
+//! until someone waits for them, so that requests for adjacent sectors can be merged before they
+//! are passed to the driver. On top of that, disks cache their sectors in page frames and write
+//! them back when they are evicted or flushed.
+
+mod cache;
+mod partition;
+mod request;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use nekos_abi::Errno;
+use spin::Mutex;
+use ubyte::ToByteUnit;
+
+use crate::arch::PAGE_SIZE;
+use crate::log;
+
+use cache::{Cache, SECTORS_PER_PAGE};
+
+pub use partition::{PartUuid, PartitionInfo};
+pub use request::{Operation, Request, Segment};
+
+pub const SECTOR_SIZE: u64 = 512;
+
+/// How many pages of sectors a disk caches.
+const MAX_CACHE_PAGES: usize = 1024;
+
+/// How many pages a single read or write goes through at a time, which has to be well below
+/// `MAX_CACHE_PAGES`.
+const CHUNK_PAGES: u64 = 16;
+
+/// The most sectors requests are merged up to.
+const MAX_MERGED_SECTORS: u64 = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockError {
+    Io,
+    ReadOnly,
+    OutOfRange,
+    OutOfMemory,
+    Unsupported,
+}
+
+impl From<BlockError> for Errno {
+    fn from(err: BlockError) -> Self {
+        match err {
+            BlockError::Io => Errno::EIO,
+            BlockError::ReadOnly => Errno::EROFS,
+            BlockError::OutOfRange => Errno::EINVAL,
+            BlockError::OutOfMemory => Errno::ENOMEM,
+            BlockError::Unsupported => Errno::EOPNOTSUPP,
+        }
+    }
+}
+
+/// The driver side of a disk, which transfers whole sectors.
+pub trait BlockDevice: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn sector_count(&self) -> u64;
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// The most segments a single request may be made of.
+    fn max_segments(&self) -> usize;
+
+    /// Starts working on `request`, whose outcome is reported through [`Request::complete`]. This
+    /// may happen before `submit` returns.
+    fn submit(&self, request: Arc<Request>);
+
+    /// Completes the requests the device is done with, for when interrupts cannot be taken.
+    fn poll(&self);
+
+    /// Where the device lies on its disk if it is a partition.
+    fn partition(&self) -> Option<&PartitionInfo> {
+        None
+    }
+}
+
+pub struct Disk {
+    device: Arc<dyn BlockDevice>,
+    /// Requests that have been submitted but not passed to the device yet.
+    plugged: Mutex<Vec<Arc<Request>>>,
+    cache: Mutex<Cache>,
+}
+
+static DISKS: Mutex<Vec<Arc<Disk>>> = Mutex::new(Vec::new());
+
+/// Makes `device` available as a disk.
+pub fn register(device: Arc<dyn BlockDevice>) -> Arc<Disk> {
+    let disk = Arc::new(Disk {
+        device,
+        plugged: Mutex::new(Vec::new()),
+        cache: Mutex::new(Cache::new(MAX_CACHE_PAGES)),
+    });
+
+    log::info!(
+        "Found disk {} with {}{}.",
+        disk.name(),
+        disk.size().bytes(),
+        if disk.is_read_only() {
+            " (read-only)"
+        } else {
+            ""
+        }
+    );
+
+    DISKS.lock().push(disk.clone());
+    disk
+}
+
+/// Registers the partitions of every disk found by the drivers.
+pub fn init() {
+    for disk in disks() {
+        if disk.device().partition().is_none() {
+            partition::scan(&disk);
+        }
+    }
+}
+
+pub fn find(name: &str) -> Option<Arc<Disk>> {
+    DISKS
+        .lock()
+        .iter()
+        .find(|disk| disk.name() == name)
+        .cloned()
+}
+
+pub fn find_by_partuuid(uuid: PartUuid) -> Option<Arc<Disk>> {
+    DISKS
+        .lock()
+        .iter()
+        .find(|disk| {
+            disk.device()
+                .partition()
+                .is_some_and(|info| info.uuid == uuid)
+        })
+        .cloned()
+}
+
+/// Finds the disk described by `spec`, which is either `PARTUUID=...`, a name like `vda1` or the
+/// same name under `/dev`.
+pub fn resolve(spec: &str) -> Option<Arc<Disk>> {
+    if let Some(uuid) = spec.strip_prefix("PARTUUID=") {
+        return find_by_partuuid(PartUuid::parse(uuid)?);
+    }
+
+    find(spec.strip_prefix("/dev/").unwrap_or(spec))
+}
+
+pub fn disks() -> Vec<Arc<Disk>> {
+    DISKS.lock().clone()
+}
+
+/// Writes the dirty sectors of every disk back, before the kernel stops.
+pub fn flush_all() {
+    for disk in disks().iter().filter(|disk| !disk.is_read_only()) {
+        if let Err(err) = disk.flush() {
+            log::error!("Failed to flush {}: {:?}", disk.name(), err);
+        }
+    }
+}
+
+impl Disk {
+    pub fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.device
+    }
+
+    pub fn sector_count(&self) -> u64 {
+        self.device.sector_count()
+    }
+
+    /// The capacity in bytes.
+    pub fn size(&self) -> u64 {
+        self.sector_count() * SECTOR_SIZE
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.device.is_read_only()
+    }
+
+    /// Queues `request`, which is passed to the device once someone waits for a request or calls
+    /// [`Disk::unplug`].
+    pub fn submit(&self, request: Arc<Request>) {
+        if request.end_sector() > self.sector_count() {
+            request.complete(Err(BlockError::OutOfRange));
+        } else if request.operation == Operation::Write && self.is_read_only() {
+            request.complete(Err(BlockError::ReadOnly));
+        } else {
+            self.plugged.lock().push(request);
+        }
+    }
+
+    /// Passes every queued request to the device, merging requests for adjacent sectors.
+    ///
+    /// Flushes are passed on last. They only cover the writes that completed before them, so
+    /// writes have to be waited for before they are flushed.
+    pub fn unplug(&self) {
+        let requests = core::mem::take(&mut *self.plugged.lock());
+        let (flushes, mut requests): (Vec<_>, Vec<_>) = requests
+            .into_iter()
+            .partition(|request| request.operation == Operation::Flush);
+
+        requests.sort_by_key(|request| request.sector);
+
+        let max_segments = self.device.max_segments();
+        let mut batch: Vec<Arc<Request>> = Vec::new();
+        let mut segments = 0;
+        let mut sectors = 0;
+
+        for request in requests {
+            if let Some(last) = batch.last() {
+                let mergeable = last.operation == request.operation
+                    && last.end_sector() == request.sector
+                    && segments + request.segments.len() <= max_segments
+                    && sectors + request.sector_count() <= MAX_MERGED_SECTORS;
+
+                if !mergeable {
+                    self.dispatch(core::mem::take(&mut batch));
+                    segments = 0;
+                    sectors = 0;
+                }
+            }
+
+            segments += request.segments.len();
+            sectors += request.sector_count();
+            batch.push(request);
+        }
+
+        self.dispatch(batch);
+
+        for flush in flushes {
+            self.device.submit(flush);
+        }
+    }
+
+    fn dispatch(&self, mut batch: Vec<Arc<Request>>) {
+        match batch.len() {
+            0 => {}
+            1 => self.device.submit(batch.pop().unwrap()),
+            _ => self.device.submit(Request::merge(batch)),
+        }
+    }
+
+    /// Waits until `request` completed, passing queued requests to the device first.
+    pub fn wait(&self, request: &Request) -> Result<(), BlockError> {
+        self.unplug();
+
+        loop {
+            if let Some(result) = request.result() {
+                return result;
+            }
+
+            self.device.poll();
+            core::hint::spin_loop();
+        }
+    }
+
+    fn submit_and_wait(&self, requests: Vec<Arc<Request>>) -> Result<(), BlockError> {
+        for request in &requests {
+            self.submit(request.clone());
+        }
+
+        requests
+            .iter()
+            .map(|request| self.wait(request))
+            .fold(Ok(()), Result::and)
+    }
+
+    /// Reads `buf.len()` bytes starting at the byte `offset` of the disk.
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.check_range(offset, buf.len())?;
+
+        let mut cache = self.cache.lock();
+        let mut done = 0;
+
+        while done < buf.len() {
+            let (range, pages) = chunk(offset + done as u64, offset + buf.len() as u64);
+            self.load(&mut cache, sectors(&range), &pages)?;
+
+            copy_pages(&mut cache, range, |page, range| {
+                let len = range.len();
+                buf[done..][..len].copy_from_slice(&page.data()[range]);
+                done += len;
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Writes `data` starting at the byte `offset` of the disk. The data stays in the cache until
+    /// it is evicted or the disk is flushed.
+    pub fn write_at(&self, offset: u64, data: &[u8]) -> Result<(), BlockError> {
+        if self.is_read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+
+        self.check_range(offset, data.len())?;
+
+        let mut cache = self.cache.lock();
+        let mut done = 0;
+
+        while done < data.len() {
+            let (range, pages) = chunk(offset + done as u64, offset + data.len() as u64);
+            let sectors = sectors(&range);
+
+            self.make_room(&mut cache, &pages)?;
+
+            // Sectors that are only partially overwritten have to be read first.
+            if range.start % SECTOR_SIZE != 0 {
+                self.load(&mut cache, sectors.start..sectors.start + 1, &pages)?;
+            }
+
+            if range.end % SECTOR_SIZE != 0 {
+                self.load(&mut cache, sectors.end - 1..sectors.end, &pages)?;
+            }
+
+            for index in pages.clone() {
+                let page = cache.get_or_insert(index)?;
+                let mask = cache::mask(index, &sectors);
+                page.valid |= mask;
+                page.dirty |= mask;
+            }
+
+            copy_pages(&mut cache, range, |page, range| {
+                let len = range.len();
+                page.data_mut()[range].copy_from_slice(&data[done..][..len]);
+                done += len;
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Writes every dirty sector back and makes sure it reached persistent storage.
+    pub fn flush(&self) -> Result<(), BlockError> {
+        let mut cache = self.cache.lock();
+
+        let dirty = cache.dirty_pages();
+        for pages in dirty.chunks(CHUNK_PAGES as usize) {
+            self.write_back(&mut cache, pages)?;
+        }
+
+        let flush = Request::new(Operation::Flush, 0, Vec::new());
+        self.submit(flush.clone());
+        self.wait(&flush)
+    }
+
+    fn check_range(&self, offset: u64, len: usize) -> Result<(), BlockError> {
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.size() => Ok(()),
+            _ => Err(BlockError::OutOfRange),
+        }
+    }
+
+    /// Reads the sectors in `sectors` that are not cached yet. `keep` are the pages of the
+    /// current operation, which must not be evicted to make room.
+    fn load(
+        &self,
+        cache: &mut Cache,
+        sectors: Range<u64>,
+        keep: &Range<u64>,
+    ) -> Result<(), BlockError> {
+        self.make_room(cache, keep)?;
+
+        let pages = sectors.start / SECTORS_PER_PAGE..sectors.end.div_ceil(SECTORS_PER_PAGE);
+        let mut requests = Vec::new();
+
+        for index in pages.clone() {
+            let page = cache.get_or_insert(index)?;
+            let missing = cache::mask(index, &sectors) & !page.valid;
+
+            for run in cache::runs(missing) {
+                requests.push(Request::new(
+                    Operation::Read,
+                    index * SECTORS_PER_PAGE + run.start,
+                    alloc::vec![Segment {
+                        addr: page.sector_addr(run.start),
+                        sectors: (run.end - run.start) as u32,
+                    }],
+                ));
+            }
+        }
+
+        self.submit_and_wait(requests)?;
+
+        for index in pages {
+            if let Some(page) = cache.get_mut(index) {
+                page.valid |= cache::mask(index, &sectors);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Evicts pages until the pages in `keep` fit into the cache.
+    fn make_room(&self, cache: &mut Cache, keep: &Range<u64>) -> Result<(), BlockError> {
+        let missing = keep.clone().filter(|&index| !cache.contains(index)).count();
+
+        while cache.len() + missing > cache.max_pages() {
+            let Some(index) = cache.least_recently_used(keep) else {
+                break;
+            };
+
+            self.write_back(cache, &[index])?;
+            cache.remove(index);
+        }
+
+        Ok(())
+    }
+
+    /// Writes the dirty sectors of `pages` to the device.
+    fn write_back(&self, cache: &mut Cache, pages: &[u64]) -> Result<(), BlockError> {
+        let mut requests = Vec::new();
+
+        for &index in pages {
+            let Some(page) = cache.get_mut(index) else {
+                continue;
+            };
+
+            for run in cache::runs(page.dirty) {
+                requests.push(Request::new(
+                    Operation::Write,
+                    index * SECTORS_PER_PAGE + run.start,
+                    alloc::vec![Segment {
+                        addr: page.sector_addr(run.start),
+                        sectors: (run.end - run.start) as u32,
+                    }],
+                ));
+            }
+        }
+
+        self.submit_and_wait(requests)?;
+
+        for &index in pages {
+            if let Some(page) = cache.get_mut(index) {
+                page.dirty = 0;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits off the part of the byte range `start..end` that fits into `CHUNK_PAGES` pages, and
+/// returns it together with the indices of its pages.
+fn chunk(start: u64, end: u64) -> (Range<u64>, Range<u64>) {
+    let first_page = start / PAGE_SIZE;
+    let end = end.min((first_page + CHUNK_PAGES) * PAGE_SIZE);
+
+    (start..end, first_page..end.div_ceil(PAGE_SIZE))
+}
+
+/// The sectors that the byte range `range` touches.
+fn sectors(range: &Range<u64>) -> Range<u64> {
+    range.start / SECTOR_SIZE..range.end.div_ceil(SECTOR_SIZE)
+}
+
+/// Calls `f` with every cached page that the byte range `range` touches, together with the part
+/// of the page it covers.
+fn copy_pages(
+    cache: &mut Cache,
+    range: Range<u64>,
+    mut f: impl FnMut(&mut cache::CachePage, Range<usize>),
+) {
+    let mut position = range.start;
+
+    while position < range.end {
+        let index = position / PAGE_SIZE;
+        let start = (position % PAGE_SIZE) as usize;
+        let end = (range.end - index * PAGE_SIZE).min(PAGE_SIZE) as usize;
+
+        let page = cache.get_mut(index).expect("Page was evicted while in use");
+        f(page, start..end);
+
+        position = (index + 1) * PAGE_SIZE;
+    }
+}
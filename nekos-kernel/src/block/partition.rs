@@ -0,0 +1,488 @@
+//! GPT and MBR partition tables. Every partition found on a disk becomes a disk of its own.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::{BlockDevice, BlockError, Disk, Request, SECTOR_SIZE};
+use crate::{log, misc};
+
+const GPT_SIGNATURE: &[u8] = b"EFI PART";
+const GPT_MIN_HEADER_SIZE: usize = 92;
+const GPT_MIN_ENTRY_SIZE: usize = 128;
+/// The most bytes of partition entries that are read, the usual table takes up 16 KiB.
+const GPT_MAX_ENTRIES_SIZE: usize = 1024 * 1024;
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const MBR_DISK_SIGNATURE_OFFSET: usize = 440;
+const MBR_ENTRIES_OFFSET: usize = 446;
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xee;
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+
+/// The most logical partitions followed in an extended partition, in case the chain loops.
+const MAX_LOGICAL_PARTITIONS: u32 = 64;
+
+/// A GUID, in the mixed endian byte order it is stored in on disk.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Guid([u8; 16]);
+
+/// How partitions are identified, `PARTUUID` on the Linux command line.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PartUuid {
+    Gpt(Guid),
+    /// The signature of the disk and the number of the partition on it.
+    Mbr {
+        signature: u32,
+        number: u32,
+    },
+}
+
+#[derive(Clone, Copy)]
+pub enum PartitionKind {
+    /// The partition type GUID of a GPT partition.
+    Gpt(Guid),
+    /// The system id of an MBR partition.
+    Mbr(u8),
+}
+
+#[derive(Clone)]
+pub struct PartitionInfo {
+    /// The number of the partition, starting at one.
+    pub number: u32,
+    pub start_sector: u64,
+    pub sector_count: u64,
+    pub kind: PartitionKind,
+    pub uuid: PartUuid,
+    /// The name of a GPT partition.
+    pub label: String,
+}
+
+/// A partition of a disk, which passes requests on to the device of the disk.
+pub struct Partition {
+    name: String,
+    parent: Arc<Disk>,
+    info: PartitionInfo,
+}
+
+#[derive(Debug)]
+enum TableError {
+    Io,
+    InvalidSignature,
+    InvalidHeader,
+    HeaderChecksum,
+    EntriesChecksum,
+}
+
+impl From<BlockError> for TableError {
+    fn from(_: BlockError) -> Self {
+        TableError::Io
+    }
+}
+
+/// Reads the partition table of `disk` and registers every partition on it.
+pub fn scan(disk: &Arc<Disk>) {
+    let partitions = match read_table(disk) {
+        Ok(partitions) => partitions,
+        Err(err) => {
+            log::warn!(
+                "Failed to read the partition table of {}: {:?}",
+                disk.name(),
+                err
+            );
+            return;
+        }
+    };
+
+    for info in partitions {
+        // Partitions of disks whose names end in a digit get a `p` in between, as on Linux.
+        let separator = match disk.name().ends_with(|c: char| c.is_ascii_digit()) {
+            true => "p",
+            false => "",
+        };
+
+        let name = format!("{}{}{}", disk.name(), separator, info.number);
+
+        log::info!(
+            "Partition {} at sector {} of type {} with PARTUUID={} {:?}",
+            name,
+            info.start_sector,
+            info.kind,
+            info.uuid,
+            info.label
+        );
+
+        super::register(Arc::new(Partition {
+            name,
+            parent: disk.clone(),
+            info,
+        }));
+    }
+}
+
+fn read_table(disk: &Disk) -> Result<Vec<PartitionInfo>, TableError> {
+    let mut mbr = [0u8; SECTOR_SIZE as usize];
+    disk.read_at(0, &mut mbr)?;
+
+    let has_mbr = mbr[510..512] == MBR_SIGNATURE;
+    let is_protective = mbr_entries(&mbr).any(|entry| entry.kind == MBR_TYPE_GPT_PROTECTIVE);
+
+    if !has_mbr || is_protective {
+        match read_gpt(disk) {
+            Ok(partitions) => return Ok(partitions),
+            Err(TableError::InvalidSignature) if !is_protective => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    if !has_mbr {
+        return Ok(Vec::new());
+    }
+
+    read_mbr(disk, &mbr)
+}
+
+/// Reads the GPT from the primary header, or the backup header at the end of the disk if the
+/// primary one or its entries are damaged.
+fn read_gpt(disk: &Disk) -> Result<Vec<PartitionInfo>, TableError> {
+    let primary = read_gpt_at(disk, 1);
+
+    let Err(err) = primary else {
+        return primary;
+    };
+
+    if matches!(err, TableError::InvalidSignature | TableError::Io) && disk.sector_count() < 2 {
+        return Err(err);
+    }
+
+    log::info!(
+        "Primary GPT of {} is damaged ({:?}), trying the backup.",
+        disk.name(),
+        err
+    );
+
+    read_gpt_at(disk, disk.sector_count() - 1)
+}
+
+fn read_gpt_at(disk: &Disk, lba: u64) -> Result<Vec<PartitionInfo>, TableError> {
+    let header_offset = lba
+        .checked_mul(SECTOR_SIZE)
+        .ok_or(TableError::InvalidHeader)?;
+
+    let mut header = [0u8; SECTOR_SIZE as usize];
+    disk.read_at(header_offset, &mut header)?;
+
+    if &header[..8] != GPT_SIGNATURE {
+        return Err(TableError::InvalidSignature);
+    }
+
+    let header_size = read_u32(&header, 12) as usize;
+    if !(GPT_MIN_HEADER_SIZE..=SECTOR_SIZE as usize).contains(&header_size) {
+        return Err(TableError::InvalidHeader);
+    }
+
+    // The checksum covers the header with the checksum field zeroed.
+    let checksum = read_u32(&header, 16);
+    header[16..20].fill(0);
+    if misc::crc32(&header[..header_size]) != checksum {
+        return Err(TableError::HeaderChecksum);
+    }
+
+    let my_lba = read_u64(&header, 24);
+    let entries_lba = read_u64(&header, 72);
+    let entry_count = read_u32(&header, 80) as usize;
+    let entry_size = read_u32(&header, 84) as usize;
+    let entries_checksum = read_u32(&header, 88);
+
+    let entries_size = entry_count * entry_size;
+
+    if my_lba != lba
+        || entry_size < GPT_MIN_ENTRY_SIZE
+        || !entry_size.is_multiple_of(8)
+        || entries_size > GPT_MAX_ENTRIES_SIZE
+    {
+        return Err(TableError::InvalidHeader);
+    }
+
+    // The entries LBA comes straight from the disk and may be anything.
+    let entries_offset = entries_lba
+        .checked_mul(SECTOR_SIZE)
+        .ok_or(TableError::InvalidHeader)?;
+
+    let mut entries = alloc::vec![0u8; entries_size];
+    disk.read_at(entries_offset, &mut entries)?;
+
+    if misc::crc32(&entries) != entries_checksum {
+        return Err(TableError::EntriesChecksum);
+    }
+
+    let mut partitions = Vec::new();
+
+    for (index, entry) in entries.chunks_exact(entry_size).enumerate() {
+        let kind = Guid::from_bytes(entry[..16].try_into().unwrap());
+        if kind.is_nil() {
+            continue;
+        }
+
+        let first_lba = read_u64(entry, 32);
+        let last_lba = read_u64(entry, 40);
+
+        if first_lba > last_lba || last_lba >= disk.sector_count() {
+            log::warn!(
+                "Ignoring GPT entry {} of {} out of bounds.",
+                index,
+                disk.name()
+            );
+            continue;
+        }
+
+        // The name is UTF-16, terminated early by a null character.
+        let name = entry[56..128]
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .take_while(|&unit| unit != 0);
+
+        partitions.push(PartitionInfo {
+            number: index as u32 + 1,
+            start_sector: first_lba,
+            sector_count: last_lba - first_lba + 1,
+            kind: PartitionKind::Gpt(kind),
+            uuid: PartUuid::Gpt(Guid::from_bytes(entry[16..32].try_into().unwrap())),
+            label: char::decode_utf16(name)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect(),
+        });
+    }
+
+    Ok(partitions)
+}
+
+struct MbrEntry {
+    kind: u8,
+    start: u64,
+    sector_count: u64,
+}
+
+fn mbr_entries(sector: &[u8]) -> impl Iterator<Item = MbrEntry> + '_ {
+    sector[MBR_ENTRIES_OFFSET..MBR_ENTRIES_OFFSET + 64]
+        .chunks_exact(16)
+        .map(|entry| MbrEntry {
+            kind: entry[4],
+            start: read_u32(entry, 8) as u64,
+            sector_count: read_u32(entry, 12) as u64,
+        })
+}
+
+/// Reads the four primary partitions and the logical partitions in an extended partition, which
+/// are numbered from five on.
+fn read_mbr(disk: &Disk, mbr: &[u8]) -> Result<Vec<PartitionInfo>, TableError> {
+    let signature = read_u32(mbr, MBR_DISK_SIGNATURE_OFFSET);
+    let mut partitions = Vec::new();
+
+    let mut add = |number: u32, kind: u8, start: u64, sector_count: u64| {
+        if start + sector_count > disk.sector_count() {
+            log::warn!(
+                "Ignoring MBR partition {} of {} out of bounds.",
+                number,
+                disk.name()
+            );
+            return;
+        }
+
+        partitions.push(PartitionInfo {
+            number,
+            start_sector: start,
+            sector_count,
+            kind: PartitionKind::Mbr(kind),
+            uuid: PartUuid::Mbr { signature, number },
+            label: String::new(),
+        });
+    };
+
+    let mut extended = None;
+
+    for (index, entry) in mbr_entries(mbr).enumerate() {
+        if entry.kind == 0 || entry.sector_count == 0 {
+            continue;
+        }
+
+        if MBR_TYPES_EXTENDED.contains(&entry.kind) {
+            extended.get_or_insert(entry.start);
+        } else {
+            add(
+                index as u32 + 1,
+                entry.kind,
+                entry.start,
+                entry.sector_count,
+            );
+        }
+    }
+
+    let Some(extended_start) = extended else {
+        return Ok(partitions);
+    };
+
+    // Every extended boot record describes one logical partition relative to itself, and where
+    // the next record is relative to the start of the extended partition.
+    let mut ebr_lba = extended_start;
+    let mut ebr = [0u8; SECTOR_SIZE as usize];
+
+    for number in 5..5 + MAX_LOGICAL_PARTITIONS {
+        disk.read_at(ebr_lba * SECTOR_SIZE, &mut ebr)?;
+        if ebr[510..512] != MBR_SIGNATURE {
+            break;
+        }
+
+        let mut entries = mbr_entries(&ebr);
+        let (logical, next) = (entries.next().unwrap(), entries.next().unwrap());
+
+        if logical.kind != 0 && logical.sector_count != 0 {
+            add(
+                number,
+                logical.kind,
+                ebr_lba + logical.start,
+                logical.sector_count,
+            );
+        }
+
+        if next.kind == 0 || next.start == 0 {
+            break;
+        }
+
+        ebr_lba = extended_start + next.start;
+    }
+
+    Ok(partitions)
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.info.sector_count
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.parent.is_read_only()
+    }
+
+    fn max_segments(&self) -> usize {
+        self.parent.device().max_segments()
+    }
+
+    fn submit(&self, request: Arc<Request>) {
+        if request.end_sector() > self.info.sector_count {
+            request.complete(Err(BlockError::OutOfRange));
+            return;
+        }
+
+        let start = self.info.start_sector;
+        self.parent.device().submit(Request::offset(request, start));
+    }
+
+    fn poll(&self) {
+        self.parent.device().poll();
+    }
+
+    fn partition(&self) -> Option<&PartitionInfo> {
+        Some(&self.info)
+    }
+}
+
+impl Guid {
+    pub const fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn is_nil(&self) -> bool {
+        self.0.iter().all(|&b| b == 0)
+    }
+
+    /// Parses the textual form `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let groups: Vec<&str> = text.split('-').collect();
+        if groups.iter().map(|group| group.len()).ne([8, 4, 4, 4, 12]) {
+            return None;
+        }
+
+        let mut bytes = [0u8; 16];
+        let digits = groups.concat();
+
+        for (index, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(digits.get(index * 2..index * 2 + 2)?, 16).ok()?;
+        }
+
+        // The first three groups are stored little endian.
+        bytes[..4].reverse();
+        bytes[4..6].reverse();
+        bytes[6..8].reverse();
+
+        Some(Self(bytes))
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8],
+            b[9]
+        )?;
+
+        b[10..]
+            .iter()
+            .try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+impl fmt::Display for PartitionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionKind::Gpt(guid) => guid.fmt(f),
+            PartitionKind::Mbr(kind) => write!(f, "{:#04x}", kind),
+        }
+    }
+}
+
+impl PartUuid {
+    /// Parses a GUID, or the `SSSSSSSS-PP` form of MBR partitions.
+    pub fn parse(text: &str) -> Option<Self> {
+        if let Some((signature, number)) = text.split_once('-')
+            && signature.len() == 8
+            && number.len() == 2
+        {
+            return Some(PartUuid::Mbr {
+                signature: u32::from_str_radix(signature, 16).ok()?,
+                number: u32::from_str_radix(number, 16).ok()?,
+            });
+        }
+
+        Guid::parse(text).map(PartUuid::Gpt)
+    }
+}
+
+impl fmt::Display for PartUuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartUuid::Gpt(guid) => guid.fmt(f),
+            PartUuid::Mbr { signature, number } => write!(f, "{:08x}-{:02x}", signature, number),
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
@@ -0,0 +1,100 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use spin::Once;
+
+use super::BlockError;
+use crate::mem::PhysicalAddr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Read,
+    Write,
+    /// Makes every completed write persistent.
+    Flush,
+}
+
+/// A physically contiguous part of the memory a request transfers to or from.
+#[derive(Clone, Copy)]
+pub struct Segment {
+    pub addr: PhysicalAddr,
+    pub sectors: u32,
+}
+
+/// A request to a block device, which completes asynchronously.
+pub struct Request {
+    pub operation: Operation,
+    pub sector: u64,
+    pub segments: Vec<Segment>,
+
+    /// The requests that were merged into this one, which complete together with it.
+    merged: Vec<Arc<Request>>,
+    result: Once<Result<(), BlockError>>,
+}
+
+impl Request {
+    pub fn new(operation: Operation, sector: u64, segments: Vec<Segment>) -> Arc<Self> {
+        Arc::new(Self {
+            operation,
+            sector,
+            segments,
+            merged: Vec::new(),
+            result: Once::new(),
+        })
+    }
+
+    /// Creates a request that does the work of all of `requests`, which have to be of the same
+    /// operation and follow each other without gaps.
+    pub fn merge(requests: Vec<Arc<Request>>) -> Arc<Self> {
+        let first = &requests[0];
+
+        Arc::new(Self {
+            operation: first.operation,
+            sector: first.sector,
+            segments: requests
+                .iter()
+                .flat_map(|request| request.segments.iter().copied())
+                .collect(),
+            merged: requests,
+            result: Once::new(),
+        })
+    }
+
+    /// Creates a request that does the work of `request`, but `sectors` further into the device.
+    /// This is how requests to a part of a device are passed on to the whole device.
+    pub fn offset(request: Arc<Request>, sectors: u64) -> Arc<Self> {
+        Arc::new(Self {
+            operation: request.operation,
+            sector: request.sector + sectors,
+            segments: request.segments.clone(),
+            merged: alloc::vec![request],
+            result: Once::new(),
+        })
+    }
+
+    pub fn sector_count(&self) -> u64 {
+        self.segments
+            .iter()
+            .map(|segment| segment.sectors as u64)
+            .sum()
+    }
+
+    /// The sector right after the last one the request transfers.
+    pub fn end_sector(&self) -> u64 {
+        self.sector + self.sector_count()
+    }
+
+    /// Reports the outcome of the request, called by the driver once the device is done with it.
+    pub fn complete(&self, result: Result<(), BlockError>) {
+        self.result.call_once(|| result);
+
+        for request in &self.merged {
+            request.complete(result);
+        }
+    }
+
+    /// The outcome of the request, or `None` while it is still in flight.
+    pub fn result(&self) -> Option<Result<(), BlockError>> {
+        self.result.get().copied()
+    }
+}
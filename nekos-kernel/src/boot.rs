@@ -1,14 +1,19 @@
 use crate::arch::PAGE_SIZE;
-use crate::log;
+use crate::fdt::DeviceTree;
 use crate::mem::{PhysicalAddr, VirtualAddr};
+use crate::{cmdline, log};
 
 use spin::Once;
 use ubyte::ToByteUnit;
 
 use limine::BaseRevision;
+use limine::file::File;
 use limine::memory_map::{Entry, EntryType};
 use limine::paging::Mode;
-use limine::request::{ExecutableAddressRequest, HhdmRequest, MemoryMapRequest, PagingModeRequest};
+use limine::request::{
+    BspHartidRequest, DeviceTreeBlobRequest, ExecutableAddressRequest, ExecutableCmdlineRequest,
+    HhdmRequest, MemoryMapRequest, ModuleRequest, PagingModeRequest,
+};
 
 #[unsafe(link_section = ".requests")]
 static PAGING_MODE_REQUEST: PagingModeRequest = PagingModeRequest::new();
@@ -25,6 +30,18 @@ static BASE_REVISION: BaseRevision = BaseRevision::new();
 #[unsafe(link_section = ".requests")]
 static EXECUTABLE_ADDRESS_REQUEST: ExecutableAddressRequest = ExecutableAddressRequest::new();
 
+#[unsafe(link_section = ".requests")]
+static MODULE_REQUEST: ModuleRequest = ModuleRequest::new();
+
+#[unsafe(link_section = ".requests")]
+static DEVICE_TREE_BLOB_REQUEST: DeviceTreeBlobRequest = DeviceTreeBlobRequest::new();
+
+#[unsafe(link_section = ".requests")]
+static BSP_HARTID_REQUEST: BspHartidRequest = BspHartidRequest::new();
+
+#[unsafe(link_section = ".requests")]
+static EXECUTABLE_CMDLINE_REQUEST: ExecutableCmdlineRequest = ExecutableCmdlineRequest::new();
+
 pub static BOOT_INFO: Once<BootInfo> = Once::new();
 
 pub fn init() {
@@ -43,10 +60,22 @@ pub fn init() {
                 .unwrap()
                 .physical_base(),
         ),
+        bsp_hart_id: BSP_HARTID_REQUEST.get_response().unwrap().bsp_hartid(),
+        device_tree_addr: DEVICE_TREE_BLOB_REQUEST
+            .get_response()
+            .map(|response| VirtualAddr::new(response.dtb_ptr() as u64)),
+        cmdline: EXECUTABLE_CMDLINE_REQUEST
+            .get_response()
+            .and_then(|response| response.cmdline().to_str().ok())
+            .unwrap_or(""),
     });
 
     let boot_info = unsafe { BOOT_INFO.get_unchecked() };
 
+    // Parameters like the log level and memory limit have to be known before anything else runs.
+    cmdline::parse(boot_info.cmdline);
+    log::init();
+
     log::debug!("HHDM_OFFSET at {}", VirtualAddr::new(boot_info.hhdm_offset));
 
     let paging_mode = match boot_info.paging_mode {
@@ -57,6 +86,11 @@ pub fn init() {
     };
 
     log::debug!("Paging Mode at {}", paging_mode);
+    log::debug!("Booted on hart {}", boot_info.bsp_hart_id);
+
+    if let Some(device_tree_addr) = boot_info.device_tree_addr {
+        log::debug!("Device tree at {}", device_tree_addr);
+    }
 
     let entry_type_to_str = |entry_type: EntryType| match entry_type {
         EntryType::USABLE => "Usable",
@@ -81,6 +115,15 @@ pub fn init() {
             entry.length.bytes(),
         );
     }
+
+    for module in modules() {
+        log::debug!(
+            "Module {:?} at {} {}",
+            module.path(),
+            VirtualAddr::new(module.addr() as u64),
+            module.size().bytes(),
+        );
+    }
 }
 
 pub struct BootInfo<'a> {
@@ -88,6 +131,33 @@ pub struct BootInfo<'a> {
     pub paging_mode: Mode,
     pub memory_map_entries: &'a [&'a Entry],
     pub kernel_address: PhysicalAddr,
+    /// The id of the hart the kernel was started on.
+    pub bsp_hart_id: u64,
+    pub device_tree_addr: Option<VirtualAddr>,
+    /// The kernel command line set with `cmdline:` in `limine.conf`.
+    pub cmdline: &'a str,
+}
+
+/// The device tree passed by the bootloader, which describes the devices of the machine.
+pub fn device_tree() -> Option<DeviceTree<'static>> {
+    let addr = BOOT_INFO.get()?.device_tree_addr?;
+
+    // SAFETY: The blob lives in bootloader reclaimable memory, which is never handed to the page
+    // allocator.
+    match unsafe { DeviceTree::from_ptr(addr.as_ptr()) } {
+        Ok(device_tree) => Some(device_tree),
+        Err(err) => {
+            log::warn!("Invalid device tree at {}: {:?}", addr, err);
+            None
+        }
+    }
+}
+
+/// The modules loaded by Limine alongside the kernel.
+pub fn modules() -> &'static [&'static File] {
+    MODULE_REQUEST
+        .get_response()
+        .map_or(&[], |response| response.modules())
 }
 
 unsafe extern "C" {
@@ -0,0 +1,210 @@
+//! Kernel parameters passed on the command line.
+//!
+//! The command line is a whitespace separated list of `name=value` pairs and bare flags, set with
+//! `cmdline:` in `limine.conf`. Modules declare the parameters they understand with [`param!`],
+//! which places an entry in the `.params` section so [`parse`] can find every parameter without a
+//! central list:
+//!
+//! ```ignore
+//! param!(loglevel: u8 = 3);
+//!
+//! if loglevel.get() > 3 { ... }
+//! ```
+//!
+//! Parsing happens before the page allocator exists, so nothing here may allocate. String values
+//! borrow from the command line itself, which lives in bootloader reclaimable memory.
+
+use core::fmt;
+
+use spin::Once;
+use ubyte::ToByteUnit;
+
+use crate::log;
+
+/// A kernel parameter of type `T`.
+pub struct Param<T: 'static> {
+    name: &'static str,
+    default: T,
+    value: Once<T>,
+}
+
+impl<T: ParamValue> Param<T> {
+    pub const fn new(name: &'static str, default: T) -> Self {
+        Self {
+            name,
+            default,
+            value: Once::new(),
+        }
+    }
+
+    /// The value given on the command line, or the default if the parameter was not set.
+    pub fn get(&self) -> T {
+        *self.value.get().unwrap_or(&self.default)
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.value.is_completed()
+    }
+
+    /// Parses and stores the value given on the command line. Only the first occurrence counts.
+    pub fn set(&self, value: Option<&'static str>) -> Result<(), ParamError> {
+        let value = T::parse(value).ok_or(ParamError::InvalidValue)?;
+
+        if self.is_set() {
+            log::warn!("Kernel parameter {} given twice, ignoring.", self.name);
+        }
+
+        self.value.call_once(|| value);
+        Ok(())
+    }
+}
+
+/// An entry in the `.params` section, written by [`param!`].
+pub struct ParamEntry {
+    pub name: &'static str,
+    pub set: fn(Option<&'static str>) -> Result<(), ParamError>,
+}
+
+#[derive(Debug)]
+pub enum ParamError {
+    InvalidValue,
+}
+
+/// A type a kernel parameter can hold. `value` is `None` for a bare flag without `=`.
+pub trait ParamValue: Copy + Send + Sync + Sized {
+    fn parse(value: Option<&'static str>) -> Option<Self>;
+}
+
+impl ParamValue for bool {
+    fn parse(value: Option<&'static str>) -> Option<Self> {
+        match value {
+            None | Some("1" | "y" | "yes" | "on" | "true") => Some(true),
+            Some("0" | "n" | "no" | "off" | "false") => Some(false),
+            Some(_) => None,
+        }
+    }
+}
+
+impl ParamValue for &'static str {
+    fn parse(value: Option<&'static str>) -> Option<Self> {
+        value
+    }
+}
+
+macro_rules! impl_param_value_int {
+    ($($ty:ty),*) => {
+        $(
+            impl ParamValue for $ty {
+                fn parse(value: Option<&'static str>) -> Option<Self> {
+                    parse_int(value?).and_then(|value| value.try_into().ok())
+                }
+            }
+        )*
+    };
+}
+
+impl_param_value_int!(u8, u16, u32, u64, usize);
+
+impl<T: ParamValue> ParamValue for Option<T> {
+    fn parse(value: Option<&'static str>) -> Option<Self> {
+        T::parse(value).map(Some)
+    }
+}
+
+/// A size in bytes, written with an optional `K`, `M`, `G` or `T` suffix like `mem=512M`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Size(pub u64);
+
+impl ParamValue for Size {
+    fn parse(value: Option<&'static str>) -> Option<Self> {
+        let value = value?;
+
+        let (number, shift) = match value.as_bytes().last()? {
+            b'k' | b'K' => (&value[..value.len() - 1], 10),
+            b'm' | b'M' => (&value[..value.len() - 1], 20),
+            b'g' | b'G' => (&value[..value.len() - 1], 30),
+            b't' | b'T' => (&value[..value.len() - 1], 40),
+            _ => (value, 0),
+        };
+
+        let number = parse_int(number)?;
+        (number.leading_zeros() >= shift).then(|| Size(number << shift))
+    }
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.bytes())
+    }
+}
+
+/// Parses a decimal, `0x` hexadecimal or `0b` binary number.
+fn parse_int(value: &str) -> Option<u64> {
+    if let Some(hex) = value.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = value.strip_prefix("0b") {
+        u64::from_str_radix(binary, 2).ok()
+    } else {
+        value.parse().ok()
+    }
+}
+
+/// Declares a kernel parameter, settable on the command line as `name=value`.
+macro_rules! param {
+    ($(#[$meta:meta])* $vis:vis $name:ident: $ty:ty = $default:expr) => {
+        $(#[$meta])*
+        #[allow(non_upper_case_globals)]
+        $vis static $name: $crate::cmdline::Param<$ty> =
+            $crate::cmdline::Param::new(stringify!($name), $default);
+
+        const _: () = {
+            #[used]
+            #[unsafe(link_section = ".params")]
+            static ENTRY: $crate::cmdline::ParamEntry = $crate::cmdline::ParamEntry {
+                name: stringify!($name),
+                set: |value| $name.set(value),
+            };
+        };
+    };
+}
+
+pub(crate) use param;
+
+unsafe extern "C" {
+    #[link_name = "__params_begin"]
+    static PARAMS_BEGIN: u8;
+
+    #[link_name = "__params_end"]
+    static PARAMS_END: u8;
+}
+
+fn params() -> &'static [ParamEntry] {
+    // SAFETY: The linker script collects the entries written by `param!` between the two
+    // symbols, and nothing else is placed in the `.params` section.
+    unsafe {
+        let begin = (&raw const PARAMS_BEGIN).cast::<ParamEntry>();
+        let end = (&raw const PARAMS_END).cast::<ParamEntry>();
+        core::slice::from_raw_parts(begin, end.offset_from(begin) as usize)
+    }
+}
+
+/// Splits the command line into parameters and sets the ones the kernel knows about.
+pub fn parse(cmdline: &'static str) {
+    log::info!("Kernel command line: {}", cmdline);
+
+    for arg in cmdline.split_ascii_whitespace() {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg, None),
+        };
+
+        let Some(param) = params().iter().find(|param| param.name == name) else {
+            log::warn!("Unknown kernel parameter {}, ignoring.", name);
+            continue;
+        };
+
+        if let Err(err) = (param.set)(value) {
+            log::warn!("Invalid kernel parameter {}: {:?}", arg, err);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! Memory shared with devices, which access it by its physical address.
+
+use crate::arch::PAGE_SIZE;
+use crate::boot;
+use crate::mem::{self, PhysicalAddr, Usage};
+
+/// A zeroed, physically contiguous run of pages from the page allocator.
+pub struct DmaBuffer {
+    addr: PhysicalAddr,
+    num_pages: usize,
+}
+
+impl DmaBuffer {
+    /// Allocates a buffer of at least `size` bytes, or `None` if there is not enough memory.
+    pub fn new(size: usize) -> Option<Self> {
+        let num_pages = size.div_ceil(PAGE_SIZE as usize).max(1);
+        let addr = mem::allocate_pages(num_pages, true, Usage::Dma).ok()?;
+
+        Some(Self { addr, num_pages })
+    }
+
+    /// The address the device uses to access the buffer.
+    pub fn physical_addr(&self) -> PhysicalAddr {
+        self.addr
+    }
+
+    pub fn size(&self) -> usize {
+        self.num_pages * PAGE_SIZE as usize
+    }
+
+    /// A pointer through which the kernel accesses the buffer.
+    pub fn as_mut_ptr<T>(&self) -> *mut T {
+        let hhdm_offset = boot::BOOT_INFO.get().unwrap().hhdm_offset;
+        self.addr.as_virtual_by_offset(hhdm_offset).as_mut_ptr()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.as_mut_ptr(), self.size()) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.as_mut_ptr(), self.size()) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        mem::deallocate_pages(self.addr, self.num_pages, Usage::Dma);
+    }
+}
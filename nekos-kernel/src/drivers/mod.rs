@@ -0,0 +1,11 @@
+//! Drivers for the devices found in the device tree.
+
+pub mod dma;
+pub mod virtio;
+
+use crate::arch;
+
+pub fn init() {
+    arch::init_irq();
+    virtio::init();
+}
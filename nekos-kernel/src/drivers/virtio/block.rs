@@ -0,0 +1,264 @@
+//! The virtio block device.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::Mutex;
+
+use super::{
+    Buffer, DeviceType, MmioTransport, VirtQueue, VirtioDevice, VirtioDriver, VirtioError,
+};
+use crate::block::{self, BlockDevice, BlockError, Operation, Request, SECTOR_SIZE};
+use crate::drivers::dma::DmaBuffer;
+use crate::mem::PhysicalAddr;
+
+const VIRTIO_BLK_F_SEG_MAX: u64 = 1 << 2;
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+const CONFIG_CAPACITY: u64 = 0x00;
+const CONFIG_SEG_MAX: u64 = 0x0c;
+
+const QUEUE_SIZE: u16 = 128;
+
+pub static DRIVER: VirtioDriver = VirtioDriver {
+    name: "block",
+    device_type: DeviceType::Block,
+    features: VIRTIO_BLK_F_SEG_MAX | VIRTIO_BLK_F_RO | VIRTIO_BLK_F_FLUSH,
+    probe,
+};
+
+/// Used to name the disks `vda`, `vdb` and so on in the order they are found.
+static NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RequestHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+pub struct VirtioBlock {
+    name: String,
+    transport: MmioTransport,
+    sector_count: u64,
+    read_only: bool,
+    can_flush: bool,
+    max_segments: usize,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    queue: VirtQueue,
+    /// The header and status byte of every request, in the slot of the id of its chain.
+    headers: DmaBuffer,
+    in_flight: BTreeMap<u16, Arc<Request>>,
+    /// Requests that did not fit into the queue yet.
+    waiting: VecDeque<Arc<Request>>,
+}
+
+fn probe(transport: MmioTransport, features: u64) -> Result<Arc<dyn VirtioDevice>, VirtioError> {
+    let queue = VirtQueue::new(&transport, 0, QUEUE_SIZE)?;
+
+    // Every request needs a descriptor for its header and one for its status.
+    let max_segments = match features & VIRTIO_BLK_F_SEG_MAX {
+        0 => 1,
+        _ => transport.config_u32(CONFIG_SEG_MAX).max(1) as usize,
+    }
+    .min(queue.size() as usize - 2);
+
+    let headers = DmaBuffer::new(queue.size() as usize * (size_of::<RequestHeader>() + 1))
+        .ok_or(VirtioError::OutOfMemory)?;
+
+    let index = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+
+    let device = Arc::new(VirtioBlock {
+        name: format!("vd{}", (b'a' + (index % 26) as u8) as char),
+        sector_count: transport.config_u64(CONFIG_CAPACITY),
+        read_only: features & VIRTIO_BLK_F_RO != 0,
+        can_flush: features & VIRTIO_BLK_F_FLUSH != 0,
+        max_segments,
+        transport,
+        inner: Mutex::new(Inner {
+            queue,
+            headers,
+            in_flight: BTreeMap::new(),
+            waiting: VecDeque::new(),
+        }),
+    });
+
+    block::register(device.clone());
+
+    Ok(device)
+}
+
+impl VirtioBlock {
+    /// Moves waiting requests into the queue while there is room for them.
+    fn start_waiting(&self, inner: &mut Inner) {
+        let mut added = false;
+
+        while let Some(request) = inner.waiting.front() {
+            if inner.queue.num_free() < request.segments.len() as u16 + 2 {
+                break;
+            }
+
+            let request = inner.waiting.pop_front().unwrap();
+
+            let kind = match request.operation {
+                Operation::Read => VIRTIO_BLK_T_IN,
+                Operation::Write if self.read_only => {
+                    request.complete(Err(BlockError::ReadOnly));
+                    continue;
+                }
+                Operation::Write => VIRTIO_BLK_T_OUT,
+                // Without a write cache every completed write is already persistent.
+                Operation::Flush if !self.can_flush => {
+                    request.complete(Ok(()));
+                    continue;
+                }
+                Operation::Flush => VIRTIO_BLK_T_FLUSH,
+            };
+
+            let slot = inner.queue.next_id();
+            inner.write_header(
+                slot,
+                RequestHeader {
+                    kind,
+                    reserved: 0,
+                    sector: request.sector,
+                },
+            );
+
+            let mut buffers = alloc::vec![Buffer {
+                addr: inner.header_addr(slot),
+                len: size_of::<RequestHeader>() as u32,
+                device_writable: false,
+            }];
+
+            buffers.extend(request.segments.iter().map(|segment| Buffer {
+                addr: segment.addr,
+                len: segment.sectors * SECTOR_SIZE as u32,
+                device_writable: request.operation == Operation::Read,
+            }));
+
+            buffers.push(Buffer {
+                addr: inner.status_addr(slot),
+                len: 1,
+                device_writable: true,
+            });
+
+            match inner.queue.add(&buffers) {
+                Ok(id) => {
+                    debug_assert_eq!(id, slot);
+                    inner.in_flight.insert(id, request);
+                    added = true;
+                }
+                Err(_) => request.complete(Err(BlockError::Io)),
+            }
+        }
+
+        if added {
+            inner.queue.notify(&self.transport);
+        }
+    }
+}
+
+impl Inner {
+    fn write_header(&mut self, slot: u16, header: RequestHeader) {
+        let headers = self.headers.as_mut_ptr::<RequestHeader>();
+        unsafe { headers.add(slot as usize).write_volatile(header) }
+    }
+
+    fn header_addr(&self, slot: u16) -> PhysicalAddr {
+        let offset = slot as usize * size_of::<RequestHeader>();
+        PhysicalAddr::new(self.headers.physical_addr().addr() + offset as u64)
+    }
+
+    /// The status bytes follow the headers of every slot.
+    fn status_offset(&self, slot: u16) -> usize {
+        self.queue.size() as usize * size_of::<RequestHeader>() + slot as usize
+    }
+
+    fn status_addr(&self, slot: u16) -> PhysicalAddr {
+        PhysicalAddr::new(self.headers.physical_addr().addr() + self.status_offset(slot) as u64)
+    }
+
+    fn read_status(&self, slot: u16) -> u8 {
+        let status = unsafe {
+            self.headers
+                .as_mut_ptr::<u8>()
+                .add(self.status_offset(slot))
+        };
+        unsafe { status.read_volatile() }
+    }
+}
+
+impl VirtioDevice for VirtioBlock {
+    fn transport(&self) -> &MmioTransport {
+        &self.transport
+    }
+
+    fn handle_queues(&self) {
+        let mut inner = self.inner.lock();
+
+        while let Some(chain) = inner.queue.pop_used() {
+            let Some(request) = inner.in_flight.remove(&chain.id) else {
+                continue;
+            };
+
+            let result = match inner.read_status(chain.id) {
+                VIRTIO_BLK_S_OK => Ok(()),
+                VIRTIO_BLK_S_UNSUPP => Err(BlockError::Unsupported),
+                _ => Err(BlockError::Io),
+            };
+
+            request.complete(result);
+        }
+
+        self.start_waiting(&mut inner);
+    }
+}
+
+impl BlockDevice for VirtioBlock {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sector_count
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn max_segments(&self) -> usize {
+        self.max_segments
+    }
+
+    fn submit(&self, request: Arc<Request>) {
+        if request.segments.len() > self.max_segments {
+            request.complete(Err(BlockError::Unsupported));
+            return;
+        }
+
+        let mut inner = self.inner.lock();
+        inner.waiting.push_back(request);
+        self.start_waiting(&mut inner);
+    }
+
+    fn poll(&self) {
+        self.handle_queues();
+    }
+}
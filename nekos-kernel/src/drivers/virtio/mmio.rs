@@ -0,0 +1,274 @@
+//! The virtio-mmio transport, in both the legacy (version 1) and the modern (version 2) register
+//! layout.
+
+use bitflags::bitflags;
+
+use super::{DeviceStatus, VirtioError, features};
+use crate::arch::PAGE_SIZE;
+use crate::mem::{PhysicalAddr, VirtualAddr};
+
+const MAGIC: u32 = 0x7472_6976;
+
+const MAGIC_VALUE: u64 = 0x000;
+const VERSION: u64 = 0x004;
+const DEVICE_ID: u64 = 0x008;
+const DEVICE_FEATURES: u64 = 0x010;
+const DEVICE_FEATURES_SEL: u64 = 0x014;
+const DRIVER_FEATURES: u64 = 0x020;
+const DRIVER_FEATURES_SEL: u64 = 0x024;
+const LEGACY_GUEST_PAGE_SIZE: u64 = 0x028;
+const QUEUE_SEL: u64 = 0x030;
+const QUEUE_NUM_MAX: u64 = 0x034;
+const QUEUE_NUM: u64 = 0x038;
+const LEGACY_QUEUE_ALIGN: u64 = 0x03c;
+const LEGACY_QUEUE_PFN: u64 = 0x040;
+const QUEUE_READY: u64 = 0x044;
+const QUEUE_NOTIFY: u64 = 0x050;
+const INTERRUPT_STATUS: u64 = 0x060;
+const INTERRUPT_ACK: u64 = 0x064;
+const STATUS: u64 = 0x070;
+const QUEUE_DESC_LOW: u64 = 0x080;
+const QUEUE_DESC_HIGH: u64 = 0x084;
+const QUEUE_DRIVER_LOW: u64 = 0x090;
+const QUEUE_DRIVER_HIGH: u64 = 0x094;
+const QUEUE_DEVICE_LOW: u64 = 0x0a0;
+const QUEUE_DEVICE_HIGH: u64 = 0x0a4;
+const CONFIG_GENERATION: u64 = 0x0fc;
+const CONFIG: u64 = 0x100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Version {
+    Legacy,
+    Modern,
+}
+
+bitflags! {
+    #[derive(Clone, Copy, Debug)]
+    pub struct InterruptStatus: u32 {
+        const USED_BUFFER = 1 << 0;
+        const CONFIG_CHANGE = 1 << 1;
+    }
+}
+
+/// The physical addresses of the three parts of a split virtqueue.
+#[derive(Clone, Copy)]
+pub struct QueueLayout {
+    pub descriptors: PhysicalAddr,
+    pub driver: PhysicalAddr,
+    pub device: PhysicalAddr,
+}
+
+#[derive(Clone)]
+pub struct MmioTransport {
+    base: VirtualAddr,
+    version: Version,
+}
+
+impl MmioTransport {
+    /// Checks the registers at `base` for a virtio-mmio device.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be the mapped register block of a virtio-mmio slot.
+    pub unsafe fn new(base: VirtualAddr) -> Result<Self, VirtioError> {
+        let mut transport = Self {
+            base,
+            version: Version::Legacy,
+        };
+
+        if transport.read(MAGIC_VALUE) != MAGIC {
+            return Err(VirtioError::InvalidMagic);
+        }
+
+        transport.version = match transport.read(VERSION) {
+            1 => Version::Legacy,
+            2 => Version::Modern,
+            _ => return Err(VirtioError::UnsupportedVersion),
+        };
+
+        Ok(transport)
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// The type of device behind this transport, zero if the slot is empty.
+    pub fn device_id(&self) -> u32 {
+        self.read(DEVICE_ID)
+    }
+
+    pub fn status(&self) -> DeviceStatus {
+        DeviceStatus::from_bits_truncate(self.read(STATUS))
+    }
+
+    fn add_status(&self, status: DeviceStatus) {
+        self.write(STATUS, (self.status() | status).bits());
+    }
+
+    /// Resets the device, which also forgets about every queue.
+    pub fn reset(&self) {
+        self.write(STATUS, 0);
+
+        // A modern device may take a moment to finish the reset.
+        while self.version == Version::Modern && self.read(STATUS) != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Resets the device and agrees on the features both it and the driver support, out of
+    /// `driver_features`. Returns the negotiated features.
+    pub fn negotiate(&self, driver_features: u64) -> Result<u64, VirtioError> {
+        self.reset();
+        self.add_status(DeviceStatus::ACKNOWLEDGE);
+        self.add_status(DeviceStatus::DRIVER);
+
+        let device_features = self.device_features();
+
+        let features = match self.version {
+            // Legacy devices only have 32 feature bits.
+            Version::Legacy => device_features & driver_features & 0xffff_ffff,
+            Version::Modern => {
+                if device_features & features::VERSION_1 == 0 {
+                    return Err(VirtioError::FeaturesRejected);
+                }
+
+                (device_features & driver_features) | features::VERSION_1
+            }
+        };
+
+        self.set_driver_features(features);
+
+        match self.version {
+            Version::Legacy => self.write(LEGACY_GUEST_PAGE_SIZE, PAGE_SIZE as u32),
+            Version::Modern => {
+                self.add_status(DeviceStatus::FEATURES_OK);
+                if !self.status().contains(DeviceStatus::FEATURES_OK) {
+                    return Err(VirtioError::FeaturesRejected);
+                }
+            }
+        }
+
+        Ok(features)
+    }
+
+    /// Tells the device that the driver is set up, after which it may start using the queues.
+    pub fn finish_init(&self) {
+        self.add_status(DeviceStatus::DRIVER_OK);
+    }
+
+    /// Tells the device that the driver gave up on it.
+    pub fn fail(&self) {
+        self.add_status(DeviceStatus::FAILED);
+    }
+
+    fn device_features(&self) -> u64 {
+        self.write(DEVICE_FEATURES_SEL, 0);
+        let low = self.read(DEVICE_FEATURES) as u64;
+        self.write(DEVICE_FEATURES_SEL, 1);
+        let high = self.read(DEVICE_FEATURES) as u64;
+
+        (high << 32) | low
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        self.write(DRIVER_FEATURES_SEL, 0);
+        self.write(DRIVER_FEATURES, features as u32);
+        self.write(DRIVER_FEATURES_SEL, 1);
+        self.write(DRIVER_FEATURES, (features >> 32) as u32);
+    }
+
+    /// The largest size the queue `index` supports, zero if it does not exist or is in use.
+    pub fn max_queue_size(&self, index: u16) -> u16 {
+        self.write(QUEUE_SEL, index as u32);
+
+        let in_use = match self.version {
+            Version::Legacy => self.read(LEGACY_QUEUE_PFN) != 0,
+            Version::Modern => self.read(QUEUE_READY) != 0,
+        };
+
+        if in_use {
+            return 0;
+        }
+
+        self.read(QUEUE_NUM_MAX).min(u16::MAX as u32) as u16
+    }
+
+    /// Hands the queue `index` with `size` entries to the device.
+    ///
+    /// Legacy devices only take the address of the descriptor table, the rings have to follow it
+    /// with the used ring aligned to `PAGE_SIZE`.
+    pub fn setup_queue(&self, index: u16, size: u16, layout: QueueLayout) {
+        self.write(QUEUE_SEL, index as u32);
+        self.write(QUEUE_NUM, size as u32);
+
+        match self.version {
+            Version::Legacy => {
+                self.write(LEGACY_QUEUE_ALIGN, PAGE_SIZE as u32);
+                self.write(
+                    LEGACY_QUEUE_PFN,
+                    (layout.descriptors.addr() / PAGE_SIZE) as u32,
+                );
+            }
+            Version::Modern => {
+                self.write_u64(QUEUE_DESC_LOW, QUEUE_DESC_HIGH, layout.descriptors.addr());
+                self.write_u64(QUEUE_DRIVER_LOW, QUEUE_DRIVER_HIGH, layout.driver.addr());
+                self.write_u64(QUEUE_DEVICE_LOW, QUEUE_DEVICE_HIGH, layout.device.addr());
+                self.write(QUEUE_READY, 1);
+            }
+        }
+    }
+
+    /// Tells the device that there are new buffers in the queue `index`.
+    pub fn notify(&self, index: u16) {
+        self.write(QUEUE_NOTIFY, index as u32);
+    }
+
+    /// Acknowledges the pending interrupt and returns why it was raised.
+    pub fn ack_interrupt(&self) -> InterruptStatus {
+        let status = self.read(INTERRUPT_STATUS);
+        self.write(INTERRUPT_ACK, status);
+
+        InterruptStatus::from_bits_truncate(status)
+    }
+
+    pub fn config_u32(&self, offset: u64) -> u32 {
+        self.read(CONFIG + offset)
+    }
+
+    /// Reads a 64-bit field of the configuration space, which the device may change between the
+    /// accesses to its two halves.
+    pub fn config_u64(&self, offset: u64) -> u64 {
+        loop {
+            let generation = self.config_generation();
+            let low = self.read(CONFIG + offset) as u64;
+            let high = self.read(CONFIG + offset + 4) as u64;
+
+            if generation == self.config_generation() {
+                return (high << 32) | low;
+            }
+        }
+    }
+
+    fn config_generation(&self) -> u32 {
+        match self.version {
+            Version::Legacy => 0,
+            Version::Modern => self.read(CONFIG_GENERATION),
+        }
+    }
+
+    fn read(&self, offset: u64) -> u32 {
+        let register = VirtualAddr::new(self.base.addr() + offset);
+        unsafe { register.as_ptr::<u32>().read_volatile() }
+    }
+
+    fn write(&self, offset: u64, value: u32) {
+        let register = VirtualAddr::new(self.base.addr() + offset);
+        unsafe { register.as_mut_ptr::<u32>().write_volatile(value) }
+    }
+
+    fn write_u64(&self, low: u64, high: u64, value: u64) {
+        self.write(low, value as u32);
+        self.write(high, (value >> 32) as u32);
+    }
+}
@@ -0,0 +1,180 @@
+//! Virtio devices behind the virtio-mmio transport, as found on the QEMU `virt` machine.
+//!
+//! Every slot in the device tree is probed, and the device behind it handed to the driver that is
+//! registered for its type in [`DRIVERS`].
+
+pub mod block;
+pub mod mmio;
+pub mod queue;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use bitflags::bitflags;
+use spin::Mutex;
+
+use crate::fdt::Node;
+use crate::mem::{self, PhysicalAddr};
+use crate::{boot, irq, log};
+
+pub use mmio::{InterruptStatus, MmioTransport};
+pub use queue::{Buffer, VirtQueue};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceType {
+    Network,
+    Block,
+    Console,
+    Entropy,
+    Balloon,
+    Scsi,
+    Gpu,
+    Input,
+    Socket,
+}
+
+impl DeviceType {
+    pub const fn from_raw(id: u32) -> Option<Self> {
+        match id {
+            1 => Some(DeviceType::Network),
+            2 => Some(DeviceType::Block),
+            3 => Some(DeviceType::Console),
+            4 => Some(DeviceType::Entropy),
+            5 => Some(DeviceType::Balloon),
+            8 => Some(DeviceType::Scsi),
+            16 => Some(DeviceType::Gpu),
+            18 => Some(DeviceType::Input),
+            19 => Some(DeviceType::Socket),
+            _ => None,
+        }
+    }
+}
+
+bitflags! {
+    #[derive(Clone, Copy, Debug)]
+    pub struct DeviceStatus: u32 {
+        const ACKNOWLEDGE = 1 << 0;
+        const DRIVER = 1 << 1;
+        const DRIVER_OK = 1 << 2;
+        const FEATURES_OK = 1 << 3;
+        const DEVICE_NEEDS_RESET = 1 << 6;
+        const FAILED = 1 << 7;
+    }
+}
+
+/// Feature bits shared by every device type.
+pub mod features {
+    pub const VERSION_1: u64 = 1 << 32;
+}
+
+#[derive(Debug)]
+pub enum VirtioError {
+    InvalidMagic,
+    UnsupportedVersion,
+    /// The device tree node does not describe the registers of the device.
+    InvalidNode,
+    FeaturesRejected,
+    QueueUnavailable,
+    QueueFull,
+    InvalidBuffer,
+    OutOfMemory,
+}
+
+/// A virtio device that a driver has set up.
+pub trait VirtioDevice: Send + Sync {
+    fn transport(&self) -> &MmioTransport;
+
+    /// Takes back the chains the device returned through the used rings of its queues.
+    fn handle_queues(&self);
+
+    /// Reacts to a change of the configuration space of the device.
+    fn handle_config_change(&self) {}
+}
+
+pub struct VirtioDriver {
+    pub name: &'static str,
+    pub device_type: DeviceType,
+    /// The features the driver knows how to use.
+    pub features: u64,
+    /// Sets up the queues of a device once the features have been negotiated.
+    pub probe: fn(MmioTransport, u64) -> Result<Arc<dyn VirtioDevice>, VirtioError>,
+}
+
+static DRIVERS: &[&VirtioDriver] = &[&block::DRIVER];
+
+static DEVICES: Mutex<Vec<Arc<dyn VirtioDevice>>> = Mutex::new(Vec::new());
+
+pub fn init() {
+    let Some(device_tree) = boot::device_tree() else {
+        return;
+    };
+
+    for node in device_tree.compatible("virtio,mmio") {
+        if let Err(err) = probe(&node) {
+            log::warn!("Failed to probe virtio device {}: {:?}", node.name(), err);
+        }
+    }
+}
+
+fn probe(node: &Node<'_>) -> Result<(), VirtioError> {
+    let (addr, size) = node.reg().next().ok_or(VirtioError::InvalidNode)?;
+    let addr = PhysicalAddr::new(addr);
+
+    let base = mem::map_mmio(addr, size as usize).map_err(|_| VirtioError::OutOfMemory)?;
+    let transport = unsafe { MmioTransport::new(base)? };
+
+    // Slots without a device attached report a device id of zero.
+    let id = transport.device_id();
+    if id == 0 {
+        return Ok(());
+    }
+
+    let driver = DeviceType::from_raw(id).and_then(|device_type| {
+        DRIVERS
+            .iter()
+            .find(|driver| driver.device_type == device_type)
+    });
+
+    let Some(driver) = driver else {
+        log::debug!("No driver for virtio device {} at {}", id, addr);
+        return Ok(());
+    };
+
+    let device = transport
+        .negotiate(driver.features)
+        .and_then(|features| (driver.probe)(transport.clone(), features))
+        .inspect_err(|_| transport.fail())?;
+
+    transport.finish_init();
+
+    if let Some(irq) = node
+        .property("interrupts")
+        .and_then(|property| property.as_u32())
+    {
+        let device = device.clone();
+        irq::register(irq, Arc::new(move || handle_interrupt(device.as_ref())));
+    }
+
+    log::info!(
+        "Found virtio {} device at {} ({:?}).",
+        driver.name,
+        addr,
+        transport.version()
+    );
+
+    DEVICES.lock().push(device);
+
+    Ok(())
+}
+
+fn handle_interrupt(device: &dyn VirtioDevice) {
+    let status = device.transport().ack_interrupt();
+
+    if status.contains(InterruptStatus::USED_BUFFER) {
+        device.handle_queues();
+    }
+
+    if status.contains(InterruptStatus::CONFIG_CHANGE) {
+        device.handle_config_change();
+    }
+}
@@ -0,0 +1,268 @@
+//! Split virtqueues, through which buffers are passed to a device and back.
+
+use core::sync::atomic::{Ordering, fence};
+
+use super::VirtioError;
+use super::mmio::{MmioTransport, QueueLayout};
+use crate::arch::PAGE_SIZE;
+use crate::drivers::dma::DmaBuffer;
+use crate::mem::PhysicalAddr;
+use crate::misc;
+
+const DESC_F_NEXT: u16 = 1 << 0;
+const DESC_F_WRITE: u16 = 1 << 1;
+
+/// Set by the device in the used ring when it does not need to be notified of new buffers.
+const USED_F_NO_NOTIFY: u16 = 1 << 0;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UsedElement {
+    id: u32,
+    len: u32,
+}
+
+/// A buffer in a chain that is passed to the device.
+#[derive(Clone, Copy)]
+pub struct Buffer {
+    pub addr: PhysicalAddr,
+    pub len: u32,
+    /// Whether the device writes to the buffer instead of reading from it.
+    pub device_writable: bool,
+}
+
+/// A chain the device is done with.
+#[derive(Clone, Copy, Debug)]
+pub struct UsedChain {
+    /// The id returned by [`VirtQueue::add`] for the chain.
+    pub id: u16,
+}
+
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+
+    /// The descriptor table, followed by the available ring and the used ring at the next page
+    /// boundary, as legacy devices expect them.
+    memory: DmaBuffer,
+    used_offset: usize,
+
+    free_head: u16,
+    num_free: u16,
+    /// Our copy of the index of the available ring.
+    avail_idx: u16,
+    /// The index of the used ring up to which chains have been taken back.
+    last_used_idx: u16,
+}
+
+impl VirtQueue {
+    /// Sets up the queue `index` of the device with at most `max_size` entries.
+    pub fn new(transport: &MmioTransport, index: u16, max_size: u16) -> Result<Self, VirtioError> {
+        let device_max = transport.max_queue_size(index);
+        if device_max == 0 {
+            return Err(VirtioError::QueueUnavailable);
+        }
+
+        // The size of a split queue has to be a power of two.
+        let size = 1 << max_size.min(device_max).ilog2();
+
+        let avail_offset = size as usize * size_of::<Descriptor>();
+        let avail_size = 6 + 2 * size as usize;
+        let used_offset = misc::align_up_page((avail_offset + avail_size) as u64) as usize;
+        let used_size = 6 + size as usize * size_of::<UsedElement>();
+
+        let memory = DmaBuffer::new(used_offset + used_size).ok_or(VirtioError::OutOfMemory)?;
+        let base = memory.physical_addr().addr();
+
+        let mut queue = Self {
+            index,
+            size,
+            memory,
+            used_offset,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used_idx: 0,
+        };
+
+        // Link every descriptor into the free list.
+        for id in 0..size {
+            queue.write_descriptor(
+                id,
+                Descriptor {
+                    addr: 0,
+                    len: 0,
+                    flags: 0,
+                    next: (id + 1) % size,
+                },
+            );
+        }
+
+        debug_assert!(base % PAGE_SIZE == 0);
+
+        transport.setup_queue(
+            index,
+            size,
+            QueueLayout {
+                descriptors: PhysicalAddr::new(base),
+                driver: PhysicalAddr::new(base + avail_offset as u64),
+                device: PhysicalAddr::new(base + used_offset as u64),
+            },
+        );
+
+        Ok(queue)
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// How many more descriptors can be added before the queue is full.
+    pub fn num_free(&self) -> u16 {
+        self.num_free
+    }
+
+    /// The id the next chain added to the queue will get.
+    pub fn next_id(&self) -> u16 {
+        self.free_head
+    }
+
+    /// Makes the chain of `buffers` available to the device and returns its id. Buffers the
+    /// device reads from have to come before the ones it writes to.
+    ///
+    /// The buffers must stay valid until the chain is returned by [`VirtQueue::pop_used`].
+    pub fn add(&mut self, buffers: &[Buffer]) -> Result<u16, VirtioError> {
+        if buffers.is_empty() {
+            return Err(VirtioError::InvalidBuffer);
+        }
+
+        if buffers.len() > self.num_free as usize {
+            return Err(VirtioError::QueueFull);
+        }
+
+        let head = self.free_head;
+        let mut id = head;
+
+        for (index, buffer) in buffers.iter().enumerate() {
+            let next = self.read_descriptor(id).next;
+
+            let mut flags = 0;
+            if buffer.device_writable {
+                flags |= DESC_F_WRITE;
+            }
+            if index + 1 != buffers.len() {
+                flags |= DESC_F_NEXT;
+            }
+
+            self.write_descriptor(
+                id,
+                Descriptor {
+                    addr: buffer.addr.addr(),
+                    len: buffer.len,
+                    flags,
+                    next,
+                },
+            );
+
+            if index + 1 != buffers.len() {
+                id = next;
+            }
+        }
+
+        self.free_head = self.read_descriptor(id).next;
+        self.num_free -= buffers.len() as u16;
+
+        // The ring entry has to be visible before the index that publishes it.
+        let slot = self.avail_idx % self.size;
+        self.write_avail(2 + 2 * slot as usize, head);
+        fence(Ordering::SeqCst);
+
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        self.write_avail(1, self.avail_idx);
+        fence(Ordering::SeqCst);
+
+        Ok(head)
+    }
+
+    /// Tells the device about newly added chains, unless it asked not to be notified.
+    pub fn notify(&self, transport: &MmioTransport) {
+        fence(Ordering::SeqCst);
+
+        if self.read_used(0) & USED_F_NO_NOTIFY == 0 {
+            transport.notify(self.index);
+        }
+    }
+
+    /// Whether the device returned chains that have not been taken back yet.
+    pub fn has_used(&self) -> bool {
+        fence(Ordering::SeqCst);
+        self.read_used(1) != self.last_used_idx
+    }
+
+    /// Takes back the next chain the device is done with, freeing its descriptors.
+    pub fn pop_used(&mut self) -> Option<UsedChain> {
+        if !self.has_used() {
+            return None;
+        }
+
+        let slot = (self.last_used_idx % self.size) as usize;
+        let element = self.read_used_element(slot);
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+
+        let head = element.id as u16;
+        let mut id = head;
+        let mut count = 1;
+
+        while self.read_descriptor(id).flags & DESC_F_NEXT != 0 {
+            id = self.read_descriptor(id).next;
+            count += 1;
+        }
+
+        // Put the whole chain in front of the free list.
+        let mut last = self.read_descriptor(id);
+        last.next = self.free_head;
+        self.write_descriptor(id, last);
+
+        self.free_head = head;
+        self.num_free += count;
+
+        Some(UsedChain { id: head })
+    }
+
+    fn read_descriptor(&self, id: u16) -> Descriptor {
+        let descriptors = self.memory.as_mut_ptr::<Descriptor>();
+        unsafe { descriptors.add(id as usize).read_volatile() }
+    }
+
+    fn write_descriptor(&mut self, id: u16, descriptor: Descriptor) {
+        let descriptors = self.memory.as_mut_ptr::<Descriptor>();
+        unsafe { descriptors.add(id as usize).write_volatile(descriptor) }
+    }
+
+    /// Writes the 16-bit field at `index` of the available ring.
+    fn write_avail(&mut self, index: usize, value: u16) {
+        let avail_offset = self.size as usize * size_of::<Descriptor>();
+        let avail = unsafe { self.memory.as_mut_ptr::<u8>().add(avail_offset) }.cast::<u16>();
+        unsafe { avail.add(index).write_volatile(value) }
+    }
+
+    /// Reads the 16-bit field at `index` of the header of the used ring.
+    fn read_used(&self, index: usize) -> u16 {
+        let used = unsafe { self.memory.as_mut_ptr::<u8>().add(self.used_offset) }.cast::<u16>();
+        unsafe { used.add(index).read_volatile() }
+    }
+
+    fn read_used_element(&self, slot: usize) -> UsedElement {
+        let used = unsafe { self.memory.as_mut_ptr::<u8>().add(self.used_offset + 4) };
+        unsafe { used.cast::<UsedElement>().add(slot).read_volatile() }
+    }
+}
@@ -0,0 +1,691 @@
+//! Loader for statically linked ELF64 RISC-V executables, including static PIEs.
+
+use crate::arch::{self, PAGE_SIZE};
+use crate::mem::{AddressSpace, PageMapErr, VirtualAddr, VirtualMemoryFlags};
+use crate::misc;
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const EM_RISCV: u16 = 243;
+
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+const PT_PHDR: u32 = 6;
+
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+const DT_REL: u64 = 17;
+
+const R_RISCV_NONE: u32 = 0;
+const R_RISCV_RELATIVE: u32 = 3;
+
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_BASE: u64 = 7;
+const AT_ENTRY: u64 = 9;
+const AT_RANDOM: u64 = 25;
+
+/// Where position independent executables are loaded.
+const PIE_LOAD_BIAS: u64 = 0x0000_0000_0040_0000;
+
+pub const USER_STACK_SIZE: u64 = 128 * 1024;
+
+/// The most that the arguments and environment may take up on the user stack.
+const MAX_ARGUMENTS_SIZE: u64 = USER_STACK_SIZE / 4;
+
+#[derive(Debug)]
+pub enum ElfError {
+    Truncated,
+    InvalidMagic,
+    UnsupportedClass,
+    UnsupportedEndianness,
+    UnsupportedVersion,
+    UnsupportedMachine,
+    UnsupportedType,
+    /// The executable requests a program interpreter, which is not supported.
+    DynamicallyLinked,
+    NoLoadableSegments,
+    InvalidProgramHeader,
+    InvalidDynamicSection,
+    UnsupportedRelocation,
+    ArgumentsTooLarge,
+    MapFailed,
+}
+
+impl From<PageMapErr> for ElfError {
+    fn from(_: PageMapErr) -> Self {
+        ElfError::MapFailed
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FileHeader {
+    ident: [u8; 16],
+    kind: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ProgramHeader {
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Rela {
+    offset: u64,
+    info: u64,
+    addend: i64,
+}
+
+/// The state of a freshly loaded executable.
+pub struct LoadedImage {
+    pub entry: VirtualAddr,
+    pub stack_pointer: VirtualAddr,
+    /// The end of the highest segment, where the heap grown by `brk` starts.
+    pub program_break: VirtualAddr,
+}
+
+/// Loads the executable in `image` into `address_space` and sets up its stack with `argv` and
+/// `envp`.
+pub fn load(
+    address_space: &mut AddressSpace,
+    image: &[u8],
+    argv: &[&[u8]],
+    envp: &[&[u8]],
+) -> Result<LoadedImage, ElfError> {
+    let header = parse_header(image)?;
+
+    let load_bias = match header.kind {
+        ET_DYN => PIE_LOAD_BIAS,
+        _ => 0,
+    };
+
+    let mut phdr_addr = None;
+    let mut dynamic = None;
+    let mut loaded_any = false;
+    let mut program_break = 0;
+
+    for i in 0..header.phnum as usize {
+        let offset = (i as u64 * header.phentsize as u64)
+            .checked_add(header.phoff)
+            .ok_or(ElfError::Truncated)?;
+        let phdr: ProgramHeader = read(image, offset as usize)?;
+
+        match phdr.kind {
+            PT_LOAD => {
+                load_segment(address_space, image, &phdr, load_bias)?;
+                let segment_end = phdr
+                    .vaddr
+                    .checked_add(load_bias)
+                    .and_then(|start| start.checked_add(phdr.memsz))
+                    .ok_or(ElfError::InvalidProgramHeader)?;
+                program_break = program_break.max(segment_end);
+
+                // The program headers are usually part of the first loadable segment.
+                if phdr_addr.is_none()
+                    && phdr.offset <= header.phoff
+                    && header.phoff < phdr.offset + phdr.filesz
+                {
+                    phdr_addr = Some(
+                        phdr.vaddr
+                            .checked_add(load_bias)
+                            .and_then(|addr| addr.checked_sub(phdr.offset))
+                            .and_then(|base| base.checked_add(header.phoff))
+                            .ok_or(ElfError::InvalidProgramHeader)?,
+                    );
+                }
+
+                loaded_any = true;
+            }
+            PT_PHDR => {
+                phdr_addr = Some(
+                    phdr.vaddr
+                        .checked_add(load_bias)
+                        .ok_or(ElfError::InvalidProgramHeader)?,
+                );
+            }
+            PT_DYNAMIC => dynamic = Some(phdr),
+            PT_INTERP => return Err(ElfError::DynamicallyLinked),
+            _ => {}
+        }
+    }
+
+    if !loaded_any {
+        return Err(ElfError::NoLoadableSegments);
+    }
+
+    if let Some(dynamic) = dynamic
+        && load_bias != 0
+    {
+        relocate(address_space, &dynamic, load_bias)?;
+    }
+
+    let entry = header
+        .entry
+        .checked_add(load_bias)
+        .ok_or(ElfError::InvalidProgramHeader)?;
+
+    let auxv = [
+        (AT_PHDR, phdr_addr.unwrap_or(0)),
+        (AT_PHENT, header.phentsize as u64),
+        (AT_PHNUM, header.phnum as u64),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_BASE, 0),
+        (AT_ENTRY, entry),
+    ];
+
+    let stack_pointer = setup_stack(address_space, argv, envp, &auxv)?;
+
+    Ok(LoadedImage {
+        entry: VirtualAddr::new(entry),
+        stack_pointer,
+        program_break: VirtualAddr::new(misc::align_up_page(program_break)),
+    })
+}
+
+fn parse_header(image: &[u8]) -> Result<FileHeader, ElfError> {
+    let header: FileHeader = read(image, 0)?;
+
+    if header.ident[..4] != ELF_MAGIC {
+        return Err(ElfError::InvalidMagic);
+    }
+
+    if header.ident[4] != ELFCLASS64 {
+        return Err(ElfError::UnsupportedClass);
+    }
+
+    if header.ident[5] != ELFDATA2LSB {
+        return Err(ElfError::UnsupportedEndianness);
+    }
+
+    if header.ident[6] != EV_CURRENT || header.version != EV_CURRENT as u32 {
+        return Err(ElfError::UnsupportedVersion);
+    }
+
+    if header.machine != EM_RISCV {
+        return Err(ElfError::UnsupportedMachine);
+    }
+
+    if header.kind != ET_EXEC && header.kind != ET_DYN {
+        return Err(ElfError::UnsupportedType);
+    }
+
+    if (header.phentsize as usize) < size_of::<ProgramHeader>() {
+        return Err(ElfError::InvalidProgramHeader);
+    }
+
+    Ok(header)
+}
+
+fn load_segment(
+    address_space: &mut AddressSpace,
+    image: &[u8],
+    phdr: &ProgramHeader,
+    load_bias: u64,
+) -> Result<(), ElfError> {
+    if phdr.filesz > phdr.memsz {
+        return Err(ElfError::InvalidProgramHeader);
+    }
+
+    let file_end = phdr
+        .offset
+        .checked_add(phdr.filesz)
+        .ok_or(ElfError::InvalidProgramHeader)?;
+    if file_end > image.len() as u64 {
+        return Err(ElfError::Truncated);
+    }
+
+    let start = phdr
+        .vaddr
+        .checked_add(load_bias)
+        .ok_or(ElfError::InvalidProgramHeader)?;
+    let end = start
+        .checked_add(phdr.memsz)
+        .ok_or(ElfError::InvalidProgramHeader)?;
+
+    if !AddressSpace::is_user_range(VirtualAddr::new(start), phdr.memsz as usize) {
+        return Err(ElfError::InvalidProgramHeader);
+    }
+
+    let mut flags = VirtualMemoryFlags::empty();
+    if phdr.flags & PF_W != 0 {
+        flags |= VirtualMemoryFlags::Writeable;
+    }
+    if phdr.flags & PF_X != 0 {
+        flags |= VirtualMemoryFlags::Executable;
+    }
+
+    let mut page = misc::align_down_page(start);
+    while page < end {
+        let page_addr = VirtualAddr::new(page);
+
+        // Neighbouring segments may share a page, in which case it gets the permissions of both.
+        match address_space.translate(page_addr) {
+            Some(mapping) => address_space.map(
+                page_addr,
+                mapping.physical_addr,
+                PAGE_SIZE as usize,
+                mapping.flags | flags,
+            )?,
+            None => address_space.map_anonymous(page_addr, PAGE_SIZE as usize, flags)?,
+        }
+
+        page += PAGE_SIZE;
+    }
+
+    let file_bytes = &image[phdr.offset as usize..file_end as usize];
+    address_space.write(VirtualAddr::new(start), file_bytes)?;
+
+    // Whatever is not backed by the file is `.bss`.
+    address_space.zero(
+        VirtualAddr::new(start + phdr.filesz),
+        (phdr.memsz - phdr.filesz) as usize,
+    )?;
+
+    Ok(())
+}
+
+/// Applies the relocations of a static PIE that was loaded at `load_bias`.
+fn relocate(
+    address_space: &mut AddressSpace,
+    dynamic: &ProgramHeader,
+    load_bias: u64,
+) -> Result<(), ElfError> {
+    let mut rela = None;
+    let mut rela_size = 0;
+    let mut rela_entry_size = size_of::<Rela>() as u64;
+
+    let dynamic_addr = dynamic
+        .vaddr
+        .checked_add(load_bias)
+        .ok_or(ElfError::InvalidDynamicSection)?;
+
+    for i in 0..dynamic.memsz / 16 {
+        let entry_addr = dynamic_addr
+            .checked_add(i * 16)
+            .ok_or(ElfError::InvalidDynamicSection)?;
+
+        let mut entry = [0u8; 16];
+        address_space
+            .read(VirtualAddr::new(entry_addr), &mut entry)
+            .map_err(|_| ElfError::InvalidDynamicSection)?;
+
+        let tag = u64::from_le_bytes(entry[..8].try_into().unwrap());
+        let value = u64::from_le_bytes(entry[8..].try_into().unwrap());
+
+        match tag {
+            DT_NULL => break,
+            DT_RELA => rela = Some(value),
+            DT_RELASZ => rela_size = value,
+            DT_RELAENT => rela_entry_size = value,
+            DT_REL => return Err(ElfError::InvalidDynamicSection),
+            _ => {}
+        }
+    }
+
+    let Some(rela) = rela else {
+        return Ok(());
+    };
+
+    if rela_entry_size < size_of::<Rela>() as u64 {
+        return Err(ElfError::InvalidDynamicSection);
+    }
+
+    for i in 0..rela_size / rela_entry_size {
+        let entry_addr = i
+            .checked_mul(rela_entry_size)
+            .and_then(|offset| offset.checked_add(rela))
+            .and_then(|addr| addr.checked_add(load_bias))
+            .ok_or(ElfError::InvalidDynamicSection)?;
+
+        let mut bytes = [0u8; size_of::<Rela>()];
+        address_space
+            .read(VirtualAddr::new(entry_addr), &mut bytes)
+            .map_err(|_| ElfError::InvalidDynamicSection)?;
+
+        let relocation: Rela = read(&bytes, 0)?;
+
+        match (relocation.info & 0xFFFF_FFFF) as u32 {
+            R_RISCV_NONE => {}
+            R_RISCV_RELATIVE => {
+                let value = load_bias.wrapping_add_signed(relocation.addend);
+                let target = relocation
+                    .offset
+                    .checked_add(load_bias)
+                    .ok_or(ElfError::InvalidDynamicSection)?;
+                address_space.write(VirtualAddr::new(target), &value.to_le_bytes())?;
+            }
+            _ => return Err(ElfError::UnsupportedRelocation),
+        }
+    }
+
+    Ok(())
+}
+
+/// Maps the user stack at the top of the user half and lays out `argc`, `argv`, `envp` and
+/// `auxv` on it as expected by the System V ABI. `AT_RANDOM` and the terminating `AT_NULL` are
+/// added to `auxv`. Returns the initial stack pointer.
+fn setup_stack(
+    address_space: &mut AddressSpace,
+    argv: &[&[u8]],
+    envp: &[&[u8]],
+    auxv: &[(u64, u64)],
+) -> Result<VirtualAddr, ElfError> {
+    let stack_top = arch::user_space_end().addr();
+    let stack_bottom = stack_top - USER_STACK_SIZE;
+
+    address_space.map_anonymous(
+        VirtualAddr::new(stack_bottom),
+        USER_STACK_SIZE as usize,
+        VirtualMemoryFlags::Writeable | VirtualMemoryFlags::GrowsDown,
+    )?;
+
+    // libc seeds its stack protector and pointer guard from the 16 bytes `AT_RANDOM` points to,
+    // which sit right at the top of the stack.
+    let random_addr = stack_top - 16;
+    address_space.write(VirtualAddr::new(random_addr), &random_bytes())?;
+
+    let strings_size: u64 = argv
+        .iter()
+        .chain(envp.iter())
+        .map(|string| string.len() as u64 + 1)
+        .sum();
+
+    // argc, the null terminated argv and envp arrays and the auxiliary vector with `AT_RANDOM` and
+    // `AT_NULL`.
+    let words = 1 + (argv.len() + 1) + (envp.len() + 1) + (auxv.len() + 2) * 2;
+    let table_size = words as u64 * 8;
+
+    if strings_size + table_size > MAX_ARGUMENTS_SIZE {
+        return Err(ElfError::ArgumentsTooLarge);
+    }
+
+    let mut string_cursor = random_addr - strings_size;
+    let stack_pointer = misc::align_down(string_cursor - table_size, 16);
+    let mut table_cursor = stack_pointer;
+
+    let mut push_word = |address_space: &mut AddressSpace, value: u64| {
+        let result = address_space.write(VirtualAddr::new(table_cursor), &value.to_le_bytes());
+        table_cursor += 8;
+        result
+    };
+
+    push_word(address_space, argv.len() as u64)?;
+
+    for strings in [argv, envp] {
+        for string in strings {
+            push_word(address_space, string_cursor)?;
+
+            address_space.write(VirtualAddr::new(string_cursor), string)?;
+            address_space.write(VirtualAddr::new(string_cursor + string.len() as u64), &[0])?;
+            string_cursor += string.len() as u64 + 1;
+        }
+
+        push_word(address_space, 0)?;
+    }
+
+    for &(key, value) in auxv.iter().chain(&[(AT_RANDOM, random_addr), (AT_NULL, 0)]) {
+        push_word(address_space, key)?;
+        push_word(address_space, value)?;
+    }
+
+    Ok(VirtualAddr::new(stack_pointer))
+}
+
+/// 16 bytes for `AT_RANDOM`. There is no entropy source yet, so they are mixed from the time,
+/// which only makes them differ between processes.
+fn random_bytes() -> [u8; 16] {
+    // The finalizer of SplitMix64.
+    fn mix(mut value: u64) -> u64 {
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        value ^ (value >> 31)
+    }
+
+    let seed = arch::monotonic_time().as_nanos() as u64;
+    let low = mix(seed.wrapping_add(0x9e37_79b9_7f4a_7c15));
+    let high = mix(low);
+
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&low.to_le_bytes());
+    bytes[8..].copy_from_slice(&high.to_le_bytes());
+    bytes
+}
+
+/// Reads a `T` at `offset` in `bytes`.
+fn read<T: Copy>(bytes: &[u8], offset: usize) -> Result<T, ElfError> {
+    let end = offset
+        .checked_add(size_of::<T>())
+        .ok_or(ElfError::Truncated)?;
+    if end > bytes.len() {
+        return Err(ElfError::Truncated);
+    }
+
+    // SAFETY: The bounds are checked above, and `T` is only ever one of the plain old data
+    // structures of this module.
+    Ok(unsafe { bytes.as_ptr().add(offset).cast::<T>().read_unaligned() })
+}
+
+#[cfg(feature = "kernel-tests")]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::test::kernel_test;
+
+    /// Where the contents of the segments start in the images built by [`image`].
+    const DATA_OFFSET: u64 = 0x100;
+
+    fn bytes_of<T: Copy>(value: &T) -> &[u8] {
+        // SAFETY: `T` is one of the plain old data structures of this module, which have no
+        // padding.
+        unsafe { core::slice::from_raw_parts((value as *const T).cast(), size_of::<T>()) }
+    }
+
+    fn header(kind: u16, entry: u64, phnum: u16) -> FileHeader {
+        let mut ident = [0; 16];
+        ident[..4].copy_from_slice(&ELF_MAGIC);
+        ident[4] = ELFCLASS64;
+        ident[5] = ELFDATA2LSB;
+        ident[6] = EV_CURRENT;
+
+        FileHeader {
+            ident,
+            kind,
+            machine: EM_RISCV,
+            version: EV_CURRENT as u32,
+            entry,
+            phoff: size_of::<FileHeader>() as u64,
+            shoff: 0,
+            flags: 0,
+            ehsize: size_of::<FileHeader>() as u16,
+            phentsize: size_of::<ProgramHeader>() as u16,
+            phnum,
+            shentsize: 0,
+            shnum: 0,
+            shstrndx: 0,
+        }
+    }
+
+    fn segment(kind: u32, vaddr: u64, data_offset: u64, size: u64) -> ProgramHeader {
+        ProgramHeader {
+            kind,
+            flags: PF_W,
+            offset: DATA_OFFSET + data_offset,
+            vaddr,
+            paddr: vaddr,
+            filesz: size,
+            memsz: size,
+            align: PAGE_SIZE,
+        }
+    }
+
+    /// Lays out `header`, the program headers right behind it and `data` at [`DATA_OFFSET`].
+    fn image(header: &FileHeader, phdrs: &[ProgramHeader], data: &[u8]) -> Vec<u8> {
+        let mut image = Vec::new();
+        image.extend_from_slice(bytes_of(header));
+        for phdr in phdrs {
+            image.extend_from_slice(bytes_of(phdr));
+        }
+
+        assert!(image.len() <= DATA_OFFSET as usize);
+        image.resize(DATA_OFFSET as usize, 0);
+        image.extend_from_slice(data);
+        image
+    }
+
+    fn read_word(address_space: &AddressSpace, addr: u64) -> u64 {
+        let mut word = [0; 8];
+        address_space
+            .read(VirtualAddr::new(addr), &mut word)
+            .unwrap();
+        u64::from_le_bytes(word)
+    }
+
+    #[kernel_test]
+    fn rejects_bad_headers() {
+        let mut address_space = AddressSpace::new().unwrap();
+        let phdrs = [segment(PT_LOAD, 0x10000, 0, 4)];
+
+        let truncated = &image(&header(ET_EXEC, 0x10000, 1), &phdrs, &[0; 4])[..16];
+        let result = load(&mut address_space, truncated, &[], &[]);
+        assert!(matches!(result, Err(ElfError::Truncated)));
+
+        let mut wrong_machine = header(ET_EXEC, 0x10000, 1);
+        wrong_machine.machine = 62;
+        let result = load(
+            &mut address_space,
+            &image(&wrong_machine, &phdrs, &[0; 4]),
+            &[],
+            &[],
+        );
+        assert!(matches!(result, Err(ElfError::UnsupportedMachine)));
+
+        let mut bad_magic = image(&header(ET_EXEC, 0x10000, 1), &phdrs, &[0; 4]);
+        bad_magic[1] = b'e';
+        let result = load(&mut address_space, &bad_magic, &[], &[]);
+        assert!(matches!(result, Err(ElfError::InvalidMagic)));
+    }
+
+    #[kernel_test]
+    fn rejects_overflowing_segments() {
+        let mut address_space = AddressSpace::new().unwrap();
+
+        let mut phdr = segment(PT_LOAD, u64::MAX - PAGE_SIZE + 1, 0, 0);
+        phdr.memsz = 2 * PAGE_SIZE;
+        let image = image(&header(ET_EXEC, 0x10000, 1), &[phdr], &[]);
+
+        let result = load(&mut address_space, &image, &[], &[]);
+        assert!(matches!(result, Err(ElfError::InvalidProgramHeader)));
+    }
+
+    #[kernel_test]
+    fn relocates_position_independent_executables() {
+        const BASE: u64 = 0x1000;
+        const TARGET: u64 = BASE + 0x58;
+        const ADDEND: u64 = 0x1234;
+
+        let mut data = Vec::new();
+        for word in [
+            DT_RELA,
+            BASE + 0x40,
+            DT_RELASZ,
+            24,
+            DT_RELAENT,
+            24,
+            DT_NULL,
+            0,
+        ] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        let rela = Rela {
+            offset: TARGET,
+            info: R_RISCV_RELATIVE as u64,
+            addend: ADDEND as i64,
+        };
+        data.extend_from_slice(bytes_of(&rela));
+        data.extend_from_slice(&0u64.to_le_bytes());
+
+        let phdrs = [
+            segment(PT_LOAD, BASE, 0, data.len() as u64),
+            segment(PT_DYNAMIC, BASE, 0, 64),
+        ];
+        let image = image(&header(ET_DYN, BASE, 2), &phdrs, &data);
+
+        let mut address_space = AddressSpace::new().unwrap();
+        let loaded = load(&mut address_space, &image, &[], &[]).unwrap();
+
+        assert_eq!(loaded.entry.addr(), PIE_LOAD_BIAS + BASE);
+        assert_eq!(
+            loaded.program_break.addr(),
+            PIE_LOAD_BIAS + BASE + PAGE_SIZE
+        );
+        assert_eq!(
+            read_word(&address_space, PIE_LOAD_BIAS + TARGET),
+            PIE_LOAD_BIAS + ADDEND
+        );
+    }
+
+    #[kernel_test]
+    fn passes_random_bytes_in_the_auxiliary_vector() {
+        let phdrs = [segment(PT_LOAD, 0x10000, 0, 4)];
+        let image = image(&header(ET_EXEC, 0x10000, 1), &phdrs, &[0; 4]);
+
+        let mut address_space = AddressSpace::new().unwrap();
+        let loaded = load(&mut address_space, &image, &[b"init"], &[]).unwrap();
+
+        // argc, argv[0], the end of argv and the end of envp come before the auxiliary vector.
+        let mut addr = loaded.stack_pointer.addr() + 4 * 8;
+        let random = loop {
+            let key = read_word(&address_space, addr);
+            let value = read_word(&address_space, addr + 8);
+            assert_ne!(key, AT_NULL, "No AT_RANDOM in the auxiliary vector");
+
+            if key == AT_RANDOM {
+                break value;
+            }
+            addr += 16;
+        };
+
+        let mut bytes = [0; 16];
+        address_space
+            .read(VirtualAddr::new(random), &mut bytes)
+            .unwrap();
+        assert!(random + 16 <= arch::user_space_end().addr());
+    }
+}
@@ -0,0 +1,407 @@
+//! Parser for the flattened device tree blob handed over by the bootloader.
+//!
+//! Nothing is copied out of the blob, nodes and properties borrow from it and are found by walking
+//! the structure block from the start.
+
+use arrayvec::ArrayVec;
+
+const MAGIC: u32 = 0xd00d_feed;
+const HEADER_SIZE: usize = 40;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+
+/// How deep nodes may be nested, deeper nodes are not visited.
+const MAX_DEPTH: usize = 16;
+
+/// The `#address-cells` and `#size-cells` a node has if it does not specify them.
+const DEFAULT_CELLS: Cells = Cells {
+    address: 2,
+    size: 1,
+};
+
+#[derive(Debug)]
+pub enum FdtError {
+    InvalidMagic,
+    Truncated,
+}
+
+#[derive(Clone, Copy)]
+pub struct DeviceTree<'a> {
+    structure: &'a [u8],
+    strings: &'a [u8],
+}
+
+/// How many 32-bit cells the addresses and sizes in the `reg` property of a node take up.
+#[derive(Clone, Copy, Debug)]
+pub struct Cells {
+    pub address: usize,
+    pub size: usize,
+}
+
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    tree: DeviceTree<'a>,
+    name: &'a str,
+    /// The offset of the first token after the name of the node.
+    body: usize,
+    /// The cells of the parent, which describe the `reg` property of this node.
+    parent_cells: Cells,
+}
+
+#[derive(Clone, Copy)]
+pub struct Property<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+impl<'a> DeviceTree<'a> {
+    /// Parses the device tree blob at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a device tree blob that stays mapped and unchanged for `'a`.
+    pub unsafe fn from_ptr(ptr: *const u8) -> Result<Self, FdtError> {
+        let header = unsafe { core::slice::from_raw_parts(ptr, HEADER_SIZE) };
+
+        if read_u32(header, 0) != Some(MAGIC) {
+            return Err(FdtError::InvalidMagic);
+        }
+
+        let total_size = read_u32(header, 4).ok_or(FdtError::Truncated)? as usize;
+        let blob = unsafe { core::slice::from_raw_parts(ptr, total_size) };
+
+        Self::new(blob)
+    }
+
+    pub fn new(blob: &'a [u8]) -> Result<Self, FdtError> {
+        let field = |index: usize| {
+            read_u32(blob, index * 4)
+                .map(|value| value as usize)
+                .ok_or(FdtError::Truncated)
+        };
+
+        if field(0)? != MAGIC as usize {
+            return Err(FdtError::InvalidMagic);
+        }
+
+        let structure_offset = field(2)?;
+        let strings_offset = field(3)?;
+        let strings_size = field(8)?;
+        let structure_size = field(9)?;
+
+        let structure = blob
+            .get(structure_offset..structure_offset + structure_size)
+            .ok_or(FdtError::Truncated)?;
+        let strings = blob
+            .get(strings_offset..strings_offset + strings_size)
+            .ok_or(FdtError::Truncated)?;
+
+        Ok(Self { structure, strings })
+    }
+
+    pub fn root(&self) -> Option<Node<'a>> {
+        let mut offset = 0;
+
+        loop {
+            match read_u32(self.structure, offset)? {
+                FDT_NOP => offset += 4,
+                FDT_BEGIN_NODE => return self.node_at(offset + 4, DEFAULT_CELLS),
+                _ => return None,
+            }
+        }
+    }
+
+    /// Finds the node at `path`, where the unit address of a component may be left out if it is
+    /// unambiguous.
+    pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+        let mut node = self.root()?;
+
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            node = node.children().find(|child| {
+                child.name() == component || child.name().split('@').next() == Some(component)
+            })?;
+        }
+
+        Some(node)
+    }
+
+    /// Every node in the tree, in the order they appear in the blob.
+    pub fn nodes(&self) -> Nodes<'a> {
+        Nodes {
+            stack: ArrayVec::new(),
+            next: self.root(),
+        }
+    }
+
+    /// Every node that lists `compatible` in its `compatible` property.
+    pub fn compatible(&self, compatible: &'a str) -> impl Iterator<Item = Node<'a>> + 'a {
+        self.nodes()
+            .filter(move |node| node.is_compatible(compatible))
+    }
+
+    fn node_at(&self, offset: usize, parent_cells: Cells) -> Option<Node<'a>> {
+        let name_len = self.structure.get(offset..)?.iter().position(|&b| b == 0)?;
+        let name = core::str::from_utf8(&self.structure[offset..offset + name_len]).ok()?;
+
+        Some(Node {
+            tree: *self,
+            name,
+            body: align4(offset + name_len + 1),
+            parent_cells,
+        })
+    }
+
+    fn string_at(&self, offset: usize) -> Option<&'a str> {
+        let bytes = self.strings.get(offset..)?;
+        let len = bytes.iter().position(|&b| b == 0)?;
+        core::str::from_utf8(&bytes[..len]).ok()
+    }
+
+    /// Returns the offset of the token following the node whose body starts at `offset`.
+    fn skip_node(&self, mut offset: usize) -> Option<usize> {
+        let mut depth = 1;
+
+        while depth != 0 {
+            match read_u32(self.structure, offset)? {
+                FDT_BEGIN_NODE => {
+                    let name = self.structure.get(offset + 4..)?;
+                    let name_len = name.iter().position(|&b| b == 0)?;
+                    offset = align4(offset + 4 + name_len + 1);
+                    depth += 1;
+                }
+                FDT_END_NODE => {
+                    offset += 4;
+                    depth -= 1;
+                }
+                FDT_PROP => offset = self.skip_property(offset)?,
+                FDT_NOP => offset += 4,
+                _ => return None,
+            }
+        }
+
+        Some(offset)
+    }
+
+    fn skip_property(&self, offset: usize) -> Option<usize> {
+        let len = read_u32(self.structure, offset + 4)? as usize;
+        Some(align4(offset + 12 + len))
+    }
+}
+
+impl<'a> Node<'a> {
+    /// The name of the node, including the unit address.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn properties(&self) -> Properties<'a> {
+        Properties {
+            tree: self.tree,
+            offset: self.body,
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|property| property.name == name)
+    }
+
+    pub fn children(&self) -> Children<'a> {
+        let mut offset = self.body;
+
+        // Properties always come before the child nodes.
+        while let Some(token) = read_u32(self.tree.structure, offset) {
+            match token {
+                FDT_PROP => match self.tree.skip_property(offset) {
+                    Some(next) => offset = next,
+                    None => break,
+                },
+                FDT_NOP => offset += 4,
+                _ => break,
+            }
+        }
+
+        Children {
+            tree: self.tree,
+            offset: Some(offset),
+            cells: self.cells(),
+        }
+    }
+
+    /// The cells this node uses to describe the `reg` property of its children.
+    pub fn cells(&self) -> Cells {
+        let read = |name| self.property(name).and_then(|property| property.as_u32());
+
+        Cells {
+            address: read("#address-cells").map_or(DEFAULT_CELLS.address, |cells| cells as usize),
+            size: read("#size-cells").map_or(DEFAULT_CELLS.size, |cells| cells as usize),
+        }
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.property("compatible")
+            .is_some_and(|property| property.strings().any(|entry| entry == compatible))
+    }
+
+    pub fn phandle(&self) -> Option<u32> {
+        self.property("phandle")
+            .or_else(|| self.property("linux,phandle"))
+            .and_then(|property| property.as_u32())
+    }
+
+    /// The address ranges of the `reg` property as `(address, size)` pairs.
+    pub fn reg(&self) -> impl Iterator<Item = (u64, u64)> + 'a {
+        let cells = self.parent_cells;
+        let entry_size = (cells.address + cells.size) * 4;
+        let value = match self.property("reg") {
+            Some(property) if entry_size != 0 => property.value,
+            _ => &[],
+        };
+
+        value.chunks_exact(entry_size.max(1)).map(move |entry| {
+            let (address, size) = entry.split_at(cells.address * 4);
+            (read_cells(address), read_cells(size))
+        })
+    }
+}
+
+impl<'a> Property<'a> {
+    pub fn as_u32(&self) -> Option<u32> {
+        read_u32(self.value, 0)
+    }
+
+    /// Reads the value as a single 32 or 64-bit number.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.value.len() {
+            4 => self.as_u32().map(u64::from),
+            8 => Some(read_cells(self.value)),
+            _ => None,
+        }
+    }
+
+    /// The value as a list of 32-bit cells.
+    pub fn cells(&self) -> impl Iterator<Item = u32> + 'a {
+        self.value
+            .chunks_exact(4)
+            .map(|cell| u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]))
+    }
+
+    /// The value as a list of null terminated strings.
+    pub fn strings(&self) -> impl Iterator<Item = &'a str> + 'a {
+        self.value
+            .split(|&b| b == 0)
+            .filter(|entry| !entry.is_empty())
+            .filter_map(|entry| core::str::from_utf8(entry).ok())
+    }
+}
+
+pub struct Properties<'a> {
+    tree: DeviceTree<'a>,
+    offset: usize,
+}
+
+impl<'a> Iterator for Properties<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match read_u32(self.tree.structure, self.offset)? {
+                FDT_NOP => self.offset += 4,
+                FDT_PROP => break,
+                _ => return None,
+            }
+        }
+
+        let len = read_u32(self.tree.structure, self.offset + 4)? as usize;
+        let name_offset = read_u32(self.tree.structure, self.offset + 8)? as usize;
+        let value = self
+            .tree
+            .structure
+            .get(self.offset + 12..self.offset + 12 + len)?;
+
+        self.offset = align4(self.offset + 12 + len);
+
+        Some(Property {
+            name: self.tree.string_at(name_offset)?,
+            value,
+        })
+    }
+}
+
+pub struct Children<'a> {
+    tree: DeviceTree<'a>,
+    offset: Option<usize>,
+    cells: Cells,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut offset = self.offset?;
+
+        loop {
+            match read_u32(self.tree.structure, offset) {
+                Some(FDT_NOP) => offset += 4,
+                Some(FDT_BEGIN_NODE) => break,
+                _ => {
+                    self.offset = None;
+                    return None;
+                }
+            }
+        }
+
+        let node = self.tree.node_at(offset + 4, self.cells)?;
+        self.offset = self.tree.skip_node(node.body);
+
+        Some(node)
+    }
+}
+
+/// A depth first walk over the whole tree.
+pub struct Nodes<'a> {
+    /// The children of the nodes on the path to the next node that remain to be visited.
+    stack: ArrayVec<Children<'a>, MAX_DEPTH>,
+    next: Option<Node<'a>>,
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.next.take()?;
+
+        if !self.stack.is_full() {
+            self.stack.push(node.children());
+        }
+
+        while let Some(children) = self.stack.last_mut() {
+            if let Some(child) = children.next() {
+                self.next = Some(child);
+                break;
+            }
+
+            self.stack.pop();
+        }
+
+        Some(node)
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Reads a big endian number made up of any number of 32-bit cells.
+fn read_cells(bytes: &[u8]) -> u64 {
+    bytes.chunks_exact(4).fold(0, |value, cell| {
+        (value << 32) | u64::from(u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]))
+    })
+}
+
+const fn align4(value: usize) -> usize {
+    (value + 3) & !3
+}
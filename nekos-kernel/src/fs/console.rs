@@ -0,0 +1,39 @@
+use nekos_abi::time::Timespec;
+
+use super::{File, FsResult, InodeKind, Metadata};
+use crate::arch;
+
+/// The system console, which is what standard input, output and error start out as.
+pub struct Console;
+
+impl File for Console {
+    fn read(&self, _buf: &mut [u8]) -> FsResult<usize> {
+        // There is no input driver yet, so the console is always at the end of its input.
+        Ok(0)
+    }
+
+    fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        arch::console_write(buf);
+        Ok(buf.len())
+    }
+
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata {
+            dev: 0,
+            ino: 0,
+            kind: InodeKind::CharDevice,
+            mode: 0o620,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            // The major and minor numbers of `/dev/console` on Linux.
+            rdev: (5 << 8) | 1,
+            size: 0,
+            block_size: 1024,
+            blocks: 0,
+            atime: Timespec::default(),
+            mtime: Timespec::default(),
+            ctime: Timespec::default(),
+        })
+    }
+}
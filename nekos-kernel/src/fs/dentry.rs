@@ -0,0 +1,169 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+
+use nekos_abi::Errno;
+use spin::Mutex;
+
+use super::{FsResult, Inode, InodeKind, Metadata};
+
+/// A cached directory entry, linking a name in the directory tree to its inode.
+///
+/// Looked up children are kept in their parent, so that walking the same path again does not
+/// have to go through the file system.
+pub struct Dentry {
+    inode: Arc<dyn Inode>,
+    kind: InodeKind,
+    dev: u64,
+
+    /// The directory containing this entry. The root of a mounted file system points to the
+    /// parent of the directory it is mounted on, so that `..` leaves the mount.
+    parent: Option<Weak<Dentry>>,
+    children: Mutex<BTreeMap<String, Arc<Dentry>>>,
+
+    /// The root of the file system mounted on this directory.
+    mounted: Mutex<Option<Arc<Dentry>>>,
+}
+
+impl Dentry {
+    /// Creates the root entry of a file system.
+    pub fn new_root(inode: Arc<dyn Inode>, dev: u64, parent: Option<&Arc<Dentry>>) -> Arc<Self> {
+        let kind = inode.metadata().map_or(InodeKind::Directory, |m| m.kind);
+
+        Arc::new(Self {
+            inode,
+            kind,
+            dev,
+            parent: parent.map(Arc::downgrade),
+            children: Mutex::new(BTreeMap::new()),
+            mounted: Mutex::new(None),
+        })
+    }
+
+    fn new_child(self: &Arc<Self>, inode: Arc<dyn Inode>) -> FsResult<Arc<Self>> {
+        let kind = inode.metadata()?.kind;
+
+        Ok(Arc::new(Self {
+            inode,
+            kind,
+            dev: self.dev,
+            parent: Some(Arc::downgrade(self)),
+            children: Mutex::new(BTreeMap::new()),
+            mounted: Mutex::new(None),
+        }))
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    pub fn kind(&self) -> InodeKind {
+        self.kind
+    }
+
+    /// Returns the metadata of the inode, with the device of the mount it belongs to.
+    pub fn metadata(&self) -> FsResult<Metadata> {
+        let mut metadata = self.inode.metadata()?;
+        metadata.dev = self.dev;
+        Ok(metadata)
+    }
+
+    /// Returns the parent directory, the root directory is its own parent.
+    pub fn parent(self: &Arc<Self>) -> Arc<Self> {
+        self.parent
+            .as_ref()
+            .and_then(Weak::upgrade)
+            .unwrap_or_else(|| self.clone())
+    }
+
+    /// Returns the root of the file system mounted on this entry, or the entry itself.
+    pub fn follow_mounts(self: &Arc<Self>) -> Arc<Self> {
+        let mut current = self.clone();
+
+        loop {
+            let mounted = current.mounted.lock().clone();
+            match mounted {
+                Some(root) => current = root,
+                None => return current,
+            }
+        }
+    }
+
+    /// Mounts the file system with the root `inode` on this directory.
+    pub fn mount(self: &Arc<Self>, inode: Arc<dyn Inode>, dev: u64) -> FsResult<()> {
+        if self.kind != InodeKind::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+
+        let mut mounted = self.mounted.lock();
+        if mounted.is_some() {
+            return Err(Errno::EBUSY);
+        }
+
+        let root = Dentry::new_root(inode, dev, Some(&self.parent()));
+        *mounted = Some(root);
+
+        Ok(())
+    }
+
+    /// Looks up the child called `name`, going through the cache first.
+    pub fn lookup_child(self: &Arc<Self>, name: &str) -> FsResult<Arc<Self>> {
+        if self.kind != InodeKind::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+
+        if let Some(child) = self.children.lock().get(name) {
+            return Ok(child.clone());
+        }
+
+        let inode = self.inode.lookup(name)?;
+        let child = self.new_child(inode)?;
+
+        // Someone else may have looked up the same child in the meantime.
+        let mut children = self.children.lock();
+        Ok(children.entry(String::from(name)).or_insert(child).clone())
+    }
+
+    /// Creates a file or directory called `name` in this directory.
+    pub fn create_child(
+        self: &Arc<Self>,
+        name: &str,
+        kind: InodeKind,
+        mode: u32,
+    ) -> FsResult<Arc<Self>> {
+        let inode = self.inode.create(name, kind, mode)?;
+        self.insert_child(name, inode)
+    }
+
+    /// Creates a symlink called `name` in this directory.
+    pub fn symlink_child(self: &Arc<Self>, name: &str, target: &str) -> FsResult<Arc<Self>> {
+        let inode = self.inode.symlink(name, target)?;
+        self.insert_child(name, inode)
+    }
+
+    /// Removes the child called `name`, which has to be a directory if `directory` is set.
+    pub fn remove_child(self: &Arc<Self>, name: &str, directory: bool) -> FsResult<()> {
+        let child = self.lookup_child(name)?;
+
+        if child.mounted.lock().is_some() {
+            return Err(Errno::EBUSY);
+        }
+
+        if directory {
+            self.inode.rmdir(name)?;
+        } else {
+            self.inode.unlink(name)?;
+        }
+
+        self.children.lock().remove(name);
+        Ok(())
+    }
+
+    fn insert_child(self: &Arc<Self>, name: &str, inode: Arc<dyn Inode>) -> FsResult<Arc<Self>> {
+        let child = self.new_child(inode)?;
+        self.children
+            .lock()
+            .insert(String::from(name), child.clone());
+        Ok(child)
+    }
+}
@@ -0,0 +1,120 @@
+//! Directory entries, which are packed into the blocks of a directory as a linked list.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use nekos_abi::Errno;
+
+use crate::fs::{FsResult, InodeKind};
+
+/// The size of an entry without its name.
+const HEADER_SIZE: usize = 8;
+
+pub const MAX_NAME_LEN: usize = 255;
+
+pub struct Record {
+    /// The inode of the entry, zero if the entry is unused.
+    pub ino: u32,
+    pub name: String,
+    /// The type of the inode, which is only filled in with the `filetype` feature.
+    pub file_type: u8,
+    /// The offset of the entry in the directory.
+    pub offset: usize,
+    pub rec_len: usize,
+    /// The offset of the entry before this one in the same block, which absorbs it once it is
+    /// removed.
+    pub previous: Option<usize>,
+}
+
+impl Record {
+    pub fn is_dot(&self) -> bool {
+        self.name == "." || self.name == ".."
+    }
+
+    /// How many bytes of the entry are unused after its name.
+    pub fn slack(&self) -> usize {
+        match self.ino {
+            0 => self.rec_len,
+            _ => self.rec_len - entry_len(self.name.len()),
+        }
+    }
+}
+
+/// Parses every entry of the directory whose contents are `data`, including unused ones.
+pub fn records(data: &[u8], block_size: usize) -> FsResult<Vec<Record>> {
+    let mut records = Vec::new();
+
+    for (index, block) in data.chunks(block_size).enumerate() {
+        let mut offset = 0;
+        let mut previous = None;
+
+        while offset + HEADER_SIZE <= block.len() {
+            let entry = &block[offset..];
+            let ino = u32::from_le_bytes(entry[..4].try_into().unwrap());
+            let rec_len = u16::from_le_bytes([entry[4], entry[5]]) as usize;
+            let name_len = entry[6] as usize;
+
+            if rec_len < HEADER_SIZE
+                || !rec_len.is_multiple_of(4)
+                || offset + rec_len > block.len()
+                || HEADER_SIZE + name_len > rec_len
+            {
+                return Err(Errno::EIO);
+            }
+
+            let name = &entry[HEADER_SIZE..HEADER_SIZE + name_len];
+            let start = index * block_size + offset;
+
+            records.push(Record {
+                ino,
+                name: String::from_utf8_lossy(name).into_owned(),
+                file_type: entry[7],
+                offset: start,
+                rec_len,
+                previous,
+            });
+
+            previous = Some(start);
+            offset += rec_len;
+        }
+    }
+
+    Ok(records)
+}
+
+/// Writes an entry for `name` at the start of `buf`, taking up `rec_len` bytes.
+pub fn encode(buf: &mut [u8], ino: u32, rec_len: usize, name: &str, file_type: u8) {
+    buf[..4].copy_from_slice(&ino.to_le_bytes());
+    buf[4..6].copy_from_slice(&(rec_len as u16).to_le_bytes());
+    buf[6] = name.len() as u8;
+    buf[7] = file_type;
+    buf[HEADER_SIZE..HEADER_SIZE + name.len()].copy_from_slice(name.as_bytes());
+}
+
+/// Changes the length of the entry at the start of `buf`.
+pub fn set_rec_len(buf: &mut [u8], rec_len: usize) {
+    buf[4..6].copy_from_slice(&(rec_len as u16).to_le_bytes());
+}
+
+/// Marks the entry at the start of `buf` unused.
+pub fn clear_ino(buf: &mut [u8]) {
+    buf[..4].fill(0);
+}
+
+/// How many bytes an entry for a name of `name_len` bytes needs.
+pub const fn entry_len(name_len: usize) -> usize {
+    (HEADER_SIZE + name_len + 3) & !3
+}
+
+/// The type stored in entries with the `filetype` feature.
+pub const fn file_type(kind: InodeKind) -> u8 {
+    match kind {
+        InodeKind::File => 1,
+        InodeKind::Directory => 2,
+        InodeKind::CharDevice => 3,
+        InodeKind::BlockDevice => 4,
+        InodeKind::Fifo => 5,
+        InodeKind::Socket => 6,
+        InodeKind::Symlink => 7,
+    }
+}
@@ -0,0 +1,928 @@
+//! Inodes, and how the blocks of their contents are found through the block pointers.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use arrayvec::ArrayVec;
+use nekos_abi::Errno;
+use nekos_abi::fs as abi;
+use nekos_abi::time::Timespec;
+use spin::Mutex;
+
+use super::{Volume, dir};
+use crate::fs::{DirEntry, FsResult, Inode, InodeKind, Metadata, now};
+use crate::log;
+
+/// How much of an inode is read, larger inodes keep extra fields after it that are left alone.
+pub const RAW_INODE_SIZE: usize = 128;
+
+const DIRECT_BLOCKS: u64 = 12;
+const INDIRECT_BLOCK: usize = 12;
+const DOUBLE_INDIRECT_BLOCK: usize = 13;
+const TRIPLE_INDIRECT_BLOCK: usize = 14;
+
+/// Symlinks whose target is shorter than this keep it in place of the block pointers.
+const FAST_SYMLINK_MAX: usize = 60;
+
+/// The flag of directories with a hashed index, which is not kept up to date.
+const FLAG_INDEX: u32 = 0x1000;
+
+/// The largest file without the `large_file` feature.
+const MAX_SMALL_FILE_SIZE: u64 = i32::MAX as u64;
+
+pub struct RawInode {
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub atime: u32,
+    pub ctime: u32,
+    pub mtime: u32,
+    pub dtime: u32,
+    pub links_count: u16,
+    /// The number of 512-byte sectors allocated, including indirect blocks.
+    pub blocks: u32,
+    pub flags: u32,
+    pub block: [u32; 15],
+    pub file_acl: u32,
+    /// The inode as read, so that the fields not covered above are written back unchanged.
+    raw: [u8; RAW_INODE_SIZE],
+}
+
+pub struct Ext2Inode {
+    ino: u32,
+    kind: InodeKind,
+    volume: Arc<Volume>,
+    state: Mutex<RawInode>,
+}
+
+impl RawInode {
+    pub fn new(kind: InodeKind, mode: u32, links_count: u16) -> Self {
+        let time = now().tv_sec as u32;
+
+        Self {
+            mode: (kind.mode_bits() | (mode & 0o7777)) as u16,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            atime: time,
+            ctime: time,
+            mtime: time,
+            dtime: 0,
+            links_count,
+            blocks: 0,
+            flags: 0,
+            block: [0; 15],
+            file_acl: 0,
+            raw: [0; RAW_INODE_SIZE],
+        }
+    }
+
+    pub fn parse(raw: [u8; RAW_INODE_SIZE]) -> Self {
+        let read_u16 = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let read_u32 =
+            |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+
+        let mode = read_u16(0);
+
+        // The high half of the size is only used by regular files, directories used to keep an
+        // ACL there.
+        let size_high = match u32::from(mode) & abi::S_IFMT {
+            abi::S_IFREG => read_u32(108),
+            _ => 0,
+        };
+
+        Self {
+            mode,
+            uid: u32::from(read_u16(2)) | (u32::from(read_u16(120)) << 16),
+            gid: u32::from(read_u16(24)) | (u32::from(read_u16(122)) << 16),
+            size: u64::from(read_u32(4)) | (u64::from(size_high) << 32),
+            atime: read_u32(8),
+            ctime: read_u32(12),
+            mtime: read_u32(16),
+            dtime: read_u32(20),
+            links_count: read_u16(26),
+            blocks: read_u32(28),
+            flags: read_u32(32),
+            block: core::array::from_fn(|index| read_u32(40 + index * 4)),
+            file_acl: read_u32(104),
+            raw,
+        }
+    }
+
+    pub fn encode(&self) -> [u8; RAW_INODE_SIZE] {
+        let mut raw = self.raw;
+
+        let mut write = |offset: usize, bytes: &[u8]| {
+            raw[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+
+        write(0, &self.mode.to_le_bytes());
+        write(2, &(self.uid as u16).to_le_bytes());
+        write(4, &(self.size as u32).to_le_bytes());
+        write(8, &self.atime.to_le_bytes());
+        write(12, &self.ctime.to_le_bytes());
+        write(16, &self.mtime.to_le_bytes());
+        write(20, &self.dtime.to_le_bytes());
+        write(24, &(self.gid as u16).to_le_bytes());
+        write(26, &self.links_count.to_le_bytes());
+        write(28, &self.blocks.to_le_bytes());
+        write(32, &self.flags.to_le_bytes());
+
+        for (index, block) in self.block.iter().enumerate() {
+            write(40 + index * 4, &block.to_le_bytes());
+        }
+
+        write(104, &self.file_acl.to_le_bytes());
+
+        if self.kind() == Some(InodeKind::File) {
+            write(108, &((self.size >> 32) as u32).to_le_bytes());
+        }
+
+        write(120, &((self.uid >> 16) as u16).to_le_bytes());
+        write(122, &((self.gid >> 16) as u16).to_le_bytes());
+
+        raw
+    }
+
+    pub fn kind(&self) -> Option<InodeKind> {
+        match u32::from(self.mode) & abi::S_IFMT {
+            abi::S_IFREG => Some(InodeKind::File),
+            abi::S_IFDIR => Some(InodeKind::Directory),
+            abi::S_IFLNK => Some(InodeKind::Symlink),
+            abi::S_IFCHR => Some(InodeKind::CharDevice),
+            abi::S_IFBLK => Some(InodeKind::BlockDevice),
+            abi::S_IFIFO => Some(InodeKind::Fifo),
+            abi::S_IFSOCK => Some(InodeKind::Socket),
+            _ => None,
+        }
+    }
+
+    /// Whether this is a symlink keeping its target in place of the block pointers.
+    fn is_fast_symlink(&self, block_size: u64) -> bool {
+        let acl_blocks = match self.file_acl {
+            0 => 0,
+            _ => (block_size / 512) as u32,
+        };
+
+        self.kind() == Some(InodeKind::Symlink) && self.blocks == acl_blocks
+    }
+
+    /// The bytes of the block pointers, which hold the target of fast symlinks.
+    fn block_bytes(&self) -> [u8; FAST_SYMLINK_MAX] {
+        let mut bytes = [0u8; FAST_SYMLINK_MAX];
+
+        for (chunk, block) in bytes.chunks_exact_mut(4).zip(self.block.iter()) {
+            chunk.copy_from_slice(&block.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    fn touch(&mut self) {
+        let time = now().tv_sec as u32;
+        self.mtime = time;
+        self.ctime = time;
+    }
+}
+
+impl Ext2Inode {
+    pub fn new(volume: Arc<Volume>, ino: u32, raw: RawInode) -> Arc<Self> {
+        Arc::new(Self {
+            ino,
+            kind: raw.kind().unwrap_or(InodeKind::File),
+            volume,
+            state: Mutex::new(raw),
+        })
+    }
+
+    fn check_writable(&self) -> FsResult<()> {
+        match self.volume.read_only {
+            true => Err(Errno::EROFS),
+            false => Ok(()),
+        }
+    }
+
+    fn max_size(&self) -> u64 {
+        if !self.volume.large_file {
+            return MAX_SMALL_FILE_SIZE;
+        }
+
+        let per_block = self.volume.block_size / 4;
+        let mapped = DIRECT_BLOCKS + per_block + per_block.pow(2) + per_block.pow(3);
+
+        (mapped * self.volume.block_size).min(u64::from(u32::MAX) * 512)
+    }
+
+    /// Returns which block pointer of the inode leads to block `index` of the contents, and the
+    /// indices to follow through the indirect blocks from there.
+    fn block_path(&self, index: u64) -> FsResult<(usize, ArrayVec<u64, 3>)> {
+        let per_block = self.volume.block_size / 4;
+        let mut path = ArrayVec::new();
+
+        if index < DIRECT_BLOCKS {
+            return Ok((index as usize, path));
+        }
+
+        let mut index = index - DIRECT_BLOCKS;
+
+        for (slot, level) in [
+            (INDIRECT_BLOCK, 1),
+            (DOUBLE_INDIRECT_BLOCK, 2),
+            (TRIPLE_INDIRECT_BLOCK, 3),
+        ] {
+            let span = per_block.pow(level);
+
+            if index < span {
+                for depth in (0..level).rev() {
+                    path.push(index / per_block.pow(depth) % per_block);
+                }
+
+                return Ok((slot, path));
+            }
+
+            index -= span;
+        }
+
+        Err(Errno::EFBIG)
+    }
+
+    /// Returns the block holding block `index` of the contents, or zero for a hole.
+    fn lookup_block(&self, raw: &RawInode, index: u64) -> FsResult<u32> {
+        let (slot, path) = self.block_path(index)?;
+        let mut block = raw.block[slot];
+
+        for &offset in &path {
+            if block == 0 {
+                break;
+            }
+
+            block = self.volume.read_pointer(block, offset)?;
+        }
+
+        Ok(block)
+    }
+
+    /// Returns the block holding block `index` of the contents, allocating it and the indirect
+    /// blocks leading to it if needed. The flag says whether the block is new, its contents are
+    /// whatever was on disk.
+    fn map_block(&self, raw: &mut RawInode, index: u64) -> FsResult<(u32, bool)> {
+        let (slot, path) = self.block_path(index)?;
+
+        let mut fresh = false;
+        let mut block = raw.block[slot];
+
+        if block == 0 {
+            block = self.allocate_block(raw, !path.is_empty())?;
+            raw.block[slot] = block;
+            fresh = true;
+        }
+
+        for (depth, &offset) in path.iter().enumerate() {
+            let mut next = match fresh {
+                true => 0,
+                false => self.volume.read_pointer(block, offset)?,
+            };
+
+            fresh = next == 0;
+
+            if fresh {
+                next = self.allocate_block(raw, depth + 1 < path.len())?;
+                self.volume.write_pointer(block, offset, next)?;
+            }
+
+            block = next;
+        }
+
+        Ok((block, fresh))
+    }
+
+    fn allocate_block(&self, raw: &mut RawInode, zero: bool) -> FsResult<u32> {
+        let block = self.volume.allocate_block(self.volume.group_of(self.ino))?;
+
+        raw.blocks += (self.volume.block_size / 512) as u32;
+
+        if zero && let Err(err) = self.volume.zero_block(block) {
+            self.free_block(raw, block)?;
+            return Err(err.into());
+        }
+
+        Ok(block)
+    }
+
+    fn free_block(&self, raw: &mut RawInode, block: u32) -> FsResult<()> {
+        raw.blocks = raw
+            .blocks
+            .saturating_sub((self.volume.block_size / 512) as u32);
+
+        self.volume.free_block(block)
+    }
+
+    /// Frees every block of the contents past the first `keep`, along with the indirect blocks
+    /// that become empty.
+    fn truncate_blocks(&self, raw: &mut RawInode, keep: u64) -> FsResult<()> {
+        for slot in keep.min(DIRECT_BLOCKS)..DIRECT_BLOCKS {
+            let block = core::mem::take(&mut raw.block[slot as usize]);
+            if block != 0 {
+                self.free_block(raw, block)?;
+            }
+        }
+
+        let per_block = self.volume.block_size / 4;
+        let mut start = DIRECT_BLOCKS;
+
+        for (slot, level) in [
+            (INDIRECT_BLOCK, 1),
+            (DOUBLE_INDIRECT_BLOCK, 2),
+            (TRIPLE_INDIRECT_BLOCK, 3),
+        ] {
+            let span = per_block.pow(level);
+            let block = raw.block[slot];
+
+            if keep < start + span && block != 0 {
+                let keep_in_tree = keep.saturating_sub(start);
+                if self.free_tree(raw, block, level, keep_in_tree)? {
+                    raw.block[slot] = 0;
+                }
+            }
+
+            start += span;
+        }
+
+        Ok(())
+    }
+
+    /// Frees the blocks past the first `keep` below the indirect block `block` at `level`.
+    /// Returns whether `block` itself was freed because nothing is kept below it.
+    fn free_tree(&self, raw: &mut RawInode, block: u32, level: u32, keep: u64) -> FsResult<bool> {
+        let per_block = self.volume.block_size / 4;
+        let child_span = per_block.pow(level - 1);
+
+        let mut pointers = alloc::vec![0u8; self.volume.block_size as usize];
+        self.volume
+            .disk
+            .read_at(self.volume.block_offset(block), &mut pointers)?;
+
+        for (index, pointer) in pointers.chunks_exact_mut(4).enumerate() {
+            let child = u32::from_le_bytes((&*pointer).try_into().unwrap());
+            let child_start = index as u64 * child_span;
+
+            if child == 0 || child_start + child_span <= keep {
+                continue;
+            }
+
+            let freed = match level {
+                1 => {
+                    self.free_block(raw, child)?;
+                    true
+                }
+                _ => self.free_tree(raw, child, level - 1, keep.saturating_sub(child_start))?,
+            };
+
+            if freed {
+                pointer.fill(0);
+            }
+        }
+
+        if keep == 0 {
+            self.free_block(raw, block)?;
+            return Ok(true);
+        }
+
+        self.volume
+            .disk
+            .write_at(self.volume.block_offset(block), &pointers)?;
+
+        Ok(false)
+    }
+
+    /// Reads `buf.len()` bytes of the contents at `offset`, where holes read as zeroes.
+    fn read_contents(&self, raw: &RawInode, offset: u64, buf: &mut [u8]) -> FsResult<()> {
+        let block_size = self.volume.block_size;
+        let mut done = 0;
+
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let within = position % block_size;
+            let chunk = (buf.len() - done).min((block_size - within) as usize);
+            let dst = &mut buf[done..done + chunk];
+
+            match self.lookup_block(raw, position / block_size)? {
+                0 => dst.fill(0),
+                block => self
+                    .volume
+                    .disk
+                    .read_at(self.volume.block_offset(block) + within, dst)?,
+            }
+
+            done += chunk;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `data` to the contents at `offset`, allocating blocks as needed. Returns how much
+    /// was written before running out of space, failing only if nothing was.
+    fn write_contents(&self, raw: &mut RawInode, offset: u64, data: &[u8]) -> FsResult<usize> {
+        let block_size = self.volume.block_size;
+        let mut done = 0;
+
+        while done < data.len() {
+            let position = offset + done as u64;
+            let within = position % block_size;
+            let chunk = (data.len() - done).min((block_size - within) as usize);
+
+            let (block, fresh) = match self.map_block(raw, position / block_size) {
+                Ok(mapping) => mapping,
+                Err(_) if done != 0 => break,
+                Err(err) => return Err(err),
+            };
+
+            // The rest of a new block has to read as zeroes.
+            if fresh && chunk as u64 != block_size {
+                self.volume.zero_block(block)?;
+            }
+
+            self.volume.disk.write_at(
+                self.volume.block_offset(block) + within,
+                &data[done..done + chunk],
+            )?;
+
+            done += chunk;
+        }
+
+        Ok(done)
+    }
+
+    fn read_records(&self, raw: &RawInode) -> FsResult<(Vec<u8>, Vec<dir::Record>)> {
+        if self.kind != InodeKind::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+
+        let mut data = alloc::vec![0u8; raw.size as usize];
+        self.read_contents(raw, 0, &mut data)?;
+
+        let records = dir::records(&data, self.volume.block_size as usize)?;
+        Ok((data, records))
+    }
+
+    /// Writes back the block of the directory contents `data` that contains `offset`.
+    fn write_dir_block(&self, raw: &mut RawInode, data: &[u8], offset: usize) -> FsResult<()> {
+        let block_size = self.volume.block_size as usize;
+        let start = offset / block_size * block_size;
+
+        self.write_contents(raw, start as u64, &data[start..start + block_size])?;
+        Ok(())
+    }
+
+    fn add_entry(&self, raw: &mut RawInode, name: &str, ino: u32, kind: InodeKind) -> FsResult<()> {
+        let (mut data, records) = self.read_records(raw)?;
+        let needed = dir::entry_len(name.len());
+        let file_type = match self.volume.filetype {
+            true => dir::file_type(kind),
+            false => 0,
+        };
+
+        // The hashed index would not know about the new entry.
+        raw.flags &= !FLAG_INDEX;
+        raw.touch();
+
+        if let Some(record) = records.iter().find(|record| record.slack() >= needed) {
+            let (offset, rec_len) = match record.ino {
+                0 => (record.offset, record.rec_len),
+                _ => {
+                    let used = dir::entry_len(record.name.len());
+                    dir::set_rec_len(&mut data[record.offset..], used);
+                    (record.offset + used, record.rec_len - used)
+                }
+            };
+
+            dir::encode(&mut data[offset..], ino, rec_len, name, file_type);
+            self.write_dir_block(raw, &data, offset)?;
+
+            return self.volume.write_inode(self.ino, raw);
+        }
+
+        let block_size = self.volume.block_size as usize;
+        let mut block = alloc::vec![0u8; block_size];
+        dir::encode(&mut block, ino, block_size, name, file_type);
+
+        let offset = raw.size;
+        if self.write_contents(raw, offset, &block)? != block.len() {
+            return Err(Errno::ENOSPC);
+        }
+
+        raw.size += block.len() as u64;
+        self.volume.write_inode(self.ino, raw)
+    }
+
+    fn remove_entry(
+        &self,
+        raw: &mut RawInode,
+        data: &mut [u8],
+        record: &dir::Record,
+    ) -> FsResult<()> {
+        match record.previous {
+            Some(previous) => {
+                let rec_len = u16::from_le_bytes([data[previous + 4], data[previous + 5]]);
+                dir::set_rec_len(&mut data[previous..], rec_len as usize + record.rec_len);
+            }
+            None => dir::clear_ino(&mut data[record.offset..]),
+        }
+
+        self.write_dir_block(raw, data, record.offset)?;
+
+        raw.flags &= !FLAG_INDEX;
+        raw.touch();
+        self.volume.write_inode(self.ino, raw)
+    }
+
+    /// Finds the entry called `name`, which `.` and `..` do not count as.
+    fn find<'a>(&self, records: &'a [dir::Record], name: &str) -> FsResult<&'a dir::Record> {
+        records
+            .iter()
+            .find(|record| record.ino != 0 && !record.is_dot() && record.name == name)
+            .ok_or(Errno::ENOENT)
+    }
+
+    /// Creates a new inode of `kind` and links it into this directory as `name`, with `init`
+    /// filling in the contents.
+    fn create_inode(
+        &self,
+        name: &str,
+        kind: InodeKind,
+        mode: u32,
+        init: impl FnOnce(&Ext2Inode, &mut RawInode) -> FsResult<()>,
+    ) -> FsResult<Arc<dyn Inode>> {
+        if self.kind != InodeKind::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+
+        self.check_writable()?;
+
+        if name.len() > dir::MAX_NAME_LEN {
+            return Err(Errno::ENAMETOOLONG);
+        }
+
+        let mut state = self.state.lock();
+        let (_, records) = self.read_records(&state)?;
+
+        if records
+            .iter()
+            .any(|record| record.ino != 0 && record.name == name)
+        {
+            return Err(Errno::EEXIST);
+        }
+
+        let directory = kind == InodeKind::Directory;
+        let ino = self
+            .volume
+            .allocate_inode(self.volume.group_of(self.ino), directory)?;
+
+        let links_count = if directory { 2 } else { 1 };
+        let inode = Ext2Inode::new(
+            self.volume.clone(),
+            ino,
+            RawInode::new(kind, mode, links_count),
+        );
+
+        let result = {
+            let mut raw = inode.state.lock();
+
+            self.volume
+                .clear_inode(ino)
+                .and_then(|_| init(&inode, &mut raw))
+                .and_then(|_| self.volume.write_inode(ino, &raw))
+        };
+
+        if let Err(err) = result.and_then(|_| self.add_entry(&mut state, name, ino, kind)) {
+            // Dropping the inode with no links frees it again.
+            inode.state.lock().links_count = 0;
+            return Err(err);
+        }
+
+        if directory {
+            state.links_count += 1;
+            self.volume.write_inode(self.ino, &state)?;
+        }
+
+        self.volume.insert_inode(ino, &inode);
+        Ok(inode)
+    }
+
+    /// Frees the blocks and the inode itself once the last link is gone.
+    fn release(&self, raw: &mut RawInode) -> FsResult<()> {
+        if !raw.is_fast_symlink(self.volume.block_size) {
+            self.truncate_blocks(raw, 0)?;
+        }
+
+        raw.size = 0;
+        raw.dtime = now().tv_sec as u32;
+        self.volume.write_inode(self.ino, raw)?;
+
+        self.volume
+            .free_inode(self.ino, self.kind == InodeKind::Directory)
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn metadata(&self) -> FsResult<Metadata> {
+        let raw = self.state.lock();
+        let time = |seconds: u32| Timespec {
+            tv_sec: i64::from(seconds),
+            tv_nsec: 0,
+        };
+
+        Ok(Metadata {
+            dev: 0,
+            ino: u64::from(self.ino),
+            kind: self.kind,
+            mode: u32::from(raw.mode) & 0o7777,
+            nlink: u32::from(raw.links_count),
+            uid: raw.uid,
+            gid: raw.gid,
+            rdev: 0,
+            size: raw.size,
+            block_size: self.volume.block_size as u32,
+            blocks: u64::from(raw.blocks),
+            atime: time(raw.atime),
+            mtime: time(raw.mtime),
+            ctime: time(raw.ctime),
+        })
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        let raw = self.state.lock();
+        let (_, records) = self.read_records(&raw)?;
+        let record = self.find(&records, name)?;
+
+        Ok(self.volume.inode(record.ino)?)
+    }
+
+    fn read_dir(&self, index: usize) -> FsResult<Option<DirEntry>> {
+        let raw = self.state.lock();
+        let (_, records) = self.read_records(&raw)?;
+
+        let Some(record) = records
+            .iter()
+            .filter(|record| record.ino != 0 && !record.is_dot())
+            .nth(index)
+        else {
+            return Ok(None);
+        };
+
+        let kind = match (self.volume.filetype, record.file_type) {
+            (true, 1) => InodeKind::File,
+            (true, 2) => InodeKind::Directory,
+            (true, 3) => InodeKind::CharDevice,
+            (true, 4) => InodeKind::BlockDevice,
+            (true, 5) => InodeKind::Fifo,
+            (true, 6) => InodeKind::Socket,
+            (true, 7) => InodeKind::Symlink,
+            _ => self.volume.inode(record.ino)?.kind,
+        };
+
+        Ok(Some(DirEntry {
+            ino: u64::from(record.ino),
+            kind,
+            name: record.name.clone(),
+        }))
+    }
+
+    fn create(&self, name: &str, kind: InodeKind, mode: u32) -> FsResult<Arc<dyn Inode>> {
+        match kind {
+            InodeKind::File => self.create_inode(name, kind, mode, |_, _| Ok(())),
+            InodeKind::Directory => self.create_inode(name, kind, mode, |inode, raw| {
+                let parent = self.ino;
+                let block_size = self.volume.block_size as usize;
+                let file_type = |kind| match self.volume.filetype {
+                    true => dir::file_type(kind),
+                    false => 0,
+                };
+
+                let mut block = alloc::vec![0u8; block_size];
+                let dot_len = dir::entry_len(1);
+                dir::encode(
+                    &mut block,
+                    inode.ino,
+                    dot_len,
+                    ".",
+                    file_type(InodeKind::Directory),
+                );
+                dir::encode(
+                    &mut block[dot_len..],
+                    parent,
+                    block_size - dot_len,
+                    "..",
+                    file_type(InodeKind::Directory),
+                );
+
+                if inode.write_contents(raw, 0, &block)? != block_size {
+                    return Err(Errno::ENOSPC);
+                }
+
+                raw.size = block_size as u64;
+                Ok(())
+            }),
+            _ => Err(Errno::EPERM),
+        }
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> FsResult<Arc<dyn Inode>> {
+        self.create_inode(name, InodeKind::Symlink, 0o777, |inode, raw| {
+            let bytes = target.as_bytes();
+
+            if bytes.len() < FAST_SYMLINK_MAX {
+                let mut padded = [0u8; FAST_SYMLINK_MAX];
+                padded[..bytes.len()].copy_from_slice(bytes);
+
+                for (block, chunk) in raw.block.iter_mut().zip(padded.chunks_exact(4)) {
+                    *block = u32::from_le_bytes(chunk.try_into().unwrap());
+                }
+            } else if inode.write_contents(raw, 0, bytes)? != bytes.len() {
+                return Err(Errno::ENOSPC);
+            }
+
+            raw.size = bytes.len() as u64;
+            Ok(())
+        })
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()> {
+        self.check_writable()?;
+
+        let mut state = self.state.lock();
+        let (mut data, records) = self.read_records(&state)?;
+        let record = self.find(&records, name)?;
+
+        let inode = self.volume.inode(record.ino)?;
+        if inode.kind == InodeKind::Directory {
+            return Err(Errno::EISDIR);
+        }
+
+        self.remove_entry(&mut state, &mut data, record)?;
+
+        let mut raw = inode.state.lock();
+        raw.links_count = raw.links_count.saturating_sub(1);
+        raw.ctime = now().tv_sec as u32;
+        self.volume.write_inode(inode.ino, &raw)?;
+        drop(raw);
+
+        // The inode is freed here unless someone still has it open.
+        drop(inode);
+        Ok(())
+    }
+
+    fn rmdir(&self, name: &str) -> FsResult<()> {
+        self.check_writable()?;
+
+        let mut state = self.state.lock();
+        let (mut data, records) = self.read_records(&state)?;
+        let record = self.find(&records, name)?;
+
+        let inode = self.volume.inode(record.ino)?;
+        if inode.kind != InodeKind::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+
+        let mut raw = inode.state.lock();
+        let (_, children) = inode.read_records(&raw)?;
+        if children
+            .iter()
+            .any(|child| child.ino != 0 && !child.is_dot())
+        {
+            return Err(Errno::ENOTEMPTY);
+        }
+
+        self.remove_entry(&mut state, &mut data, record)?;
+
+        raw.links_count = 0;
+        raw.ctime = now().tv_sec as u32;
+        self.volume.write_inode(inode.ino, &raw)?;
+        drop(raw);
+
+        // The `..` entry of the removed directory no longer links back here.
+        state.links_count = state.links_count.saturating_sub(1);
+        self.volume.write_inode(self.ino, &state)?;
+
+        drop(inode);
+        Ok(())
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        if self.kind != InodeKind::File {
+            return Err(Errno::EISDIR);
+        }
+
+        let raw = self.state.lock();
+        if offset >= raw.size {
+            return Ok(0);
+        }
+
+        let len = buf.len().min((raw.size - offset) as usize);
+        self.read_contents(&raw, offset, &mut buf[..len])?;
+
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        if self.kind != InodeKind::File {
+            return Err(Errno::EISDIR);
+        }
+
+        self.check_writable()?;
+
+        match offset.checked_add(buf.len() as u64) {
+            Some(end) if end <= self.max_size() => {}
+            _ => return Err(Errno::EFBIG),
+        }
+
+        let mut raw = self.state.lock();
+        let written = self.write_contents(&mut raw, offset, buf);
+
+        if let Ok(len) = written {
+            raw.size = raw.size.max(offset + len as u64);
+            raw.touch();
+        }
+
+        // Blocks may have been allocated even if the write failed.
+        self.volume.write_inode(self.ino, &raw)?;
+        written
+    }
+
+    fn truncate(&self, new_size: u64) -> FsResult<()> {
+        if self.kind != InodeKind::File {
+            return Err(Errno::EISDIR);
+        }
+
+        self.check_writable()?;
+
+        if new_size > self.max_size() {
+            return Err(Errno::EFBIG);
+        }
+
+        let mut raw = self.state.lock();
+        let block_size = self.volume.block_size;
+
+        if new_size < raw.size {
+            self.truncate_blocks(&mut raw, new_size.div_ceil(block_size))?;
+
+            // Clear the rest of the last block, so that growing the file again reads zeroes.
+            let within = new_size % block_size;
+            if within != 0 {
+                let block = self.lookup_block(&raw, new_size / block_size)?;
+
+                if block != 0 {
+                    let zeroes = alloc::vec![0u8; (block_size - within) as usize];
+                    self.volume
+                        .disk
+                        .write_at(self.volume.block_offset(block) + within, &zeroes)?;
+                }
+            }
+        }
+
+        raw.size = new_size;
+        raw.touch();
+        self.volume.write_inode(self.ino, &raw)
+    }
+
+    fn read_link(&self) -> FsResult<String> {
+        if self.kind != InodeKind::Symlink {
+            return Err(Errno::EINVAL);
+        }
+
+        let raw = self.state.lock();
+        let len = raw.size as usize;
+
+        let target = match raw.is_fast_symlink(self.volume.block_size) {
+            true => raw.block_bytes().get(..len).ok_or(Errno::EIO)?.to_vec(),
+            false => {
+                let mut target = alloc::vec![0u8; len];
+                self.read_contents(&raw, 0, &mut target)?;
+                target
+            }
+        };
+
+        String::from_utf8(target).map_err(|_| Errno::EINVAL)
+    }
+}
+
+impl Drop for Ext2Inode {
+    fn drop(&mut self) {
+        if self.volume.read_only || self.state.get_mut().links_count != 0 {
+            return;
+        }
+
+        let mut raw = core::mem::replace(self.state.get_mut(), RawInode::new(self.kind, 0, 0));
+
+        if let Err(err) = self.release(&mut raw) {
+            log::error!(
+                "{}: failed to free inode {}: {:?}",
+                self.volume.disk.name(),
+                self.ino,
+                err
+            );
+        }
+    }
+}
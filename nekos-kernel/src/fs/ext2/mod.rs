@@ -0,0 +1,556 @@
+//! The second extended file system.
+//!
+//! The disk is split into block groups, each with a bitmap of its blocks and inodes and a table
+//! of the inodes themselves. Inodes point to their data through twelve direct blocks and a
+//! single, double and triple indirect block.
+//!
+//! Only ext2 proper is supported. Volumes using incompatible features of ext3 and ext4 are
+//! refused, those with features that would have to be kept up to date on writes are mounted
+//! read-only.
+
+mod dir;
+mod inode;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use nekos_abi::Errno;
+use spin::Mutex;
+use ubyte::ToByteUnit;
+
+use super::{FileSystem, FsResult, Inode, now};
+use crate::block::{BlockError, Disk};
+use crate::log;
+
+use inode::{Ext2Inode, RawInode};
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const MAGIC: u16 = 0xef53;
+
+const ROOT_INO: u32 = 2;
+const GROUP_DESCRIPTOR_SIZE: u64 = 32;
+
+/// `s_state` of a volume that was unmounted cleanly, and of one with errors.
+const STATE_VALID: u16 = 0x1;
+const STATE_ERRORS: u16 = 0x2;
+
+const INCOMPAT_FILETYPE: u32 = 0x2;
+const INCOMPAT_FLEX_BG: u32 = 0x200;
+const SUPPORTED_INCOMPAT: u32 = INCOMPAT_FILETYPE | INCOMPAT_FLEX_BG;
+
+const RO_COMPAT_SPARSE_SUPER: u32 = 0x1;
+const RO_COMPAT_LARGE_FILE: u32 = 0x2;
+const RO_COMPAT_EXTRA_ISIZE: u32 = 0x40;
+const SUPPORTED_RO_COMPAT: u32 =
+    RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE | RO_COMPAT_EXTRA_ISIZE;
+
+const INCOMPAT_NAMES: &[(u32, &str)] = &[
+    (0x1, "compression"),
+    (0x4, "needs_recovery"),
+    (0x8, "journal_dev"),
+    (0x10, "meta_bg"),
+    (0x40, "extent"),
+    (0x80, "64bit"),
+    (0x100, "mmp"),
+    (0x400, "ea_inode"),
+    (0x1000, "dirdata"),
+    (0x2000, "metadata_csum_seed"),
+    (0x4000, "large_dir"),
+    (0x8000, "inline_data"),
+    (0x10000, "encrypt"),
+    (0x20000, "casefold"),
+];
+
+const RO_COMPAT_NAMES: &[(u32, &str)] = &[
+    (0x4, "btree_dir"),
+    (0x8, "huge_file"),
+    (0x10, "uninit_bg"),
+    (0x20, "dir_nlink"),
+    (0x80, "has_snapshot"),
+    (0x100, "quota"),
+    (0x200, "bigalloc"),
+    (0x400, "metadata_csum"),
+    (0x1000, "project"),
+    (0x2000, "verity"),
+];
+
+pub struct Ext2 {
+    volume: Arc<Volume>,
+    root: Arc<Ext2Inode>,
+}
+
+struct Volume {
+    disk: Arc<Disk>,
+    read_only: bool,
+    block_size: u64,
+    first_data_block: u32,
+    blocks_count: u32,
+    blocks_per_group: u32,
+    inodes_per_group: u32,
+    inode_size: u64,
+    /// The first inode that is not reserved.
+    first_ino: u32,
+    /// Whether directory entries carry the type of their inode.
+    filetype: bool,
+    /// Whether files may be larger than 2 GiB.
+    large_file: bool,
+    allocator: Mutex<Allocator>,
+    /// The inodes in use by their number.
+    inodes: Mutex<BTreeMap<u32, Weak<Ext2Inode>>>,
+}
+
+struct Allocator {
+    groups: Vec<Group>,
+    free_blocks: u32,
+    free_inodes: u32,
+}
+
+struct Group {
+    block_bitmap: u32,
+    inode_bitmap: u32,
+    inode_table: u32,
+    free_blocks: u16,
+    free_inodes: u16,
+    used_dirs: u16,
+}
+
+impl Ext2 {
+    /// Whether `disk` holds an ext2 volume, going by the magic number of its superblock.
+    pub fn probe(disk: &Disk) -> bool {
+        let mut magic = [0u8; 2];
+        disk.read_at(SUPERBLOCK_OFFSET + 56, &mut magic).is_ok()
+            && u16::from_le_bytes(magic) == MAGIC
+    }
+
+    /// Mounts the ext2 volume on `disk`.
+    pub fn new(disk: Arc<Disk>) -> FsResult<Arc<Self>> {
+        let mut superblock = [0u8; SUPERBLOCK_SIZE];
+        disk.read_at(SUPERBLOCK_OFFSET, &mut superblock)?;
+
+        let read_u16 =
+            |offset: usize| u16::from_le_bytes([superblock[offset], superblock[offset + 1]]);
+        let read_u32 =
+            |offset: usize| u32::from_le_bytes(superblock[offset..offset + 4].try_into().unwrap());
+
+        if read_u16(56) != MAGIC {
+            return Err(Errno::EINVAL);
+        }
+
+        let revision = read_u32(76);
+        let (first_ino, inode_size, incompat, ro_compat) = match revision {
+            0 => (11, 128, 0, 0),
+            _ => (
+                read_u32(84),
+                read_u16(88) as u64,
+                read_u32(96),
+                read_u32(100),
+            ),
+        };
+
+        let unsupported = incompat & !SUPPORTED_INCOMPAT;
+        if unsupported != 0 {
+            log::warn!(
+                "Refusing to mount {}, it uses the unsupported features {}.",
+                disk.name(),
+                feature_names(unsupported, INCOMPAT_NAMES)
+            );
+
+            return Err(Errno::EINVAL);
+        }
+
+        let log_block_size = read_u32(24);
+        if log_block_size > 6 {
+            return Err(Errno::EINVAL);
+        }
+
+        let block_size = 1024u64 << log_block_size;
+        let blocks_count = read_u32(4);
+        let first_data_block = read_u32(20);
+        let blocks_per_group = read_u32(32);
+        let inodes_per_group = read_u32(40);
+
+        if blocks_per_group == 0
+            || blocks_per_group as u64 > block_size * 8
+            || inodes_per_group == 0
+            || inodes_per_group as u64 > block_size * 8
+            || inode_size < 128
+            || inode_size > block_size
+            || !inode_size.is_power_of_two()
+            || blocks_count as u64 * block_size > disk.size()
+            || first_data_block >= blocks_count
+        {
+            return Err(Errno::EINVAL);
+        }
+
+        let mut read_only = disk.is_read_only();
+
+        let unsupported = ro_compat & !SUPPORTED_RO_COMPAT;
+        if unsupported != 0 && !read_only {
+            log::warn!(
+                "Mounting {} read-only, it uses the features {} that can not be written.",
+                disk.name(),
+                feature_names(unsupported, RO_COMPAT_NAMES)
+            );
+
+            read_only = true;
+        }
+
+        let state = read_u16(58);
+        if (state & STATE_VALID == 0 || state & STATE_ERRORS != 0) && !read_only {
+            log::warn!(
+                "Mounting {} read-only, it was not unmounted cleanly or has errors.",
+                disk.name()
+            );
+
+            read_only = true;
+        }
+
+        let group_count = (blocks_count - first_data_block).div_ceil(blocks_per_group);
+        let mut descriptors =
+            alloc::vec![0u8; (group_count as u64 * GROUP_DESCRIPTOR_SIZE) as usize];
+        let descriptors_offset = (first_data_block as u64 + 1) * block_size;
+        disk.read_at(descriptors_offset, &mut descriptors)?;
+
+        let groups = descriptors
+            .chunks_exact(GROUP_DESCRIPTOR_SIZE as usize)
+            .map(|descriptor| {
+                let read_u16 = |offset: usize| {
+                    u16::from_le_bytes([descriptor[offset], descriptor[offset + 1]])
+                };
+                let read_u32 = |offset: usize| {
+                    u32::from_le_bytes(descriptor[offset..offset + 4].try_into().unwrap())
+                };
+
+                Group {
+                    block_bitmap: read_u32(0),
+                    inode_bitmap: read_u32(4),
+                    inode_table: read_u32(8),
+                    free_blocks: read_u16(12),
+                    free_inodes: read_u16(14),
+                    used_dirs: read_u16(16),
+                }
+            })
+            .collect();
+
+        let volume = Arc::new(Volume {
+            disk,
+            read_only,
+            block_size,
+            first_data_block,
+            blocks_count,
+            blocks_per_group,
+            inodes_per_group,
+            inode_size,
+            first_ino,
+            filetype: incompat & INCOMPAT_FILETYPE != 0,
+            large_file: ro_compat & RO_COMPAT_LARGE_FILE != 0,
+            allocator: Mutex::new(Allocator {
+                groups,
+                free_blocks: read_u32(12),
+                free_inodes: read_u32(16),
+            }),
+            inodes: Mutex::new(BTreeMap::new()),
+        });
+
+        let name = &superblock[120..136];
+        let name_len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+
+        log::info!(
+            "Found ext2 volume {:?} on {} with {} blocks of {}.",
+            core::str::from_utf8(&name[..name_len]).unwrap_or(""),
+            volume.disk.name(),
+            blocks_count,
+            block_size.bytes()
+        );
+
+        let root = volume.inode(ROOT_INO)?;
+        Ok(Arc::new(Self { volume, root }))
+    }
+}
+
+impl FileSystem for Ext2 {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        if self.volume.read_only {
+            return Ok(());
+        }
+
+        // The group descriptors are written as they change, only the totals in the superblock
+        // are left.
+        let allocator = self.volume.allocator.lock();
+
+        let mut counts = [0u8; 8];
+        counts[..4].copy_from_slice(&allocator.free_blocks.to_le_bytes());
+        counts[4..].copy_from_slice(&allocator.free_inodes.to_le_bytes());
+
+        let disk = &self.volume.disk;
+        disk.write_at(SUPERBLOCK_OFFSET + 12, &counts)?;
+        disk.write_at(SUPERBLOCK_OFFSET + 48, &(now().tv_sec as u32).to_le_bytes())?;
+
+        drop(allocator);
+        Ok(disk.flush()?)
+    }
+}
+
+impl Volume {
+    /// Returns the inode `ino`, reading it from disk if it is not in use yet.
+    fn inode(self: &Arc<Self>, ino: u32) -> FsResult<Arc<Ext2Inode>> {
+        let mut inodes = self.inodes.lock();
+
+        if let Some(inode) = inodes.get(&ino).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+
+        let raw = self.read_inode(ino)?;
+        if raw.links_count == 0 {
+            return Err(Errno::EIO);
+        }
+
+        let inode = Ext2Inode::new(self.clone(), ino, raw);
+
+        inodes.retain(|_, inode| inode.strong_count() != 0);
+        inodes.insert(ino, Arc::downgrade(&inode));
+
+        Ok(inode)
+    }
+
+    /// Makes a newly allocated inode available to [`Volume::inode`].
+    fn insert_inode(&self, ino: u32, inode: &Arc<Ext2Inode>) {
+        self.inodes.lock().insert(ino, Arc::downgrade(inode));
+    }
+
+    fn inode_offset(&self, ino: u32) -> FsResult<u64> {
+        let index = ino.checked_sub(1).ok_or(Errno::EIO)?;
+        let group = (index / self.inodes_per_group) as usize;
+        let allocator = self.allocator.lock();
+        let table = allocator.groups.get(group).ok_or(Errno::EIO)?.inode_table;
+
+        Ok(table as u64 * self.block_size
+            + (index % self.inodes_per_group) as u64 * self.inode_size)
+    }
+
+    fn read_inode(&self, ino: u32) -> FsResult<RawInode> {
+        let mut data = [0u8; inode::RAW_INODE_SIZE];
+        self.disk.read_at(self.inode_offset(ino)?, &mut data)?;
+        Ok(RawInode::parse(data))
+    }
+
+    /// Zeroes the whole of a newly allocated inode, including the fields past the ones that are
+    /// read.
+    fn clear_inode(&self, ino: u32) -> FsResult<()> {
+        let zeroes = alloc::vec![0u8; self.inode_size as usize];
+        Ok(self.disk.write_at(self.inode_offset(ino)?, &zeroes)?)
+    }
+
+    fn write_inode(&self, ino: u32, raw: &RawInode) -> FsResult<()> {
+        Ok(self.disk.write_at(self.inode_offset(ino)?, &raw.encode())?)
+    }
+
+    fn block_offset(&self, block: u32) -> u64 {
+        block as u64 * self.block_size
+    }
+
+    /// Reads the pointer at `index` of the indirect block `block`.
+    fn read_pointer(&self, block: u32, index: u64) -> FsResult<u32> {
+        let mut pointer = [0u8; 4];
+        self.disk
+            .read_at(self.block_offset(block) + index * 4, &mut pointer)?;
+        Ok(u32::from_le_bytes(pointer))
+    }
+
+    fn write_pointer(&self, block: u32, index: u64, pointer: u32) -> FsResult<()> {
+        let offset = self.block_offset(block) + index * 4;
+        Ok(self.disk.write_at(offset, &pointer.to_le_bytes())?)
+    }
+
+    fn zero_block(&self, block: u32) -> Result<(), BlockError> {
+        let zeroes = alloc::vec![0u8; self.block_size as usize];
+        self.disk.write_at(self.block_offset(block), &zeroes)
+    }
+
+    /// The number of blocks in `group`, which is less than usual for the last one.
+    fn blocks_in_group(&self, group: usize) -> u32 {
+        let start = group as u32 * self.blocks_per_group;
+        (self.blocks_count - self.first_data_block - start).min(self.blocks_per_group)
+    }
+
+    /// Allocates a block, preferably in `goal`, the group of the inode it belongs to.
+    fn allocate_block(&self, goal: usize) -> FsResult<u32> {
+        let mut allocator = self.allocator.lock();
+        let group_count = allocator.groups.len();
+
+        for group in (0..group_count).map(|index| (goal + index) % group_count) {
+            if allocator.groups[group].free_blocks == 0 {
+                continue;
+            }
+
+            let bitmap = allocator.groups[group].block_bitmap;
+            let Some(bit) = self.take_bit(bitmap, self.blocks_in_group(group))? else {
+                continue;
+            };
+
+            allocator.groups[group].free_blocks -= 1;
+            allocator.free_blocks = allocator.free_blocks.saturating_sub(1);
+            self.write_group(&allocator, group)?;
+
+            return Ok(self.first_data_block + group as u32 * self.blocks_per_group + bit);
+        }
+
+        Err(Errno::ENOSPC)
+    }
+
+    fn free_block(&self, block: u32) -> FsResult<()> {
+        let index = block.checked_sub(self.first_data_block).ok_or(Errno::EIO)?;
+        let group = (index / self.blocks_per_group) as usize;
+
+        let mut allocator = self.allocator.lock();
+        let bitmap = allocator.groups.get(group).ok_or(Errno::EIO)?.block_bitmap;
+        self.clear_bit(bitmap, index % self.blocks_per_group)?;
+
+        allocator.groups[group].free_blocks += 1;
+        allocator.free_blocks += 1;
+        self.write_group(&allocator, group)
+    }
+
+    /// Allocates an inode, preferably in `goal`, the group of its parent directory.
+    fn allocate_inode(&self, goal: usize, directory: bool) -> FsResult<u32> {
+        let mut allocator = self.allocator.lock();
+        let group_count = allocator.groups.len();
+
+        for group in (0..group_count).map(|index| (goal + index) % group_count) {
+            if allocator.groups[group].free_inodes == 0 {
+                continue;
+            }
+
+            let bitmap = allocator.groups[group].inode_bitmap;
+            let Some(bit) = self.take_bit(bitmap, self.inodes_per_group)? else {
+                continue;
+            };
+
+            let ino = group as u32 * self.inodes_per_group + bit + 1;
+
+            // The reserved inodes are marked used by mke2fs, this is just to be safe.
+            if ino < self.first_ino {
+                continue;
+            }
+
+            let group_state = &mut allocator.groups[group];
+            group_state.free_inodes -= 1;
+            if directory {
+                group_state.used_dirs += 1;
+            }
+
+            allocator.free_inodes = allocator.free_inodes.saturating_sub(1);
+            self.write_group(&allocator, group)?;
+
+            return Ok(ino);
+        }
+
+        Err(Errno::ENOSPC)
+    }
+
+    fn free_inode(&self, ino: u32, directory: bool) -> FsResult<()> {
+        let index = ino.checked_sub(1).ok_or(Errno::EIO)?;
+        let group = (index / self.inodes_per_group) as usize;
+
+        let mut allocator = self.allocator.lock();
+        let bitmap = allocator.groups.get(group).ok_or(Errno::EIO)?.inode_bitmap;
+        self.clear_bit(bitmap, index % self.inodes_per_group)?;
+
+        let group_state = &mut allocator.groups[group];
+        group_state.free_inodes += 1;
+        if directory {
+            group_state.used_dirs = group_state.used_dirs.saturating_sub(1);
+        }
+
+        allocator.free_inodes += 1;
+        self.write_group(&allocator, group)
+    }
+
+    /// Sets the first clear bit among the first `count` of the bitmap in `block` and returns it.
+    fn take_bit(&self, block: u32, count: u32) -> FsResult<Option<u32>> {
+        let mut bitmap = alloc::vec![0u8; self.block_size as usize];
+        self.disk.read_at(self.block_offset(block), &mut bitmap)?;
+
+        let Some(bit) = (0..count).find(|&bit| bitmap[bit as usize / 8] & (1 << (bit % 8)) == 0)
+        else {
+            return Ok(None);
+        };
+
+        let byte = bitmap[bit as usize / 8] | (1 << (bit % 8));
+        self.disk
+            .write_at(self.block_offset(block) + bit as u64 / 8, &[byte])?;
+
+        Ok(Some(bit))
+    }
+
+    fn clear_bit(&self, block: u32, bit: u32) -> FsResult<()> {
+        let offset = self.block_offset(block) + bit as u64 / 8;
+
+        let mut byte = [0u8];
+        self.disk.read_at(offset, &mut byte)?;
+
+        if byte[0] & (1 << (bit % 8)) == 0 {
+            log::error!(
+                "{}: freeing bit {} of block {} twice.",
+                self.disk.name(),
+                bit,
+                block
+            );
+        }
+
+        byte[0] &= !(1 << (bit % 8));
+        Ok(self.disk.write_at(offset, &byte)?)
+    }
+
+    /// Writes the counters of `group` back into its descriptor.
+    fn write_group(&self, allocator: &Allocator, group: usize) -> FsResult<()> {
+        let state = &allocator.groups[group];
+
+        let mut counts = [0u8; 6];
+        counts[0..2].copy_from_slice(&state.free_blocks.to_le_bytes());
+        counts[2..4].copy_from_slice(&state.free_inodes.to_le_bytes());
+        counts[4..6].copy_from_slice(&state.used_dirs.to_le_bytes());
+
+        let offset = (self.first_data_block as u64 + 1) * self.block_size
+            + group as u64 * GROUP_DESCRIPTOR_SIZE
+            + 12;
+
+        Ok(self.disk.write_at(offset, &counts)?)
+    }
+
+    fn group_of(&self, ino: u32) -> usize {
+        ((ino - 1) / self.inodes_per_group) as usize
+    }
+}
+
+/// Lists the names of the features in `bits`.
+fn feature_names(bits: u32, names: &[(u32, &str)]) -> String {
+    let mut list = String::new();
+
+    for bit in (0..32)
+        .map(|shift| 1 << shift)
+        .filter(|bit| bits & bit != 0)
+    {
+        if !list.is_empty() {
+            list.push_str(", ");
+        }
+
+        match names.iter().find(|(value, _)| *value == bit) {
+            Some((_, name)) => list.push_str(name),
+            None => list.push_str(&alloc::format!("{:#x}", bit)),
+        }
+    }
+
+    list
+}
@@ -0,0 +1,230 @@
+//! Consistency checks run when a volume is mounted, along the lines of `fsck.fat -n`.
+//!
+//! Nothing is repaired. Problems that would get worse by writing to the volume are counted as
+//! errors, others are only reported.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::dir;
+use super::table::{Entry, FIRST_CLUSTER, FatKind};
+use super::{Contents, RootDir, Volume};
+use crate::log;
+
+/// The bits of the second FAT entry that say the volume was unmounted cleanly, and that no I/O
+/// errors were seen.
+const FAT16_CLEAN: u32 = 0x8000;
+const FAT16_NO_ERRORS: u32 = 0x4000;
+const FAT32_CLEAN: u32 = 0x0800_0000;
+const FAT32_NO_ERRORS: u32 = 0x0400_0000;
+
+struct Checker<'a> {
+    volume: &'a Volume,
+    /// The clusters that belong to some file or directory, one bit each.
+    used: Vec<u64>,
+    errors: usize,
+}
+
+/// Checks the table and the directory tree of `volume`, logs every problem found and returns how
+/// many of them are errors.
+pub fn check(volume: &Volume) -> usize {
+    let table = volume.table.lock();
+    let mut checker = Checker {
+        volume,
+        used: alloc::vec![0; (table.cluster_count() as usize + FIRST_CLUSTER as usize).div_ceil(64)],
+        errors: 0,
+    };
+    drop(table);
+
+    checker.check_copies();
+    checker.check_reserved();
+
+    // The directories that remain to be checked, by path and contents.
+    let mut directories: Vec<(String, Option<Vec<u32>>)> = Vec::new();
+
+    match volume.root_dir {
+        RootDir::Fixed { .. } => directories.push((String::new(), None)),
+        RootDir::Cluster(cluster) => {
+            if let Some(clusters) = checker.walk("/", cluster) {
+                directories.push((String::new(), Some(clusters)));
+            }
+        }
+    }
+
+    while let Some((path, clusters)) = directories.pop() {
+        let contents = match (&clusters, &volume.root_dir) {
+            (Some(clusters), _) => Contents::Clusters(clusters),
+            (None, &RootDir::Fixed { offset, size }) => Contents::Fixed { offset, size },
+            (None, RootDir::Cluster(_)) => unreachable!(),
+        };
+
+        let size = match contents {
+            Contents::Fixed { size, .. } => size,
+            Contents::Clusters(clusters) => clusters.len() as u64 * volume.cluster_size,
+        };
+
+        let mut data = alloc::vec![0u8; size as usize];
+        if volume.read(contents, 0, &mut data).is_err() {
+            checker.error(&path, "can not be read");
+            continue;
+        }
+
+        let records = dir::records(&data);
+
+        if clusters.is_some() && !path.is_empty() {
+            let dots = records
+                .iter()
+                .take(2)
+                .filter(|record| record.is_dot())
+                .count();
+            if dots != 2 {
+                checker.error(&path, "lacks the . and .. entries");
+            }
+        }
+
+        for record in records.iter().filter(|record| !record.is_dot()) {
+            let path = format!("{}/{}", path, record.name);
+
+            if record.first_cluster == 0 {
+                if record.is_directory() {
+                    checker.error(&path, "is a directory without clusters");
+                } else if record.size != 0 {
+                    checker.error(&path, "has a size but no clusters");
+                }
+
+                continue;
+            }
+
+            let Some(clusters) = checker.walk(&path, record.first_cluster) else {
+                continue;
+            };
+
+            if record.is_directory() {
+                directories.push((path, Some(clusters)));
+                continue;
+            }
+
+            let expected = u64::from(record.size).div_ceil(volume.cluster_size);
+            if clusters.len() as u64 != expected {
+                checker.error(
+                    &path,
+                    &format!(
+                        "has {} clusters, but its size needs {}",
+                        clusters.len(),
+                        expected
+                    ),
+                );
+            }
+        }
+    }
+
+    checker.check_lost();
+    checker.errors
+}
+
+impl Checker<'_> {
+    fn error(&mut self, path: &str, problem: &str) {
+        let path = if path.is_empty() { "/" } else { path };
+        log::warn!("{}: {} {}.", self.volume.disk.name(), path, problem);
+        self.errors += 1;
+    }
+
+    fn warn(&self, problem: &str) {
+        log::warn!("{}: {}.", self.volume.disk.name(), problem);
+    }
+
+    /// Follows the chain starting at `first`, marking its clusters as used. Returns `None` if the
+    /// chain is broken or shares clusters with another one.
+    fn walk(&mut self, path: &str, first: u32) -> Option<Vec<u32>> {
+        let table = self.volume.table.lock();
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+
+        let problem = loop {
+            if !table.is_valid(cluster) {
+                break format!("points to the invalid cluster {}", cluster);
+            }
+
+            let (word, bit) = (cluster as usize / 64, cluster % 64);
+            if self.used[word] & (1 << bit) != 0 {
+                break format!("shares the cluster {} with another file", cluster);
+            }
+
+            self.used[word] |= 1 << bit;
+            clusters.push(cluster);
+
+            match table.get(cluster) {
+                Entry::Next(next) => cluster = next,
+                Entry::End => return Some(clusters),
+                Entry::Free => break format!("continues in the free cluster {}", cluster),
+                Entry::Bad => break format!("continues in the bad cluster {}", cluster),
+            }
+        };
+
+        drop(table);
+        self.error(path, &problem);
+        None
+    }
+
+    /// Checks that every copy of the table is the same as the first.
+    fn check_copies(&mut self) {
+        let volume = self.volume;
+        let table = volume.table.lock();
+        let mut copy = alloc::vec![0u8; table.data().len()];
+
+        for index in 1..volume.fat_count as u64 {
+            let offset = volume.fat_offset + index * volume.fat_size;
+            let differs = match volume.disk.read_at(offset, &mut copy) {
+                Ok(()) => copy != table.data(),
+                Err(_) => true,
+            };
+
+            if differs {
+                let problem = format!("copy {} of the FAT differs from the first", index + 1);
+                self.errors += 1;
+                self.warn(&problem);
+            }
+        }
+    }
+
+    /// Checks the two reserved entries, which hold the media byte and the state of the volume.
+    fn check_reserved(&mut self) {
+        let table = self.volume.table.lock();
+
+        if table.raw(0) & 0xff != u32::from(self.volume.media) {
+            self.warn("the media byte of the FAT does not match the boot sector");
+        }
+
+        let (clean, no_errors) = match table.kind() {
+            FatKind::Fat12 => return,
+            FatKind::Fat16 => (FAT16_CLEAN, FAT16_NO_ERRORS),
+            FatKind::Fat32 => (FAT32_CLEAN, FAT32_NO_ERRORS),
+        };
+
+        let state = table.raw(1);
+
+        if state & clean == 0 {
+            self.warn("the volume was not unmounted cleanly");
+        }
+
+        if state & no_errors == 0 {
+            self.warn("the volume has seen I/O errors");
+        }
+    }
+
+    /// Reports clusters that are in use without belonging to anything.
+    fn check_lost(&self) {
+        let table = self.volume.table.lock();
+
+        let lost = table
+            .clusters()
+            .filter(|&cluster| matches!(table.get(cluster), Entry::Next(_) | Entry::End))
+            .filter(|&cluster| self.used[cluster as usize / 64] & (1 << (cluster % 64)) == 0)
+            .count();
+
+        if lost != 0 {
+            self.warn(&format!("{} clusters are lost", lost));
+        }
+    }
+}
@@ -0,0 +1,458 @@
+//! Directory entries, including the long file name entries that precede the short ones.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use nekos_abi::Errno;
+use nekos_abi::time::Timespec;
+
+use crate::fs::FsResult;
+
+pub const ENTRY_SIZE: usize = 32;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+pub const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+/// The first byte of a free entry.
+pub const FREE: u8 = 0xe5;
+/// The first byte of the entry after the last one in use.
+const END: u8 = 0x00;
+/// Stands in for a first byte of 0xe5, which would mark the entry free.
+const KANJI_E5: u8 = 0x05;
+
+/// Flags of Windows NT that a short name is shown in lower case.
+const LOWER_BASE: u8 = 0x08;
+const LOWER_EXTENSION: u8 = 0x10;
+
+/// The order of the last long name entry, which comes first.
+const LAST_LONG_ENTRY: u8 = 0x40;
+/// How many UTF-16 units a long name entry holds.
+const LONG_ENTRY_UNITS: usize = 13;
+/// The offsets of the UTF-16 units in a long name entry.
+const LONG_ENTRY_OFFSETS: [usize; LONG_ENTRY_UNITS] =
+    [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// The most UTF-16 units a long name may have.
+const MAX_LONG_NAME: usize = 255;
+
+/// Seconds from the Unix epoch to 1980-01-01, the earliest time FAT can represent.
+const FAT_EPOCH: i64 = 315_532_800;
+
+/// An entry in use, with the long name entries in front of it already folded in.
+pub struct Record {
+    pub name: String,
+    pub short_name: [u8; 11],
+    pub attributes: u8,
+    pub first_cluster: u32,
+    pub size: u32,
+    pub created: Timespec,
+    pub accessed: Timespec,
+    pub modified: Timespec,
+    /// The offset in the directory of the first entry of this record, including the long name.
+    pub start: u64,
+    /// The offset in the directory of the short entry.
+    pub offset: u64,
+}
+
+/// The entries making up a new record.
+pub struct NewRecord<'a> {
+    pub name: &'a str,
+    pub short_name: [u8; 11],
+    /// The flags for the case of the short name, if the name needs no long entries.
+    pub case: Option<u8>,
+    pub attributes: u8,
+    pub first_cluster: u32,
+    pub time: Timespec,
+}
+
+impl Record {
+    pub fn is_directory(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    /// Whether this is the `.` or `..` entry.
+    pub fn is_dot(&self) -> bool {
+        self.short_name[0] == b'.'
+    }
+
+    /// Whether `name` refers to this record, which FAT treats case-insensitively.
+    pub fn matches(&self, name: &str) -> bool {
+        eq_ignore_case(&self.name, name)
+            || eq_ignore_case(&short_name_str(&self.short_name, 0), name)
+    }
+}
+
+/// Parses the records of the directory whose contents are `data`, stopping at the end marker.
+pub fn records(data: &[u8]) -> Vec<Record> {
+    let mut records = Vec::new();
+
+    // The long name collected so far, which belongs to the next short entry once every part of it
+    // has been seen. A length of zero means there is none.
+    let mut long_name = [0u16; LONG_ENTRY_UNITS * 20];
+    let mut long_len = 0;
+    let mut long_start = 0;
+    let mut long_checksum = 0;
+    let mut expected_order = 0;
+
+    for (index, entry) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+        let offset = (index * ENTRY_SIZE) as u64;
+
+        match entry[0] {
+            END => break,
+            FREE => {
+                long_len = 0;
+                continue;
+            }
+            _ => {}
+        }
+
+        if entry[11] & 0x3f == ATTR_LONG_NAME {
+            let order = entry[0] & 0x1f;
+
+            if entry[0] & LAST_LONG_ENTRY != 0 && (1..=20).contains(&order) {
+                long_len = order as usize * LONG_ENTRY_UNITS;
+                long_start = offset;
+                long_checksum = entry[13];
+                expected_order = order;
+            } else if long_len == 0
+                || order == 0
+                || order != expected_order
+                || entry[13] != long_checksum
+            {
+                long_len = 0;
+                continue;
+            }
+
+            let units =
+                &mut long_name[(order as usize - 1) * LONG_ENTRY_UNITS..][..LONG_ENTRY_UNITS];
+            for (unit, &position) in units.iter_mut().zip(LONG_ENTRY_OFFSETS.iter()) {
+                *unit = u16::from_le_bytes([entry[position], entry[position + 1]]);
+            }
+
+            expected_order -= 1;
+            continue;
+        }
+
+        let mut short_name: [u8; 11] = entry[..11].try_into().unwrap();
+        let has_long_name =
+            long_len != 0 && expected_order == 0 && long_checksum == checksum(&short_name);
+        let start = if has_long_name { long_start } else { offset };
+        let len = core::mem::take(&mut long_len);
+
+        let attributes = entry[11];
+        if attributes & ATTR_VOLUME_ID != 0 {
+            continue;
+        }
+
+        if short_name[0] == KANJI_E5 {
+            short_name[0] = FREE;
+        }
+
+        let name = match has_long_name {
+            true => decode_long_name(&long_name[..len]),
+            false => short_name_str(&short_name, entry[12]),
+        };
+
+        let read_u16 = |offset: usize| u16::from_le_bytes([entry[offset], entry[offset + 1]]);
+
+        records.push(Record {
+            name,
+            short_name,
+            attributes,
+            first_cluster: (u32::from(read_u16(20)) << 16) | u32::from(read_u16(26)),
+            size: u32::from_le_bytes(entry[28..32].try_into().unwrap()),
+            created: decode_time(read_u16(16), read_u16(14)),
+            accessed: decode_time(read_u16(18), 0),
+            modified: decode_time(read_u16(24), read_u16(22)),
+            start,
+            offset,
+        });
+    }
+
+    records
+}
+
+/// Builds the entries of `record`, the long name entries first.
+pub fn encode(record: &NewRecord) -> Vec<[u8; ENTRY_SIZE]> {
+    let mut entries = Vec::new();
+
+    if record.case.is_none() {
+        let units: Vec<u16> = record.name.encode_utf16().collect();
+        let count = units.len().div_ceil(LONG_ENTRY_UNITS);
+        let checksum = checksum(&record.short_name);
+
+        for order in (1..=count).rev() {
+            let mut entry = [0u8; ENTRY_SIZE];
+            entry[0] = order as u8 | if order == count { LAST_LONG_ENTRY } else { 0 };
+            entry[11] = ATTR_LONG_NAME;
+            entry[13] = checksum;
+
+            // The name is terminated by a null unit if it does not fill the last entry, and padded
+            // with 0xffff after that.
+            for (index, &position) in LONG_ENTRY_OFFSETS.iter().enumerate() {
+                let unit = match (order - 1) * LONG_ENTRY_UNITS + index {
+                    i if i < units.len() => units[i],
+                    i if i == units.len() => 0,
+                    _ => 0xffff,
+                };
+
+                entry[position..position + 2].copy_from_slice(&unit.to_le_bytes());
+            }
+
+            entries.push(entry);
+        }
+    }
+
+    let mut entry = [0u8; ENTRY_SIZE];
+    entry[..11].copy_from_slice(&record.short_name);
+    if entry[0] == FREE {
+        entry[0] = KANJI_E5;
+    }
+
+    let (date, time) = encode_time(record.time);
+
+    entry[11] = record.attributes;
+    entry[12] = record.case.unwrap_or(0);
+    entry[14..16].copy_from_slice(&time.to_le_bytes());
+    entry[16..18].copy_from_slice(&date.to_le_bytes());
+    entry[18..20].copy_from_slice(&date.to_le_bytes());
+    entry[20..22].copy_from_slice(&((record.first_cluster >> 16) as u16).to_le_bytes());
+    entry[22..24].copy_from_slice(&time.to_le_bytes());
+    entry[24..26].copy_from_slice(&date.to_le_bytes());
+    entry[26..28].copy_from_slice(&(record.first_cluster as u16).to_le_bytes());
+
+    entries.push(entry);
+    entries
+}
+
+/// Checks that `name` can be stored as a long name.
+pub fn validate_name(name: &str) -> FsResult<()> {
+    if name.encode_utf16().count() > MAX_LONG_NAME {
+        return Err(Errno::ENAMETOOLONG);
+    }
+
+    let invalid = |c: char| c < ' ' || "\"*/:<>?\\|".contains(c);
+
+    if name.is_empty() || name.contains(invalid) || name.ends_with(['.', ' ']) {
+        return Err(Errno::EINVAL);
+    }
+
+    Ok(())
+}
+
+/// Returns the short name of `name` and the flags for its case, if it fits into one as is.
+pub fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, extension) = match name.rsplit_once('.') {
+        Some((base, extension)) => (base, extension),
+        None => (name, ""),
+    };
+
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 {
+        return None;
+    }
+
+    let mut case = 0;
+
+    for (part, flag) in [(base, LOWER_BASE), (extension, LOWER_EXTENSION)] {
+        if !part.bytes().all(is_short_name_char) {
+            return None;
+        }
+
+        let has_lower = part.bytes().any(|b| b.is_ascii_lowercase());
+        let has_upper = part.bytes().any(|b| b.is_ascii_uppercase());
+
+        match (has_lower, has_upper) {
+            (true, true) => return None,
+            (true, false) => case |= flag,
+            _ => {}
+        }
+    }
+
+    let mut short_name = [b' '; 11];
+    short_name[..base.len()].copy_from_slice(base.as_bytes());
+    short_name[8..8 + extension.len()].copy_from_slice(extension.as_bytes());
+    short_name.make_ascii_uppercase();
+
+    Some((short_name, case))
+}
+
+/// Generates a short name for `name` like `LONGFI~1.TXT` that `exists` does not know yet.
+pub fn generate_short_name(name: &str, exists: impl Fn(&[u8; 11]) -> bool) -> FsResult<[u8; 11]> {
+    let name = name.trim_start_matches('.');
+    let (base, extension) = match name.rsplit_once('.') {
+        Some((base, extension)) => (base, extension),
+        None => (name, ""),
+    };
+
+    let convert = |part: &str, len: usize| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| match c.is_ascii() && is_short_name_char(c as u8) {
+                true => c.to_ascii_uppercase() as u8,
+                false => b'_',
+            })
+            .take(len)
+            .collect()
+    };
+
+    let base = convert(base, 8);
+    let extension = convert(extension, 3);
+
+    let mut short_name = [b' '; 11];
+    short_name[8..8 + extension.len()].copy_from_slice(&extension);
+
+    for number in 1..1_000_000u32 {
+        let mut tail = [0u8; 8];
+        let tail = format_tail(number, &mut tail);
+        let base_len = base.len().min(8 - tail.len());
+
+        short_name[..8].fill(b' ');
+        short_name[..base_len].copy_from_slice(&base[..base_len]);
+        short_name[base_len..base_len + tail.len()].copy_from_slice(tail);
+
+        if !exists(&short_name) {
+            return Ok(short_name);
+        }
+    }
+
+    Err(Errno::EEXIST)
+}
+
+/// Writes `~number` into `buf` and returns it.
+fn format_tail(number: u32, buf: &mut [u8; 8]) -> &[u8] {
+    let digits = number.ilog10() as usize + 1;
+    buf[0] = b'~';
+
+    let mut value = number;
+    for index in (1..=digits).rev() {
+        buf[index] = b'0' + (value % 10) as u8;
+        value /= 10;
+    }
+
+    &buf[..digits + 1]
+}
+
+fn is_short_name_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"$%'-_@~`!(){}^#&".contains(&b)
+}
+
+/// The checksum of a short name stored in its long name entries.
+fn checksum(short_name: &[u8; 11]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+fn decode_long_name(units: &[u16]) -> String {
+    let len = units
+        .iter()
+        .position(|&unit| unit == 0 || unit == 0xffff)
+        .unwrap_or(units.len());
+
+    char::decode_utf16(units[..len].iter().copied())
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// Turns a short name into `NAME.EXT`, in lower case where `case` says so.
+fn short_name_str(short_name: &[u8; 11], case: u8) -> String {
+    let mut name = String::new();
+
+    let part = |bytes: &[u8], lower: bool, name: &mut String| {
+        let len = bytes
+            .iter()
+            .rposition(|&b| b != b' ')
+            .map_or(0, |index| index + 1);
+
+        // Bytes outside of ASCII are in some OEM code page, which is assumed to be Latin-1.
+        name.extend(bytes[..len].iter().map(|&b| match lower {
+            true => char::from(b.to_ascii_lowercase()),
+            false => char::from(b),
+        }));
+    };
+
+    part(&short_name[..8], case & LOWER_BASE != 0, &mut name);
+
+    if short_name[8..].iter().any(|&b| b != b' ') {
+        name.push('.');
+        part(&short_name[8..], case & LOWER_EXTENSION != 0, &mut name);
+    }
+
+    name
+}
+
+fn eq_ignore_case(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.chars()
+            .zip(b.chars())
+            .all(|(a, b)| a == b || a.to_lowercase().eq(b.to_lowercase()))
+}
+
+/// Converts a FAT date and time, which are local time, into a timestamp taken to be in UTC.
+pub fn decode_time(date: u16, time: u16) -> Timespec {
+    if date == 0 {
+        return Timespec::default();
+    }
+
+    let year = 1980 + i64::from(date >> 9);
+    let month = i64::from((date >> 5) & 0xf).clamp(1, 12);
+    let day = i64::from(date & 0x1f).max(1);
+
+    let seconds = i64::from(time >> 11) * 3600
+        + i64::from((time >> 5) & 0x3f) * 60
+        + i64::from(time & 0x1f) * 2;
+
+    Timespec {
+        tv_sec: days_from_civil(year, month, day) * 86400 + seconds,
+        tv_nsec: 0,
+    }
+}
+
+/// Converts a timestamp into a FAT date and time, clamped to the range FAT can represent.
+pub fn encode_time(time: Timespec) -> (u16, u16) {
+    let seconds = time
+        .tv_sec
+        .clamp(FAT_EPOCH, days_from_civil(2107, 12, 31) * 86400 + 86399);
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let seconds = seconds.rem_euclid(86400);
+
+    let date = (((year - 1980) << 9) | (month << 5) | day) as u16;
+    let time = (((seconds / 3600) << 11) | ((seconds / 60 % 60) << 5) | (seconds % 60 / 2)) as u16;
+
+    (date, time)
+}
+
+/// The number of days from 1970-01-01 to the given date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
@@ -0,0 +1,989 @@
+//! The FAT12, FAT16 and FAT32 file systems, with long file names.
+//!
+//! FAT has no inodes, the size and first cluster of a file are kept in its entry in the parent
+//! directory. Inodes are therefore identified by the position of that entry on disk, and are
+//! cached while in use so that everyone sees the same size and clusters.
+
+mod check;
+mod dir;
+mod table;
+
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use nekos_abi::Errno;
+use nekos_abi::time::Timespec;
+use spin::Mutex;
+use ubyte::ToByteUnit;
+
+use super::{DirEntry, FileSystem, FsResult, Inode, InodeKind, Metadata, now};
+use crate::block::{BlockError, Disk};
+use crate::log;
+
+use dir::{NewRecord, Record};
+use table::{Entry, FIRST_CLUSTER, FatKind, Table};
+
+const ROOT_INO: u64 = 1;
+
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+/// Volumes with fewer clusters than these are FAT12 or FAT16 respectively, whatever they claim.
+const MAX_FAT12_CLUSTERS: u32 = 4085;
+const MAX_FAT16_CLUSTERS: u32 = 65525;
+
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+/// The value of the fields of the FSInfo sector that are not known.
+const FSINFO_UNKNOWN: u32 = 0xffff_ffff;
+
+const MAX_FILE_SIZE: u64 = u32::MAX as u64;
+/// Directories may have at most this many entries.
+const MAX_DIRECTORY_SIZE: u64 = 65536 * dir::ENTRY_SIZE as u64;
+
+const ZEROES: [u8; 4096] = [0; 4096];
+
+pub struct Fat {
+    volume: Arc<Volume>,
+    root: Arc<FatInode>,
+}
+
+enum RootDir {
+    /// FAT12 and FAT16 keep the root directory in a region of its own in front of the clusters.
+    Fixed {
+        offset: u64,
+        size: u64,
+    },
+    Cluster(u32),
+}
+
+struct Volume {
+    disk: Arc<Disk>,
+    read_only: bool,
+    cluster_size: u64,
+    fat_offset: u64,
+    fat_size: u64,
+    fat_count: u32,
+    media: u8,
+    root_dir: RootDir,
+    /// The byte offset of the first cluster.
+    data_offset: u64,
+    /// The byte offset of the FSInfo sector of FAT32.
+    fsinfo_offset: Option<u64>,
+    table: Mutex<Table>,
+    /// The inodes in use by the position of their entry.
+    inodes: Mutex<BTreeMap<u64, Weak<FatInode>>>,
+}
+
+/// Where the contents of a file or directory are.
+#[derive(Clone, Copy)]
+enum Contents<'a> {
+    Fixed { offset: u64, size: u64 },
+    Clusters(&'a [u32]),
+}
+
+struct FatInode {
+    ino: u64,
+    /// The byte offset of the entry of this inode on disk, which the root directory has none of.
+    entry: Option<u64>,
+    kind: InodeKind,
+    volume: Arc<Volume>,
+    state: Mutex<InodeState>,
+}
+
+struct InodeState {
+    attributes: u8,
+    size: u64,
+    /// The clusters of the contents, empty for empty files and the fixed root directory.
+    clusters: Vec<u32>,
+    created: Timespec,
+    accessed: Timespec,
+    modified: Timespec,
+    /// Whether the entry was removed, in which case the clusters are freed once the inode is
+    /// dropped.
+    unlinked: bool,
+}
+
+impl Fat {
+    /// Mounts the FAT volume on `disk`, after checking it for consistency. Volumes with errors
+    /// are mounted read-only.
+    pub fn new(disk: Arc<Disk>) -> FsResult<Arc<Self>> {
+        let mut boot = [0u8; 512];
+        disk.read_at(0, &mut boot)?;
+
+        if boot[510..512] != BOOT_SIGNATURE {
+            return Err(Errno::EINVAL);
+        }
+
+        let read_u16 =
+            |offset: usize| u64::from(u16::from_le_bytes([boot[offset], boot[offset + 1]]));
+        let read_u32 = |offset: usize| {
+            u64::from(u32::from_le_bytes(
+                boot[offset..offset + 4].try_into().unwrap(),
+            ))
+        };
+
+        let sector_size = read_u16(11);
+        let sectors_per_cluster = u64::from(boot[13]);
+        let reserved_sectors = read_u16(14);
+        let fat_count = u64::from(boot[16]);
+        let root_entries = read_u16(17);
+        let media = boot[21];
+
+        let total_sectors = match read_u16(19) {
+            0 => read_u32(32),
+            sectors => sectors,
+        };
+
+        let fat_sectors = match read_u16(22) {
+            0 => read_u32(36),
+            sectors => sectors,
+        };
+
+        if !sector_size.is_power_of_two()
+            || !(512..=4096).contains(&sector_size)
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || fat_count == 0
+            || fat_sectors == 0
+            || total_sectors * sector_size > disk.size()
+        {
+            return Err(Errno::EINVAL);
+        }
+
+        let root_dir_sectors = (root_entries * dir::ENTRY_SIZE as u64).div_ceil(sector_size);
+        let data_start = reserved_sectors + fat_count * fat_sectors + root_dir_sectors;
+        let cluster_count =
+            total_sectors.checked_sub(data_start).ok_or(Errno::EINVAL)? / sectors_per_cluster;
+        let cluster_count = u32::try_from(cluster_count).map_err(|_| Errno::EINVAL)?;
+
+        let kind = match cluster_count {
+            count if count < MAX_FAT12_CLUSTERS => FatKind::Fat12,
+            count if count < MAX_FAT16_CLUSTERS => FatKind::Fat16,
+            _ => FatKind::Fat32,
+        };
+
+        let entry_bits = match kind {
+            FatKind::Fat12 => 12,
+            FatKind::Fat16 => 16,
+            FatKind::Fat32 => 32,
+        };
+
+        // The table needs an entry for every cluster plus the two reserved ones.
+        let fat_size = fat_sectors * sector_size;
+        if (u64::from(cluster_count) + 2) * entry_bits > fat_size * 8 || cluster_count == 0 {
+            return Err(Errno::EINVAL);
+        }
+
+        let (root_dir, fsinfo_offset, label) = match kind {
+            FatKind::Fat32 => (
+                RootDir::Cluster(read_u32(44) as u32),
+                Some(read_u16(48)).filter(|&sector| sector != 0 && sector < reserved_sectors),
+                &boot[71..82],
+            ),
+            _ => (
+                RootDir::Fixed {
+                    offset: (reserved_sectors + fat_count * fat_sectors) * sector_size,
+                    size: root_entries * dir::ENTRY_SIZE as u64,
+                },
+                None,
+                &boot[43..54],
+            ),
+        };
+
+        let fat_offset = reserved_sectors * sector_size;
+        let table = Table::load(
+            &disk,
+            kind,
+            fat_offset,
+            fat_size,
+            sector_size,
+            cluster_count,
+        )?;
+
+        let mut volume = Volume {
+            read_only: disk.is_read_only(),
+            disk,
+            cluster_size: sectors_per_cluster * sector_size,
+            fat_offset,
+            fat_size,
+            fat_count: fat_count as u32,
+            media,
+            root_dir,
+            data_offset: data_start * sector_size,
+            fsinfo_offset: fsinfo_offset.map(|sector| sector * sector_size),
+            table: Mutex::new(table),
+            inodes: Mutex::new(BTreeMap::new()),
+        };
+
+        log::info!(
+            "Found {:?} volume {:?} on {} with {} clusters of {}.",
+            kind,
+            core::str::from_utf8(label).unwrap_or("").trim_end(),
+            volume.disk.name(),
+            cluster_count,
+            volume.cluster_size.bytes()
+        );
+
+        volume.read_fsinfo()?;
+
+        let errors = check::check(&volume);
+        if errors != 0 && !volume.read_only {
+            log::warn!(
+                "Found {} errors on {}, mounting it read-only.",
+                errors,
+                volume.disk.name()
+            );
+
+            volume.read_only = true;
+        }
+
+        let clusters = match volume.root_dir {
+            RootDir::Fixed { .. } => Vec::new(),
+            RootDir::Cluster(cluster) => volume.table.lock().chain(cluster)?,
+        };
+
+        let volume = Arc::new(volume);
+
+        let root = Arc::new(FatInode {
+            ino: ROOT_INO,
+            entry: None,
+            kind: InodeKind::Directory,
+            volume: volume.clone(),
+            state: Mutex::new(InodeState {
+                attributes: dir::ATTR_DIRECTORY,
+                size: 0,
+                clusters,
+                created: Timespec::default(),
+                accessed: Timespec::default(),
+                modified: Timespec::default(),
+                unlinked: false,
+            }),
+        });
+
+        Ok(Arc::new(Self { volume, root }))
+    }
+}
+
+impl FileSystem for Fat {
+    fn name(&self) -> &'static str {
+        "vfat"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        if self.volume.read_only {
+            return Ok(());
+        }
+
+        let volume = &self.volume;
+        let mut table = volume.table.lock();
+
+        table.write_back(
+            &volume.disk,
+            volume.fat_offset,
+            volume.fat_size,
+            volume.fat_count,
+        )?;
+
+        if let Some(offset) = volume.fsinfo_offset {
+            let mut hints = [0u8; 8];
+            hints[..4].copy_from_slice(&table.free_count().to_le_bytes());
+            hints[4..].copy_from_slice(&table.next_free().to_le_bytes());
+            volume.disk.write_at(offset + 488, &hints)?;
+        }
+
+        drop(table);
+        Ok(volume.disk.flush()?)
+    }
+}
+
+impl Volume {
+    /// Takes the hint where to look for free clusters from the FSInfo sector, and reports if its
+    /// count of free clusters is off.
+    fn read_fsinfo(&mut self) -> Result<(), BlockError> {
+        let Some(offset) = self.fsinfo_offset else {
+            return Ok(());
+        };
+
+        let mut sector = [0u8; 512];
+        self.disk.read_at(offset, &mut sector)?;
+
+        let read_u32 =
+            |offset: usize| u32::from_le_bytes(sector[offset..offset + 4].try_into().unwrap());
+
+        if read_u32(0) != FSINFO_LEAD_SIGNATURE || read_u32(484) != FSINFO_STRUCT_SIGNATURE {
+            log::warn!(
+                "Ignoring the invalid FSInfo sector of {}.",
+                self.disk.name()
+            );
+            self.fsinfo_offset = None;
+            return Ok(());
+        }
+
+        let table = self.table.get_mut();
+        let (free_count, next_free) = (read_u32(488), read_u32(492));
+
+        if free_count != FSINFO_UNKNOWN && free_count != table.free_count() {
+            log::warn!(
+                "FSInfo of {} claims {} free clusters, but there are {}.",
+                self.disk.name(),
+                free_count,
+                table.free_count()
+            );
+        }
+
+        if next_free != FSINFO_UNKNOWN {
+            table.set_next_free(next_free);
+        }
+
+        Ok(())
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_offset + u64::from(cluster - FIRST_CLUSTER) * self.cluster_size
+    }
+
+    /// Calls `f` with the disk offset of every piece of the range `range` of `contents` and where
+    /// the piece lies in that range, merging clusters that follow each other on disk.
+    fn pieces(
+        &self,
+        contents: Contents,
+        range: Range<u64>,
+        mut f: impl FnMut(u64, Range<usize>) -> Result<(), BlockError>,
+    ) -> FsResult<()> {
+        let len = (range.end - range.start) as usize;
+
+        let clusters = match contents {
+            Contents::Fixed { offset, size } if range.end <= size => {
+                return Ok(f(offset + range.start, 0..len)?);
+            }
+            Contents::Fixed { .. } => return Err(Errno::EIO),
+            Contents::Clusters(clusters) => clusters,
+        };
+
+        let mut done = 0;
+
+        while done < len {
+            let position = range.start + done as u64;
+            let index = (position / self.cluster_size) as usize;
+            let within = position % self.cluster_size;
+            let first = *clusters.get(index).ok_or(Errno::EIO)?;
+
+            let mut run = 1;
+            while clusters.get(index + run) == Some(&(first + run as u32)) {
+                run += 1;
+            }
+
+            let chunk = ((run as u64 * self.cluster_size - within) as usize).min(len - done);
+            f(self.cluster_offset(first) + within, done..done + chunk)?;
+            done += chunk;
+        }
+
+        Ok(())
+    }
+
+    fn read(&self, contents: Contents, offset: u64, buf: &mut [u8]) -> FsResult<()> {
+        let range = offset..offset + buf.len() as u64;
+        self.pieces(contents, range, |position, range| {
+            self.disk.read_at(position, &mut buf[range])
+        })
+    }
+
+    fn write(&self, contents: Contents, offset: u64, data: &[u8]) -> FsResult<()> {
+        let range = offset..offset + data.len() as u64;
+        self.pieces(contents, range, |position, range| {
+            self.disk.write_at(position, &data[range])
+        })
+    }
+
+    fn zero(&self, contents: Contents, range: Range<u64>) -> FsResult<()> {
+        self.pieces(contents, range, |position, range| {
+            let mut done = 0;
+
+            while done < range.len() {
+                let chunk = (range.len() - done).min(ZEROES.len());
+                self.disk
+                    .write_at(position + done as u64, &ZEROES[..chunk])?;
+                done += chunk;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// The disk offset of the byte at `offset` of `contents`.
+    fn position(&self, contents: Contents, offset: u64) -> FsResult<u64> {
+        match contents {
+            Contents::Fixed { offset: start, .. } => Ok(start + offset),
+            Contents::Clusters(clusters) => {
+                let cluster = *clusters
+                    .get((offset / self.cluster_size) as usize)
+                    .ok_or(Errno::EIO)?;
+
+                Ok(self.cluster_offset(cluster) + offset % self.cluster_size)
+            }
+        }
+    }
+
+    /// Returns the inode whose entry is at `position`, or creates it with the state `init`
+    /// returns if it is not in use yet.
+    fn inode(
+        self: &Arc<Self>,
+        position: u64,
+        kind: InodeKind,
+        init: impl FnOnce() -> FsResult<InodeState>,
+    ) -> FsResult<Arc<FatInode>> {
+        let mut inodes = self.inodes.lock();
+
+        if let Some(inode) = inodes.get(&position).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+
+        let inode = Arc::new(FatInode {
+            ino: position,
+            entry: Some(position),
+            kind,
+            volume: self.clone(),
+            state: Mutex::new(init()?),
+        });
+
+        inodes.retain(|_, inode| inode.strong_count() != 0);
+        inodes.insert(position, Arc::downgrade(&inode));
+
+        Ok(inode)
+    }
+}
+
+impl FatInode {
+    fn contents<'a>(&self, state: &'a InodeState) -> Contents<'a> {
+        match (&self.volume.root_dir, self.entry) {
+            (&RootDir::Fixed { offset, size }, None) => Contents::Fixed { offset, size },
+            _ => Contents::Clusters(&state.clusters),
+        }
+    }
+
+    /// How many bytes the clusters of this inode hold.
+    fn capacity(&self, state: &InodeState) -> u64 {
+        match self.contents(state) {
+            Contents::Fixed { size, .. } => size,
+            Contents::Clusters(clusters) => clusters.len() as u64 * self.volume.cluster_size,
+        }
+    }
+
+    fn check_writable(&self) -> FsResult<()> {
+        match self.volume.read_only {
+            true => Err(Errno::EROFS),
+            false => Ok(()),
+        }
+    }
+
+    /// Reads the records of this directory, returning the raw contents along with them.
+    fn read_records(&self, state: &InodeState) -> FsResult<(Vec<u8>, Vec<Record>)> {
+        if self.kind != InodeKind::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+
+        let mut data = alloc::vec![0u8; self.capacity(state) as usize];
+        self.volume.read(self.contents(state), 0, &mut data)?;
+
+        let records = dir::records(&data);
+        Ok((data, records))
+    }
+
+    /// Allocates clusters until there are `count`, which are zeroed if `zero` is set.
+    fn grow(&self, state: &mut InodeState, count: usize, zero: bool) -> FsResult<()> {
+        let mut table = self.volume.table.lock();
+
+        while state.clusters.len() < count {
+            let cluster = table.allocate(state.clusters.last().copied())?;
+            state.clusters.push(cluster);
+
+            if zero {
+                let range = 0..self.volume.cluster_size;
+                self.volume.zero(Contents::Clusters(&[cluster]), range)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Frees every cluster past the first `count`.
+    fn shrink(&self, state: &mut InodeState, count: usize) {
+        if count >= state.clusters.len() {
+            return;
+        }
+
+        let mut table = self.volume.table.lock();
+        table.free_chain(state.clusters[count]);
+
+        if count != 0 {
+            table.set(state.clusters[count - 1], Entry::End);
+        }
+
+        state.clusters.truncate(count);
+    }
+
+    /// Writes the first cluster, size and modification time back into the entry of this inode.
+    fn write_entry(&self, state: &InodeState) -> FsResult<()> {
+        let Some(position) = self.entry else {
+            return Ok(());
+        };
+
+        if state.unlinked {
+            return Ok(());
+        }
+
+        let first_cluster = state.clusters.first().copied().unwrap_or(0);
+        let (date, time) = dir::encode_time(state.modified);
+        let size = match self.kind {
+            InodeKind::Directory => 0,
+            _ => state.size as u32,
+        };
+
+        // Only the fields from the high half of the first cluster on are written, so that the
+        // name, which may have been marked free in the meantime, is left alone.
+        let mut fields = [0u8; 12];
+        fields[0..2].copy_from_slice(&((first_cluster >> 16) as u16).to_le_bytes());
+        fields[2..4].copy_from_slice(&time.to_le_bytes());
+        fields[4..6].copy_from_slice(&date.to_le_bytes());
+        fields[6..8].copy_from_slice(&(first_cluster as u16).to_le_bytes());
+        fields[8..12].copy_from_slice(&size.to_le_bytes());
+
+        Ok(self.volume.disk.write_at(position + 20, &fields)?)
+    }
+
+    /// Finds room for `count` consecutive entries in this directory, growing it if needed, and
+    /// returns the offset of the first.
+    fn find_free_entries(
+        &self,
+        state: &mut InodeState,
+        data: &[u8],
+        count: usize,
+    ) -> FsResult<u64> {
+        let mut run_start = 0;
+        let mut run_len = 0;
+
+        for (index, entry) in data.chunks_exact(dir::ENTRY_SIZE).enumerate() {
+            if entry[0] == 0 {
+                // Everything past the end marker is free.
+                if run_len == 0 {
+                    run_start = index;
+                }
+
+                run_len = usize::MAX;
+                break;
+            }
+
+            if entry[0] == dir::FREE {
+                if run_len == 0 {
+                    run_start = index;
+                }
+
+                run_len += 1;
+                if run_len == count {
+                    break;
+                }
+            } else {
+                run_len = 0;
+            }
+        }
+
+        if run_len == 0 {
+            run_start = data.len() / dir::ENTRY_SIZE;
+        }
+
+        let start = (run_start * dir::ENTRY_SIZE) as u64;
+        let end = start + (count * dir::ENTRY_SIZE) as u64;
+
+        if end > self.capacity(state) {
+            if self.entry.is_none() && matches!(self.volume.root_dir, RootDir::Fixed { .. })
+                || end > MAX_DIRECTORY_SIZE
+            {
+                return Err(Errno::ENOSPC);
+            }
+
+            let clusters = end.div_ceil(self.volume.cluster_size) as usize;
+            self.grow(state, clusters, true)?;
+        }
+
+        Ok(start)
+    }
+
+    /// Adds the entries of `record` to this directory and returns the disk offset of the short
+    /// entry.
+    fn add_record(&self, state: &mut InodeState, data: &[u8], record: &NewRecord) -> FsResult<u64> {
+        let entries = dir::encode(record);
+        let start = self.find_free_entries(state, data, entries.len())?;
+
+        self.volume
+            .write(self.contents(state), start, entries.as_flattened())?;
+
+        let short_entry = start + ((entries.len() - 1) * dir::ENTRY_SIZE) as u64;
+        self.volume.position(self.contents(state), short_entry)
+    }
+
+    fn remove(&self, name: &str, directory: bool) -> FsResult<()> {
+        self.check_writable()?;
+
+        let mut state = self.state.lock();
+        let (_, records) = self.read_records(&state)?;
+
+        let record = records
+            .iter()
+            .find(|record| !record.is_dot() && record.matches(name))
+            .ok_or(Errno::ENOENT)?;
+
+        match (record.is_directory(), directory) {
+            (true, false) => return Err(Errno::EISDIR),
+            (false, true) => return Err(Errno::ENOTDIR),
+            _ => {}
+        }
+
+        let contents = self.contents(&state);
+        let position = self.volume.position(contents, record.offset)?;
+        let inode = self.child(position, record)?;
+
+        let mut child_state = inode.state.lock();
+        if directory {
+            let (_, children) = inode.read_records(&child_state)?;
+            if children.iter().any(|child| !child.is_dot()) {
+                return Err(Errno::ENOTEMPTY);
+            }
+        }
+
+        for offset in (record.start..=record.offset).step_by(dir::ENTRY_SIZE) {
+            let position = self.volume.position(contents, offset)?;
+            self.volume.disk.write_at(position, &[dir::FREE])?;
+        }
+
+        child_state.unlinked = true;
+        drop(child_state);
+
+        self.volume.inodes.lock().remove(&position);
+
+        state.modified = now();
+        self.write_entry(&state)?;
+
+        // The clusters are freed here unless someone still has the inode open.
+        drop(inode);
+        Ok(())
+    }
+
+    /// Returns the inode of `record`, whose entry is at `position`.
+    fn child(&self, position: u64, record: &Record) -> FsResult<Arc<FatInode>> {
+        let kind = match record.is_directory() {
+            true => InodeKind::Directory,
+            false => InodeKind::File,
+        };
+
+        self.volume.inode(position, kind, || {
+            let clusters = self.volume.table.lock().chain(record.first_cluster)?;
+
+            Ok(InodeState {
+                attributes: record.attributes,
+                size: u64::from(record.size),
+                clusters,
+                created: record.created,
+                accessed: record.accessed,
+                modified: record.modified,
+                unlinked: false,
+            })
+        })
+    }
+}
+
+impl Inode for FatInode {
+    fn metadata(&self) -> FsResult<Metadata> {
+        let state = self.state.lock();
+
+        // FAT has no permissions, only a flag that makes files read-only.
+        let mode = match state.attributes & dir::ATTR_READ_ONLY {
+            0 => 0o755,
+            _ => 0o555,
+        };
+
+        let size = match self.kind {
+            InodeKind::Directory => self.capacity(&state),
+            _ => state.size,
+        };
+
+        Ok(Metadata {
+            dev: 0,
+            ino: self.ino,
+            kind: self.kind,
+            mode,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            size,
+            block_size: self.volume.cluster_size as u32,
+            blocks: self.capacity(&state) / 512,
+            atime: state.accessed,
+            mtime: state.modified,
+            ctime: state.created,
+        })
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        let state = self.state.lock();
+        let (_, records) = self.read_records(&state)?;
+
+        let record = records
+            .iter()
+            .find(|record| !record.is_dot() && record.matches(name))
+            .ok_or(Errno::ENOENT)?;
+
+        let position = self.volume.position(self.contents(&state), record.offset)?;
+        Ok(self.child(position, record)?)
+    }
+
+    fn read_dir(&self, index: usize) -> FsResult<Option<DirEntry>> {
+        let state = self.state.lock();
+        let (_, records) = self.read_records(&state)?;
+
+        let Some(record) = records.iter().filter(|record| !record.is_dot()).nth(index) else {
+            return Ok(None);
+        };
+
+        Ok(Some(DirEntry {
+            ino: self.volume.position(self.contents(&state), record.offset)?,
+            kind: match record.is_directory() {
+                true => InodeKind::Directory,
+                false => InodeKind::File,
+            },
+            name: record.name.clone(),
+        }))
+    }
+
+    fn create(&self, name: &str, kind: InodeKind, mode: u32) -> FsResult<Arc<dyn Inode>> {
+        if self.kind != InodeKind::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+
+        if !matches!(kind, InodeKind::File | InodeKind::Directory) {
+            return Err(Errno::EPERM);
+        }
+
+        self.check_writable()?;
+        dir::validate_name(name)?;
+
+        let mut state = self.state.lock();
+        let (data, records) = self.read_records(&state)?;
+
+        if records.iter().any(|record| record.matches(name)) {
+            return Err(Errno::EEXIST);
+        }
+
+        let (short_name, case) = match dir::exact_short_name(name) {
+            Some((short_name, case)) => (short_name, Some(case)),
+            None => {
+                let exists = |short_name: &[u8; 11]| {
+                    records
+                        .iter()
+                        .any(|record| &record.short_name == short_name)
+                };
+
+                (dir::generate_short_name(name, exists)?, None)
+            }
+        };
+
+        let mut attributes = match kind {
+            InodeKind::Directory => dir::ATTR_DIRECTORY,
+            _ => dir::ATTR_ARCHIVE,
+        };
+
+        if mode & 0o222 == 0 {
+            attributes |= dir::ATTR_READ_ONLY;
+        }
+
+        let time = now();
+        let mut child = InodeState {
+            attributes,
+            size: 0,
+            clusters: Vec::new(),
+            created: time,
+            accessed: time,
+            modified: time,
+            unlinked: false,
+        };
+
+        // Directories start out with a cluster holding the `.` and `..` entries.
+        if kind == InodeKind::Directory {
+            self.grow(&mut child, 1, true)?;
+
+            let parent_cluster = match self.entry {
+                Some(_) => state.clusters.first().copied().unwrap_or(0),
+                None => 0,
+            };
+
+            let dot = |name: &'static [u8; 11], first_cluster| {
+                dir::encode(&NewRecord {
+                    name: "",
+                    short_name: *name,
+                    case: Some(0),
+                    attributes: dir::ATTR_DIRECTORY,
+                    first_cluster,
+                    time,
+                })
+            };
+
+            let mut entries = dot(b".          ", child.clusters[0]);
+            entries.extend(dot(b"..         ", parent_cluster));
+
+            let written = self.volume.write(
+                Contents::Clusters(&child.clusters),
+                0,
+                entries.as_flattened(),
+            );
+
+            if let Err(err) = written {
+                self.shrink(&mut child, 0);
+                return Err(err);
+            }
+        }
+
+        let record = NewRecord {
+            name,
+            short_name,
+            case,
+            attributes,
+            first_cluster: child.clusters.first().copied().unwrap_or(0),
+            time,
+        };
+
+        let position = match self.add_record(&mut state, &data, &record) {
+            Ok(position) => position,
+            Err(err) => {
+                self.shrink(&mut child, 0);
+                return Err(err);
+            }
+        };
+
+        state.modified = time;
+        self.write_entry(&state)?;
+
+        Ok(self.volume.inode(position, kind, || Ok(child))?)
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> FsResult<Arc<dyn Inode>> {
+        match self.kind {
+            InodeKind::Directory => Err(Errno::EPERM),
+            _ => Err(Errno::ENOTDIR),
+        }
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()> {
+        self.remove(name, false)
+    }
+
+    fn rmdir(&self, name: &str) -> FsResult<()> {
+        self.remove(name, true)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        if self.kind != InodeKind::File {
+            return Err(Errno::EISDIR);
+        }
+
+        let state = self.state.lock();
+        if offset >= state.size {
+            return Ok(0);
+        }
+
+        let len = buf.len().min((state.size - offset) as usize);
+        self.volume
+            .read(self.contents(&state), offset, &mut buf[..len])?;
+
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        if self.kind != InodeKind::File {
+            return Err(Errno::EISDIR);
+        }
+
+        self.check_writable()?;
+
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let end = offset
+            .checked_add(buf.len() as u64)
+            .filter(|&end| end <= MAX_FILE_SIZE)
+            .ok_or(Errno::EFBIG)?;
+
+        let mut state = self.state.lock();
+        let clusters = end.div_ceil(self.volume.cluster_size) as usize;
+
+        // Write as much as fits if the volume runs full, the next write fails.
+        if let Err(err) = self.grow(&mut state, clusters, false)
+            && self.capacity(&state) <= offset
+        {
+            self.write_entry(&state)?;
+            return Err(err);
+        }
+
+        let len = buf.len().min((self.capacity(&state) - offset) as usize);
+
+        // Clusters are not zeroed when they are allocated, so a gap left by writing past the end
+        // has to be.
+        if offset > state.size {
+            self.volume
+                .zero(self.contents(&state), state.size..offset)?;
+        }
+
+        self.volume
+            .write(self.contents(&state), offset, &buf[..len])?;
+
+        state.size = state.size.max(offset + len as u64);
+        state.modified = now();
+        self.write_entry(&state)?;
+
+        Ok(len)
+    }
+
+    fn truncate(&self, new_size: u64) -> FsResult<()> {
+        if self.kind != InodeKind::File {
+            return Err(Errno::EISDIR);
+        }
+
+        self.check_writable()?;
+
+        if new_size > MAX_FILE_SIZE {
+            return Err(Errno::EFBIG);
+        }
+
+        let mut state = self.state.lock();
+        let clusters = new_size.div_ceil(self.volume.cluster_size) as usize;
+
+        if new_size > state.size {
+            self.grow(&mut state, clusters, false)?;
+            self.volume
+                .zero(self.contents(&state), state.size..new_size)?;
+        } else {
+            self.shrink(&mut state, clusters);
+        }
+
+        state.size = new_size;
+        state.modified = now();
+        self.write_entry(&state)
+    }
+}
+
+impl Drop for FatInode {
+    fn drop(&mut self) {
+        let state = self.state.get_mut();
+
+        if state.unlinked
+            && let Some(&first) = state.clusters.first()
+        {
+            self.volume.table.lock().free_chain(first);
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! The file allocation table, which links the clusters of a file into a chain.
+//!
+//! The whole table is kept in memory while the volume is mounted. Changes are written back to
+//! every copy on disk when the file system is synced.
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+use nekos_abi::Errno;
+
+use crate::block::{BlockError, Disk};
+use crate::fs::FsResult;
+
+/// The first cluster of the data region, the first two entries of the table are reserved.
+pub const FIRST_CLUSTER: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FatKind {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// What the entry of a cluster says about it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Entry {
+    Free,
+    /// The cluster is followed by another one, which is not necessarily a valid cluster.
+    Next(u32),
+    Bad,
+    /// The cluster is the last one of its chain.
+    End,
+}
+
+pub struct Table {
+    kind: FatKind,
+    data: Vec<u8>,
+    /// The number of clusters in the data region, which are numbered from [`FIRST_CLUSTER`].
+    cluster_count: u32,
+    free_count: u32,
+    /// Where searching for a free cluster starts.
+    next_free: u32,
+    /// The offsets of the sectors of the table that changed since it was last written back.
+    dirty: BTreeSet<u64>,
+    sector_size: u64,
+}
+
+impl FatKind {
+    /// The value of entries for bad clusters, anything above it marks the end of a chain.
+    const fn bad(&self) -> u32 {
+        match self {
+            FatKind::Fat12 => 0xff7,
+            FatKind::Fat16 => 0xfff7,
+            FatKind::Fat32 => 0x0fff_fff7,
+        }
+    }
+
+    const fn end(&self) -> u32 {
+        match self {
+            FatKind::Fat12 => 0xfff,
+            FatKind::Fat16 => 0xffff,
+            FatKind::Fat32 => 0x0fff_ffff,
+        }
+    }
+}
+
+impl Table {
+    /// Reads the first copy of the table, which is `size` bytes at the byte `offset` of `disk`.
+    pub fn load(
+        disk: &Disk,
+        kind: FatKind,
+        offset: u64,
+        size: u64,
+        sector_size: u64,
+        cluster_count: u32,
+    ) -> Result<Self, BlockError> {
+        let mut data = alloc::vec![0u8; size as usize];
+        disk.read_at(offset, &mut data)?;
+
+        let mut table = Self {
+            kind,
+            data,
+            cluster_count,
+            free_count: 0,
+            next_free: FIRST_CLUSTER,
+            dirty: BTreeSet::new(),
+            sector_size,
+        };
+
+        table.free_count = table
+            .clusters()
+            .filter(|&cluster| table.get(cluster) == Entry::Free)
+            .count() as u32;
+
+        Ok(table)
+    }
+
+    pub fn kind(&self) -> FatKind {
+        self.kind
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn cluster_count(&self) -> u32 {
+        self.cluster_count
+    }
+
+    pub fn free_count(&self) -> u32 {
+        self.free_count
+    }
+
+    pub fn next_free(&self) -> u32 {
+        self.next_free
+    }
+
+    /// Starts searching for free clusters at `cluster`, as suggested by the FSInfo sector.
+    pub fn set_next_free(&mut self, cluster: u32) {
+        if self.is_valid(cluster) {
+            self.next_free = cluster;
+        }
+    }
+
+    /// Every cluster of the data region.
+    pub fn clusters(&self) -> impl Iterator<Item = u32> + use<> {
+        FIRST_CLUSTER..FIRST_CLUSTER + self.cluster_count
+    }
+
+    pub fn is_valid(&self, cluster: u32) -> bool {
+        (FIRST_CLUSTER..FIRST_CLUSTER + self.cluster_count).contains(&cluster)
+    }
+
+    pub fn get(&self, cluster: u32) -> Entry {
+        match self.raw(cluster) {
+            0 => Entry::Free,
+            value if value == self.kind.bad() => Entry::Bad,
+            value if value > self.kind.bad() => Entry::End,
+            value => Entry::Next(value),
+        }
+    }
+
+    pub fn set(&mut self, cluster: u32, entry: Entry) {
+        let was_free = self.get(cluster) == Entry::Free;
+
+        let value = match entry {
+            Entry::Free => 0,
+            Entry::Next(next) => next,
+            Entry::Bad => self.kind.bad(),
+            Entry::End => self.kind.end(),
+        };
+
+        self.set_raw(cluster, value);
+
+        match (was_free, entry == Entry::Free) {
+            (true, false) => self.free_count -= 1,
+            (false, true) => self.free_count += 1,
+            _ => {}
+        }
+    }
+
+    /// The raw value of the entry at `index`, which includes the reserved entries.
+    pub fn raw(&self, index: u32) -> u32 {
+        let index = index as usize;
+
+        match self.kind {
+            FatKind::Fat12 => {
+                let offset = index + index / 2;
+                let value = u16::from_le_bytes([self.data[offset], self.data[offset + 1]]);
+
+                match index % 2 {
+                    0 => u32::from(value & 0xfff),
+                    _ => u32::from(value >> 4),
+                }
+            }
+            FatKind::Fat16 => u32::from(u16::from_le_bytes([
+                self.data[index * 2],
+                self.data[index * 2 + 1],
+            ])),
+            FatKind::Fat32 => {
+                let bytes = &self.data[index * 4..index * 4 + 4];
+                u32::from_le_bytes(bytes.try_into().unwrap()) & 0x0fff_ffff
+            }
+        }
+    }
+
+    fn set_raw(&mut self, index: u32, value: u32) {
+        let index = index as usize;
+
+        let (offset, len) = match self.kind {
+            FatKind::Fat12 => {
+                let offset = index + index / 2;
+                let old = u16::from_le_bytes([self.data[offset], self.data[offset + 1]]);
+
+                let new = match index % 2 {
+                    0 => (old & 0xf000) | value as u16,
+                    _ => (old & 0x000f) | ((value as u16) << 4),
+                };
+
+                self.data[offset..offset + 2].copy_from_slice(&new.to_le_bytes());
+                (offset, 2)
+            }
+            FatKind::Fat16 => {
+                self.data[index * 2..index * 2 + 2].copy_from_slice(&(value as u16).to_le_bytes());
+                (index * 2, 2)
+            }
+            FatKind::Fat32 => {
+                // The top four bits are reserved and have to be preserved.
+                let bytes = &mut self.data[index * 4..index * 4 + 4];
+                let old = u32::from_le_bytes((&*bytes).try_into().unwrap());
+                let new = (old & 0xf000_0000) | (value & 0x0fff_ffff);
+
+                bytes.copy_from_slice(&new.to_le_bytes());
+                (index * 4, 4)
+            }
+        };
+
+        let first = offset as u64 / self.sector_size;
+        let last = (offset + len - 1) as u64 / self.sector_size;
+        self.dirty.extend(first..=last);
+    }
+
+    /// Returns the clusters of the chain starting at `first`, which is empty if `first` is zero.
+    pub fn chain(&self, first: u32) -> FsResult<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+
+        if first == 0 {
+            return Ok(chain);
+        }
+
+        loop {
+            // A chain can not be longer than the volume, anything longer has to be a loop.
+            if !self.is_valid(cluster) || chain.len() >= self.cluster_count as usize {
+                return Err(Errno::EIO);
+            }
+
+            chain.push(cluster);
+
+            match self.get(cluster) {
+                Entry::Next(next) => cluster = next,
+                Entry::End => return Ok(chain),
+                Entry::Free | Entry::Bad => return Err(Errno::EIO),
+            }
+        }
+    }
+
+    /// Allocates a free cluster and appends it to the chain ending in `last`, if any.
+    pub fn allocate(&mut self, last: Option<u32>) -> FsResult<u32> {
+        if self.free_count == 0 {
+            return Err(Errno::ENOSPC);
+        }
+
+        let start = self.next_free - FIRST_CLUSTER;
+
+        let cluster = (0..self.cluster_count)
+            .map(|index| FIRST_CLUSTER + (start + index) % self.cluster_count)
+            .find(|&cluster| self.get(cluster) == Entry::Free)
+            .ok_or(Errno::ENOSPC)?;
+
+        self.set(cluster, Entry::End);
+
+        if let Some(last) = last {
+            self.set(last, Entry::Next(cluster));
+        }
+
+        self.next_free = match self.is_valid(cluster + 1) {
+            true => cluster + 1,
+            false => FIRST_CLUSTER,
+        };
+
+        Ok(cluster)
+    }
+
+    /// Frees `first` and every cluster following it.
+    pub fn free_chain(&mut self, first: u32) {
+        let mut cluster = first;
+        let mut remaining = self.cluster_count;
+
+        while self.is_valid(cluster) && remaining != 0 {
+            let entry = self.get(cluster);
+            if matches!(entry, Entry::Free | Entry::Bad) {
+                break;
+            }
+
+            self.set(cluster, Entry::Free);
+            remaining -= 1;
+
+            match entry {
+                Entry::Next(next) => cluster = next,
+                _ => break,
+            }
+        }
+    }
+
+    /// Writes the changed sectors to each of the `copies` of the table starting at the byte
+    /// `offset`, which are `stride` bytes apart.
+    pub fn write_back(
+        &mut self,
+        disk: &Disk,
+        offset: u64,
+        stride: u64,
+        copies: u32,
+    ) -> Result<(), BlockError> {
+        while let Some(sector) = self.dirty.pop_first() {
+            let start = (sector * self.sector_size) as usize;
+            let end = (start + self.sector_size as usize).min(self.data.len());
+
+            for copy in 0..copies as u64 {
+                let result = disk.write_at(
+                    offset + copy * stride + start as u64,
+                    &self.data[start..end],
+                );
+
+                if let Err(err) = result {
+                    self.dirty.insert(sector);
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
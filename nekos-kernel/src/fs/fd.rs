@@ -0,0 +1,67 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use nekos_abi::Errno;
+
+use super::{Console, File, FsResult};
+
+/// The most file descriptors a process may have open at once.
+const MAX_FILES: usize = 256;
+
+/// The open files of a process, indexed by file descriptor.
+#[derive(Clone, Default)]
+pub struct FileTable {
+    files: Vec<Option<Arc<dyn File>>>,
+}
+
+impl FileTable {
+    pub const fn new() -> Self {
+        Self { files: Vec::new() }
+    }
+
+    /// Creates a table with the console open as standard input, output and error.
+    pub fn with_console() -> Self {
+        let console: Arc<dyn File> = Arc::new(Console);
+
+        let mut table = Self::new();
+        for _ in 0..3 {
+            table
+                .insert(console.clone())
+                .expect("Empty file table is full");
+        }
+
+        table
+    }
+
+    /// Adds `file` at the lowest free file descriptor and returns it.
+    pub fn insert(&mut self, file: Arc<dyn File>) -> FsResult<usize> {
+        let descriptor = Some(file);
+
+        if let Some(fd) = self.files.iter().position(Option::is_none) {
+            self.files[fd] = descriptor;
+            return Ok(fd);
+        }
+
+        if self.files.len() >= MAX_FILES {
+            return Err(Errno::EMFILE);
+        }
+
+        self.files.push(descriptor);
+        Ok(self.files.len() - 1)
+    }
+
+    pub fn get(&self, fd: usize) -> FsResult<Arc<dyn File>> {
+        self.files
+            .get(fd)
+            .and_then(Option::as_ref)
+            .cloned()
+            .ok_or(Errno::EBADF)
+    }
+
+    pub fn remove(&mut self, fd: usize) -> FsResult<Arc<dyn File>> {
+        self.files
+            .get_mut(fd)
+            .and_then(Option::take)
+            .ok_or(Errno::EBADF)
+    }
+}
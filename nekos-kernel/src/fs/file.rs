@@ -0,0 +1,150 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+
+use nekos_abi::Errno;
+use nekos_abi::fs::OpenFlags;
+use spin::Mutex;
+
+use super::{Dentry, DirEntry, FsResult, InodeKind, Metadata};
+
+#[derive(Clone, Copy, Debug)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// An open file, shared by every file descriptor that refers to it.
+pub trait File: Send + Sync {
+    fn read(&self, _buf: &mut [u8]) -> FsResult<usize> {
+        Err(Errno::EINVAL)
+    }
+
+    fn write(&self, _buf: &[u8]) -> FsResult<usize> {
+        Err(Errno::EINVAL)
+    }
+
+    fn seek(&self, _pos: SeekFrom) -> FsResult<u64> {
+        Err(Errno::ESPIPE)
+    }
+
+    fn metadata(&self) -> FsResult<Metadata>;
+
+    /// Calls `emit` with the directory entries starting at the current position, until it
+    /// returns `false` or there are no entries left. Only the accepted entries are consumed.
+    fn read_dir(&self, _emit: &mut dyn FnMut(&DirEntry, u64) -> bool) -> FsResult<()> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// The directory entry this file was opened through, if any.
+    fn dentry(&self) -> Option<&Arc<Dentry>> {
+        None
+    }
+}
+
+/// A file opened through the directory tree, which forwards everything to its inode.
+pub struct InodeFile {
+    dentry: Arc<Dentry>,
+    flags: OpenFlags,
+    offset: Mutex<u64>,
+}
+
+impl InodeFile {
+    pub fn new(dentry: Arc<Dentry>, flags: OpenFlags) -> Self {
+        Self {
+            dentry,
+            flags,
+            offset: Mutex::new(0),
+        }
+    }
+}
+
+impl File for InodeFile {
+    fn read(&self, buf: &mut [u8]) -> FsResult<usize> {
+        if !self.flags.readable() {
+            return Err(Errno::EBADF);
+        }
+
+        let mut offset = self.offset.lock();
+        let read = self.dentry.inode().read_at(*offset, buf)?;
+        *offset += read as u64;
+
+        Ok(read)
+    }
+
+    fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        if !self.flags.writable() {
+            return Err(Errno::EBADF);
+        }
+
+        let mut offset = self.offset.lock();
+        if self.flags.contains(OpenFlags::APPEND) {
+            *offset = self.dentry.inode().metadata()?.size;
+        }
+
+        let written = self.dentry.inode().write_at(*offset, buf)?;
+        *offset += written as u64;
+
+        Ok(written)
+    }
+
+    fn seek(&self, pos: SeekFrom) -> FsResult<u64> {
+        let mut offset = self.offset.lock();
+
+        let new_offset = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::Current(delta) => offset.checked_add_signed(delta),
+            SeekFrom::End(delta) => self
+                .dentry
+                .inode()
+                .metadata()?
+                .size
+                .checked_add_signed(delta),
+        };
+
+        *offset = new_offset.ok_or(Errno::EINVAL)?;
+        Ok(*offset)
+    }
+
+    fn metadata(&self) -> FsResult<Metadata> {
+        self.dentry.metadata()
+    }
+
+    fn read_dir(&self, emit: &mut dyn FnMut(&DirEntry, u64) -> bool) -> FsResult<()> {
+        if self.dentry.kind() != InodeKind::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+
+        // The position counts entries, the first two of which are `.` and `..`.
+        let mut offset = self.offset.lock();
+
+        loop {
+            let entry = match *offset {
+                0 => DirEntry {
+                    ino: self.dentry.metadata()?.ino,
+                    kind: InodeKind::Directory,
+                    name: String::from("."),
+                },
+                1 => DirEntry {
+                    ino: self.dentry.parent().metadata()?.ino,
+                    kind: InodeKind::Directory,
+                    name: String::from(".."),
+                },
+                index => match self.dentry.inode().read_dir(index as usize - 2)? {
+                    Some(entry) => entry,
+                    None => return Ok(()),
+                },
+            };
+
+            if !emit(&entry, *offset + 1) {
+                return Ok(());
+            }
+
+            *offset += 1;
+        }
+    }
+
+    fn dentry(&self) -> Option<&Arc<Dentry>> {
+        Some(&self.dentry)
+    }
+}
@@ -0,0 +1,308 @@
+//! The virtual file system.
+//!
+//! File systems expose their contents through the [`Inode`] trait. The VFS caches the directory
+//! tree that has been looked up so far as [`Dentry`]s, resolves paths across mount points, and
+//! hands out [`File`]s which processes refer to through their [`FileTable`].
+
+mod console;
+mod dentry;
+mod ext2;
+mod fat;
+mod fd;
+mod file;
+mod path;
+mod tmpfs;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use nekos_abi::Errno;
+use nekos_abi::fs::{self as abi, OpenFlags, Stat};
+use nekos_abi::time::Timespec;
+use spin::{Mutex, Once};
+
+use crate::{arch, log};
+
+pub use console::Console;
+pub use dentry::Dentry;
+pub use ext2::Ext2;
+pub use fat::Fat;
+pub use fd::FileTable;
+pub use file::{File, InodeFile, SeekFrom};
+pub use path::{lookup, lookup_parent};
+pub use tmpfs::Tmpfs;
+
+pub type FsResult<T> = Result<T, Errno>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InodeKind {
+    File,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+}
+
+impl InodeKind {
+    /// The file type bits of `st_mode`.
+    pub const fn mode_bits(&self) -> u32 {
+        match self {
+            InodeKind::File => abi::S_IFREG,
+            InodeKind::Directory => abi::S_IFDIR,
+            InodeKind::Symlink => abi::S_IFLNK,
+            InodeKind::CharDevice => abi::S_IFCHR,
+            InodeKind::BlockDevice => abi::S_IFBLK,
+            InodeKind::Fifo => abi::S_IFIFO,
+            InodeKind::Socket => abi::S_IFSOCK,
+        }
+    }
+
+    /// The `d_type` reported by `getdents64`.
+    pub const fn dirent_type(&self) -> u8 {
+        match self {
+            InodeKind::File => abi::DT_REG,
+            InodeKind::Directory => abi::DT_DIR,
+            InodeKind::Symlink => abi::DT_LNK,
+            InodeKind::CharDevice => abi::DT_CHR,
+            InodeKind::BlockDevice => abi::DT_BLK,
+            InodeKind::Fifo => abi::DT_FIFO,
+            InodeKind::Socket => abi::DT_SOCK,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Metadata {
+    /// The device the inode belongs to, filled in by the VFS.
+    pub dev: u64,
+    pub ino: u64,
+    pub kind: InodeKind,
+    /// The permission bits.
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub rdev: u64,
+    pub size: u64,
+    pub block_size: u32,
+    /// The number of 512-byte blocks allocated to the inode.
+    pub blocks: u64,
+    pub atime: Timespec,
+    pub mtime: Timespec,
+    pub ctime: Timespec,
+}
+
+impl Metadata {
+    pub fn to_stat(self) -> Stat {
+        Stat {
+            st_dev: self.dev,
+            st_ino: self.ino,
+            st_mode: self.kind.mode_bits() | (self.mode & !abi::S_IFMT),
+            st_nlink: self.nlink,
+            st_uid: self.uid,
+            st_gid: self.gid,
+            st_rdev: self.rdev,
+            st_size: self.size as i64,
+            st_blksize: self.block_size as i32,
+            st_blocks: self.blocks as i64,
+            st_atim: self.atime,
+            st_mtim: self.mtime,
+            st_ctim: self.ctime,
+            ..Default::default()
+        }
+    }
+}
+
+pub struct DirEntry {
+    pub ino: u64,
+    pub kind: InodeKind,
+    pub name: String,
+}
+
+pub trait FileSystem: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Writes back any cached state to the backing storage.
+    fn sync(&self) -> FsResult<()> {
+        Ok(())
+    }
+}
+
+/// A file, directory or other object of a file system.
+///
+/// Every operation has a default that fails the way it would on an inode of the wrong kind, so
+/// that implementations only need to provide what makes sense for them.
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> FsResult<Metadata>;
+
+    /// Finds the child called `name` in this directory.
+    fn lookup(&self, _name: &str) -> FsResult<Arc<dyn Inode>> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Returns the entry at `index` in this directory, not counting `.` and `..`, or `None` past
+    /// the last entry.
+    fn read_dir(&self, _index: usize) -> FsResult<Option<DirEntry>> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Creates an empty file or directory called `name` in this directory.
+    fn create(&self, _name: &str, _kind: InodeKind, _mode: u32) -> FsResult<Arc<dyn Inode>> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Creates a symlink called `name` in this directory that points to `target`.
+    fn symlink(&self, _name: &str, _target: &str) -> FsResult<Arc<dyn Inode>> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Removes the non-directory entry called `name` from this directory.
+    fn unlink(&self, _name: &str) -> FsResult<()> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Removes the empty directory called `name` from this directory.
+    fn rmdir(&self, _name: &str) -> FsResult<()> {
+        Err(Errno::ENOTDIR)
+    }
+
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> FsResult<usize> {
+        Err(Errno::EISDIR)
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> FsResult<usize> {
+        Err(Errno::EISDIR)
+    }
+
+    /// Changes the size of this file, growing it with zeroes if needed.
+    fn truncate(&self, _size: u64) -> FsResult<()> {
+        Err(Errno::EISDIR)
+    }
+
+    /// Returns the target of this symlink.
+    fn read_link(&self) -> FsResult<String> {
+        Err(Errno::EINVAL)
+    }
+}
+
+struct MountPoint {
+    path: String,
+    file_system: Arc<dyn FileSystem>,
+}
+
+static ROOT: Once<Arc<Dentry>> = Once::new();
+static MOUNTS: Mutex<Vec<MountPoint>> = Mutex::new(Vec::new());
+
+/// Returns the root of the directory tree.
+pub fn root() -> FsResult<Arc<Dentry>> {
+    ROOT.get().cloned().ok_or(Errno::ENOENT)
+}
+
+/// Mounts `file_system` on the directory at `path`. The first file system has to be mounted on
+/// `/`.
+pub fn mount(path: &str, file_system: Arc<dyn FileSystem>) -> FsResult<()> {
+    let mut mounts = MOUNTS.lock();
+    let dev = mounts.len() as u64 + 1;
+
+    if path == "/" {
+        if ROOT.is_completed() {
+            return Err(Errno::EBUSY);
+        }
+
+        ROOT.call_once(|| Dentry::new_root(file_system.root(), dev, None));
+    } else {
+        let target = lookup(None, path, true)?;
+        target.mount(file_system.root(), dev)?;
+    }
+
+    log::info!("Mounted {} on {}.", file_system.name(), path);
+
+    mounts.push(MountPoint {
+        path: String::from(path),
+        file_system,
+    });
+
+    Ok(())
+}
+
+/// Writes back the cached state of every mounted file system.
+pub fn sync() -> FsResult<()> {
+    for mount in MOUNTS.lock().iter() {
+        if let Err(err) = mount.file_system.sync() {
+            log::error!("Failed to sync {}: {:?}", mount.path, err);
+        }
+    }
+
+    Ok(())
+}
+
+/// The time stamped on inodes. There is no real-time clock yet, so this counts from boot.
+fn now() -> Timespec {
+    Timespec::from(arch::monotonic_time())
+}
+
+/// Opens the file at `path`, relative to `base` or the root directory.
+pub fn open(
+    base: Option<&Arc<Dentry>>,
+    path: &str,
+    flags: OpenFlags,
+    mode: u32,
+) -> FsResult<Arc<dyn File>> {
+    let follow = !flags.contains(OpenFlags::NOFOLLOW);
+
+    let dentry = if flags.contains(OpenFlags::CREAT) {
+        let (parent, name) = lookup_parent(base, path)?;
+
+        match parent.lookup_child(name) {
+            Ok(_) if flags.contains(OpenFlags::EXCL) => return Err(Errno::EEXIST),
+            Ok(_) => lookup(Some(&parent), name, follow)?,
+            Err(Errno::ENOENT) => parent.create_child(name, InodeKind::File, mode)?,
+            Err(err) => return Err(err),
+        }
+    } else {
+        lookup(base, path, follow)?
+    };
+
+    match dentry.kind() {
+        InodeKind::Directory if flags.writable() => return Err(Errno::EISDIR),
+        InodeKind::Symlink => return Err(Errno::ELOOP),
+        kind if kind != InodeKind::Directory && flags.contains(OpenFlags::DIRECTORY) => {
+            return Err(Errno::ENOTDIR);
+        }
+        InodeKind::File if flags.contains(OpenFlags::TRUNC) && flags.writable() => {
+            dentry.inode().truncate(0)?;
+        }
+        _ => {}
+    }
+
+    Ok(Arc::new(InodeFile::new(dentry, flags)))
+}
+
+/// Reads the whole file at `path` into memory.
+pub fn read_to_end(path: &str) -> FsResult<Vec<u8>> {
+    let dentry = lookup(None, path, true)?;
+    if dentry.kind() != InodeKind::File {
+        return Err(Errno::EINVAL);
+    }
+
+    let inode = dentry.inode();
+    let size = inode.metadata()?.size as usize;
+
+    let mut data = alloc::vec![0u8; size];
+    let mut offset = 0;
+
+    while offset < size {
+        match inode.read_at(offset as u64, &mut data[offset..])? {
+            0 => break,
+            read => offset += read,
+        }
+    }
+
+    data.truncate(offset);
+    Ok(data)
+}
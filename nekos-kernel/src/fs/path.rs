@@ -0,0 +1,112 @@
+use alloc::sync::Arc;
+
+use nekos_abi::Errno;
+use nekos_abi::fs::NAME_MAX;
+
+use super::{Dentry, FsResult, InodeKind};
+
+/// How many symlinks may be followed while resolving a single path.
+const MAX_SYMLINKS: usize = 40;
+
+/// Resolves `path` to a directory entry. Relative paths start at `base`, or the root directory if
+/// there is none. A symlink in the last component is only followed if `follow` is set.
+pub fn lookup(base: Option<&Arc<Dentry>>, path: &str, follow: bool) -> FsResult<Arc<Dentry>> {
+    let base = match base {
+        Some(base) => base.clone(),
+        None => super::root()?,
+    };
+
+    let mut symlinks = 0;
+    resolve(&base, path, follow, &mut symlinks)
+}
+
+/// Resolves everything but the last component of `path`, returning the directory and the name of
+/// the last component.
+pub fn lookup_parent<'a>(
+    base: Option<&Arc<Dentry>>,
+    path: &'a str,
+) -> FsResult<(Arc<Dentry>, &'a str)> {
+    let path = path.trim_end_matches('/');
+
+    let (directory, name) = match path.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((directory, name)) => (directory, name),
+        None => (".", path),
+    };
+
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(Errno::EINVAL);
+    }
+
+    if name.len() > NAME_MAX {
+        return Err(Errno::ENAMETOOLONG);
+    }
+
+    let parent = lookup(base, directory, true)?;
+    if parent.kind() != InodeKind::Directory {
+        return Err(Errno::ENOTDIR);
+    }
+
+    Ok((parent, name))
+}
+
+fn resolve(
+    base: &Arc<Dentry>,
+    path: &str,
+    follow: bool,
+    symlinks: &mut usize,
+) -> FsResult<Arc<Dentry>> {
+    if path.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+
+    let mut current = if path.starts_with('/') {
+        super::root()?
+    } else {
+        base.clone()
+    };
+
+    let mut components = path.split('/').filter(|name| !name.is_empty()).peekable();
+
+    while let Some(name) = components.next() {
+        let is_last = components.peek().is_none();
+
+        if current.kind() != InodeKind::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+
+        match name {
+            "." => continue,
+            ".." => {
+                current = current.parent().follow_mounts();
+                continue;
+            }
+            _ => {}
+        }
+
+        if name.len() > NAME_MAX {
+            return Err(Errno::ENAMETOOLONG);
+        }
+
+        let child = current.lookup_child(name)?.follow_mounts();
+
+        if child.kind() == InodeKind::Symlink && (follow || !is_last) {
+            *symlinks += 1;
+            if *symlinks > MAX_SYMLINKS {
+                return Err(Errno::ELOOP);
+            }
+
+            let target = child.inode().read_link()?;
+            current = resolve(&current, &target, true, symlinks)?;
+        } else {
+            current = child;
+        }
+    }
+
+    // A trailing slash only makes sense for directories.
+    if path.ends_with('/') && current.kind() != InodeKind::Directory {
+        return Err(Errno::ENOTDIR);
+    }
+
+    Ok(current)
+}
@@ -0,0 +1,415 @@
+//! An in-memory file system.
+//!
+//! The contents of files are kept in pages from the page allocator, which are only allocated once
+//! something is written to them, so unwritten ranges of a file are holes that read as zeroes.
+
+use alloc::collections::BTreeMap;
+use alloc::collections::btree_map::Entry;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use nekos_abi::Errno;
+use nekos_abi::time::Timespec;
+use spin::Mutex;
+
+use super::{DirEntry, FileSystem, FsResult, Inode, InodeKind, Metadata, now};
+use crate::arch::PAGE_SIZE;
+use crate::boot;
+use crate::mem::{self, PhysicalAddr, Usage};
+
+const ROOT_INO: u64 = 1;
+
+pub struct Tmpfs {
+    root: Arc<TmpfsInode>,
+}
+
+/// State shared by every inode of a file system.
+struct Shared {
+    /// The most pages the contents of files may take up.
+    max_pages: usize,
+    used_pages: AtomicUsize,
+    next_ino: AtomicU64,
+}
+
+/// A page holding part of the contents of a file, which is freed once dropped.
+struct Page {
+    addr: PhysicalAddr,
+    shared: Arc<Shared>,
+}
+
+struct TmpfsInode {
+    ino: u64,
+    kind: InodeKind,
+    shared: Arc<Shared>,
+    state: Mutex<InodeState>,
+}
+
+struct InodeState {
+    mode: u32,
+    nlink: u32,
+    atime: Timespec,
+    mtime: Timespec,
+    ctime: Timespec,
+    node: Node,
+}
+
+enum Node {
+    File {
+        size: u64,
+        /// The pages of the file by their index, missing pages are holes.
+        pages: BTreeMap<u64, Page>,
+    },
+    Directory(BTreeMap<String, Arc<TmpfsInode>>),
+    Symlink(String),
+}
+
+impl Tmpfs {
+    /// Creates an empty file system whose files may take up at most `size_limit` bytes.
+    pub fn new(size_limit: u64) -> Arc<Self> {
+        let shared = Arc::new(Shared {
+            max_pages: (size_limit / PAGE_SIZE) as usize,
+            used_pages: AtomicUsize::new(0),
+            next_ino: AtomicU64::new(ROOT_INO + 1),
+        });
+
+        let root = TmpfsInode::new(ROOT_INO, shared, 0o755, Node::Directory(BTreeMap::new()));
+
+        Arc::new(Self { root })
+    }
+}
+
+impl FileSystem for Tmpfs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+impl Shared {
+    fn allocate_page(self: &Arc<Self>) -> FsResult<Page> {
+        self.used_pages
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                (used < self.max_pages).then_some(used + 1)
+            })
+            .map_err(|_| Errno::ENOSPC)?;
+
+        match mem::allocate_pages(1, true, Usage::PageCache) {
+            Ok(addr) => Ok(Page {
+                addr,
+                shared: self.clone(),
+            }),
+            Err(_) => {
+                self.used_pages.fetch_sub(1, Ordering::Relaxed);
+                Err(Errno::ENOSPC)
+            }
+        }
+    }
+}
+
+impl Page {
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        let hhdm_offset = boot::BOOT_INFO.get().unwrap().hhdm_offset;
+        let ptr = self
+            .addr
+            .as_virtual_by_offset(hhdm_offset)
+            .as_mut_ptr::<u8>();
+
+        unsafe { core::slice::from_raw_parts_mut(ptr, PAGE_SIZE as usize) }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        let hhdm_offset = boot::BOOT_INFO.get().unwrap().hhdm_offset;
+        let ptr = self
+            .addr
+            .as_virtual_by_offset(hhdm_offset)
+            .as_mut_ptr::<u8>();
+
+        unsafe { core::slice::from_raw_parts(ptr, PAGE_SIZE as usize) }
+    }
+}
+
+impl Drop for Page {
+    fn drop(&mut self) {
+        mem::deallocate_pages(self.addr, 1, Usage::PageCache);
+        self.shared.used_pages.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl TmpfsInode {
+    fn new(ino: u64, shared: Arc<Shared>, mode: u32, node: Node) -> Arc<Self> {
+        let kind = match node {
+            Node::File { .. } => InodeKind::File,
+            Node::Directory(_) => InodeKind::Directory,
+            Node::Symlink(_) => InodeKind::Symlink,
+        };
+
+        let now = now();
+
+        Arc::new(Self {
+            ino,
+            kind,
+            shared,
+            state: Mutex::new(InodeState {
+                mode,
+                nlink: if kind == InodeKind::Directory { 2 } else { 1 },
+                atime: now,
+                mtime: now,
+                ctime: now,
+                node,
+            }),
+        })
+    }
+
+    /// Creates a new inode in the same file system.
+    fn new_sibling(&self, mode: u32, node: Node) -> Arc<Self> {
+        let ino = self.shared.next_ino.fetch_add(1, Ordering::Relaxed);
+        Self::new(ino, self.shared.clone(), mode, node)
+    }
+
+    /// Adds `inode` to this directory under `name`.
+    fn insert(&self, name: &str, inode: Arc<TmpfsInode>) -> FsResult<Arc<dyn Inode>> {
+        let mut state = self.state.lock();
+        let Node::Directory(entries) = &mut state.node else {
+            return Err(Errno::ENOTDIR);
+        };
+
+        if entries.contains_key(name) {
+            return Err(Errno::EEXIST);
+        }
+
+        entries.insert(String::from(name), inode.clone());
+
+        // The `..` entry of a new directory links back to this one.
+        if inode.kind == InodeKind::Directory {
+            state.nlink += 1;
+        }
+
+        state.touch();
+        Ok(inode)
+    }
+
+    /// Removes the entry called `name` from this directory, if `check` accepts its inode.
+    fn remove(&self, name: &str, check: impl FnOnce(&TmpfsInode) -> FsResult<()>) -> FsResult<()> {
+        let mut state = self.state.lock();
+        let Node::Directory(entries) = &mut state.node else {
+            return Err(Errno::ENOTDIR);
+        };
+
+        let inode = entries.get(name).ok_or(Errno::ENOENT)?.clone();
+        check(&inode)?;
+        entries.remove(name);
+
+        let mut inode_state = inode.state.lock();
+        if inode.kind == InodeKind::Directory {
+            inode_state.nlink = 0;
+            state.nlink -= 1;
+        } else {
+            inode_state.nlink -= 1;
+        }
+        inode_state.ctime = now();
+        drop(inode_state);
+
+        state.touch();
+        Ok(())
+    }
+}
+
+impl InodeState {
+    /// Updates the timestamps after the contents changed.
+    fn touch(&mut self) {
+        let now = now();
+        self.mtime = now;
+        self.ctime = now;
+    }
+}
+
+impl Inode for TmpfsInode {
+    fn metadata(&self) -> FsResult<Metadata> {
+        let state = self.state.lock();
+
+        let (size, pages) = match &state.node {
+            Node::File { size, pages } => (*size, pages.len() as u64),
+            Node::Directory(entries) => (entries.len() as u64, 0),
+            Node::Symlink(target) => (target.len() as u64, 0),
+        };
+
+        Ok(Metadata {
+            dev: 0,
+            ino: self.ino,
+            kind: self.kind,
+            mode: state.mode,
+            nlink: state.nlink,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            size,
+            block_size: PAGE_SIZE as u32,
+            blocks: pages * (PAGE_SIZE / 512),
+            atime: state.atime,
+            mtime: state.mtime,
+            ctime: state.ctime,
+        })
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        let state = self.state.lock();
+        let Node::Directory(entries) = &state.node else {
+            return Err(Errno::ENOTDIR);
+        };
+
+        match entries.get(name) {
+            Some(inode) => Ok(inode.clone()),
+            None => Err(Errno::ENOENT),
+        }
+    }
+
+    fn read_dir(&self, index: usize) -> FsResult<Option<DirEntry>> {
+        let state = self.state.lock();
+        let Node::Directory(entries) = &state.node else {
+            return Err(Errno::ENOTDIR);
+        };
+
+        Ok(entries.iter().nth(index).map(|(name, inode)| DirEntry {
+            ino: inode.ino,
+            kind: inode.kind,
+            name: name.clone(),
+        }))
+    }
+
+    fn create(&self, name: &str, kind: InodeKind, mode: u32) -> FsResult<Arc<dyn Inode>> {
+        let node = match kind {
+            InodeKind::File => Node::File {
+                size: 0,
+                pages: BTreeMap::new(),
+            },
+            InodeKind::Directory => Node::Directory(BTreeMap::new()),
+            _ => return Err(Errno::EPERM),
+        };
+
+        self.insert(name, self.new_sibling(mode & 0o7777, node))
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> FsResult<Arc<dyn Inode>> {
+        let node = Node::Symlink(String::from(target));
+        self.insert(name, self.new_sibling(0o777, node))
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()> {
+        self.remove(name, |inode| match inode.kind {
+            InodeKind::Directory => Err(Errno::EISDIR),
+            _ => Ok(()),
+        })
+    }
+
+    fn rmdir(&self, name: &str) -> FsResult<()> {
+        self.remove(name, |inode| match &inode.state.lock().node {
+            Node::Directory(entries) if entries.is_empty() => Ok(()),
+            Node::Directory(_) => Err(Errno::ENOTEMPTY),
+            _ => Err(Errno::ENOTDIR),
+        })
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let mut state = self.state.lock();
+        let Node::File { size, pages } = &state.node else {
+            return Err(Errno::EISDIR);
+        };
+
+        if offset >= *size {
+            return Ok(0);
+        }
+
+        let len = buf.len().min((*size - offset) as usize);
+        let mut done = 0;
+
+        while done < len {
+            let position = offset + done as u64;
+            let page_offset = (position % PAGE_SIZE) as usize;
+            let chunk = (len - done).min(PAGE_SIZE as usize - page_offset);
+            let dst = &mut buf[done..done + chunk];
+
+            match pages.get(&(position / PAGE_SIZE)) {
+                Some(page) => dst.copy_from_slice(&page.as_slice()[page_offset..][..chunk]),
+                None => dst.fill(0),
+            }
+
+            done += chunk;
+        }
+
+        state.atime = now();
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        let mut state = self.state.lock();
+        let Node::File { size, pages } = &mut state.node else {
+            return Err(Errno::EISDIR);
+        };
+
+        offset.checked_add(buf.len() as u64).ok_or(Errno::EFBIG)?;
+
+        let mut done = 0;
+
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let page_offset = (position % PAGE_SIZE) as usize;
+            let chunk = (buf.len() - done).min(PAGE_SIZE as usize - page_offset);
+
+            let page = match pages.entry(position / PAGE_SIZE) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    match self.shared.allocate_page() {
+                        Ok(page) => entry.insert(page),
+                        // Report what has been written so far, the next write fails.
+                        Err(_) if done != 0 => break,
+                        Err(err) => return Err(err),
+                    }
+                }
+            };
+
+            page.as_mut_slice()[page_offset..][..chunk].copy_from_slice(&buf[done..done + chunk]);
+            done += chunk;
+        }
+
+        *size = (*size).max(offset + done as u64);
+        state.touch();
+
+        Ok(done)
+    }
+
+    fn truncate(&self, new_size: u64) -> FsResult<()> {
+        let mut state = self.state.lock();
+        let Node::File { size, pages } = &mut state.node else {
+            return Err(Errno::EISDIR);
+        };
+
+        if new_size < *size {
+            // Drop every page past the end and clear the rest of the last one, so that growing
+            // the file again reads zeroes.
+            drop(pages.split_off(&new_size.div_ceil(PAGE_SIZE)));
+
+            let tail = (new_size % PAGE_SIZE) as usize;
+            if tail != 0
+                && let Some(page) = pages.get_mut(&(new_size / PAGE_SIZE))
+            {
+                page.as_mut_slice()[tail..].fill(0);
+            }
+        }
+
+        *size = new_size;
+        state.touch();
+
+        Ok(())
+    }
+
+    fn read_link(&self) -> FsResult<String> {
+        match &self.state.lock().node {
+            Node::Symlink(target) => Ok(target.clone()),
+            _ => Err(Errno::EINVAL),
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Parser for CPIO archives in the "new ASCII" (newc) format, as produced by `cpio -H newc`.
+
+const MAGIC: &[u8] = b"070701";
+const MAGIC_CRC: &[u8] = b"070702";
+const HEADER_SIZE: usize = 110;
+const TRAILER: &[u8] = b"TRAILER!!!";
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+#[derive(Debug)]
+pub enum CpioError {
+    Truncated,
+    InvalidMagic,
+    InvalidHeader,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+pub struct Entry<'a> {
+    /// The path of the entry relative to the root of the archive, without a leading `./` or `/`.
+    pub path: &'a [u8],
+    pub mode: u32,
+    /// The contents of a file, or the target of a symlink.
+    pub data: &'a [u8],
+}
+
+impl Entry<'_> {
+    pub fn kind(&self) -> EntryKind {
+        match self.mode & S_IFMT {
+            S_IFREG => EntryKind::File,
+            S_IFDIR => EntryKind::Directory,
+            S_IFLNK => EntryKind::Symlink,
+            _ => EntryKind::Other,
+        }
+    }
+
+    /// The permission bits of the entry.
+    pub fn permissions(&self) -> u32 {
+        self.mode & !S_IFMT
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Archive<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Archive<'a> {
+    pub const fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn entries(&self) -> Entries<'a> {
+        Entries {
+            data: self.data,
+            offset: 0,
+            done: false,
+        }
+    }
+}
+
+pub struct Entries<'a> {
+    data: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> Entries<'a> {
+    fn parse_entry(&mut self) -> Result<Option<Entry<'a>>, CpioError> {
+        let header = self
+            .data
+            .get(self.offset..self.offset + HEADER_SIZE)
+            .ok_or(CpioError::Truncated)?;
+
+        if &header[..6] != MAGIC && &header[..6] != MAGIC_CRC {
+            return Err(CpioError::InvalidMagic);
+        }
+
+        // Every field after the magic is 8 hexadecimal digits.
+        let field = |index: usize| parse_hex(&header[6 + index * 8..6 + (index + 1) * 8]);
+
+        let mode = field(1)?;
+        let file_size = field(6)? as usize;
+        let name_size = field(11)? as usize;
+
+        if name_size == 0 {
+            return Err(CpioError::InvalidHeader);
+        }
+
+        let name_start = self.offset + HEADER_SIZE;
+        let name = self
+            .data
+            .get(name_start..name_start + name_size - 1)
+            .ok_or(CpioError::Truncated)?;
+
+        let data_start = align4(name_start + name_size);
+        let data = self
+            .data
+            .get(data_start..data_start + file_size)
+            .ok_or(CpioError::Truncated)?;
+
+        self.offset = align4(data_start + file_size);
+
+        if name == TRAILER {
+            return Ok(None);
+        }
+
+        Ok(Some(Entry {
+            path: normalize(name),
+            mode,
+            data,
+        }))
+    }
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<Entry<'a>, CpioError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.parse_entry() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+fn parse_hex(digits: &[u8]) -> Result<u32, CpioError> {
+    let digits = core::str::from_utf8(digits).map_err(|_| CpioError::InvalidHeader)?;
+    u32::from_str_radix(digits, 16).map_err(|_| CpioError::InvalidHeader)
+}
+
+const fn align4(value: usize) -> usize {
+    (value + 3) & !3
+}
+
+/// Strips the leading `./` or `/` from a path, the root directory itself becomes empty.
+fn normalize(mut path: &[u8]) -> &[u8] {
+    loop {
+        if let Some(rest) = path.strip_prefix(b"./") {
+            path = rest;
+        } else if let Some(rest) = path.strip_prefix(b"/") {
+            path = rest;
+        } else if path == b"." {
+            return &[];
+        } else {
+            return path;
+        }
+    }
+}
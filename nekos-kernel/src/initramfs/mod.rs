@@ -0,0 +1,136 @@
+//! The initial ramdisk loaded by Limine as a boot module.
+//!
+//! Its contents are unpacked into the root file system by [`unpack`]. The memory of the module
+//! stays reserved until [`release`] is called, after which it is handed over to the page
+//! allocator.
+
+pub mod cpio;
+
+use spin::Mutex;
+use ubyte::ToByteUnit;
+
+use nekos_abi::Errno;
+
+use crate::arch::PAGE_SIZE;
+use crate::fs::{self, InodeKind};
+use crate::mem::{self, PhysicalAddr};
+use crate::{boot, log, misc};
+
+use cpio::{Archive, Entry, EntryKind};
+
+/// The string given to the module with `module_string` in `limine.conf`.
+const MODULE_STRING: &[u8] = b"initramfs";
+
+static INITRAMFS: Mutex<Option<&'static [u8]>> = Mutex::new(None);
+
+pub fn init() {
+    let Some(module) = boot::modules()
+        .iter()
+        .find(|module| module.string().to_bytes() == MODULE_STRING)
+    else {
+        log::info!("No initramfs was loaded.");
+        return;
+    };
+
+    let data = unsafe { core::slice::from_raw_parts(module.addr(), module.size() as usize) };
+    *INITRAMFS.lock() = Some(data);
+
+    log::info!("Found initramfs with {}.", module.size().bytes());
+}
+
+/// Runs `f` with the initramfs archive, if there is one and it has not been released yet.
+pub fn with_archive<T>(f: impl FnOnce(Archive<'_>) -> T) -> Option<T> {
+    let initramfs = INITRAMFS.lock();
+    initramfs.map(|data| f(Archive::new(data)))
+}
+
+/// Copies every directory, file and symlink of the initramfs into the root file system.
+pub fn unpack() {
+    let unpacked = with_archive(|archive| {
+        let mut count = 0;
+
+        for entry in archive.entries() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    log::error!("Initramfs is corrupted: {:?}", err);
+                    break;
+                }
+            };
+
+            // The root directory itself already exists.
+            if entry.path.is_empty() {
+                continue;
+            }
+
+            match unpack_entry(&entry) {
+                Ok(()) => count += 1,
+                Err(err) => log::info!(
+                    "Failed to unpack {} from the initramfs: {:?}",
+                    entry.path.escape_ascii(),
+                    err
+                ),
+            }
+        }
+
+        count
+    });
+
+    if let Some(count) = unpacked {
+        log::info!("Unpacked {} entries from the initramfs.", count);
+    }
+}
+
+fn unpack_entry(entry: &Entry<'_>) -> Result<(), Errno> {
+    let path = core::str::from_utf8(entry.path).map_err(|_| Errno::EINVAL)?;
+    let (parent, name) = fs::lookup_parent(None, path)?;
+
+    match entry.kind() {
+        EntryKind::Directory => {
+            match parent.create_child(name, InodeKind::Directory, entry.permissions()) {
+                Ok(_) | Err(Errno::EEXIST) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        EntryKind::File => {
+            let file = parent.create_child(name, InodeKind::File, entry.permissions())?;
+
+            let mut offset = 0;
+            while offset < entry.data.len() {
+                offset += file
+                    .inode()
+                    .write_at(offset as u64, &entry.data[offset..])?;
+            }
+        }
+        EntryKind::Symlink => {
+            let target = core::str::from_utf8(entry.data).map_err(|_| Errno::EINVAL)?;
+            parent.symlink_child(name, target)?;
+        }
+        EntryKind::Other => return Err(Errno::EPERM),
+    }
+
+    Ok(())
+}
+
+/// Gives the memory of the initramfs back to the page allocator.
+pub fn release() {
+    let Some(data) = INITRAMFS.lock().take() else {
+        return;
+    };
+
+    let boot_info = boot::BOOT_INFO.get().unwrap();
+
+    // Limine places modules at page aligned addresses, in memory that is not shared with the
+    // kernel image.
+    let base = PhysicalAddr::new(data.as_ptr() as u64 - boot_info.hhdm_offset);
+    debug_assert!(base.is_aligned_with(PAGE_SIZE));
+
+    let end = misc::align_up_page(base.addr() + data.len() as u64);
+    let num_pages = ((end - base.addr()) / PAGE_SIZE) as usize;
+
+    if num_pages != 0 {
+        mem::add_memory(base, num_pages);
+    }
+
+    log::info!("Released initramfs, freed {}.", (end - base.addr()).bytes());
+}
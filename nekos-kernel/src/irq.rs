@@ -0,0 +1,31 @@
+//! Dispatching of external interrupts to the drivers that registered for them.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+
+use spin::Mutex;
+
+use crate::{arch, log};
+
+pub type IrqHandler = Arc<dyn Fn() + Send + Sync>;
+
+static HANDLERS: Mutex<BTreeMap<u32, IrqHandler>> = Mutex::new(BTreeMap::new());
+
+/// Calls `handler` whenever the interrupt `irq` is raised, replacing any previous handler.
+pub fn register(irq: u32, handler: IrqHandler) {
+    HANDLERS.lock().insert(irq, handler);
+    arch::enable_irq(irq);
+}
+
+/// Runs the handler of `irq`, called by the interrupt controller once it claimed `irq`.
+pub fn handle(irq: u32) {
+    // The handler runs without the lock held, so that it may register other handlers.
+    let handler = HANDLERS.lock().get(&irq).cloned();
+
+    match handler {
+        Some(handler) => handler(),
+        None => {
+            log::debug!("Spurious interrupt {}", irq);
+        }
+    }
+}
@@ -0,0 +1,441 @@
+//! Kernel log messages.
+//!
+//! The kernel logs with the macros of this module, crates it depends on log through the `log`
+//! crate, and both end up here. Every message is stamped with the time since boot and the hart it
+//! came from, kept in a ring buffer that user space can read back with `syslog`, and printed to
+//! the console once it is up.
+//! Which messages are recorded is decided by `loglevel`, which `logfilter` overrides for single
+//! modules, like `logfilter=fs::ext2=debug,block=3`.
+
+mod ring;
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+
+use crate::arch;
+use crate::cmdline::param;
+
+use ring::RingBuffer;
+
+pub use ring::SIZE as BUFFER_SIZE;
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Something failed and could not be recovered from.
+    Error = 1,
+    /// Something unexpected that the kernel works around, like a damaged file system.
+    Warn = 2,
+    /// Messages that are always interesting, like which devices were found.
+    Info = 3,
+    /// Details for debugging the kernel itself.
+    Debug = 4,
+    /// Very detailed output from hot paths.
+    Trace = 5,
+}
+
+impl Level {
+    pub const fn from_raw(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Level::Error),
+            2 => Some(Level::Warn),
+            3 => Some(Level::Info),
+            4 => Some(Level::Debug),
+            5 => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+impl From<::log::Level> for Level {
+    fn from(level: ::log::Level) -> Self {
+        match level {
+            ::log::Level::Error => Level::Error,
+            ::log::Level::Warn => Level::Warn,
+            ::log::Level::Info => Level::Info,
+            ::log::Level::Debug => Level::Debug,
+            ::log::Level::Trace => Level::Trace,
+        }
+    }
+}
+
+const DEFAULT_LEVEL: u8 = if cfg!(debug_assertions) {
+    Level::Debug as u8
+} else {
+    Level::Info as u8
+};
+
+param!(
+    /// The most verbose level of messages recorded, `loglevel=4` also records debug messages.
+    pub loglevel: u8 = DEFAULT_LEVEL
+);
+
+param!(
+    /// Comma separated `module=level` pairs that override `loglevel` for a module and everything
+    /// below it. Levels are given by name or number.
+    pub logfilter: &str = ""
+);
+
+/// The level in effect, which starts out as `loglevel` and can be changed at run time.
+static LEVEL: AtomicU8 = AtomicU8::new(DEFAULT_LEVEL);
+
+/// Messages are only kept in the ring buffer until the console comes up.
+static CONSOLE_READY: AtomicBool = AtomicBool::new(false);
+
+static BUFFER: RingBuffer = RingBuffer::new();
+
+/// Where `syslog` starts reading after the log was cleared.
+static CLEARED: AtomicUsize = AtomicUsize::new(0);
+
+/// Messages longer than this are cut off.
+const LINE_MAX: usize = 512;
+
+/// Hands the messages of the `log` crate to the kernel log.
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+impl ::log::Log for Logger {
+    fn enabled(&self, metadata: &::log::Metadata) -> bool {
+        enabled(metadata.level().into(), metadata.target())
+    }
+
+    fn log(&self, record: &::log::Record) {
+        log(record.level().into(), record.target(), *record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+/// Applies the level from the command line and installs the logger of the `log` crate.
+pub fn init() {
+    set_level(loglevel.get());
+
+    // This can only fail if it is called twice.
+    let _ = ::log::set_logger(&LOGGER);
+}
+
+/// Starts printing messages to the console, beginning with the ones recorded so far.
+pub fn init_console() {
+    let mut position = BUFFER.start();
+    let mut buf = [0; LINE_MAX];
+
+    loop {
+        let (start, len) = BUFFER.read(position, &mut buf);
+        if len == 0 {
+            break;
+        }
+
+        // Only replay whole lines, a line cut off by the buffer size is printed on its own.
+        let text = &buf[..len];
+        let line_len = text
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(len, |index| index + 1);
+
+        if let Ok(line) = core::str::from_utf8(&text[..line_len])
+            && let Some((level, header, message)) = split_record(line)
+        {
+            print(level, header, message.trim_end_matches('\n'));
+        }
+
+        position = start + line_len;
+    }
+
+    CONSOLE_READY.store(true, Ordering::Release);
+}
+
+/// Prints the recorded messages if the console has not been started yet, so that they are not
+/// lost when the kernel panics early.
+pub fn flush() {
+    if !CONSOLE_READY.load(Ordering::Acquire) {
+        init_console();
+    }
+}
+
+pub fn level() -> u8 {
+    LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn set_level(level: u8) {
+    LEVEL.store(level, Ordering::Relaxed);
+
+    // Let the `log` crate drop messages early that no filter would let through.
+    let max_level = logfilter
+        .get()
+        .split(',')
+        .filter_map(|directive| parse_level(directive.split_once('=')?.1))
+        .fold(level, u8::max);
+
+    ::log::set_max_level(match Level::from_raw(max_level.min(Level::Trace as u8)) {
+        Some(Level::Error) => ::log::LevelFilter::Error,
+        Some(Level::Warn) => ::log::LevelFilter::Warn,
+        Some(Level::Info) => ::log::LevelFilter::Info,
+        Some(Level::Debug) => ::log::LevelFilter::Debug,
+        Some(Level::Trace) => ::log::LevelFilter::Trace,
+        None => ::log::LevelFilter::Off,
+    });
+}
+
+/// Whether messages of `level` from `module` are recorded.
+pub fn enabled(level: Level, module: &str) -> bool {
+    level as u8 <= max_level(module)
+}
+
+fn max_level(module: &str) -> u8 {
+    let module = module.strip_prefix("nekos_kernel::").unwrap_or(module);
+
+    // The longest matching module path wins.
+    let mut best: Option<(usize, u8)> = None;
+
+    for directive in logfilter.get().split(',') {
+        let Some((path, level)) = directive.split_once('=') else {
+            continue;
+        };
+
+        let Some(level) = parse_level(level) else {
+            continue;
+        };
+
+        let matches = module
+            .strip_prefix(path)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"));
+
+        if matches && best.is_none_or(|(len, _)| path.len() > len) {
+            best = Some((path.len(), level));
+        }
+    }
+
+    best.map_or_else(level, |(_, level)| level)
+}
+
+fn parse_level(value: &str) -> Option<u8> {
+    if let Ok(level) = value.parse() {
+        return Some(level);
+    }
+
+    (1..=5)
+        .filter_map(Level::from_raw)
+        .find(|level| level.name() == value)
+        .map(|level| level as u8)
+}
+
+/// Records a message and prints it if the console is up. Called by the logging macros.
+pub fn log(level: Level, module: &str, args: fmt::Arguments) {
+    if !enabled(level, module) {
+        return;
+    }
+
+    let time = arch::monotonic_time();
+    let mut line = Line::new();
+
+    // Writing to a `Line` never fails, it cuts off what does not fit.
+    let _ = write!(
+        line,
+        "<{}>[{:5}.{:06}] [hart {}] ",
+        level as u8,
+        time.as_secs(),
+        time.subsec_micros(),
+        arch::current_hart_id()
+    );
+    let header_len = line.len;
+    let _ = line.write_fmt(args);
+    line.finish();
+
+    BUFFER.push(line.as_bytes());
+
+    if CONSOLE_READY.load(Ordering::Acquire) {
+        let text = line.as_str();
+        // Skip the `<level>` prefix, which is only meant for readers of the ring buffer.
+        let prefix = text.find('>').map_or(0, |index| index + 1);
+        print(
+            level,
+            &text[prefix..header_len],
+            text[header_len..].trim_end_matches('\n'),
+        );
+    }
+}
+
+/// Splits a line of the ring buffer into its level, timestamp and hart header and the message.
+fn split_record(line: &str) -> Option<(Level, &str, &str)> {
+    let (level, rest) = line.strip_prefix('<')?.split_once('>')?;
+    let level = Level::from_raw(level.parse().ok()?)?;
+
+    let hart = rest.find("] [hart ")?;
+    let header_len = hart + rest[hart + 2..].find("] ")? + 4;
+
+    Some((level, &rest[..header_len], &rest[header_len..]))
+}
+
+fn print(level: Level, header: &str, message: &str) {
+    use colorz::Colorize;
+
+    match level {
+        Level::Error => {
+            arch::print!(
+                "{}{}{}{} {}\n",
+                header.bright_black(),
+                "[".red(),
+                "error".red().bold(),
+                "]:".red(),
+                message.red()
+            );
+        }
+        Level::Warn => {
+            arch::print!(
+                "{}{}{}{} {}\n",
+                header.bright_black(),
+                "[".yellow(),
+                "warn".yellow().bold(),
+                "]:".yellow(),
+                message.yellow()
+            );
+        }
+        Level::Info => {
+            arch::print!(
+                "{}{}{}{} {}\n",
+                header.bright_black(),
+                "[".green(),
+                "info".cyan().bold(),
+                "]:".green(),
+                message.green()
+            );
+        }
+        Level::Debug => {
+            arch::print!(
+                "{}{}{}{} {}\n",
+                header.bright_black(),
+                "[".bright_black(),
+                "debug".bright_black().bold(),
+                "]:".bright_black(),
+                message.bright_black()
+            );
+        }
+        Level::Trace => {
+            arch::print!(
+                "{}{}{}{} {}\n",
+                header.bright_black(),
+                "[".bright_black(),
+                "trace".magenta().bold(),
+                "]:".bright_black(),
+                message.bright_black()
+            );
+        }
+    }
+}
+
+/// Copies the text recorded since the log was last cleared into `buf`, keeping the newest text if
+/// it does not all fit. Returns the number of bytes copied.
+pub fn read_all(buf: &mut [u8]) -> usize {
+    let end = BUFFER.end();
+    let position = CLEARED
+        .load(Ordering::Relaxed)
+        .max(end.saturating_sub(buf.len()));
+
+    BUFFER.read(position, buf).1
+}
+
+/// Makes [`read_all`] skip the text recorded so far.
+pub fn clear() {
+    CLEARED.store(BUFFER.end(), Ordering::Relaxed);
+}
+
+/// A log line being formatted on the stack, since messages are logged before the heap exists.
+struct Line {
+    buf: [u8; LINE_MAX],
+    len: usize,
+}
+
+impl Line {
+    fn new() -> Self {
+        Self {
+            buf: [0; LINE_MAX],
+            len: 0,
+        }
+    }
+
+    /// Terminates the line, replacing the last character if the line is full.
+    fn finish(&mut self) {
+        if self.len == LINE_MAX {
+            self.len -= 1;
+
+            // Continuation bytes of UTF-8 start with `0b10`.
+            while self.buf[self.len] & 0xC0 == 0x80 {
+                self.len -= 1;
+            }
+        }
+
+        self.buf[self.len] = b'\n';
+        self.len += 1;
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    fn as_str(&self) -> &str {
+        // SAFETY: Only whole characters are ever written to the buffer.
+        unsafe { core::str::from_utf8_unchecked(self.as_bytes()) }
+    }
+}
+
+impl fmt::Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(LINE_MAX - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+
+        Ok(())
+    }
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Error, module_path!(), format_args!($($arg)*))
+    };
+}
+
+// Named differently since `warn` alone would clash with the built-in attribute.
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Warn, module_path!(), format_args!($($arg)*))
+    };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Info, module_path!(), format_args!($($arg)*))
+    };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Debug, module_path!(), format_args!($($arg)*))
+    };
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Trace, module_path!(), format_args!($($arg)*))
+    };
+}
+
+pub(crate) use debug;
+pub(crate) use error;
+pub(crate) use info;
+pub(crate) use trace;
+pub(crate) use warning as warn;
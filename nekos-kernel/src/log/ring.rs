@@ -0,0 +1,121 @@
+//! A fixed-size ring buffer of log text that never blocks writers.
+//!
+//! Every byte ever written has a position in an endless stream. Writers reserve a range of the
+//! stream with a single atomic add and copy their bytes into it, older text is silently
+//! overwritten once the stream wraps around. Readers copy a range out and check afterwards that
+//! no writer reached it in the meantime.
+
+use core::cell::UnsafeCell;
+use core::hint;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub const SIZE: usize = 64 * 1024;
+
+/// How long a reader waits for writers that are still copying before it reads around them.
+const MAX_WAIT_SPINS: usize = 1 << 20;
+
+pub struct RingBuffer {
+    data: UnsafeCell<[u8; SIZE]>,
+    /// The end of the text writers have reserved space for.
+    reserved: AtomicUsize,
+    /// The end of the text writers have finished copying.
+    committed: AtomicUsize,
+}
+
+/// SAFETY: Writers only touch the range they reserved, and readers discard anything a writer may
+/// have touched while they were copying.
+unsafe impl Sync for RingBuffer {}
+
+impl RingBuffer {
+    pub const fn new() -> Self {
+        Self {
+            data: UnsafeCell::new([0; SIZE]),
+            reserved: AtomicUsize::new(0),
+            committed: AtomicUsize::new(0),
+        }
+    }
+
+    /// Appends `bytes`, keeping only the last [`SIZE`] bytes of anything longer.
+    pub fn push(&self, bytes: &[u8]) {
+        let bytes = &bytes[bytes.len().saturating_sub(SIZE)..];
+        let start = self.reserved.fetch_add(bytes.len(), Ordering::AcqRel);
+
+        self.copy_in(start, bytes);
+        self.committed.fetch_add(bytes.len(), Ordering::Release);
+    }
+
+    /// The position just past the last byte written.
+    pub fn end(&self) -> usize {
+        self.committed.load(Ordering::Acquire)
+    }
+
+    /// The position of the oldest byte still in the buffer.
+    pub fn start(&self) -> usize {
+        self.end().saturating_sub(SIZE)
+    }
+
+    /// Copies the text from `position` on into `buf`, skipping anything that was already
+    /// overwritten. Returns the position the copied text starts at and its length.
+    ///
+    /// A writer that never finishes, like one on a hart that was stopped by a panic, leaves a hole
+    /// of stale text in what is read once the reader gave up waiting for it.
+    pub fn read(&self, position: usize, buf: &mut [u8]) -> (usize, usize) {
+        let mut spins = 0;
+
+        loop {
+            // Wait for writers that are still copying, so the range read has no holes.
+            let end = self.reserved.load(Ordering::Acquire);
+            if self.end() != end && spins < MAX_WAIT_SPINS {
+                spins += 1;
+                hint::spin_loop();
+                continue;
+            }
+
+            let start = position.max(end.saturating_sub(SIZE)).min(end);
+            let len = (end - start).min(buf.len());
+            self.copy_out(start, &mut buf[..len]);
+
+            // Anything a writer reserved since then may have overwritten the front of the copy.
+            let overwritten = self
+                .reserved
+                .load(Ordering::Acquire)
+                .saturating_sub(SIZE)
+                .saturating_sub(start);
+
+            if overwritten == 0 {
+                return (start, len);
+            }
+
+            if overwritten < len {
+                buf.copy_within(overwritten..len, 0);
+                return (start + overwritten, len - overwritten);
+            }
+        }
+    }
+
+    fn copy_in(&self, position: usize, bytes: &[u8]) {
+        let data = self.data.get().cast::<u8>();
+        let offset = position % SIZE;
+        let first = bytes.len().min(SIZE - offset);
+
+        unsafe {
+            data.add(offset)
+                .copy_from_nonoverlapping(bytes.as_ptr(), first);
+            data.copy_from_nonoverlapping(bytes[first..].as_ptr(), bytes.len() - first);
+        }
+    }
+
+    fn copy_out(&self, position: usize, buf: &mut [u8]) {
+        let data = self.data.get().cast::<u8>();
+        let offset = position % SIZE;
+        let first = buf.len().min(SIZE - offset);
+
+        unsafe {
+            buf.as_mut_ptr()
+                .copy_from_nonoverlapping(data.add(offset), first);
+            buf[first..]
+                .as_mut_ptr()
+                .copy_from_nonoverlapping(data, buf.len() - first);
+        }
+    }
+}
@@ -1,34 +1,111 @@
 #![no_std]
 #![no_main]
 
+extern crate alloc;
+
 pub mod log;
 pub mod misc;
 
 pub mod arch;
+mod backtrace;
+mod block;
 mod boot;
+mod cmdline;
+mod drivers;
+mod elf;
+mod fdt;
+mod fs;
+mod initramfs;
+mod irq;
 mod mem;
+mod panic;
+mod process;
+mod syscall;
+#[cfg(feature = "kernel-tests")]
+mod test;
+
+use alloc::sync::Arc;
 
-use arch::print;
+use cmdline::param;
 
 #[unsafe(no_mangle)]
 extern "C" fn kmain() -> ! {
     boot::init();
     arch::init();
+    log::init_console();
     mem::init();
+    drivers::init();
+    block::init();
+    initramfs::init();
+
+    mount_root();
+    initramfs::release();
+    mem::meminfo::log(log::Level::Info);
+
+    // Kernels built for testing run their tests in place of init.
+    #[cfg(feature = "kernel-tests")]
+    test::run();
 
-    arch::halt();
+    #[cfg(not(feature = "kernel-tests"))]
+    {
+        spawn_init();
+        process::run();
+    }
 }
 
-#[panic_handler]
-fn panic_handler(info: &core::panic::PanicInfo) -> ! {
-    use colorz::Colorize;
-    print!(
-        "{}{}{} {}\n",
-        "[".red(),
-        "panic".red().bold(),
-        "]:".red(),
-        info.red()
-    );
-
-    arch::halt();
+/// The most memory the files of the root file system may take up.
+const ROOT_FS_SIZE: u64 = 256 * 1024 * 1024;
+
+param!(
+    /// The block device holding the root file system, like `root=vda1` or `root=PARTUUID=...`.
+    /// Without it the initramfs is unpacked into a tmpfs.
+    root: Option<&str> = None
+);
+
+param!(
+    /// The first user program.
+    init: &str = "/init"
+);
+
+fn mount_root() {
+    let Some(spec) = root.get() else {
+        fs::mount("/", fs::Tmpfs::new(ROOT_FS_SIZE)).expect("Failed to mount the root file system");
+        initramfs::unpack();
+        return;
+    };
+
+    let Some(disk) = block::resolve(spec) else {
+        panic!("Root device {} not found", spec);
+    };
+
+    // Anything that is not ext2 is tried as FAT, but an ext2 volume that can not be mounted is
+    // not.
+    let file_system: Arc<dyn fs::FileSystem> = if fs::Ext2::probe(&disk) {
+        match fs::Ext2::new(disk) {
+            Ok(ext2) => ext2,
+            Err(err) => panic!("Failed to mount the ext2 volume on {}: {:?}", spec, err),
+        }
+    } else {
+        match fs::Fat::new(disk) {
+            Ok(fat) => fat,
+            Err(err) => panic!("No supported file system on {}: {:?}", spec, err),
+        }
+    };
+
+    fs::mount("/", file_system).expect("Failed to mount the root file system");
+    log::info!("Mounted {} as the root file system.", spec);
+}
+
+#[cfg(not(feature = "kernel-tests"))]
+fn spawn_init() {
+    let path = init.get();
+    let image = match fs::read_to_end(path) {
+        Ok(image) => image,
+        Err(err) => panic!("Failed to read {}: {:?}", path, err),
+    };
+
+    match process::spawn_elf(&image, &[path.as_bytes()], &[]) {
+        Ok(pid) => log::info!("Started init as process {}.", pid),
+        Err(err) => panic!("Failed to start init: {:?}", err),
+    }
 }
@@ -0,0 +1,816 @@
+use alloc::sync::Arc;
+
+use nekos_mem::vma::{Area, Backing, VmaTree};
+
+use crate::arch::{self, PAGE_SIZE};
+use crate::fs::Dentry;
+use crate::{boot, misc};
+
+use super::{
+    Mapping, PageDirectory, PageMapErr, PhysicalAddr, Usage, VirtualAddr, VirtualMemoryFlags,
+};
+
+/// How far a stack may grow down from the end of its area.
+const MAX_STACK_SIZE: u64 = 8 * 1024 * 1024;
+
+/// A file mapped into an address space. Mappings of the same file are told apart from others by
+/// the identity of its dentry.
+#[derive(Clone)]
+pub struct MappedFile(pub Arc<Dentry>);
+
+impl PartialEq for MappedFile {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// The kind of access that faulted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+/// A user address space.
+///
+/// The kernel half is shared with the kernel page directory, while the user half is private to
+/// this address space. Every frame mapped into the user half is owned by the address space and is
+/// freed along with its page tables when it is dropped, unless a fork still shares it.
+///
+/// What the user half may contain is described by its areas. Their pages are mapped either right
+/// away or on the first fault, which is how file mappings, `brk` and growing stacks get memory.
+pub struct AddressSpace {
+    root_page_table: PhysicalAddr,
+    areas: VmaTree<MappedFile>,
+}
+
+impl AddressSpace {
+    pub fn new() -> Result<Self, PageMapErr> {
+        let root_page_table = super::allocate_pages(1, true, Usage::PageTable)
+            .map_err(|_| PageMapErr::PageFrameAllocError)?;
+
+        arch::copy_kernel_mappings(
+            super::kernel_page_directory().root_page_table(),
+            root_page_table,
+        );
+
+        Ok(Self {
+            root_page_table,
+            areas: VmaTree::new(),
+        })
+    }
+
+    /// Creates a copy of this address space that shares all user frames copy-on-write.
+    pub fn fork(&mut self) -> Result<AddressSpace, PageMapErr> {
+        let mut child = AddressSpace::new()?;
+        child.areas = self.areas.clone();
+
+        let result = arch::share_user_mappings(self.root_page_table, child.root_page_table);
+
+        // Pages of this address space that were writable are not anymore, even if sharing failed
+        // halfway through.
+        arch::shootdown_tlb(VirtualAddr::new(0), arch::user_space_end().addr() as usize);
+
+        result.map(|()| child)
+    }
+
+    /// Maps `size` bytes at `physical_addr` to `virtual_addr` as user accessible memory. The
+    /// ownership of the frames is transferred to this address space.
+    pub fn map(
+        &mut self,
+        virtual_addr: VirtualAddr,
+        physical_addr: PhysicalAddr,
+        size: usize,
+        flags: VirtualMemoryFlags,
+    ) -> Result<(), PageMapErr> {
+        if !Self::is_user_range(virtual_addr, size) {
+            return Err(PageMapErr::InvalidVirtualAddr);
+        }
+
+        arch::map_page(
+            self.root_page_table,
+            virtual_addr,
+            physical_addr,
+            size,
+            flags | VirtualMemoryFlags::UserAccessible,
+        )?;
+
+        let end = VirtualAddr::new(virtual_addr.addr() + misc::align_up_page(size as u64));
+        let flags = flags - VirtualMemoryFlags::UserAccessible - VirtualMemoryFlags::CopyOnWrite;
+        self.areas
+            .insert(Area::new(virtual_addr, end, flags, Backing::Anonymous));
+
+        Ok(())
+    }
+
+    /// Reserves `[virtual_addr, virtual_addr + size)` for memory filled from `backing`, replacing
+    /// what was there. Nothing is mapped until the pages are touched.
+    pub fn map_area(
+        &mut self,
+        virtual_addr: VirtualAddr,
+        size: usize,
+        flags: VirtualMemoryFlags,
+        backing: Backing<MappedFile>,
+    ) -> Result<(), PageMapErr> {
+        if !virtual_addr.is_aligned_with(PAGE_SIZE) {
+            return Err(PageMapErr::UnalignedVirtualAddr);
+        }
+
+        let size = misc::align_up_page(size as u64);
+        if size == 0 || !Self::is_user_range(virtual_addr, size as usize) {
+            return Err(PageMapErr::InvalidVirtualAddr);
+        }
+
+        self.unmap(virtual_addr, size as usize)?;
+
+        let end = VirtualAddr::new(virtual_addr.addr() + size);
+        self.areas
+            .insert(Area::new(virtual_addr, end, flags, backing));
+
+        Ok(())
+    }
+
+    /// Maps `size` bytes of freshly allocated zeroed memory to `virtual_addr`.
+    pub fn map_anonymous(
+        &mut self,
+        virtual_addr: VirtualAddr,
+        size: usize,
+        flags: VirtualMemoryFlags,
+    ) -> Result<(), PageMapErr> {
+        if !virtual_addr.is_aligned_with(PAGE_SIZE) {
+            return Err(PageMapErr::UnalignedVirtualAddr);
+        }
+
+        let size = misc::align_up_page(size as u64);
+
+        for offset in (0..size).step_by(PAGE_SIZE as usize) {
+            let frame = super::allocate_pages(1, true, Usage::User)
+                .map_err(|_| PageMapErr::PageFrameAllocError)?;
+
+            let page = VirtualAddr::new(virtual_addr.addr() + offset);
+            if let Err(err) = self.map(page, frame, PAGE_SIZE as usize, flags) {
+                super::deallocate_pages(frame, 1, Usage::User);
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Copies `bytes` to `virtual_addr` in this address space, regardless of whether it is the
+    /// active one. The pages have to be mapped already, but need not be writable.
+    pub fn write(&mut self, virtual_addr: VirtualAddr, bytes: &[u8]) -> Result<(), PageMapErr> {
+        self.unshare(virtual_addr, bytes.len())?;
+        self.for_each_chunk(virtual_addr, bytes.len(), |ptr, offset, len| unsafe {
+            core::ptr::copy_nonoverlapping(bytes[offset..].as_ptr(), ptr, len);
+        })
+    }
+
+    /// Fills `size` bytes at `virtual_addr` in this address space with zeroes.
+    pub fn zero(&mut self, virtual_addr: VirtualAddr, size: usize) -> Result<(), PageMapErr> {
+        self.unshare(virtual_addr, size)?;
+        self.for_each_chunk(virtual_addr, size, |ptr, _, len| unsafe {
+            core::ptr::write_bytes(ptr, 0, len);
+        })
+    }
+
+    /// Copies `buf.len()` bytes at `virtual_addr` in this address space into `buf`.
+    pub fn read(&self, virtual_addr: VirtualAddr, buf: &mut [u8]) -> Result<(), PageMapErr> {
+        self.for_each_chunk(virtual_addr, buf.len(), |ptr, offset, len| unsafe {
+            core::ptr::copy_nonoverlapping(ptr, buf[offset..].as_mut_ptr(), len);
+        })
+    }
+
+    /// Gives the pages of `[virtual_addr, virtual_addr + size)` that are shared with a fork frames
+    /// of their own, before the kernel writes to them through the direct map.
+    fn unshare(&mut self, virtual_addr: VirtualAddr, size: usize) -> Result<(), PageMapErr> {
+        let end = virtual_addr.addr() + size as u64;
+        let mut addr = misc::align_down_page(virtual_addr.addr());
+
+        while addr < end {
+            let mapping = self
+                .translate(VirtualAddr::new(addr))
+                .ok_or(PageMapErr::InvalidVirtualAddr)?;
+
+            // Writes to shared pages are meant to be seen by the other users.
+            let shared = mapping.flags.contains(VirtualMemoryFlags::Shared);
+            if !shared
+                && super::frame(mapping.physical_addr).is_some_and(|frame| frame.refcount() > 1)
+            {
+                super::cow::unshare(self.root_page_table, mapping)?;
+            }
+
+            addr = mapping.virtual_addr.addr() + mapping.size;
+        }
+
+        Ok(())
+    }
+
+    /// Calls `f` with the kernel pointer, the offset and the length of each physically contiguous
+    /// chunk of `[virtual_addr, virtual_addr + size)`.
+    fn for_each_chunk(
+        &self,
+        virtual_addr: VirtualAddr,
+        size: usize,
+        mut f: impl FnMut(*mut u8, usize, usize),
+    ) -> Result<(), PageMapErr> {
+        let hhdm_offset = boot::BOOT_INFO.get().unwrap().hhdm_offset;
+        let mut offset = 0;
+
+        while offset < size {
+            let addr = virtual_addr.addr() + offset as u64;
+            let mapping = self
+                .translate(VirtualAddr::new(addr))
+                .ok_or(PageMapErr::InvalidVirtualAddr)?;
+
+            let offset_in_mapping = addr - mapping.virtual_addr.addr();
+            let len = ((mapping.size - offset_in_mapping) as usize).min(size - offset);
+
+            let physical_addr = PhysicalAddr::new(mapping.physical_addr.addr() + offset_in_mapping);
+            f(
+                physical_addr.as_virtual_by_offset(hhdm_offset).as_mut_ptr(),
+                offset,
+                len,
+            );
+
+            offset += len;
+        }
+
+        Ok(())
+    }
+
+    pub fn translate(&self, virtual_addr: VirtualAddr) -> Option<Mapping> {
+        arch::translate(self.root_page_table, virtual_addr)
+    }
+
+    /// Unmaps `[virtual_addr, virtual_addr + size)` and frees the frames that backed it, unless a
+    /// fork still shares them. Pages in the range that are not mapped are skipped, and the areas
+    /// are cut to what is left.
+    pub fn unmap(&mut self, virtual_addr: VirtualAddr, size: usize) -> Result<(), PageMapErr> {
+        if !virtual_addr.is_aligned_with(PAGE_SIZE) {
+            return Err(PageMapErr::UnalignedVirtualAddr);
+        }
+
+        if !Self::is_user_range(virtual_addr, size) {
+            return Err(PageMapErr::InvalidVirtualAddr);
+        }
+
+        let end = virtual_addr.addr() + misc::align_up_page(size as u64);
+
+        // Large pages can only be removed as a whole.
+        self.check_whole_pages(virtual_addr.addr(), end)?;
+
+        let mut addr = virtual_addr.addr();
+
+        while addr < end {
+            let Some(mapping) = self.translate(VirtualAddr::new(addr)) else {
+                addr += PAGE_SIZE;
+                continue;
+            };
+
+            let mapping_end = mapping.virtual_addr.addr() + mapping.size;
+
+            arch::unmap_page(self.root_page_table, mapping.virtual_addr);
+            arch::shootdown_tlb(mapping.virtual_addr, mapping.size as usize);
+            super::release_pages(
+                mapping.physical_addr,
+                (mapping.size / PAGE_SIZE) as usize,
+                Usage::User,
+            );
+
+            addr = mapping_end;
+        }
+
+        self.areas.remove(virtual_addr, VirtualAddr::new(end));
+
+        Ok(())
+    }
+
+    /// Changes whether the pages of `[virtual_addr, virtual_addr + size)` are accessible, writable
+    /// and executable to what `flags` says. The whole range has to be mapped.
+    ///
+    /// Pages that become inaccessible stay mapped without the user bit, so that their contents
+    /// are kept for when they are made accessible again.
+    pub fn protect(
+        &mut self,
+        virtual_addr: VirtualAddr,
+        size: usize,
+        flags: VirtualMemoryFlags,
+    ) -> Result<(), PageMapErr> {
+        if !virtual_addr.is_aligned_with(PAGE_SIZE) {
+            return Err(PageMapErr::UnalignedVirtualAddr);
+        }
+
+        if !Self::is_user_range(virtual_addr, size) {
+            return Err(PageMapErr::InvalidVirtualAddr);
+        }
+
+        let end = virtual_addr.addr() + misc::align_up_page(size as u64);
+
+        // Large pages can only be changed as a whole.
+        self.check_whole_pages(virtual_addr.addr(), end)?;
+
+        self.areas
+            .protect(virtual_addr, VirtualAddr::new(end), flags)
+            .map_err(|_| PageMapErr::NotMapped)?;
+
+        let mut addr = virtual_addr.addr();
+
+        while addr < end {
+            let Some(mapping) = self.translate(VirtualAddr::new(addr)) else {
+                addr += PAGE_SIZE;
+                continue;
+            };
+
+            let mapping_end = mapping.virtual_addr.addr() + mapping.size;
+
+            let mut new_flags = mapping.flags
+                - VirtualMemoryFlags::Writeable
+                - VirtualMemoryFlags::Executable
+                - VirtualMemoryFlags::CopyOnWrite
+                - VirtualMemoryFlags::UserAccessible;
+
+            if !flags.contains(VirtualMemoryFlags::NoAccess) {
+                new_flags |= VirtualMemoryFlags::UserAccessible;
+            }
+
+            if flags.contains(VirtualMemoryFlags::Executable) {
+                new_flags |= VirtualMemoryFlags::Executable;
+            }
+
+            // Frames still shared with a fork are only written to once they are copied.
+            if flags.contains(VirtualMemoryFlags::Writeable) {
+                let shared_with_fork = !mapping.flags.contains(VirtualMemoryFlags::Shared)
+                    && super::frame(mapping.physical_addr)
+                        .is_some_and(|frame| frame.refcount() > 1);
+
+                new_flags |= if shared_with_fork {
+                    VirtualMemoryFlags::CopyOnWrite
+                } else {
+                    VirtualMemoryFlags::Writeable
+                };
+            }
+
+            arch::unmap_page(self.root_page_table, mapping.virtual_addr);
+            arch::map_page(
+                self.root_page_table,
+                mapping.virtual_addr,
+                mapping.physical_addr,
+                mapping.size as usize,
+                new_flags,
+            )
+            .expect("Failed to remap a page in place");
+            arch::shootdown_tlb(mapping.virtual_addr, mapping.size as usize);
+
+            addr = mapping_end;
+        }
+
+        Ok(())
+    }
+
+    /// Fails with `UnalignedSize` if a page mapped in `[start, end)` reaches outside of it, before
+    /// anything in the range is changed.
+    fn check_whole_pages(&self, start: u64, end: u64) -> Result<(), PageMapErr> {
+        let mut addr = start;
+
+        while addr < end {
+            let Some(mapping) = self.translate(VirtualAddr::new(addr)) else {
+                addr += PAGE_SIZE;
+                continue;
+            };
+
+            let mapping_end = mapping.virtual_addr.addr() + mapping.size;
+            if mapping.virtual_addr.addr() < addr || mapping_end > end {
+                return Err(PageMapErr::UnalignedSize);
+            }
+
+            addr = mapping_end;
+        }
+
+        Ok(())
+    }
+
+    /// Whether no area overlaps `[virtual_addr, virtual_addr + size)`.
+    pub fn is_free(&self, virtual_addr: VirtualAddr, size: usize) -> bool {
+        let end = VirtualAddr::new(virtual_addr.addr() + size as u64);
+        self.areas.is_free(virtual_addr, end)
+    }
+
+    /// The lowest address from `above` on where `size` bytes are not mapped yet.
+    pub fn find_free(&self, size: usize, above: VirtualAddr) -> Option<VirtualAddr> {
+        self.areas
+            .find_free(size as u64, above, arch::user_space_end())
+    }
+
+    /// Resolves a fault of an `access` to `virtual_addr`, returning whether it may be retried.
+    ///
+    /// Pages of an area that are not mapped yet are populated, writes to copy-on-write pages copy
+    /// them, and stacks grow down to faults just below them. Anything else, like a fault outside
+    /// of the areas or one the area does not permit, is left to the caller.
+    pub fn handle_fault(
+        &mut self,
+        virtual_addr: VirtualAddr,
+        access: Access,
+    ) -> Result<bool, PageMapErr> {
+        if virtual_addr >= arch::user_space_end() {
+            return Ok(false);
+        }
+
+        if self.areas.find(virtual_addr).is_none()
+            && !self.areas.grow_down(virtual_addr, MAX_STACK_SIZE)
+        {
+            return Ok(false);
+        }
+
+        let area = self.areas.find(virtual_addr).unwrap();
+
+        let permitted = !area.flags.contains(VirtualMemoryFlags::NoAccess)
+            && match access {
+                Access::Read => true,
+                Access::Write => area.flags.contains(VirtualMemoryFlags::Writeable),
+                Access::Execute => area.flags.contains(VirtualMemoryFlags::Executable),
+            };
+
+        if !permitted {
+            return Ok(false);
+        }
+
+        let page = VirtualAddr::new(misc::align_down_page(virtual_addr.addr()));
+
+        if self.translate(page).is_some() {
+            return match access {
+                Access::Write => super::cow::handle_write_fault(self.root_page_table, virtual_addr),
+                _ => Ok(false),
+            };
+        }
+
+        let frame = super::allocate_pages(1, true, Usage::User)
+            .map_err(|_| PageMapErr::PageFrameAllocError)?;
+
+        // Private pages of a file are copies that are never written back. Whatever lies past the
+        // end of the file reads as zeroes.
+        if let Some((file, offset)) = area.file_offset(page) {
+            let hhdm_offset = boot::BOOT_INFO.get().unwrap().hhdm_offset;
+            let buf = unsafe {
+                core::slice::from_raw_parts_mut(
+                    frame.as_virtual_by_offset(hhdm_offset).as_mut_ptr::<u8>(),
+                    PAGE_SIZE as usize,
+                )
+            };
+
+            if file.0.inode().read_at(offset, buf).is_err() {
+                super::deallocate_pages(frame, 1, Usage::User);
+                return Ok(false);
+            }
+        }
+
+        let flags = area.flags - VirtualMemoryFlags::GrowsDown - VirtualMemoryFlags::NoReserve;
+
+        if let Err(err) = arch::map_page(
+            self.root_page_table,
+            page,
+            frame,
+            PAGE_SIZE as usize,
+            flags | VirtualMemoryFlags::UserAccessible,
+        ) {
+            super::deallocate_pages(frame, 1, Usage::User);
+            return Err(err);
+        }
+
+        Ok(true)
+    }
+
+    /// Whether `[virtual_addr, virtual_addr + size)` lies entirely in the user half.
+    pub fn is_user_range(virtual_addr: VirtualAddr, size: usize) -> bool {
+        virtual_addr
+            .addr()
+            .checked_add(size as u64)
+            .is_some_and(|end| end <= arch::user_space_end().addr())
+    }
+}
+
+impl PageDirectory for AddressSpace {
+    fn root_page_table(&self) -> PhysicalAddr {
+        self.root_page_table
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        debug_assert!(
+            arch::root_page_table() != self.root_page_table,
+            "Dropping the active address space"
+        );
+
+        arch::free_user_mappings(self.root_page_table);
+    }
+}
+
+#[cfg(feature = "kernel-tests")]
+mod tests {
+    use super::*;
+    use crate::mem::{self, cow::handle_write_fault};
+    use crate::test::kernel_test;
+
+    #[kernel_test]
+    fn anonymous_memory() {
+        let mut address_space = AddressSpace::new().unwrap();
+        let base = VirtualAddr::new(0x1000_0000);
+        let size = 2 * PAGE_SIZE as usize;
+
+        address_space
+            .map_anonymous(base, size, VirtualMemoryFlags::Writeable)
+            .unwrap();
+
+        let mapping = address_space.translate(base).unwrap();
+        assert!(mapping.flags.contains(VirtualMemoryFlags::UserAccessible));
+
+        // Crosses into the second page.
+        let addr = VirtualAddr::new(base.addr() + PAGE_SIZE - 2);
+        address_space.write(addr, b"nekos").unwrap();
+
+        let mut buf = [0; 5];
+        address_space.read(addr, &mut buf).unwrap();
+        assert_eq!(&buf, b"nekos");
+
+        address_space.unmap(base, size).unwrap();
+        assert!(address_space.translate(base).is_none());
+    }
+
+    #[kernel_test]
+    fn fork_copies_on_write() {
+        let mut parent = AddressSpace::new().unwrap();
+        let base = VirtualAddr::new(0x1000_0000);
+
+        parent
+            .map_anonymous(base, PAGE_SIZE as usize, VirtualMemoryFlags::Writeable)
+            .unwrap();
+        parent.write(base, b"parent").unwrap();
+
+        let child = parent.fork().unwrap();
+        let shared = parent.translate(base).unwrap();
+        assert_eq!(
+            child.translate(base).unwrap().physical_addr,
+            shared.physical_addr
+        );
+        assert!(!shared.flags.contains(VirtualMemoryFlags::Writeable));
+        assert!(shared.flags.contains(VirtualMemoryFlags::CopyOnWrite));
+        assert_eq!(mem::frame(shared.physical_addr).unwrap().refcount(), 2);
+
+        // The child writes first and gets a copy.
+        assert!(handle_write_fault(child.root_page_table, base).unwrap());
+        let copy = child.translate(base).unwrap();
+        assert_ne!(copy.physical_addr, shared.physical_addr);
+        assert!(copy.flags.contains(VirtualMemoryFlags::Writeable));
+
+        let mut buf = [0; 6];
+        child.read(base, &mut buf).unwrap();
+        assert_eq!(&buf, b"parent");
+
+        // Which leaves the parent as the only user of the frame, which it keeps.
+        assert!(handle_write_fault(parent.root_page_table, base).unwrap());
+        let own = parent.translate(base).unwrap();
+        assert_eq!(own.physical_addr, shared.physical_addr);
+        assert!(own.flags.contains(VirtualMemoryFlags::Writeable));
+        assert!(!handle_write_fault(parent.root_page_table, base).unwrap());
+    }
+
+    const LARGE_PAGE: u64 = 512 * PAGE_SIZE;
+
+    /// Allocates a frame for a large page at a large page boundary.
+    fn allocate_large_page() -> PhysicalAddr {
+        let block = mem::allocate_pages(1023, true, Usage::User).unwrap();
+        let frame = PhysicalAddr::new(misc::align_up(block.addr(), LARGE_PAGE));
+        let head = ((frame.addr() - block.addr()) / PAGE_SIZE) as usize;
+        if head != 0 {
+            mem::deallocate_pages(block, head, Usage::User);
+        }
+        if head != 511 {
+            let tail = PhysicalAddr::new(frame.addr() + LARGE_PAGE);
+            mem::deallocate_pages(tail, 511 - head, Usage::User);
+        }
+
+        frame
+    }
+
+    #[kernel_test]
+    fn fork_copies_large_pages() {
+        let frame = allocate_large_page();
+
+        let mut parent = AddressSpace::new().unwrap();
+        let base = VirtualAddr::new(0x4000_0000);
+        parent
+            .map(
+                base,
+                frame,
+                LARGE_PAGE as usize,
+                VirtualMemoryFlags::Writeable,
+            )
+            .unwrap();
+        parent.write(base, b"large").unwrap();
+
+        let child = parent.fork().unwrap();
+        assert_eq!(child.translate(base).unwrap().size, LARGE_PAGE);
+
+        let end = VirtualAddr::new(base.addr() + LARGE_PAGE - 1);
+        assert!(handle_write_fault(child.root_page_table, end).unwrap());
+
+        let mut buf = [0; 5];
+        child.read(base, &mut buf).unwrap();
+        assert_eq!(&buf, b"large");
+        assert_ne!(
+            child.translate(base).unwrap().physical_addr,
+            parent.translate(base).unwrap().physical_addr
+        );
+
+        // The parent kept the large page, writing to it through the kernel no longer copies it.
+        parent.write(base, b"LARGE").unwrap();
+        assert_eq!(parent.translate(base).unwrap().physical_addr, frame);
+        child.read(base, &mut buf).unwrap();
+        assert_eq!(&buf, b"large");
+    }
+
+    #[kernel_test]
+    fn populates_areas_on_fault() {
+        let mut address_space = AddressSpace::new().unwrap();
+        let base = VirtualAddr::new(0x1000_0000);
+
+        address_space
+            .map_area(
+                base,
+                2 * PAGE_SIZE as usize,
+                VirtualMemoryFlags::empty(),
+                Backing::Anonymous,
+            )
+            .unwrap();
+        assert!(address_space.translate(base).is_none());
+
+        // Read-only memory is populated on reads but not on writes.
+        assert!(!address_space.handle_fault(base, Access::Write).unwrap());
+        assert!(address_space.handle_fault(base, Access::Read).unwrap());
+        assert!(address_space.translate(base).is_some());
+
+        let outside = VirtualAddr::new(base.addr() + 2 * PAGE_SIZE);
+        assert!(!address_space.handle_fault(outside, Access::Read).unwrap());
+
+        // Making it writable lets the second page be populated by a write.
+        address_space
+            .protect(base, 2 * PAGE_SIZE as usize, VirtualMemoryFlags::Writeable)
+            .unwrap();
+        let second = VirtualAddr::new(base.addr() + PAGE_SIZE);
+        assert!(address_space.handle_fault(second, Access::Write).unwrap());
+
+        let mapping = address_space.translate(base).unwrap();
+        assert!(mapping.flags.contains(VirtualMemoryFlags::Writeable));
+    }
+
+    #[kernel_test]
+    fn stacks_grow_down() {
+        let mut address_space = AddressSpace::new().unwrap();
+        let base = VirtualAddr::new(0x1000_0000);
+
+        address_space
+            .map_anonymous(
+                base,
+                PAGE_SIZE as usize,
+                VirtualMemoryFlags::Writeable | VirtualMemoryFlags::GrowsDown,
+            )
+            .unwrap();
+
+        let below = VirtualAddr::new(base.addr() - 8);
+        assert!(address_space.handle_fault(below, Access::Write).unwrap());
+        assert!(address_space.translate(below).is_some());
+
+        let far_below = VirtualAddr::new(base.addr() - 2 * MAX_STACK_SIZE);
+        assert!(
+            !address_space
+                .handle_fault(far_below, Access::Write)
+                .unwrap()
+        );
+    }
+
+    #[kernel_test]
+    fn protect_keeps_forked_pages_copy_on_write() {
+        let mut parent = AddressSpace::new().unwrap();
+        let base = VirtualAddr::new(0x1000_0000);
+        let size = PAGE_SIZE as usize;
+
+        parent
+            .map_anonymous(base, size, VirtualMemoryFlags::empty())
+            .unwrap();
+        let child = parent.fork().unwrap();
+
+        parent
+            .protect(base, size, VirtualMemoryFlags::Writeable)
+            .unwrap();
+        let mapping = parent.translate(base).unwrap();
+        assert!(!mapping.flags.contains(VirtualMemoryFlags::Writeable));
+        assert!(mapping.flags.contains(VirtualMemoryFlags::CopyOnWrite));
+
+        drop(child);
+        assert!(parent.handle_fault(base, Access::Write).unwrap());
+        assert!(
+            parent
+                .translate(base)
+                .unwrap()
+                .flags
+                .contains(VirtualMemoryFlags::Writeable)
+        );
+    }
+
+    #[kernel_test]
+    fn protect_none_keeps_the_contents() {
+        let mut address_space = AddressSpace::new().unwrap();
+        let base = VirtualAddr::new(0x1000_0000);
+        let size = PAGE_SIZE as usize;
+
+        address_space
+            .map_anonymous(base, size, VirtualMemoryFlags::Writeable)
+            .unwrap();
+        address_space.write(base, b"kept").unwrap();
+
+        address_space
+            .protect(base, size, VirtualMemoryFlags::NoAccess)
+            .unwrap();
+        let mapping = address_space.translate(base).unwrap();
+        assert!(!mapping.flags.contains(VirtualMemoryFlags::UserAccessible));
+        assert!(!address_space.handle_fault(base, Access::Read).unwrap());
+
+        address_space
+            .protect(base, size, VirtualMemoryFlags::Writeable)
+            .unwrap();
+        let mapping = address_space.translate(base).unwrap();
+        assert!(mapping.flags.contains(VirtualMemoryFlags::UserAccessible));
+
+        let mut buf = [0; 4];
+        address_space.read(base, &mut buf).unwrap();
+        assert_eq!(&buf, b"kept");
+    }
+
+    #[kernel_test]
+    fn protect_leaves_large_pages_alone_on_failure() {
+        let frame = allocate_large_page();
+
+        let mut address_space = AddressSpace::new().unwrap();
+        let base = VirtualAddr::new(0x4000_0000);
+        address_space
+            .map(
+                base,
+                frame,
+                LARGE_PAGE as usize,
+                VirtualMemoryFlags::Writeable,
+            )
+            .unwrap();
+
+        let result = address_space.protect(base, PAGE_SIZE as usize, VirtualMemoryFlags::empty());
+        assert!(matches!(result, Err(PageMapErr::UnalignedSize)));
+        assert_eq!(
+            address_space.areas.find(base).unwrap().flags,
+            VirtualMemoryFlags::Writeable
+        );
+    }
+
+    #[kernel_test]
+    fn unmap_leaves_large_pages_alone_on_failure() {
+        let frame = allocate_large_page();
+
+        let mut address_space = AddressSpace::new().unwrap();
+        let small = VirtualAddr::new(0x4000_0000 - PAGE_SIZE);
+        let base = VirtualAddr::new(0x4000_0000);
+        address_space
+            .map_anonymous(small, PAGE_SIZE as usize, VirtualMemoryFlags::Writeable)
+            .unwrap();
+        address_space
+            .map(
+                base,
+                frame,
+                LARGE_PAGE as usize,
+                VirtualMemoryFlags::Writeable,
+            )
+            .unwrap();
+
+        let result = address_space.unmap(small, 2 * PAGE_SIZE as usize);
+        assert!(matches!(result, Err(PageMapErr::UnalignedSize)));
+        assert!(address_space.translate(small).is_some());
+        assert!(address_space.areas.find(small).is_some());
+    }
+
+    #[kernel_test]
+    fn shared_no_access_pages_are_not_user_accessible() {
+        let mut address_space = AddressSpace::new().unwrap();
+        let base = VirtualAddr::new(0x1000_0000);
+
+        address_space
+            .map_anonymous(
+                base,
+                PAGE_SIZE as usize,
+                VirtualMemoryFlags::Shared | VirtualMemoryFlags::NoAccess,
+            )
+            .unwrap();
+
+        let mapping = address_space.translate(base).unwrap();
+        assert!(!mapping.flags.contains(VirtualMemoryFlags::UserAccessible));
+        assert!(!address_space.handle_fault(base, Access::Read).unwrap());
+    }
+}
@@ -0,0 +1,104 @@
+//! Copy-on-write sharing of user frames between forked address spaces.
+//!
+//! Forking maps every user frame into both address spaces and takes another reference to it, and
+//! writable pages become read-only pages marked `CopyOnWrite`. The first write to such a page
+//! faults and gets the faulting address space a private copy of the frame, unless nobody else
+//! references it anymore, in which case the page is simply made writable again.
+
+use crate::arch::{self, PAGE_SIZE};
+use crate::boot;
+
+use super::{Mapping, PageMapErr, PhysicalAddr, Usage, VirtualAddr, VirtualMemoryFlags};
+
+/// Resolves a write to `virtual_addr` in the page table at `root_page_table`, returning whether it
+/// hit a copy-on-write page. Other faults are left to the caller.
+pub fn handle_write_fault(
+    root_page_table: PhysicalAddr,
+    virtual_addr: VirtualAddr,
+) -> Result<bool, PageMapErr> {
+    if virtual_addr >= arch::user_space_end() {
+        return Ok(false);
+    }
+
+    let Some(mapping) = arch::translate(root_page_table, virtual_addr) else {
+        return Ok(false);
+    };
+
+    if !mapping.flags.contains(VirtualMemoryFlags::CopyOnWrite) {
+        return Ok(false);
+    }
+
+    unshare(root_page_table, mapping)?;
+    Ok(true)
+}
+
+/// Gives `mapping` frames of its own, copying them if they are shared. Copy-on-write pages become
+/// writable. Large pages are copied as a whole, though the copy may end up mapped as small pages.
+pub(super) fn unshare(root_page_table: PhysicalAddr, mapping: Mapping) -> Result<(), PageMapErr> {
+    let num_pages = (mapping.size / PAGE_SIZE) as usize;
+
+    let mut flags = mapping.flags;
+    if flags.contains(VirtualMemoryFlags::CopyOnWrite) {
+        flags.remove(VirtualMemoryFlags::CopyOnWrite);
+        flags.insert(VirtualMemoryFlags::Writeable);
+    }
+
+    let frame = super::frame(mapping.physical_addr).expect("User frames have descriptors");
+
+    // The other users may have dropped their references since the fork.
+    let physical_addr = if frame.refcount() == 1 {
+        mapping.physical_addr
+    } else {
+        let copy = super::allocate_pages(num_pages, false, Usage::User)
+            .map_err(|_| PageMapErr::PageFrameAllocError)?;
+
+        let hhdm_offset = boot::BOOT_INFO.get().unwrap().hhdm_offset;
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                mapping
+                    .physical_addr
+                    .as_virtual_by_offset(hhdm_offset)
+                    .as_ptr::<u8>(),
+                copy.as_virtual_by_offset(hhdm_offset).as_mut_ptr::<u8>(),
+                mapping.size as usize,
+            );
+        }
+
+        copy
+    };
+
+    arch::unmap_page(root_page_table, mapping.virtual_addr);
+
+    if let Err(err) = arch::map_page(
+        root_page_table,
+        mapping.virtual_addr,
+        physical_addr,
+        mapping.size as usize,
+        flags,
+    ) {
+        // Mapping a copy of a large page as small pages may need a page table, which is only
+        // allocated before anything is mapped, so the old mapping fits back in place.
+        arch::map_page(
+            root_page_table,
+            mapping.virtual_addr,
+            mapping.physical_addr,
+            mapping.size as usize,
+            mapping.flags,
+        )
+        .expect("Failed to restore a mapping");
+
+        if physical_addr != mapping.physical_addr {
+            super::deallocate_pages(physical_addr, num_pages, Usage::User);
+        }
+
+        return Err(err);
+    }
+
+    if physical_addr != mapping.physical_addr {
+        super::release_pages(mapping.physical_addr, num_pages, Usage::User);
+    }
+
+    arch::shootdown_tlb(mapping.virtual_addr, mapping.size as usize);
+
+    Ok(())
+}
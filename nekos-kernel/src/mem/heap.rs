@@ -0,0 +1,116 @@
+//! The kernel heap backing `alloc`.
+//!
+//! Small allocations are served from power-of-two size classes carved out of single pages, larger
+//! ones get their own run of pages from the page allocator.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+
+use spin::Mutex;
+
+use crate::arch::PAGE_SIZE;
+use crate::boot;
+
+use super::{PhysicalAddr, Usage, VirtualAddr};
+
+const SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+struct FreeBlock {
+    next: Option<NonNull<FreeBlock>>,
+}
+
+struct Heap {
+    free_lists: [Option<NonNull<FreeBlock>>; SIZE_CLASSES.len()],
+}
+
+/// SAFETY: The free blocks are only reachable through the mutex.
+unsafe impl Send for Heap {}
+
+pub struct KernelHeap(Mutex<Heap>);
+
+#[global_allocator]
+static KERNEL_HEAP: KernelHeap = KernelHeap(Mutex::new(Heap {
+    free_lists: [None; SIZE_CLASSES.len()],
+}));
+
+impl Heap {
+    fn allocate(&mut self, class: usize) -> *mut u8 {
+        if self.free_lists[class].is_none() && !self.refill(class) {
+            return ptr::null_mut();
+        }
+
+        let mut block = self.free_lists[class].unwrap();
+        self.free_lists[class] = unsafe { block.as_mut().next };
+
+        block.as_ptr().cast()
+    }
+
+    fn deallocate(&mut self, class: usize, ptr: *mut u8) {
+        let block = ptr.cast::<FreeBlock>();
+
+        unsafe {
+            block.write(FreeBlock {
+                next: self.free_lists[class],
+            });
+        }
+
+        self.free_lists[class] = NonNull::new(block);
+    }
+
+    /// Splits a fresh page into blocks of the given size class.
+    fn refill(&mut self, class: usize) -> bool {
+        let Ok(page) = super::allocate_pages(1, false, Usage::Slab) else {
+            return false;
+        };
+
+        let base = to_virtual(page).as_mut_ptr::<u8>();
+        let block_size = SIZE_CLASSES[class];
+
+        for offset in (0..PAGE_SIZE as usize).step_by(block_size).rev() {
+            self.deallocate(class, unsafe { base.add(offset) });
+        }
+
+        true
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // Blocks of a size class are aligned to their size, pages to `PAGE_SIZE`.
+        if layout.align() > PAGE_SIZE as usize {
+            return ptr::null_mut();
+        }
+
+        match size_class(layout) {
+            Some(class) => self.0.lock().allocate(class),
+            None => match super::allocate_pages(num_pages(layout), false, Usage::KernelHeap) {
+                Ok(page) => to_virtual(page).as_mut_ptr(),
+                Err(_) => ptr::null_mut(),
+            },
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match size_class(layout) {
+            Some(class) => self.0.lock().deallocate(class, ptr),
+            None => {
+                let hhdm_offset = boot::BOOT_INFO.get().unwrap().hhdm_offset;
+                let page = PhysicalAddr::new(ptr as u64 - hhdm_offset);
+                super::deallocate_pages(page, num_pages(layout), Usage::KernelHeap);
+            }
+        }
+    }
+}
+
+fn size_class(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().position(|&class| class >= size)
+}
+
+fn num_pages(layout: Layout) -> usize {
+    layout.size().div_ceil(PAGE_SIZE as usize)
+}
+
+fn to_virtual(page: PhysicalAddr) -> VirtualAddr {
+    page.as_virtual_by_offset(boot::BOOT_INFO.get().unwrap().hhdm_offset)
+}
@@ -0,0 +1,178 @@
+//! Accounting of physical memory.
+//!
+//! Every page taken from the page allocator is charged to what it is used for, so that a leak
+//! shows up as a counter that keeps growing from one report to the next.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use ubyte::ToByteUnit;
+
+use crate::arch::PAGE_SIZE;
+use crate::log::{self, Level};
+
+/// What pages from the page allocator are used for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Usage {
+    /// Page tables of the kernel and of user address spaces.
+    PageTable,
+    /// Pages split into the blocks of the small size classes of the kernel heap.
+    Slab,
+    /// Allocations of the kernel heap too large for a size class.
+    KernelHeap,
+    /// The file data of a tmpfs.
+    PageCache,
+    /// Frames mapped into user address spaces.
+    User,
+    /// Buffers shared with devices.
+    Dma,
+}
+
+impl Usage {
+    pub const ALL: [Usage; 6] = [
+        Usage::PageTable,
+        Usage::Slab,
+        Usage::KernelHeap,
+        Usage::PageCache,
+        Usage::User,
+        Usage::Dma,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Usage::PageTable => "page tables",
+            Usage::Slab => "slab",
+            Usage::KernelHeap => "kernel heap",
+            Usage::PageCache => "page cache",
+            Usage::User => "user",
+            Usage::Dma => "dma",
+        }
+    }
+}
+
+/// The pages in use, indexed by `Usage`.
+static USED_PAGES: [AtomicU64; Usage::ALL.len()] = [const { AtomicU64::new(0) }; Usage::ALL.len()];
+
+/// The pages handed to the page allocator.
+static TOTAL_PAGES: AtomicU64 = AtomicU64::new(0);
+
+/// The memory the memory map describes but the page allocator never got, like the kernel image,
+/// the firmware or what `mem=` cut off.
+static RESERVED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+pub(super) fn charge(usage: Usage, num_pages: usize) {
+    USED_PAGES[usage as usize].fetch_add(num_pages as u64, Ordering::Relaxed);
+}
+
+pub(super) fn uncharge(usage: Usage, num_pages: usize) {
+    let previous = USED_PAGES[usage as usize].fetch_sub(num_pages as u64, Ordering::Relaxed);
+    debug_assert!(
+        previous >= num_pages as u64,
+        "Freeing more {} pages than were allocated",
+        usage.name()
+    );
+}
+
+/// Records that memory which was reserved until now was given to the page allocator.
+pub(super) fn add_total(num_pages: usize) {
+    TOTAL_PAGES.fetch_add(num_pages as u64, Ordering::Relaxed);
+    let _ = RESERVED_BYTES.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |reserved| {
+        Some(reserved.saturating_sub(num_pages as u64 * PAGE_SIZE))
+    });
+}
+
+pub(super) fn set_reserved(bytes: u64) {
+    RESERVED_BYTES.store(bytes, Ordering::Relaxed);
+}
+
+/// A snapshot of how physical memory is used. All sizes are in bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct MemInfo {
+    /// The memory managed by the page allocator.
+    pub total: u64,
+    pub free: u64,
+    pub reserved: u64,
+    /// The memory in use, indexed by `Usage`.
+    pub used: [u64; Usage::ALL.len()],
+    /// The largest allocation that can currently succeed.
+    pub largest_free: u64,
+}
+
+impl MemInfo {
+    pub const fn used(&self, usage: Usage) -> u64 {
+        self.used[usage as usize]
+    }
+
+    /// How much of the free memory is not part of the largest free block, in percent. A single
+    /// free block is 0%, many small ones approach 100%.
+    pub const fn fragmentation(&self) -> u64 {
+        if self.free == 0 {
+            return 0;
+        }
+
+        100 - self.largest_free * 100 / self.free
+    }
+}
+
+/// Takes a snapshot of the memory counters.
+pub fn get() -> MemInfo {
+    let (free_pages, largest_free_pages) = {
+        let allocator = super::page_allocator().lock();
+        (allocator.free_pages(), allocator.largest_free_region())
+    };
+
+    MemInfo {
+        total: TOTAL_PAGES.load(Ordering::Relaxed) * PAGE_SIZE,
+        free: free_pages as u64 * PAGE_SIZE,
+        reserved: RESERVED_BYTES.load(Ordering::Relaxed),
+        used: core::array::from_fn(|index| USED_PAGES[index].load(Ordering::Relaxed) * PAGE_SIZE),
+        largest_free: largest_free_pages as u64 * PAGE_SIZE,
+    }
+}
+
+/// Logs the `meminfo` report at `level`.
+pub fn log(level: Level) {
+    let info = get();
+
+    log::log(
+        level,
+        module_path!(),
+        format_args!(
+            "Memory: {} total, {} free, {} reserved",
+            info.total.bytes(),
+            info.free.bytes(),
+            info.reserved.bytes()
+        ),
+    );
+    log::log(
+        level,
+        module_path!(),
+        format_args!("Memory in use: {}", Used(&info)),
+    );
+    log::log(
+        level,
+        module_path!(),
+        format_args!(
+            "Largest free block {}, fragmentation {}%",
+            info.largest_free.bytes(),
+            info.fragmentation()
+        ),
+    );
+}
+
+/// The memory in use by each `Usage`.
+struct Used<'a>(&'a MemInfo);
+
+impl fmt::Display for Used<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, usage) in Usage::ALL.into_iter().enumerate() {
+            if index != 0 {
+                f.write_str(", ")?;
+            }
+
+            write!(f, "{} {}", usage.name(), self.0.used(usage).bytes())?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,29 +1,25 @@
-mod addr;
-mod page_allocator;
-mod range_allocator;
+mod address_space;
+mod cow;
+mod heap;
+pub mod meminfo;
 
 use core::ptr;
-use spin::Once;
+use spin::{Mutex, Once};
 use ubyte::ToByteUnit;
 
 use crate::arch::{self, PAGE_SIZE};
-use crate::mem::page_allocator::PAGE_ALLOCATOR;
+use crate::cmdline::{Size, param};
 use crate::{boot, log, misc};
 
-use bitflags::bitflags;
 use limine::memory_map::EntryType;
+use nekos_mem::frame::{FrameDescriptor, FrameFlags, FrameTable};
+use nekos_mem::page_allocator::{AllocError, FreeListAllocator};
 
-pub use addr::*;
+pub use address_space::{Access, AddressSpace, MappedFile};
+pub use meminfo::Usage;
+pub use nekos_mem::{PhysicalAddr, VirtualAddr, VirtualMemoryFlags};
 
-bitflags! {
-    #[derive(Clone, Copy, Debug)]
-    pub struct VirtualMemoryFlags: u8 {
-        const Writeable = 1 << 0;
-        const Executable = 1 << 1;
-        const UserAccessible = 1 << 2;
-        const MMIO = 1 << 3;
-    }
-}
+misc::const_assert!(PAGE_SIZE == nekos_mem::PAGE_SIZE);
 
 #[derive(Debug)]
 pub enum PageMapErr {
@@ -31,37 +27,199 @@ pub enum PageMapErr {
     UnalignedVirtualAddr,
     UnalignedSize,
     PageFrameAllocError,
+    InvalidVirtualAddr,
+    /// Part of the range is not mapped.
+    NotMapped,
+}
+
+#[derive(Debug)]
+pub enum UserAccessErr {
+    InvalidAddr,
+    NotMapped,
+    PermissionDenied,
+    /// A string did not fit in the buffer it was copied to.
+    TooLong,
+}
+
+/// A leaf mapping found by walking a page table.
+#[derive(Clone, Copy)]
+pub struct Mapping {
+    pub virtual_addr: VirtualAddr,
+    pub physical_addr: PhysicalAddr,
+    pub size: u64,
+    pub flags: VirtualMemoryFlags,
 }
 
 pub trait PageDirectory {
     fn root_page_table(&self) -> PhysicalAddr;
+
+    /// Switches the current hart to this page directory.
+    fn activate(&self) {
+        arch::switch_page_table(self.root_page_table());
+    }
+}
+
+param!(
+    /// The most memory handed to the page allocator, `mem=512M` ignores the rest.
+    mem: Option<Size> = None
+);
+
+pub fn kernel_page_directory() -> &'static KernelPageDirectory {
+    KERNEL_PAGE_DIRECTORY
+        .get()
+        .expect("Kernel page directory is not initialized")
+}
+
+static PAGE_ALLOCATOR: Once<Mutex<FreeListAllocator>> = Once::new();
+
+fn page_allocator() -> &'static Mutex<FreeListAllocator> {
+    PAGE_ALLOCATOR
+        .get()
+        .expect("Page allocator is not initialized")
+}
+
+static FRAME_TABLE: Once<FrameTable> = Once::new();
+
+fn frame_table() -> &'static FrameTable {
+    FRAME_TABLE.get().expect("Frame table is not initialized")
 }
 
 pub fn init() {
     log::debug!("Setting up the paging system.");
 
-    init_page_allocator();
+    let frame_table_pages = init_frame_table();
+    init_page_allocator(frame_table_pages);
     init_kernel_page_directory();
 }
 
-fn init_page_allocator() {
+/// Sets up the descriptors of all frames the page allocator may ever hand out, which are usable
+/// memory and the modules released after boot. They are stored at the start of the first usable
+/// region large enough, which is returned so that the page allocator leaves it alone.
+fn init_frame_table() -> (PhysicalAddr, u64) {
+    let boot_info = boot::BOOT_INFO.get().unwrap();
+
+    let covered = || {
+        boot_info.memory_map_entries.iter().filter(|entry| {
+            matches!(
+                entry.entry_type,
+                EntryType::USABLE | EntryType::EXECUTABLE_AND_MODULES
+            )
+        })
+    };
+
+    let start = covered()
+        .map(|entry| misc::align_down_page(entry.base))
+        .min()
+        .expect("No usable memory");
+    let end = covered()
+        .map(|entry| misc::align_up_page(entry.base + entry.length))
+        .max()
+        .expect("No usable memory");
+
+    let num_frames = ((end - start) / PAGE_SIZE) as usize;
+    let num_pages = FrameTable::num_pages(num_frames) as u64;
+
+    let memory = boot_info
+        .memory_map_entries
+        .iter()
+        .find(|entry| {
+            entry.entry_type == EntryType::USABLE && entry.length / PAGE_SIZE >= num_pages
+        })
+        .map(|entry| PhysicalAddr::new(entry.base))
+        .expect("No usable memory region can hold the frame table");
+
+    let table = FRAME_TABLE.call_once(|| {
+        let ptr = memory
+            .as_virtual_by_offset(boot_info.hhdm_offset)
+            .as_mut_ptr::<u8>();
+
+        // SAFETY: The memory is usable and is never given to the page allocator.
+        unsafe {
+            FrameTable::new(
+                PhysicalAddr::new(start),
+                ptr::NonNull::new(ptr).unwrap(),
+                num_frames,
+            )
+        }
+    });
+
+    for frame in table.frames(memory, num_pages as usize).unwrap() {
+        frame.claim(FrameFlags::Kernel);
+    }
+
+    log::debug!(
+        "Frame table for {} to {} at {} takes {}",
+        PhysicalAddr::new(start),
+        PhysicalAddr::new(end),
+        memory,
+        (num_pages * PAGE_SIZE).bytes()
+    );
+
+    (memory, num_pages)
+}
+
+fn init_page_allocator(frame_table_pages: (PhysicalAddr, u64)) {
     let boot_info = boot::BOOT_INFO.get().unwrap();
 
-    let mut allocator = PAGE_ALLOCATOR.lock();
+    let mut remaining = mem.get().map_or(u64::MAX, |limit| limit.0 / PAGE_SIZE);
+
+    let mut allocator = PAGE_ALLOCATOR
+        .call_once(|| Mutex::new(FreeListAllocator::new(boot_info.hhdm_offset)))
+        .lock();
+
+    // Whatever the page allocator does not get below stays reserved.
+    meminfo::set_reserved(
+        boot_info
+            .memory_map_entries
+            .iter()
+            .map(|entry| entry.length)
+            .sum(),
+    );
+
     for entry in boot_info
         .memory_map_entries
         .iter()
         .filter(|entry| entry.entry_type == EntryType::USABLE)
     {
-        let base = PhysicalAddr::new(entry.base);
-        let pages = (entry.length / PAGE_SIZE) as usize;
+        let (mut base, mut pages) = (PhysicalAddr::new(entry.base), entry.length / PAGE_SIZE);
+
+        if base == frame_table_pages.0 {
+            base = PhysicalAddr::new(base.addr() + frame_table_pages.1 * PAGE_SIZE);
+            pages -= frame_table_pages.1;
+        }
+
+        let pages = pages.min(remaining);
+
+        if pages == 0 {
+            continue;
+        }
 
         log::debug!(
             "Adding page frame to page allocator {}",
-            entry.length.bytes()
+            (pages * PAGE_SIZE).bytes()
         );
 
-        allocator.deallocate(base, pages);
+        if let Err(err) = allocator.add_usable_memory(base, pages as usize) {
+            log::warn!(
+                "Skipping {} of memory at {}: {:?}",
+                (pages * PAGE_SIZE).bytes(),
+                base,
+                err
+            );
+            continue;
+        }
+
+        allocator.deallocate(base, pages as usize);
+        meminfo::add_total(pages as usize);
+        remaining -= pages;
+    }
+
+    if cfg!(debug_assertions) {
+        allocator.verify();
+    }
+
+    if let Some(limit) = mem.get() {
+        log::info!("Limited usable memory to {}.", limit);
     }
 
     log::info!("Initialized page allocator!");
@@ -86,7 +244,8 @@ fn init_kernel_page_directory() {
         VirtualAddr::new(kernel_image_offset)
     );
 
-    let root_page_table = allocate_pages(1, true).expect("Failed to allocate page");
+    let root_page_table =
+        allocate_pages(1, true, Usage::PageTable).expect("Failed to allocate page");
 
     let map_section =
         |section_begin: *const u8, section_end: *const u8, flags: VirtualMemoryFlags| {
@@ -127,47 +286,95 @@ fn init_kernel_page_directory() {
     );
 
     let boot_info = boot::BOOT_INFO.get().unwrap();
-    let mut usable_memory = 0u64;
 
-    for entry in boot_info
-        .memory_map_entries
-        .iter()
-        .filter(|entry| entry.entry_type == EntryType::USABLE)
-    {
+    // Besides usable memory, the HHDM also has to cover the memory that is still in use after
+    // boot (the boot stack, the Limine responses, the modules and the framebuffer), otherwise we
+    // cannot keep running once we switch to a page table derived from this one.
+    for entry in boot_info.memory_map_entries.iter().filter(|entry| {
+        matches!(
+            entry.entry_type,
+            EntryType::USABLE
+                | EntryType::BOOTLOADER_RECLAIMABLE
+                | EntryType::EXECUTABLE_AND_MODULES
+                | EntryType::FRAMEBUFFER
+                | EntryType::ACPI_RECLAIMABLE
+                | EntryType::ACPI_NVS
+        )
+    }) {
         log::debug!(
             "Mapping entry {} with size {}",
             PhysicalAddr::new(entry.base),
             entry.length.bytes()
         );
 
-        let physical_addr = PhysicalAddr::new(entry.base);
-        let virtual_addr = physical_addr.as_virtual_by_offset(boot_info.hhdm_offset);
+        // Only usable and bootloader reclaimable entries are guaranteed to be page aligned.
+        let base = misc::align_down_page(entry.base);
+        let end = misc::align_up_page(entry.base + entry.length);
 
-        usable_memory += entry.length;
+        let physical_addr = PhysicalAddr::new(base);
+        let virtual_addr = physical_addr.as_virtual_by_offset(boot_info.hhdm_offset);
 
         arch::map_page(
             root_page_table,
             virtual_addr,
             physical_addr,
-            entry.length as usize,
+            (end - base) as usize,
             VirtualMemoryFlags::Writeable,
         )
         .expect("Failed to map page");
     }
 
-    use ubyte::ToByteUnit;
+    KERNEL_PAGE_DIRECTORY.call_once(|| KernelPageDirectory { root_page_table });
+
+    // Device registers are mapped into this page directory later on, so stop running on the one
+    // set up by the bootloader.
+    kernel_page_directory().activate();
+}
 
-    log::info!("Total Usable Memory {}", usable_memory.bytes());
+/// Maps the device registers at `physical_addr` into the HHDM of the kernel page directory and
+/// returns where they can be accessed.
+pub fn map_mmio(physical_addr: PhysicalAddr, size: usize) -> Result<VirtualAddr, PageMapErr> {
+    let hhdm_offset = boot::BOOT_INFO.get().unwrap().hhdm_offset;
+    let root_page_table = kernel_page_directory().root_page_table();
 
-    KERNEL_PAGE_DIRECTORY.call_once(|| KernelPageDirectory { root_page_table });
+    let base = misc::align_down_page(physical_addr.addr());
+    let end = misc::align_up_page(physical_addr.addr() + size as u64);
+
+    for page in (base..end).step_by(PAGE_SIZE as usize) {
+        let page = PhysicalAddr::new(page);
+        let virtual_addr = page.as_virtual_by_offset(hhdm_offset);
+
+        // Several devices may share a page.
+        if arch::translate(root_page_table, virtual_addr).is_some() {
+            continue;
+        }
+
+        arch::map_page(
+            root_page_table,
+            virtual_addr,
+            page,
+            PAGE_SIZE as usize,
+            VirtualMemoryFlags::Writeable | VirtualMemoryFlags::MMIO,
+        )?;
+    }
+
+    arch::flush_tlb();
+
+    Ok(physical_addr.as_virtual_by_offset(hhdm_offset))
 }
 
+/// Allocates `num_pages` contiguous pages and charges them to `usage`.
 pub fn allocate_pages(
     num_pages: usize,
     zeroed: bool,
-) -> Result<PhysicalAddr, page_allocator::AllocError> {
-    let mut allocator = PAGE_ALLOCATOR.lock();
-    let page = allocator.allocate(num_pages)?;
+    usage: Usage,
+) -> Result<PhysicalAddr, AllocError> {
+    let page = page_allocator().lock().allocate(num_pages)?;
+    meminfo::charge(usage, num_pages);
+
+    for frame in frames(page, num_pages) {
+        frame.claim(frame_flags(usage));
+    }
     let boot_info = boot::BOOT_INFO.get().unwrap();
 
     if zeroed {
@@ -184,9 +391,87 @@ pub fn allocate_pages(
     Ok(page)
 }
 
-pub fn deallocate_pages(physical_addr: PhysicalAddr, num_pages: usize) {
-    let mut allocator = PAGE_ALLOCATOR.lock();
-    allocator.deallocate(physical_addr, num_pages);
+/// Frees pages that were allocated for `usage`.
+pub fn deallocate_pages(physical_addr: PhysicalAddr, num_pages: usize, usage: Usage) {
+    for frame in frames(physical_addr, num_pages) {
+        frame.release();
+    }
+
+    page_allocator().lock().deallocate(physical_addr, num_pages);
+    meminfo::uncharge(usage, num_pages);
+}
+
+/// Takes another reference to each of the `num_pages` frames at `base`, which are shared from now
+/// on.
+pub fn share_pages(base: PhysicalAddr, num_pages: usize) {
+    for frame in frames(base, num_pages) {
+        frame.get();
+    }
+}
+
+/// Drops a reference to each of the `num_pages` frames at `base` and frees them once nobody
+/// references them anymore.
+pub fn release_pages(base: PhysicalAddr, num_pages: usize, usage: Usage) {
+    // The frames of a mapping are always shared together.
+    let unused = frames(base, num_pages)
+        .iter()
+        .filter(|frame| frame.put())
+        .count();
+
+    if unused == num_pages {
+        deallocate_pages(base, num_pages, usage);
+    } else {
+        assert!(unused == 0, "Frames of {} are shared unevenly", base);
+    }
+}
+
+fn frame_flags(usage: Usage) -> FrameFlags {
+    match usage {
+        Usage::PageTable => FrameFlags::Kernel | FrameFlags::PageTable,
+        Usage::Slab => FrameFlags::Kernel | FrameFlags::Slab,
+        Usage::KernelHeap | Usage::PageCache | Usage::Dma => FrameFlags::Kernel,
+        Usage::User => FrameFlags::User,
+    }
+}
+
+/// The descriptor of the frame containing `addr`, if it is memory the page allocator manages.
+pub fn frame(addr: PhysicalAddr) -> Option<&'static FrameDescriptor> {
+    frame_table().frame(addr)
+}
+
+/// The address of the frame described by `frame`.
+#[cfg(feature = "kernel-tests")]
+pub fn frame_addr(frame: &FrameDescriptor) -> PhysicalAddr {
+    frame_table().addr(frame)
+}
+
+fn frames(base: PhysicalAddr, num_pages: usize) -> &'static [FrameDescriptor] {
+    frame_table()
+        .frames(base, num_pages)
+        .expect("The frame table does not cover the pages")
+}
+
+/// Hands memory that was in use since boot, like the initramfs, over to the page allocator.
+pub fn add_memory(base: PhysicalAddr, num_pages: usize) {
+    let mut allocator = page_allocator().lock();
+    if let Err(err) = allocator.add_usable_memory(base, num_pages) {
+        log::warn!(
+            "Skipping {} pages of memory at {}: {:?}",
+            num_pages,
+            base,
+            err
+        );
+        return;
+    }
+
+    allocator.deallocate(base, num_pages);
+    meminfo::add_total(num_pages);
+}
+
+/// Walks the free list of the page allocator and panics if it is broken.
+#[cfg(feature = "kernel-tests")]
+pub fn verify_page_allocator() {
+    page_allocator().lock().verify();
 }
 
 pub struct KernelPageDirectory {
@@ -204,3 +489,58 @@ impl PageDirectory for KernelPageDirectory {
         self.root_page_table
     }
 }
+
+#[cfg(feature = "kernel-tests")]
+mod tests {
+    use super::*;
+    use crate::test::kernel_test;
+
+    #[kernel_test]
+    fn freed_pages_are_reused() {
+        let first = allocate_pages(2, false, Usage::KernelHeap).unwrap();
+        deallocate_pages(first, 2, Usage::KernelHeap);
+
+        let second = allocate_pages(2, true, Usage::KernelHeap).unwrap();
+        deallocate_pages(second, 2, Usage::KernelHeap);
+
+        assert_eq!(first, second);
+        verify_page_allocator();
+    }
+
+    #[kernel_test]
+    fn charges_allocations() {
+        let before = meminfo::get();
+        let page = allocate_pages(3, false, Usage::Dma).unwrap();
+
+        let during = meminfo::get();
+        assert_eq!(
+            during.used(Usage::Dma),
+            before.used(Usage::Dma) + 3 * PAGE_SIZE
+        );
+        assert_eq!(during.free, before.free - 3 * PAGE_SIZE);
+        assert!(during.largest_free <= during.free);
+
+        deallocate_pages(page, 3, Usage::Dma);
+        let after = meminfo::get();
+        assert_eq!(after.used(Usage::Dma), before.used(Usage::Dma));
+        assert_eq!(after.free, before.free);
+    }
+
+    #[kernel_test]
+    fn describes_allocated_frames() {
+        let page = allocate_pages(2, false, Usage::PageTable).unwrap();
+
+        let second = PhysicalAddr::new(page.addr() + PAGE_SIZE);
+        let descriptor = frame(second).unwrap();
+        assert_eq!(frame_addr(descriptor), second);
+        assert_eq!(descriptor.refcount(), 1);
+        assert_eq!(
+            descriptor.flags(),
+            FrameFlags::Kernel | FrameFlags::PageTable
+        );
+
+        deallocate_pages(page, 2, Usage::PageTable);
+        assert_eq!(descriptor.refcount(), 0);
+        assert_eq!(descriptor.flags(), FrameFlags::empty());
+    }
+}
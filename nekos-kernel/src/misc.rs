@@ -1,12 +1,4 @@
-#[inline]
-pub const fn align_up(addr: u64, align: u64) -> u64 {
-    ((addr + align - 1) / align) * align
-}
-
-#[inline]
-pub const fn align_down(addr: u64, align: u64) -> u64 {
-    (addr / align) * align
-}
+pub use nekos_mem::{align_down, align_up};
 
 #[inline]
 pub const fn align_up_page(addr: u64) -> u64 {
@@ -14,6 +6,12 @@ pub const fn align_up_page(addr: u64) -> u64 {
     ((addr + align - 1) / align) * align
 }
 
+/// Rounds `addr` up to the next page boundary, unless that does not fit in a `u64`.
+#[inline]
+pub const fn checked_align_up_page(addr: u64) -> Option<u64> {
+    addr.checked_next_multiple_of(crate::arch::PAGE_SIZE)
+}
+
 #[inline]
 pub const fn align_down_page(addr: u64) -> u64 {
     let align = crate::arch::PAGE_SIZE;
@@ -29,3 +27,46 @@ macro_rules! const_assert {
 }
 
 pub(crate) use const_assert;
+
+/// The CRC-32 lookup table for the reflected IEEE 802.3 polynomial.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[index] = crc;
+        index += 1;
+    }
+
+    table
+};
+
+/// Computes the CRC-32 checksum used by GPT, zlib and Ethernet.
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(feature = "kernel-tests")]
+mod tests {
+    use super::*;
+    use crate::test::kernel_test;
+
+    #[kernel_test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+}
@@ -0,0 +1,140 @@
+//! The panic handler.
+//!
+//! The first hart to panic takes the panic lock, stops all other harts, prints whatever the log
+//! has not shown yet and then halts, reboots or powers off the machine depending on the `panic`
+//! parameter. A hart panicking again while it handles a panic halts right away.
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use crate::arch::{self, print};
+use crate::backtrace;
+use crate::cmdline::{ParamValue, param};
+use crate::log;
+
+/// What to do with the machine once a panic has been reported.
+#[derive(Clone, Copy, Debug)]
+pub enum PanicAction {
+    Halt,
+    Reboot,
+    Poweroff,
+}
+
+impl ParamValue for PanicAction {
+    fn parse(value: Option<&'static str>) -> Option<Self> {
+        match value? {
+            "halt" => Some(Self::Halt),
+            "reboot" => Some(Self::Reboot),
+            "poweroff" => Some(Self::Poweroff),
+            _ => None,
+        }
+    }
+}
+
+param!(
+    /// What to do after a panic: `halt`, `reboot` or `poweroff`.
+    panic: PanicAction = PanicAction::Halt
+);
+
+param!(
+    /// Whether harts stopped by a panic on another hart log their registers.
+    panic_dump: bool = false
+);
+
+/// The hart holding the panic lock, if any.
+static PANIC_HART: AtomicU64 = AtomicU64::new(NO_HART);
+
+const NO_HART: u64 = u64::MAX;
+
+/// The number of harts that stopped after being interrupted by the panicking hart.
+static STOPPED_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// How long the panicking hart waits for the others to stop.
+const STOP_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Whether some hart is handling a panic.
+pub fn in_progress() -> bool {
+    PANIC_HART.load(Ordering::Acquire) != NO_HART
+}
+
+/// Stops the current hart on behalf of the panicking one, after running `dump_registers` if the
+/// registers were asked for.
+pub fn stop_hart(dump_registers: &dyn Fn()) -> ! {
+    if panic_dump.get() {
+        log::error!("Stopped hart {}:", arch::current_hart_id());
+        dump_registers();
+    }
+
+    STOPPED_HARTS.fetch_add(1, Ordering::AcqRel);
+    arch::halt();
+}
+
+#[panic_handler]
+fn panic_handler(info: &core::panic::PanicInfo) -> ! {
+    use colorz::Colorize;
+
+    let hart_id = arch::current_hart_id();
+    if let Err(owner) =
+        PANIC_HART.compare_exchange(NO_HART, hart_id, Ordering::AcqRel, Ordering::Acquire)
+    {
+        // Anything more could panic yet again.
+        if owner == hart_id {
+            print!(
+                "{} {}\n",
+                "[panic]: Panicked while panicking:".red(),
+                info.red()
+            );
+        }
+
+        // The hart that panicked first stops this one as well.
+        arch::halt();
+    }
+
+    // Other harts could write over the report.
+    stop_other_harts(hart_id);
+
+    // Messages logged before the console was started come first.
+    log::flush();
+
+    print!(
+        "{}{}{} {}\n",
+        "[".red(),
+        "panic".red().bold(),
+        "]:".red(),
+        info.red()
+    );
+
+    backtrace::log(log::Level::Error, None, arch::frame_pointer());
+
+    #[cfg(feature = "kernel-tests")]
+    crate::test::fail();
+
+    match panic.get() {
+        PanicAction::Halt => arch::halt(),
+        PanicAction::Reboot => arch::reboot(),
+        PanicAction::Poweroff => arch::poweroff(),
+    }
+}
+
+/// Interrupts all other harts and waits a moment for them to stop.
+fn stop_other_harts(hart_id: u64) {
+    let mut count = 0;
+    for other in arch::online_harts().filter(|&other| other != hart_id) {
+        arch::send_ipi(other);
+        count += 1;
+    }
+
+    let deadline = arch::monotonic_time() + STOP_TIMEOUT;
+    while STOPPED_HARTS.load(Ordering::Acquire) < count {
+        if arch::monotonic_time() >= deadline {
+            log::error!(
+                "Only {} of {} harts stopped.",
+                STOPPED_HARTS.load(Ordering::Acquire),
+                count
+            );
+            break;
+        }
+
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,249 @@
+use alloc::sync::Arc;
+use arrayvec::ArrayVec;
+use spin::Mutex;
+
+use crate::arch::{self, TrapFrame};
+use crate::block;
+use crate::elf;
+use crate::fs::{self, Dentry, FileTable};
+use crate::log;
+use crate::mem::{self, Access, AddressSpace, PageDirectory, PageMapErr, VirtualAddr};
+
+pub type Pid = usize;
+
+const MAX_PROCESSES: usize = 64;
+
+pub struct Process {
+    pub pid: Pid,
+    pub address_space: AddressSpace,
+
+    /// The user context, only valid while the process is not running.
+    context: TrapFrame,
+
+    /// Where the heap grown by `brk` starts.
+    pub brk_start: VirtualAddr,
+    /// The current end of the heap, which need not be page aligned.
+    pub brk: VirtualAddr,
+
+    pub files: FileTable,
+    /// The current working directory, relative paths start at the root directory if unset.
+    pub cwd: Option<Arc<Dentry>>,
+}
+
+/// What the current process asked for during the last trap.
+enum Pending {
+    Yield,
+    Exit(i32),
+    /// The child that was just forked, which starts from the state of the current trap.
+    Fork(Pid),
+}
+
+struct Scheduler {
+    processes: ArrayVec<Process, MAX_PROCESSES>,
+    current: Option<usize>,
+    pending: Option<Pending>,
+    next_pid: Pid,
+}
+
+static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler {
+    processes: ArrayVec::new_const(),
+    current: None,
+    pending: None,
+    next_pid: 1,
+});
+
+#[derive(Debug)]
+pub enum SpawnError {
+    TooManyProcesses,
+    AddressSpace,
+    Elf,
+}
+
+/// Creates a new process running the ELF executable in `image`.
+pub fn spawn_elf(image: &[u8], argv: &[&[u8]], envp: &[&[u8]]) -> Result<Pid, SpawnError> {
+    let mut address_space = AddressSpace::new().map_err(|_| SpawnError::AddressSpace)?;
+    let loaded = elf::load(&mut address_space, image, argv, envp).map_err(|_| SpawnError::Elf)?;
+
+    spawn(
+        address_space,
+        loaded.entry,
+        loaded.stack_pointer,
+        loaded.program_break,
+    )
+}
+
+/// Creates a new process that starts executing at `entry` with its stack pointer set to `stack`
+/// and its heap starting at `program_break`.
+pub fn spawn(
+    address_space: AddressSpace,
+    entry: VirtualAddr,
+    stack: VirtualAddr,
+    program_break: VirtualAddr,
+) -> Result<Pid, SpawnError> {
+    let mut scheduler = SCHEDULER.lock();
+
+    let pid = scheduler.next_pid;
+
+    scheduler
+        .processes
+        .try_push(Process {
+            pid,
+            address_space,
+            context: TrapFrame::new_user(entry, stack),
+            brk_start: program_break,
+            brk: program_break,
+            files: FileTable::with_console(),
+            cwd: None,
+        })
+        .map_err(|_| SpawnError::TooManyProcesses)?;
+
+    scheduler.next_pid += 1;
+
+    log::debug!("Spawned process {} at {}", pid, entry);
+
+    Ok(pid)
+}
+
+/// Creates a copy of the current process that shares its memory copy-on-write. The child resumes
+/// from the current system call once it returns, with 0 as the result.
+pub fn fork() -> Result<Pid, SpawnError> {
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current.expect("No process is running");
+
+    if scheduler.processes.is_full() {
+        return Err(SpawnError::TooManyProcesses);
+    }
+
+    let pid = scheduler.next_pid;
+    let parent = &mut scheduler.processes[current];
+
+    let address_space = parent
+        .address_space
+        .fork()
+        .map_err(|_| SpawnError::AddressSpace)?;
+
+    let child = Process {
+        pid,
+        address_space,
+        // Filled in from the trap frame once the system call returns.
+        context: parent.context,
+        brk_start: parent.brk_start,
+        brk: parent.brk,
+        files: parent.files.clone(),
+        cwd: parent.cwd.clone(),
+    };
+
+    scheduler.processes.push(child);
+    scheduler.next_pid += 1;
+    scheduler.pending = Some(Pending::Fork(pid));
+
+    log::debug!(
+        "Forked process {} from {}",
+        pid,
+        scheduler.processes[current].pid
+    );
+
+    Ok(pid)
+}
+
+/// Starts running the first process. This never returns, once every process exits the hart is
+/// halted.
+pub fn run() -> ! {
+    let context = {
+        let mut scheduler = SCHEDULER.lock();
+        assert!(!scheduler.processes.is_empty(), "No process to run");
+
+        scheduler.current = Some(0);
+
+        let process = &scheduler.processes[0];
+        process.address_space.activate();
+        process.context
+    };
+
+    arch::resume_user(context);
+}
+
+/// Runs `f` with the process that is currently running.
+pub fn with_current<T>(f: impl FnOnce(&mut Process) -> T) -> T {
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current.expect("No process is running");
+
+    f(&mut scheduler.processes[current])
+}
+
+/// Resolves a page fault of the current process, returning whether the access may be retried.
+pub fn handle_page_fault(addr: VirtualAddr, access: Access) -> Result<bool, PageMapErr> {
+    with_current(|process| process.address_space.handle_fault(addr, access))
+}
+
+/// Gives up the rest of the current time slice once the current trap returns.
+pub fn yield_now() {
+    SCHEDULER.lock().pending = Some(Pending::Yield);
+}
+
+/// Terminates the current process once the current trap returns.
+pub fn exit(status: i32) {
+    SCHEDULER.lock().pending = Some(Pending::Exit(status));
+}
+
+/// Switches `frame` to the context of the next process if the current process yielded or exited.
+pub fn schedule(frame: &mut TrapFrame) {
+    let mut scheduler = SCHEDULER.lock();
+
+    let Some(current) = scheduler.current else {
+        return;
+    };
+
+    // The address space of an exited process can only be freed after switching away from it.
+    let mut exited = None;
+
+    let next = match scheduler.pending.take() {
+        None => return,
+        Some(Pending::Fork(pid)) => {
+            let mut context = *frame;
+            context.set_return_value(0);
+
+            if let Some(child) = scheduler
+                .processes
+                .iter_mut()
+                .find(|process| process.pid == pid)
+            {
+                child.context = context;
+            }
+
+            return;
+        }
+        Some(Pending::Yield) => {
+            scheduler.processes[current].context = *frame;
+            (current + 1) % scheduler.processes.len()
+        }
+        Some(Pending::Exit(status)) => {
+            let process = scheduler.processes.remove(current);
+            log::info!("Process {} exited with status {}", process.pid, status);
+
+            if scheduler.processes.is_empty() {
+                scheduler.current = None;
+                drop(scheduler);
+
+                mem::kernel_page_directory().activate();
+                drop(process);
+
+                log::info!("No processes left to run.");
+                let _ = fs::sync();
+                block::flush_all();
+                arch::halt();
+            }
+
+            exited = Some(process);
+            current % scheduler.processes.len()
+        }
+    };
+
+    scheduler.current = Some(next);
+
+    let process = &scheduler.processes[next];
+    process.address_space.activate();
+    *frame = process.context;
+
+    drop(exited);
+}
@@ -0,0 +1,231 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use nekos_abi::Errno;
+use nekos_abi::fs::{
+    AT_EMPTY_PATH, AT_FDCWD, AT_REMOVEDIR, AT_SYMLINK_NOFOLLOW, DIRENT64_NAME_OFFSET, Dirent64,
+    OpenFlags, PATH_MAX, SEEK_CUR, SEEK_END, SEEK_SET, Stat,
+};
+
+use super::SyscallResult;
+use crate::fs::{self, Dentry, File, SeekFrom};
+use crate::mem::{UserAccessErr, VirtualAddr};
+use crate::{arch, process};
+
+/// The largest chunk copied between user memory and a file at once.
+const CHUNK_SIZE: usize = 4096;
+
+pub fn sys_read(args: [u64; 6]) -> SyscallResult {
+    let [fd, buf, count, ..] = args;
+    let file = get_file(fd)?;
+
+    let mut chunk = alloc::vec![0u8; CHUNK_SIZE.min(count as usize)];
+    let mut total = 0;
+
+    while total < count {
+        let len = (count - total).min(chunk.len() as u64) as usize;
+
+        let read = file.read(&mut chunk[..len])?;
+        arch::copy_to_user(VirtualAddr::new(buf + total), &chunk[..read]).map_err(to_errno)?;
+        total += read as u64;
+
+        if read < len {
+            break;
+        }
+    }
+
+    Ok(total)
+}
+
+pub fn sys_write(args: [u64; 6]) -> SyscallResult {
+    let [fd, buf, count, ..] = args;
+    let file = get_file(fd)?;
+
+    let mut chunk = alloc::vec![0u8; CHUNK_SIZE.min(count as usize)];
+    let mut total = 0;
+
+    while total < count {
+        let len = (count - total).min(chunk.len() as u64) as usize;
+
+        arch::copy_from_user(&mut chunk[..len], VirtualAddr::new(buf + total)).map_err(to_errno)?;
+        let written = file.write(&chunk[..len])?;
+        total += written as u64;
+
+        if written < len {
+            break;
+        }
+    }
+
+    Ok(total)
+}
+
+pub fn sys_openat(args: [u64; 6]) -> SyscallResult {
+    let [dirfd, path, flags, mode, ..] = args;
+
+    let path = read_path(path)?;
+    let flags = OpenFlags::from_bits_truncate(flags as usize);
+    let base = base_dentry(dirfd)?;
+
+    let file = fs::open(base.as_ref(), &path, flags, mode as u32)?;
+
+    // There is no `execve`, so `O_CLOEXEC` is accepted but changes nothing.
+    let fd = process::with_current(|process| process.files.insert(file))?;
+
+    Ok(fd as u64)
+}
+
+pub fn sys_unlinkat(args: [u64; 6]) -> SyscallResult {
+    let [dirfd, path, flags, ..] = args;
+
+    if flags as usize & !AT_REMOVEDIR != 0 {
+        return Err(Errno::EINVAL);
+    }
+
+    let path = read_path(path)?;
+    let base = base_dentry(dirfd)?;
+
+    let (parent, name) = fs::lookup_parent(base.as_ref(), &path)?;
+    parent.remove_child(name, flags as usize & AT_REMOVEDIR != 0)?;
+
+    Ok(0)
+}
+
+pub fn sys_close(args: [u64; 6]) -> SyscallResult {
+    process::with_current(|process| process.files.remove(args[0] as usize))?;
+    Ok(0)
+}
+
+pub fn sys_lseek(args: [u64; 6]) -> SyscallResult {
+    let [fd, offset, whence, ..] = args;
+    let file = get_file(fd)?;
+
+    let pos = match whence as usize {
+        SEEK_SET => SeekFrom::Start(offset),
+        SEEK_CUR => SeekFrom::Current(offset as i64),
+        SEEK_END => SeekFrom::End(offset as i64),
+        _ => return Err(Errno::EINVAL),
+    };
+
+    file.seek(pos)
+}
+
+pub fn sys_getdents64(args: [u64; 6]) -> SyscallResult {
+    let [fd, dirp, count, ..] = args;
+    let file = get_file(fd)?;
+
+    let mut buf = Vec::new();
+    file.read_dir(&mut |entry, next_offset| {
+        let name = entry.name.as_bytes();
+        let record_len = (DIRENT64_NAME_OFFSET + name.len() + 1).next_multiple_of(8);
+
+        if buf.len() + record_len > count as usize {
+            return false;
+        }
+
+        let header = Dirent64 {
+            d_ino: entry.ino,
+            d_off: next_offset as i64,
+            d_reclen: record_len as u16,
+            d_type: entry.kind.dirent_type(),
+        };
+
+        let start = buf.len();
+        buf.resize(start + record_len, 0);
+        buf[start..start + DIRENT64_NAME_OFFSET]
+            .copy_from_slice(&as_bytes(&header)[..DIRENT64_NAME_OFFSET]);
+        buf[start + DIRENT64_NAME_OFFSET..][..name.len()].copy_from_slice(name);
+
+        true
+    })?;
+
+    // Not even a single entry fits in the buffer.
+    if buf.is_empty() && count != 0 && !is_end_of_directory(&*file)? {
+        return Err(Errno::EINVAL);
+    }
+
+    arch::copy_to_user(VirtualAddr::new(dirp), &buf).map_err(to_errno)?;
+
+    Ok(buf.len() as u64)
+}
+
+pub fn sys_newfstatat(args: [u64; 6]) -> SyscallResult {
+    let [dirfd, path, statbuf, flags, ..] = args;
+
+    let path = read_path(path)?;
+    let flags = flags as usize;
+
+    let metadata = if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+        get_file(dirfd)?.metadata()?
+    } else {
+        let base = base_dentry(dirfd)?;
+        let follow = flags & AT_SYMLINK_NOFOLLOW == 0;
+        fs::lookup(base.as_ref(), &path, follow)?.metadata()?
+    };
+
+    write_stat(statbuf, &metadata.to_stat())
+}
+
+pub fn sys_fstat(args: [u64; 6]) -> SyscallResult {
+    let [fd, statbuf, ..] = args;
+    let metadata = get_file(fd)?.metadata()?;
+
+    write_stat(statbuf, &metadata.to_stat())
+}
+
+pub fn sys_sync(_args: [u64; 6]) -> SyscallResult {
+    fs::sync()?;
+    Ok(0)
+}
+
+pub(super) fn get_file(fd: u64) -> Result<Arc<dyn File>, Errno> {
+    process::with_current(|process| process.files.get(fd as usize))
+}
+
+/// Returns the directory that relative paths given together with `dirfd` start at.
+fn base_dentry(dirfd: u64) -> Result<Option<Arc<Dentry>>, Errno> {
+    if dirfd as i64 == AT_FDCWD as i64 {
+        return Ok(process::with_current(|process| process.cwd.clone()));
+    }
+
+    let file = get_file(dirfd)?;
+    file.dentry().cloned().map(Some).ok_or(Errno::ENOTDIR)
+}
+
+/// Copies the null terminated path at `addr` out of user memory.
+fn read_path(addr: u64) -> Result<String, Errno> {
+    let mut buf = alloc::vec![0u8; PATH_MAX];
+    let len =
+        arch::copy_cstr_from_user(&mut buf, VirtualAddr::new(addr)).map_err(|err| match err {
+            UserAccessErr::TooLong => Errno::ENAMETOOLONG,
+            err => to_errno(err),
+        })?;
+
+    buf.truncate(len);
+    String::from_utf8(buf).map_err(|_| Errno::EINVAL)
+}
+
+fn is_end_of_directory(file: &dyn File) -> Result<bool, Errno> {
+    let mut has_entries = false;
+    file.read_dir(&mut |_, _| {
+        has_entries = true;
+        false
+    })?;
+
+    Ok(!has_entries)
+}
+
+fn write_stat(statbuf: u64, stat: &Stat) -> SyscallResult {
+    arch::copy_to_user(VirtualAddr::new(statbuf), as_bytes(stat)).map_err(to_errno)?;
+    Ok(0)
+}
+
+fn as_bytes<T>(value: &T) -> &[u8] {
+    // SAFETY: Only used on the `repr(C)` structures of the ABI, which have no padding that
+    // userland could observe the contents of.
+    unsafe { core::slice::from_raw_parts((value as *const T).cast::<u8>(), size_of::<T>()) }
+}
+
+fn to_errno(_err: UserAccessErr) -> Errno {
+    Errno::EFAULT
+}
@@ -0,0 +1,221 @@
+use nekos_abi::Errno;
+use nekos_abi::mman::{MapFlags, Prot};
+use nekos_mem::vma::Backing;
+
+use super::SyscallResult;
+use crate::arch::PAGE_SIZE;
+use crate::mem::{AddressSpace, MappedFile, PageMapErr, VirtualAddr, VirtualMemoryFlags, meminfo};
+use crate::{misc, process};
+
+/// The lowest address handed out by `mmap` when the caller does not ask for a fixed address.
+const MMAP_BASE: u64 = 0x0000_0010_0000_0000;
+
+pub fn sys_mmap(args: [u64; 6]) -> SyscallResult {
+    let [addr, length, prot, flags, fd, offset] = args;
+
+    let prot = Prot::from_bits(prot as usize).ok_or(Errno::EINVAL)?;
+    let flags = MapFlags::from_bits(flags as usize).ok_or(Errno::EINVAL)?;
+
+    let shared = flags.contains(MapFlags::SHARED);
+    if shared == flags.contains(MapFlags::PRIVATE) {
+        return Err(Errno::EINVAL);
+    }
+
+    if length == 0 {
+        return Err(Errno::EINVAL);
+    }
+
+    if flags.contains(MapFlags::FIXED) && !VirtualAddr::new(addr).is_aligned_with(PAGE_SIZE) {
+        return Err(Errno::EINVAL);
+    }
+
+    let length = misc::checked_align_up_page(length).ok_or(Errno::ENOMEM)?;
+
+    let mut vm_flags = protection(prot);
+    if shared {
+        vm_flags |= VirtualMemoryFlags::Shared;
+    }
+    if flags.contains(MapFlags::GROWSDOWN) {
+        vm_flags |= VirtualMemoryFlags::GrowsDown;
+    }
+    if flags.contains(MapFlags::NORESERVE) {
+        vm_flags |= VirtualMemoryFlags::NoReserve;
+    }
+
+    let backing = if flags.contains(MapFlags::ANONYMOUS) {
+        Backing::Anonymous
+    } else {
+        if !VirtualAddr::new(offset).is_aligned_with(PAGE_SIZE) {
+            return Err(Errno::EINVAL);
+        }
+
+        let file = super::fs::get_file(fd)?;
+        let dentry = file.dentry().cloned().ok_or(Errno::ENODEV)?;
+
+        // Pages of a file are never written back, so they can only be shared if nobody writes.
+        if shared && prot.contains(Prot::WRITE) {
+            return Err(Errno::ENODEV);
+        }
+
+        Backing::File {
+            file: MappedFile(dentry),
+            offset,
+        }
+    };
+
+    // Private writable memory needs frames of its own eventually, unless the caller is willing to
+    // be killed when there are none left.
+    if vm_flags.contains(VirtualMemoryFlags::Writeable)
+        && !shared
+        && !flags.contains(MapFlags::NORESERVE)
+        && length > meminfo::get().free
+    {
+        return Err(Errno::ENOMEM);
+    }
+
+    process::with_current(|process| {
+        let address_space = &mut process.address_space;
+        let hint = VirtualAddr::new(misc::align_down_page(addr));
+
+        let base = if flags.contains(MapFlags::FIXED) {
+            address_space
+                .unmap(hint, length as usize)
+                .map_err(to_errno)?;
+            hint
+        } else if addr != 0
+            && AddressSpace::is_user_range(hint, length as usize)
+            && address_space.is_free(hint, length as usize)
+        {
+            hint
+        } else {
+            address_space
+                .find_free(length as usize, VirtualAddr::new(MMAP_BASE))
+                .ok_or(Errno::ENOMEM)?
+        };
+
+        // Shared anonymous memory has to exist before a fork, which shares only what is mapped.
+        if shared && let Backing::Anonymous = backing {
+            address_space
+                .map_anonymous(base, length as usize, vm_flags)
+                .inspect_err(|_| {
+                    let _ = address_space.unmap(base, length as usize);
+                })
+                .map_err(to_errno)?;
+        } else {
+            address_space
+                .map_area(base, length as usize, vm_flags, backing)
+                .map_err(to_errno)?;
+        }
+
+        Ok(base.addr())
+    })
+}
+
+pub fn sys_munmap(args: [u64; 6]) -> SyscallResult {
+    let [addr, length, ..] = args;
+
+    if length == 0 {
+        return Err(Errno::EINVAL);
+    }
+
+    process::with_current(|process| {
+        process
+            .address_space
+            .unmap(VirtualAddr::new(addr), length as usize)
+    })
+    .map_err(|_| Errno::EINVAL)?;
+
+    Ok(0)
+}
+
+pub fn sys_mprotect(args: [u64; 6]) -> SyscallResult {
+    let [addr, length, prot, ..] = args;
+
+    let prot = Prot::from_bits(prot as usize).ok_or(Errno::EINVAL)?;
+
+    if !VirtualAddr::new(addr).is_aligned_with(PAGE_SIZE) {
+        return Err(Errno::EINVAL);
+    }
+
+    if length == 0 {
+        return Ok(0);
+    }
+
+    process::with_current(|process| {
+        process
+            .address_space
+            .protect(VirtualAddr::new(addr), length as usize, protection(prot))
+    })
+    .map_err(to_errno)?;
+
+    Ok(0)
+}
+
+pub fn sys_brk(args: [u64; 6]) -> SyscallResult {
+    let [addr, ..] = args;
+
+    // Failing is not an error, the caller sees that the break did not move.
+    Ok(process::with_current(|process| {
+        if addr < process.brk_start.addr() {
+            return process.brk.addr();
+        }
+
+        let Some(new_end) = misc::checked_align_up_page(addr) else {
+            return process.brk.addr();
+        };
+        let old_end = misc::align_up_page(process.brk.addr());
+        let address_space = &mut process.address_space;
+
+        let moved = if new_end > old_end {
+            let size = (new_end - old_end) as usize;
+            let start = VirtualAddr::new(old_end);
+
+            address_space.is_free(start, size)
+                && address_space
+                    .map_area(
+                        start,
+                        size,
+                        VirtualMemoryFlags::Writeable,
+                        Backing::Anonymous,
+                    )
+                    .is_ok()
+        } else if new_end < old_end {
+            address_space
+                .unmap(VirtualAddr::new(new_end), (old_end - new_end) as usize)
+                .is_ok()
+        } else {
+            true
+        };
+
+        if moved {
+            process.brk = VirtualAddr::new(addr);
+        }
+
+        process.brk.addr()
+    }))
+}
+
+fn protection(prot: Prot) -> VirtualMemoryFlags {
+    // Pages can not be writable or executable without being readable, so only `PROT_NONE` takes
+    // reading away.
+    if prot.is_empty() {
+        return VirtualMemoryFlags::NoAccess;
+    }
+
+    let mut flags = VirtualMemoryFlags::empty();
+    if prot.contains(Prot::WRITE) {
+        flags |= VirtualMemoryFlags::Writeable;
+    }
+    if prot.contains(Prot::EXEC) {
+        flags |= VirtualMemoryFlags::Executable;
+    }
+
+    flags
+}
+
+fn to_errno(err: PageMapErr) -> Errno {
+    match err {
+        PageMapErr::PageFrameAllocError | PageMapErr::NotMapped => Errno::ENOMEM,
+        _ => Errno::EINVAL,
+    }
+}
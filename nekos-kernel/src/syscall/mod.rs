@@ -0,0 +1,56 @@
+mod fs;
+mod mem;
+mod process;
+mod syslog;
+mod time;
+
+use nekos_abi::{Errno, Syscall};
+
+use crate::log;
+
+type SyscallResult = Result<u64, Errno>;
+type SyscallHandler = fn(args: [u64; 6]) -> SyscallResult;
+
+/// The system call table, indexed by the system call number.
+static SYSCALL_TABLE: [Option<SyscallHandler>; Syscall::MAX] = {
+    let mut table: [Option<SyscallHandler>; Syscall::MAX] = [None; Syscall::MAX];
+
+    table[Syscall::UnlinkAt as usize] = Some(fs::sys_unlinkat);
+    table[Syscall::OpenAt as usize] = Some(fs::sys_openat);
+    table[Syscall::Close as usize] = Some(fs::sys_close);
+    table[Syscall::Getdents64 as usize] = Some(fs::sys_getdents64);
+    table[Syscall::Lseek as usize] = Some(fs::sys_lseek);
+    table[Syscall::Read as usize] = Some(fs::sys_read);
+    table[Syscall::Write as usize] = Some(fs::sys_write);
+    table[Syscall::NewFstatAt as usize] = Some(fs::sys_newfstatat);
+    table[Syscall::Fstat as usize] = Some(fs::sys_fstat);
+    table[Syscall::Sync as usize] = Some(fs::sys_sync);
+    table[Syscall::Exit as usize] = Some(process::sys_exit);
+    table[Syscall::ClockGettime as usize] = Some(time::sys_clock_gettime);
+    table[Syscall::Syslog as usize] = Some(syslog::sys_syslog);
+    table[Syscall::Yield as usize] = Some(process::sys_yield);
+    table[Syscall::GetPid as usize] = Some(process::sys_getpid);
+    table[Syscall::Brk as usize] = Some(mem::sys_brk);
+    table[Syscall::Munmap as usize] = Some(mem::sys_munmap);
+    table[Syscall::Mmap as usize] = Some(mem::sys_mmap);
+    table[Syscall::Mprotect as usize] = Some(mem::sys_mprotect);
+    table[Syscall::Clone as usize] = Some(process::sys_clone);
+
+    table
+};
+
+/// Runs the system call `number` and returns the value that is placed in `a0`.
+pub fn dispatch(number: u64, args: [u64; 6]) -> i64 {
+    log::trace!("System call {} with {:x?}", number, args);
+
+    let handler = SYSCALL_TABLE
+        .get(number as usize)
+        .copied()
+        .flatten()
+        .ok_or(Errno::ENOSYS);
+
+    match handler.and_then(|handler| handler(args)) {
+        Ok(value) => value as i64,
+        Err(errno) => errno.as_result() as i64,
+    }
+}
@@ -0,0 +1,34 @@
+use nekos_abi::Errno;
+use nekos_abi::sched::SIGCHLD;
+
+use super::SyscallResult;
+use crate::process::{self, SpawnError};
+
+pub fn sys_exit(args: [u64; 6]) -> SyscallResult {
+    process::exit(args[0] as i32);
+    Ok(0)
+}
+
+pub fn sys_yield(_args: [u64; 6]) -> SyscallResult {
+    process::yield_now();
+    Ok(0)
+}
+
+pub fn sys_getpid(_args: [u64; 6]) -> SyscallResult {
+    Ok(process::with_current(|process| process.pid) as u64)
+}
+
+pub fn sys_clone(args: [u64; 6]) -> SyscallResult {
+    let [flags, stack, ..] = args;
+
+    // Only `fork` is supported, threads need more than a copy of the address space.
+    if flags as usize != SIGCHLD || stack != 0 {
+        return Err(Errno::EINVAL);
+    }
+
+    match process::fork() {
+        Ok(pid) => Ok(pid as u64),
+        Err(SpawnError::TooManyProcesses) => Err(Errno::EAGAIN),
+        Err(_) => Err(Errno::ENOMEM),
+    }
+}
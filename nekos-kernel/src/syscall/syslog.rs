@@ -0,0 +1,38 @@
+use alloc::vec;
+
+use nekos_abi::Errno;
+use nekos_abi::syslog::SyslogAction;
+
+use super::SyscallResult;
+use crate::arch;
+use crate::log;
+use crate::mem::VirtualAddr;
+
+pub fn sys_syslog(args: [u64; 6]) -> SyscallResult {
+    let [action, buf, len, ..] = args;
+
+    match SyslogAction::from_raw(action as usize).ok_or(Errno::EINVAL)? {
+        action @ (SyslogAction::ReadAll | SyslogAction::ReadClear) => {
+            let mut text = vec![0; (len as usize).min(log::BUFFER_SIZE)];
+            let len = log::read_all(&mut text);
+
+            arch::copy_to_user(VirtualAddr::new(buf), &text[..len]).map_err(|_| Errno::EFAULT)?;
+
+            if action == SyslogAction::ReadClear {
+                log::clear();
+            }
+
+            Ok(len as u64)
+        }
+        SyslogAction::Clear => {
+            log::clear();
+            Ok(0)
+        }
+        SyslogAction::ConsoleLevel => {
+            let level = u8::try_from(len).map_err(|_| Errno::EINVAL)?;
+            log::set_level(level);
+            Ok(0)
+        }
+        SyslogAction::SizeBuffer => Ok(log::BUFFER_SIZE as u64),
+    }
+}
@@ -0,0 +1,26 @@
+use nekos_abi::Errno;
+use nekos_abi::time::{ClockId, Timespec};
+
+use super::SyscallResult;
+use crate::arch;
+use crate::mem::VirtualAddr;
+
+pub fn sys_clock_gettime(args: [u64; 6]) -> SyscallResult {
+    let [clock_id, tp, ..] = args;
+
+    // There is no real-time clock yet, so only the clocks counting from boot are supported.
+    let time = match ClockId::from_raw(clock_id as usize) {
+        Some(ClockId::Monotonic | ClockId::Boottime) => arch::monotonic_time(),
+        _ => return Err(Errno::EINVAL),
+    };
+
+    let timespec = Timespec::from(time);
+
+    let bytes = unsafe {
+        core::slice::from_raw_parts((&raw const timespec).cast::<u8>(), size_of::<Timespec>())
+    };
+
+    arch::copy_to_user(VirtualAddr::new(tp), bytes).map_err(|_| Errno::EFAULT)?;
+
+    Ok(0)
+}
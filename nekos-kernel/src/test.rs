@@ -0,0 +1,99 @@
+//! Kernel tests, which run after boot in kernels built with the `kernel-tests` feature.
+//!
+//! Tests are functions marked with `#[kernel_test]`, which places an entry for each into the
+//! `.kernel_tests` section. They run one after the other in place of init, and the machine is
+//! stopped with the result as its exit status. Tests fail by panicking, and since the kernel can
+//! not unwind, the first failure ends the run.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use colorz::Colorize;
+
+use crate::arch::{self, print};
+use crate::cmdline::param;
+use crate::{log, mem};
+
+pub use nekos_macros::kernel_test;
+
+/// A test as registered by `#[kernel_test]`.
+#[repr(C)]
+pub struct KernelTest {
+    pub name: &'static str,
+    pub run: fn(),
+}
+
+param!(
+    /// Only runs the tests whose names contain this, like `test=mem::`.
+    test: &str = ""
+);
+
+unsafe extern "C" {
+    #[link_name = "__kernel_tests_begin"]
+    static KERNEL_TESTS_BEGIN: u8;
+
+    #[link_name = "__kernel_tests_end"]
+    static KERNEL_TESTS_END: u8;
+}
+
+const NO_TEST: usize = usize::MAX;
+
+/// The index of the test that is running.
+static CURRENT: AtomicUsize = AtomicUsize::new(NO_TEST);
+
+fn tests() -> &'static [KernelTest] {
+    // SAFETY: The linker script collects the entries written by `#[kernel_test]` between the two
+    // symbols, and nothing else is placed in the `.kernel_tests` section.
+    unsafe {
+        let begin = &raw const KERNEL_TESTS_BEGIN as *const KernelTest;
+        let end = &raw const KERNEL_TESTS_END as *const KernelTest;
+        core::slice::from_raw_parts(begin, end.offset_from(begin) as usize)
+    }
+}
+
+/// Runs the selected tests and stops the machine with exit status 0.
+pub fn run() -> ! {
+    let filter = test.get();
+    let selected = tests()
+        .iter()
+        .filter(|kernel_test| kernel_test.name.contains(filter))
+        .count();
+
+    print!("\nrunning {} kernel tests\n", selected);
+
+    for (index, kernel_test) in tests().iter().enumerate() {
+        if !kernel_test.name.contains(filter) {
+            continue;
+        }
+
+        CURRENT.store(index, Ordering::Release);
+        (kernel_test.run)();
+        print!("test {} ... {}\n", kernel_test.name, "ok".green());
+    }
+
+    CURRENT.store(NO_TEST, Ordering::Release);
+
+    // Memory that is still charged after the tests were leaked by them.
+    mem::meminfo::log(log::Level::Info);
+
+    print!(
+        "\ntest result: {}. {} passed; {} filtered out\n",
+        "ok".green(),
+        selected,
+        tests().len() - selected
+    );
+
+    arch::exit(0);
+}
+
+/// Reports the running test as failed and stops the machine with exit status 1. Called by the
+/// panic handler, returns if no test is running.
+pub fn fail() {
+    let Some(kernel_test) = tests().get(CURRENT.load(Ordering::Acquire)) else {
+        return;
+    };
+
+    print!("test {} ... {}\n", kernel_test.name, "FAILED".red());
+    print!("\ntest result: {}\n", "FAILED".red());
+
+    arch::exit(1);
+}
@@ -0,0 +1,58 @@
+//! Procedural macros of the kernel.
+
+use proc_macro::{TokenStream, TokenTree};
+
+/// Registers a function as a kernel test, which runs after boot in kernels built with the
+/// `kernel-tests` feature. The test fails by panicking.
+///
+/// ```ignore
+/// #[cfg(feature = "kernel-tests")]
+/// mod tests {
+///     use crate::test::kernel_test;
+///
+///     #[kernel_test]
+///     fn addition() {
+///         assert_eq!(1 + 1, 2);
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn kernel_test(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return compile_error("`#[kernel_test]` takes no arguments");
+    }
+
+    let mut tokens = item.clone().into_iter();
+    let name = tokens
+        .by_ref()
+        .find(|token| matches!(token, TokenTree::Ident(ident) if ident.to_string() == "fn"))
+        .and(tokens.next());
+
+    let Some(TokenTree::Ident(name)) = name else {
+        return compile_error("`#[kernel_test]` only applies to functions");
+    };
+
+    // The entry is collected by the linker, the same way as the kernel parameters.
+    let entry: TokenStream = format!(
+        r#"
+        const _: () = {{
+            #[used]
+            #[unsafe(link_section = ".kernel_tests")]
+            static ENTRY: crate::test::KernelTest = crate::test::KernelTest {{
+                name: concat!(module_path!(), "::", stringify!({name})),
+                run: {name},
+            }};
+        }};
+        "#
+    )
+    .parse()
+    .unwrap();
+
+    let mut output = item;
+    output.extend(entry);
+    output
+}
+
+fn compile_error(message: &str) -> TokenStream {
+    format!("compile_error!({message:?});").parse().unwrap()
+}
@@ -0,0 +1,139 @@
+use core::fmt;
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+#[repr(transparent)]
+pub struct VirtualAddr(u64);
+
+impl VirtualAddr {
+    #[inline]
+    pub const fn new(value: u64) -> Self {
+        VirtualAddr(value)
+    }
+
+    #[inline]
+    pub const fn as_mut_ptr<T>(&self) -> *mut T {
+        self.0 as *mut T
+    }
+
+    #[inline]
+    pub const fn as_ptr<T>(&self) -> *const T {
+        self.0 as *const T
+    }
+
+    #[inline]
+    pub const fn addr(&self) -> u64 {
+        self.0
+    }
+
+    pub const fn is_aligned_with(&self, alignment: u64) -> bool {
+        self.addr() & (alignment - 1) == 0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+#[repr(transparent)]
+pub struct PhysicalAddr(u64);
+
+impl PhysicalAddr {
+    #[inline]
+    pub const fn new(value: u64) -> Self {
+        PhysicalAddr(value)
+    }
+
+    #[inline]
+    pub const fn addr(&self) -> u64 {
+        self.0
+    }
+
+    /// The address in a mapping of all physical memory at `offset`. The offset wraps around, so
+    /// that memory below the physical address can be used as well.
+    #[inline]
+    pub const fn as_virtual_by_offset(&self, offset: u64) -> VirtualAddr {
+        VirtualAddr::new(self.0.wrapping_add(offset))
+    }
+
+    pub const fn is_aligned_with(&self, alignment: u64) -> bool {
+        self.addr() & (alignment - 1) == 0
+    }
+}
+
+impl fmt::Display for VirtualAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:016X}", self.0)
+    }
+}
+
+impl fmt::Display for PhysicalAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:016X}", self.0)
+    }
+}
+
+impl fmt::Debug for VirtualAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Debug for PhysicalAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::format;
+
+    use super::*;
+
+    #[test]
+    fn alignment() {
+        assert!(PhysicalAddr::new(0x2000).is_aligned_with(0x1000));
+        assert!(!PhysicalAddr::new(0x2008).is_aligned_with(0x1000));
+        assert!(PhysicalAddr::new(0x2008).is_aligned_with(8));
+        assert!(VirtualAddr::new(0).is_aligned_with(0x20_0000));
+        assert!(!VirtualAddr::new(0x1000).is_aligned_with(0x20_0000));
+    }
+
+    #[test]
+    fn virtual_by_offset() {
+        let offset = 0xffff_8000_0000_0000;
+        assert_eq!(
+            PhysicalAddr::new(0x8020_0000).as_virtual_by_offset(offset),
+            VirtualAddr::new(0xffff_8000_8020_0000)
+        );
+
+        // An arena on the host may lie below the physical addresses it simulates.
+        let offset = 0x1000u64.wrapping_sub(0x8000_0000);
+        assert_eq!(
+            PhysicalAddr::new(0x8000_2000).as_virtual_by_offset(offset),
+            VirtualAddr::new(0x3000)
+        );
+    }
+
+    #[test]
+    fn ordering() {
+        assert!(PhysicalAddr::new(0x1000) < PhysicalAddr::new(0x2000));
+        assert!(VirtualAddr::new(0x2000) > VirtualAddr::new(0x1000));
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(
+            format!("{}", PhysicalAddr::new(0xabc)),
+            "0x0000000000000ABC"
+        );
+        assert_eq!(
+            format!("{:?}", VirtualAddr::new(0xffff_ffff_8000_0000)),
+            "0xFFFFFFFF80000000"
+        );
+    }
+
+    #[test]
+    fn pointers() {
+        let value = 42u64;
+        let addr = VirtualAddr::new(&value as *const u64 as u64);
+        assert_eq!(unsafe { *addr.as_ptr::<u64>() }, 42);
+    }
+}
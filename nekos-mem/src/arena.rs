@@ -0,0 +1,52 @@
+//! Simulated physical memory for the tests of the allocators.
+
+use std::alloc::{self, Layout};
+
+use crate::{PAGE_SIZE, PhysicalAddr};
+
+/// Where the simulated physical memory starts, which is where RAM starts on QEMU `virt`.
+pub const BASE: u64 = 0x8000_0000;
+
+/// A page aligned block of host memory standing in for physical memory at `BASE`.
+pub struct Arena {
+    memory: *mut u8,
+    layout: Layout,
+}
+
+impl Arena {
+    pub fn new(num_pages: usize) -> Self {
+        let layout =
+            Layout::from_size_align(num_pages * PAGE_SIZE as usize, PAGE_SIZE as usize).unwrap();
+        let memory = unsafe { alloc::alloc_zeroed(layout) };
+        assert!(!memory.is_null(), "Failed to allocate the arena");
+
+        Self { memory, layout }
+    }
+
+    /// The offset at which the arena maps physical memory, like the HHDM of the kernel.
+    pub fn hhdm_offset(&self) -> u64 {
+        (self.memory as u64).wrapping_sub(BASE)
+    }
+
+    /// The physical address of the page `index` of the arena.
+    pub fn page(&self, index: usize) -> PhysicalAddr {
+        assert!(index <= self.num_pages());
+        PhysicalAddr::new(BASE + index as u64 * PAGE_SIZE)
+    }
+
+    /// The index of the page at `addr`.
+    pub fn index(&self, addr: PhysicalAddr) -> usize {
+        assert!(addr.is_aligned_with(PAGE_SIZE));
+        ((addr.addr() - BASE) / PAGE_SIZE) as usize
+    }
+
+    pub fn num_pages(&self) -> usize {
+        self.layout.size() / PAGE_SIZE as usize
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.memory, self.layout) }
+    }
+}
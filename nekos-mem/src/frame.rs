@@ -0,0 +1,274 @@
+//! Descriptors of the physical page frames.
+//!
+//! The page allocator only knows which frames are free. Everything else there is to know about a
+//! frame, like how many mappings share it and what it is used for, is kept in a `FrameDescriptor`.
+//! The descriptors of all frames are stored in one array, the `FrameTable`, so that the descriptor
+//! of a frame is found from its address and the other way around.
+
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use bitflags::bitflags;
+
+use crate::{PAGE_SIZE, PhysicalAddr, align_up};
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct FrameFlags: u32 {
+        /// Memory of the kernel itself, like its heap.
+        const Kernel = 1 << 0;
+        /// Memory mapped into user address spaces.
+        const User = 1 << 1;
+        const PageTable = 1 << 2;
+        /// A page of the kernel heap split into blocks of a size class.
+        const Slab = 1 << 3;
+    }
+}
+
+/// What is known about a physical page frame.
+#[derive(Debug)]
+pub struct FrameDescriptor {
+    /// The number of users of the frame, which is freed when the last one drops it. Free frames
+    /// have none.
+    refcount: AtomicU32,
+    flags: AtomicU32,
+}
+
+impl FrameDescriptor {
+    /// The descriptor of a free frame.
+    pub const fn new() -> Self {
+        Self {
+            refcount: AtomicU32::new(0),
+            flags: AtomicU32::new(0),
+        }
+    }
+
+    /// Sets up the descriptor of a frame that was just allocated, with a single reference.
+    pub fn claim(&self, flags: FrameFlags) {
+        let previous = self.refcount.swap(1, Ordering::Relaxed);
+        debug_assert!(previous == 0, "Allocated a frame that is still referenced");
+
+        self.flags.store(flags.bits(), Ordering::Relaxed);
+    }
+
+    /// Resets the descriptor of a frame that is about to be freed. At most the reference of the
+    /// caller may be left.
+    pub fn release(&self) {
+        let previous = self.refcount.swap(0, Ordering::Relaxed);
+        debug_assert!(
+            previous <= 1,
+            "Freeing a frame with {} references",
+            previous
+        );
+
+        self.flags.store(0, Ordering::Relaxed);
+    }
+
+    pub fn refcount(&self) -> u32 {
+        self.refcount.load(Ordering::Acquire)
+    }
+
+    /// Takes another reference to the frame.
+    pub fn get(&self) {
+        let previous = self.refcount.fetch_add(1, Ordering::Relaxed);
+        assert!(previous != 0, "Took a reference to a free frame");
+    }
+
+    /// Drops a reference to the frame, returning whether it was the last one, in which case the
+    /// caller frees the frame.
+    #[must_use]
+    pub fn put(&self) -> bool {
+        let previous = self.refcount.fetch_sub(1, Ordering::Release);
+        assert!(previous != 0, "Dropped a reference to a free frame");
+
+        previous == 1
+    }
+
+    pub fn flags(&self) -> FrameFlags {
+        FrameFlags::from_bits_retain(self.flags.load(Ordering::Relaxed))
+    }
+}
+
+impl Default for FrameDescriptor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The descriptors of the frames from `base` on, one for each page.
+pub struct FrameTable {
+    base: PhysicalAddr,
+    frames: NonNull<FrameDescriptor>,
+    num_frames: usize,
+}
+
+// SAFETY: The table owns its descriptors, which are made of atomics.
+unsafe impl Send for FrameTable {}
+unsafe impl Sync for FrameTable {}
+
+impl FrameTable {
+    /// The number of pages the descriptors of `num_frames` frames take up.
+    pub const fn num_pages(num_frames: usize) -> usize {
+        let size = (num_frames * size_of::<FrameDescriptor>()) as u64;
+        (align_up(size, PAGE_SIZE) / PAGE_SIZE) as usize
+    }
+
+    /// Sets up the descriptors of `num_frames` free frames starting at `base` in `memory`.
+    ///
+    /// # Safety
+    ///
+    /// `memory` is valid for writes of `num_pages(num_frames)` pages, which are owned by the
+    /// table from now on.
+    pub unsafe fn new(base: PhysicalAddr, memory: NonNull<u8>, num_frames: usize) -> Self {
+        assert!(base.is_aligned_with(PAGE_SIZE));
+
+        let frames = memory.cast::<FrameDescriptor>();
+        assert!(frames.is_aligned());
+
+        for index in 0..num_frames {
+            unsafe { frames.add(index).write(FrameDescriptor::new()) };
+        }
+
+        Self {
+            base,
+            frames,
+            num_frames,
+        }
+    }
+
+    fn as_slice(&self) -> &[FrameDescriptor] {
+        unsafe { core::slice::from_raw_parts(self.frames.as_ptr(), self.num_frames) }
+    }
+
+    /// The first address past the frames the table covers.
+    pub fn end(&self) -> PhysicalAddr {
+        PhysicalAddr::new(self.base.addr() + self.num_frames as u64 * PAGE_SIZE)
+    }
+
+    /// The descriptor of the frame containing `addr`, if the table covers it.
+    pub fn frame(&self, addr: PhysicalAddr) -> Option<&FrameDescriptor> {
+        let index = addr.addr().checked_sub(self.base.addr())? / PAGE_SIZE;
+        self.as_slice().get(index as usize)
+    }
+
+    /// The descriptors of the `num_pages` frames starting at `base`, if the table covers them.
+    pub fn frames(&self, base: PhysicalAddr, num_pages: usize) -> Option<&[FrameDescriptor]> {
+        let index = base.addr().checked_sub(self.base.addr())? / PAGE_SIZE;
+        self.as_slice()
+            .get(index as usize..index as usize + num_pages)
+    }
+
+    /// The address of the frame `frame` describes.
+    ///
+    /// # Panics
+    ///
+    /// If `frame` is not a descriptor of this table.
+    pub fn addr(&self, frame: &FrameDescriptor) -> PhysicalAddr {
+        let index = self
+            .as_slice()
+            .as_ptr_range()
+            .contains(&ptr::from_ref(frame))
+            .then(|| unsafe { ptr::from_ref(frame).offset_from(self.frames.as_ptr()) })
+            .expect("The frame descriptor is not part of the frame table");
+
+        PhysicalAddr::new(self.base.addr() + index as u64 * PAGE_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    /// A frame table over `num_frames` frames from `BASE` and the memory holding it.
+    fn table(num_frames: usize) -> (FrameTable, Vec<FrameDescriptor>) {
+        let mut memory: Vec<FrameDescriptor> = Vec::with_capacity(num_frames);
+        let frames = NonNull::new(memory.as_mut_ptr()).unwrap().cast();
+        let table = unsafe { FrameTable::new(PhysicalAddr::new(BASE), frames, num_frames) };
+
+        (table, memory)
+    }
+
+    const BASE: u64 = 0x8000_0000;
+
+    #[test]
+    fn num_pages_rounds_up() {
+        assert_eq!(FrameTable::num_pages(0), 0);
+        assert_eq!(FrameTable::num_pages(1), 1);
+
+        let per_page = PAGE_SIZE as usize / size_of::<FrameDescriptor>();
+        assert_eq!(FrameTable::num_pages(per_page), 1);
+        assert_eq!(FrameTable::num_pages(per_page + 1), 2);
+    }
+
+    #[test]
+    fn converts_between_addresses_and_descriptors() {
+        let (table, _memory) = table(8);
+
+        let addr = PhysicalAddr::new(BASE + 3 * PAGE_SIZE);
+        let frame = table.frame(addr).unwrap();
+        assert_eq!(table.addr(frame), addr);
+
+        // Any address inside the frame finds it.
+        let inside = PhysicalAddr::new(addr.addr() + 123);
+        assert!(ptr::eq(table.frame(inside).unwrap(), frame));
+
+        assert_eq!(table.end(), PhysicalAddr::new(BASE + 8 * PAGE_SIZE));
+    }
+
+    #[test]
+    fn does_not_cover_addresses_outside_the_table() {
+        let (table, _memory) = table(8);
+
+        assert!(table.frame(PhysicalAddr::new(BASE - PAGE_SIZE)).is_none());
+        assert!(table.frame(table.end()).is_none());
+        assert!(
+            table
+                .frames(PhysicalAddr::new(BASE + 6 * PAGE_SIZE), 3)
+                .is_none()
+        );
+        assert_eq!(
+            table
+                .frames(PhysicalAddr::new(BASE + 6 * PAGE_SIZE), 2)
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "not part of the frame table")]
+    fn rejects_foreign_descriptors() {
+        let (table, _memory) = table(8);
+        table.addr(&FrameDescriptor::new());
+    }
+
+    #[test]
+    fn counts_references() {
+        let frame = FrameDescriptor::new();
+        frame.claim(FrameFlags::User);
+        assert_eq!(frame.refcount(), 1);
+        assert_eq!(frame.flags(), FrameFlags::User);
+
+        frame.get();
+        assert!(!frame.put());
+        assert!(frame.put());
+        assert_eq!(frame.refcount(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "reference to a free frame")]
+    fn rejects_references_to_free_frames() {
+        FrameDescriptor::new().get();
+    }
+
+    #[test]
+    fn release_resets_the_descriptor() {
+        let frame = FrameDescriptor::new();
+        frame.claim(FrameFlags::Kernel | FrameFlags::Slab);
+
+        frame.release();
+        assert_eq!(frame.refcount(), 0);
+        assert_eq!(frame.flags(), FrameFlags::empty());
+    }
+}
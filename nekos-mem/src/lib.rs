@@ -0,0 +1,101 @@
+//! The architecture independent parts of the memory management of the kernel.
+//!
+//! Nothing in here touches the hardware or the boot information, memory is reached through the
+//! offset at which it is mapped. That is what lets the allocators run on the host, where their
+//! tests use a simulated physical memory arena:
+//!
+//! ```text
+//! cargo test --manifest-path nekos-mem/Cargo.toml --target host-tuple
+//! ```
+
+#![no_std]
+
+extern crate alloc;
+
+#[cfg(test)]
+extern crate std;
+
+pub mod addr;
+pub mod frame;
+pub mod page_allocator;
+pub mod range_allocator;
+pub mod vma;
+
+#[cfg(test)]
+mod arena;
+
+use bitflags::bitflags;
+
+pub use addr::{PhysicalAddr, VirtualAddr};
+
+/// The size of the pages handed out by the allocators.
+pub const PAGE_SIZE: u64 = 4096;
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct VirtualMemoryFlags: u16 {
+        const Writeable = 1 << 0;
+        const Executable = 1 << 1;
+        const UserAccessible = 1 << 2;
+        const MMIO = 1 << 3;
+        /// Read-only for now, but a write gets a private copy of the page that is writable.
+        const CopyOnWrite = 1 << 4;
+        /// Shared with forks rather than copied on write.
+        const Shared = 1 << 5;
+        /// A stack, which grows down to faults just below it.
+        const GrowsDown = 1 << 6;
+        /// Mapped without checking that there is memory for all of it.
+        const NoReserve = 1 << 7;
+        /// Neither readable, writable nor executable by the process, like `PROT_NONE`.
+        const NoAccess = 1 << 8;
+    }
+}
+
+/// Rounds `addr` up to a multiple of `align`, panicking if the result does not fit in a `u64`.
+#[inline]
+pub const fn align_up(addr: u64, align: u64) -> u64 {
+    match addr.checked_next_multiple_of(align) {
+        Some(aligned) => aligned,
+        None => panic!("Aligning the address up overflows."),
+    }
+}
+
+#[inline]
+pub const fn align_down(addr: u64, align: u64) -> u64 {
+    (addr / align) * align
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn align_up_rounds_to_the_next_multiple() {
+        assert_eq!(align_up(0, PAGE_SIZE), 0);
+        assert_eq!(align_up(1, PAGE_SIZE), PAGE_SIZE);
+        assert_eq!(align_up(PAGE_SIZE, PAGE_SIZE), PAGE_SIZE);
+        assert_eq!(align_up(PAGE_SIZE + 1, PAGE_SIZE), 2 * PAGE_SIZE);
+        assert_eq!(align_up(13, 16), 16);
+    }
+
+    #[test]
+    fn align_down_rounds_to_the_previous_multiple() {
+        assert_eq!(align_down(0, PAGE_SIZE), 0);
+        assert_eq!(align_down(PAGE_SIZE - 1, PAGE_SIZE), 0);
+        assert_eq!(align_down(PAGE_SIZE, PAGE_SIZE), PAGE_SIZE);
+        assert_eq!(align_down(2 * PAGE_SIZE + 7, PAGE_SIZE), 2 * PAGE_SIZE);
+        assert_eq!(align_down(31, 16), 16);
+    }
+
+    #[test]
+    fn align_works_with_odd_alignments() {
+        assert_eq!(align_up(10, 3), 12);
+        assert_eq!(align_down(10, 3), 9);
+    }
+
+    #[test]
+    #[should_panic(expected = "overflows")]
+    fn align_up_panics_on_overflow() {
+        align_up(u64::MAX - 1, PAGE_SIZE);
+    }
+}
@@ -0,0 +1,659 @@
+use crate::{PAGE_SIZE, PhysicalAddr};
+
+use arrayvec::ArrayVec;
+
+use core::fmt;
+use core::ptr::NonNull;
+
+/// The most regions of usable memory the allocator keeps track of.
+const MAX_USABLE_REGIONS: usize = 64;
+
+/// A first fit allocator of physical pages.
+///
+/// The free regions form a doubly linked list sorted by address, whose nodes are stored in the
+/// first page of the regions themselves. Adjacent regions are always merged.
+pub struct FreeListAllocator {
+    // The list does not point back into the allocator, so that the allocator can be moved and
+    // borrowing it again does not invalidate the pointers in the list.
+    head: Option<NonNull<FreeListNode>>,
+
+    // The free regions hold their own nodes, which are reached through the mapping of physical
+    // memory at this offset.
+    hhdm_offset: u64,
+
+    // The memory that may be handed out at all, which debug builds check deallocations against.
+    usable: ArrayVec<Pages, MAX_USABLE_REGIONS>,
+
+    free_pages: usize,
+}
+
+/// A range of `num_pages` pages starting at `base`, which prints as `[start, end)`.
+#[derive(Clone, Copy)]
+struct Pages {
+    base: PhysicalAddr,
+    num_pages: usize,
+}
+
+impl Pages {
+    fn end(&self) -> u64 {
+        self.base.addr() + PAGE_SIZE * self.num_pages as u64
+    }
+
+    fn overlaps(&self, other: &Pages) -> bool {
+        self.base.addr() < other.end() && other.base.addr() < self.end()
+    }
+
+    fn contains(&self, other: &Pages) -> bool {
+        self.base <= other.base && other.end() <= self.end()
+    }
+}
+
+impl fmt::Display for Pages {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}, {})", self.base, PhysicalAddr::new(self.end()))
+    }
+}
+
+struct FreeListNode {
+    next: Option<NonNull<FreeListNode>>,
+    prev: Option<NonNull<FreeListNode>>,
+
+    base: PhysicalAddr,
+    num_pages: usize,
+}
+
+/// SAFETY: The nodes will only reside in the mutex anyway.
+unsafe impl Send for FreeListAllocator {}
+
+#[derive(Debug)]
+pub struct AllocError;
+
+/// The allocator already keeps track of as many regions of usable memory as it can.
+#[derive(Debug)]
+pub struct TooManyRegions;
+
+impl FreeListAllocator {
+    /// Creates an empty allocator for physical memory that is mapped at `hhdm_offset`.
+    pub const fn new(hhdm_offset: u64) -> Self {
+        FreeListAllocator {
+            head: None,
+            hhdm_offset,
+            usable: ArrayVec::new_const(),
+            free_pages: 0,
+        }
+    }
+
+    /// Marks `num_pages` pages at `base` as memory the allocator may hand out. This does not free
+    /// them yet, but lets debug builds tell memory that was never usable from a bad free.
+    pub fn add_usable_memory(
+        &mut self,
+        base: PhysicalAddr,
+        num_pages: usize,
+    ) -> Result<(), TooManyRegions> {
+        self.usable
+            .try_push(Pages { base, num_pages })
+            .map_err(|_| TooManyRegions)
+    }
+
+    pub fn allocate(&mut self, num_pages: usize) -> Result<PhysicalAddr, AllocError> {
+        let mut cursor = self.head;
+
+        while let Some(current) = cursor {
+            let node_ref = unsafe { current.as_ref() };
+
+            // Shrink this region.
+            if node_ref.num_pages > num_pages {
+                self.free_pages -= num_pages;
+                return Ok(FreeListNode::shrink(current, num_pages));
+            }
+
+            // Remove this region from the free list
+            if node_ref.num_pages == num_pages {
+                let base = node_ref.base;
+                self.remove(current);
+                self.free_pages -= num_pages;
+                return Ok(base);
+            }
+
+            cursor = node_ref.next;
+        }
+
+        Err(AllocError)
+    }
+
+    pub fn deallocate(&mut self, phys_addr: PhysicalAddr, num_pages: usize) {
+        // The last region before `phys_addr`, if any.
+        let mut prev = None;
+        let mut cursor = self.head;
+
+        while let Some(current) = cursor {
+            if unsafe { current.as_ref().base } > phys_addr {
+                break;
+            }
+
+            prev = Some(current);
+            cursor = unsafe { current.as_ref().next };
+        }
+
+        if cfg!(debug_assertions) {
+            let freed = Pages {
+                base: phys_addr,
+                num_pages,
+            };
+
+            self.check_usable(freed);
+
+            // The list is sorted, so only the regions right before and after can overlap.
+            let next = match prev {
+                Some(prev) => unsafe { prev.as_ref().next },
+                None => self.head,
+            };
+
+            for region in [prev, next].into_iter().flatten() {
+                let region = FreeListNode::pages(region);
+                assert!(
+                    !region.overlaps(&freed),
+                    "Freeing {} which overlaps the free region {}",
+                    freed,
+                    region
+                );
+            }
+        }
+
+        let new_node = unsafe { FreeListNode::from_addr(phys_addr, num_pages, self.hhdm_offset) };
+
+        self.insert_after(prev, new_node);
+        self.coalesce(new_node);
+        self.free_pages += num_pages;
+    }
+
+    /// The number of pages that can be allocated.
+    pub const fn free_pages(&self) -> usize {
+        self.free_pages
+    }
+
+    /// The number of pages of the largest free region, which is the most that one allocation can
+    /// get.
+    pub fn largest_free_region(&self) -> usize {
+        self.regions()
+            .map(|(_, num_pages)| num_pages)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The free regions as their base and number of pages, in ascending order.
+    pub fn regions(&self) -> impl Iterator<Item = (PhysicalAddr, usize)> + '_ {
+        let mut cursor = self.head;
+
+        core::iter::from_fn(move || {
+            let node = unsafe { cursor?.as_ref() };
+            cursor = node.next;
+            Some((node.base, node.num_pages))
+        })
+    }
+
+    /// Walks the free list and panics if it is broken: if the links do not match, a region is
+    /// empty, misaligned or outside of usable memory, or two regions are out of order, overlap or
+    /// should have been merged.
+    pub fn verify(&self) {
+        let mut prev: Option<NonNull<FreeListNode>> = None;
+        let mut cursor = self.head;
+        let mut free_pages = 0;
+
+        while let Some(node) = cursor {
+            let node_ref = unsafe { node.as_ref() };
+            let region = FreeListNode::pages(node);
+
+            assert!(
+                node_ref.prev == prev,
+                "The free region {} does not link back to the one before it",
+                region
+            );
+
+            self.check_usable(region);
+
+            if let Some(prev) = prev {
+                let prev = FreeListNode::pages(prev);
+                assert!(
+                    prev.end() < region.base.addr(),
+                    "The free region {} is not apart from the free region {} before it",
+                    region,
+                    prev
+                );
+            }
+
+            free_pages += node_ref.num_pages;
+            prev = Some(node);
+            cursor = node_ref.next;
+        }
+
+        assert!(
+            free_pages == self.free_pages,
+            "The free regions hold {} pages, but {} pages are counted as free",
+            free_pages,
+            self.free_pages
+        );
+    }
+
+    /// Panics unless `pages` is a page aligned, non-empty range within usable memory.
+    fn check_usable(&self, pages: Pages) {
+        assert!(
+            pages.base.is_aligned_with(PAGE_SIZE) && pages.num_pages != 0,
+            "{} is not a page aligned range of pages",
+            pages
+        );
+
+        if let Some(usable) = self.usable.iter().find(|usable| usable.overlaps(&pages)) {
+            assert!(
+                usable.contains(&pages),
+                "{} is not within the usable memory {}",
+                pages,
+                usable
+            );
+        } else {
+            panic!("{} is not within usable memory", pages);
+        }
+    }
+
+    fn coalesce(&mut self, mut node: NonNull<FreeListNode>) {
+        let prev = unsafe { node.as_ref().prev };
+        let next = unsafe { node.as_ref().next };
+
+        if let Some(prev) = prev
+            && FreeListNode::is_adjacent(prev, node)
+        {
+            self.merge(prev, node);
+            node = prev;
+        }
+
+        if let Some(next) = next
+            && FreeListNode::is_adjacent(node, next)
+        {
+            self.merge(node, next);
+        }
+    }
+
+    /// Links `node` into the list after `prev`, or at the front if there is none.
+    fn insert_after(
+        &mut self,
+        prev: Option<NonNull<FreeListNode>>,
+        mut node: NonNull<FreeListNode>,
+    ) {
+        unsafe {
+            let next = match prev {
+                Some(mut prev) => prev.as_mut().next.replace(node),
+                None => self.head.replace(node),
+            };
+
+            node.as_mut().prev = prev;
+            node.as_mut().next = next;
+
+            if let Some(mut next) = next {
+                next.as_mut().prev = Some(node);
+            }
+        }
+    }
+
+    fn remove(&mut self, mut node: NonNull<FreeListNode>) {
+        unsafe {
+            let prev = node.as_ref().prev;
+            let next = node.as_ref().next;
+
+            match prev {
+                Some(mut prev) => prev.as_mut().next = next,
+                None => self.head = next,
+            }
+
+            if let Some(mut next) = next {
+                next.as_mut().prev = prev;
+            }
+
+            node.as_mut().next = None;
+            node.as_mut().prev = None;
+        }
+    }
+
+    /// Adds the region of `right` to the one of `left`, which it directly follows.
+    fn merge(&mut self, mut left: NonNull<FreeListNode>, right: NonNull<FreeListNode>) {
+        self.remove(right);
+
+        let left = unsafe { left.as_mut() };
+        let right = unsafe { right.as_ref() };
+
+        left.num_pages += right.num_pages;
+    }
+}
+
+impl FreeListNode {
+    pub unsafe fn from_addr(
+        phys_addr: PhysicalAddr,
+        num_pages: usize,
+        hhdm_offset: u64,
+    ) -> NonNull<FreeListNode> {
+        let virt_addr = phys_addr.as_virtual_by_offset(hhdm_offset);
+        let ptr = virt_addr.as_mut_ptr::<FreeListNode>();
+        debug_assert!(ptr.is_aligned());
+
+        let node = FreeListNode {
+            next: None,
+            prev: None,
+            base: phys_addr,
+            num_pages,
+        };
+
+        unsafe { ptr.write(node) };
+        unsafe { NonNull::new_unchecked(ptr) }
+    }
+
+    fn pages(node: NonNull<FreeListNode>) -> Pages {
+        let node = unsafe { node.as_ref() };
+        Pages {
+            base: node.base,
+            num_pages: node.num_pages,
+        }
+    }
+
+    pub fn is_adjacent(left: NonNull<FreeListNode>, right: NonNull<FreeListNode>) -> bool {
+        let right = unsafe { right.as_ref() };
+        FreeListNode::end(left) == right.base
+    }
+
+    pub fn end(node: NonNull<FreeListNode>) -> PhysicalAddr {
+        let node = unsafe { node.as_ref() };
+        PhysicalAddr::new(node.base.addr() + PAGE_SIZE * node.num_pages as u64)
+    }
+
+    /// Shrinks the given node at the end.
+    pub fn shrink(mut node: NonNull<FreeListNode>, num_pages: usize) -> PhysicalAddr {
+        let node = unsafe { node.as_mut() };
+        assert!(node.num_pages > num_pages);
+
+        node.num_pages -= num_pages;
+
+        let addr = node.base.addr() + PAGE_SIZE * (node.num_pages as u64);
+        PhysicalAddr::new(addr)
+    }
+}
+
+#[cfg(test)]
+mod model;
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::arena::{Arena, BASE};
+
+    fn allocator(arena: &Arena) -> FreeListAllocator {
+        let mut allocator = FreeListAllocator::new(arena.hhdm_offset());
+        allocator
+            .add_usable_memory(arena.page(0), arena.num_pages())
+            .unwrap();
+        allocator
+    }
+
+    fn regions(allocator: &FreeListAllocator, arena: &Arena) -> Vec<(usize, usize)> {
+        allocator
+            .regions()
+            .map(|(base, num_pages)| (arena.index(base), num_pages))
+            .collect()
+    }
+
+    #[test]
+    fn empty_allocator_fails() {
+        let mut allocator = FreeListAllocator::new(0);
+        assert!(allocator.allocate(1).is_err());
+        assert_eq!(allocator.regions().count(), 0);
+    }
+
+    #[test]
+    fn allocates_from_a_single_region() {
+        let arena = Arena::new(8);
+        let mut allocator = allocator(&arena);
+        allocator.deallocate(arena.page(0), 8);
+
+        // Regions shrink from their end.
+        assert_eq!(allocator.allocate(1).unwrap(), arena.page(7));
+        assert_eq!(allocator.allocate(3).unwrap(), arena.page(4));
+        assert_eq!(regions(&allocator, &arena), [(0, 4)]);
+    }
+
+    #[test]
+    fn exact_fit_removes_the_region() {
+        let arena = Arena::new(4);
+        let mut allocator = allocator(&arena);
+        allocator.deallocate(arena.page(0), 4);
+
+        assert_eq!(allocator.allocate(4).unwrap(), arena.page(0));
+        assert_eq!(allocator.regions().count(), 0);
+        assert!(allocator.allocate(1).is_err());
+    }
+
+    #[test]
+    fn too_large_allocation_fails() {
+        let arena = Arena::new(4);
+        let mut allocator = allocator(&arena);
+        allocator.deallocate(arena.page(0), 4);
+
+        assert!(allocator.allocate(5).is_err());
+        assert_eq!(regions(&allocator, &arena), [(0, 4)]);
+    }
+
+    #[test]
+    fn skips_regions_that_are_too_small() {
+        let arena = Arena::new(8);
+        let mut allocator = allocator(&arena);
+        allocator.deallocate(arena.page(0), 1);
+        allocator.deallocate(arena.page(2), 2);
+        allocator.deallocate(arena.page(5), 3);
+
+        assert_eq!(allocator.allocate(3).unwrap(), arena.page(5));
+        assert_eq!(allocator.allocate(2).unwrap(), arena.page(2));
+        assert_eq!(regions(&allocator, &arena), [(0, 1)]);
+    }
+
+    #[test]
+    fn coalesces_with_both_neighbours() {
+        let arena = Arena::new(3);
+        let mut allocator = allocator(&arena);
+        allocator.deallocate(arena.page(2), 1);
+        allocator.deallocate(arena.page(0), 1);
+        assert_eq!(regions(&allocator, &arena), [(0, 1), (2, 1)]);
+
+        allocator.deallocate(arena.page(1), 1);
+        assert_eq!(regions(&allocator, &arena), [(0, 3)]);
+        assert_eq!(allocator.allocate(3).unwrap(), arena.page(0));
+    }
+
+    #[test]
+    fn coalesces_in_any_order() {
+        let arena = Arena::new(16);
+        let mut allocator = allocator(&arena);
+
+        for index in [7, 3, 12, 0, 15, 1, 9, 4, 14, 2, 8, 6, 11, 5, 13, 10] {
+            allocator.deallocate(arena.page(index), 1);
+        }
+
+        assert_eq!(regions(&allocator, &arena), [(0, 16)]);
+    }
+
+    #[test]
+    fn keeps_regions_sorted_and_separate() {
+        let arena = Arena::new(10);
+        let mut allocator = allocator(&arena);
+        allocator.deallocate(arena.page(6), 2);
+        allocator.deallocate(arena.page(0), 1);
+        allocator.deallocate(arena.page(3), 2);
+
+        assert_eq!(regions(&allocator, &arena), [(0, 1), (3, 2), (6, 2)]);
+    }
+
+    #[test]
+    fn freed_pages_are_reused() {
+        let arena = Arena::new(8);
+        let mut allocator = allocator(&arena);
+        allocator.deallocate(arena.page(0), 8);
+
+        let first = allocator.allocate(2).unwrap();
+        allocator.deallocate(first, 2);
+        assert_eq!(allocator.allocate(2).unwrap(), first);
+    }
+
+    #[test]
+    fn fragmentation() {
+        let arena = Arena::new(16);
+        let mut allocator = allocator(&arena);
+        allocator.deallocate(arena.page(0), 16);
+
+        let pages: Vec<_> = (0..16).map(|_| allocator.allocate(1).unwrap()).collect();
+        assert!(allocator.allocate(1).is_err());
+
+        // Every other page leaves plenty of memory, but no two pages in a row.
+        for page in pages.iter().step_by(2) {
+            allocator.deallocate(*page, 1);
+        }
+
+        assert_eq!(allocator.regions().count(), 8);
+        assert!(allocator.allocate(2).is_err());
+
+        for page in pages.iter().skip(1).step_by(2) {
+            allocator.deallocate(*page, 1);
+        }
+
+        assert_eq!(regions(&allocator, &arena), [(0, 16)]);
+        assert_eq!(allocator.allocate(16).unwrap(), arena.page(0));
+    }
+
+    #[test]
+    fn rejects_too_many_usable_regions() {
+        let mut allocator = FreeListAllocator::new(0);
+        for i in 0..MAX_USABLE_REGIONS as u64 {
+            let base = PhysicalAddr::new(2 * i * PAGE_SIZE);
+            allocator.add_usable_memory(base, 1).unwrap();
+        }
+
+        let base = PhysicalAddr::new(2 * MAX_USABLE_REGIONS as u64 * PAGE_SIZE);
+        assert!(allocator.add_usable_memory(base, 1).is_err());
+    }
+
+    #[test]
+    fn region_at_address_zero() {
+        let arena = Arena::new(4);
+        let mut allocator = FreeListAllocator::new(arena.hhdm_offset().wrapping_add(BASE));
+        allocator
+            .add_usable_memory(PhysicalAddr::new(0), 4)
+            .unwrap();
+        allocator.deallocate(PhysicalAddr::new(0), 2);
+        allocator.deallocate(PhysicalAddr::new(2 * PAGE_SIZE), 2);
+
+        let regions: Vec<_> = allocator.regions().collect();
+        assert_eq!(regions, [(PhysicalAddr::new(0), 4)]);
+        assert_eq!(allocator.allocate(4).unwrap(), PhysicalAddr::new(0));
+    }
+
+    #[test]
+    fn counts_free_pages() {
+        let arena = Arena::new(16);
+        let mut allocator = allocator(&arena);
+        allocator.deallocate(arena.page(0), 4);
+        allocator.deallocate(arena.page(8), 8);
+        assert_eq!(allocator.free_pages(), 12);
+        assert_eq!(allocator.largest_free_region(), 8);
+
+        let page = allocator.allocate(6).unwrap();
+        assert_eq!(allocator.free_pages(), 6);
+        assert_eq!(allocator.largest_free_region(), 4);
+
+        allocator.allocate(4).unwrap();
+        assert_eq!(allocator.free_pages(), 2);
+
+        allocator.deallocate(page, 6);
+        assert_eq!(allocator.free_pages(), 8);
+        assert_eq!(allocator.largest_free_region(), 8);
+    }
+
+    #[test]
+    fn verify_accepts_a_healthy_list() {
+        let arena = Arena::new(8);
+        let mut allocator = allocator(&arena);
+        allocator.deallocate(arena.page(0), 2);
+        allocator.deallocate(arena.page(4), 3);
+        allocator.verify();
+    }
+
+    // Bad frees are only caught by the checks of debug builds.
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "overlaps the free region")]
+    fn double_free() {
+        let arena = Arena::new(4);
+        let mut allocator = allocator(&arena);
+        allocator.deallocate(arena.page(1), 1);
+        allocator.deallocate(arena.page(1), 1);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "overlaps the free region")]
+    fn overlapping_free_of_the_region_before() {
+        let arena = Arena::new(8);
+        let mut allocator = allocator(&arena);
+        allocator.deallocate(arena.page(0), 4);
+        allocator.deallocate(arena.page(3), 2);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "overlaps the free region")]
+    fn overlapping_free_of_the_region_after() {
+        let arena = Arena::new(8);
+        let mut allocator = allocator(&arena);
+        allocator.deallocate(arena.page(4), 4);
+        allocator.deallocate(arena.page(2), 3);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "is not within usable memory")]
+    fn free_outside_of_usable_memory() {
+        let arena = Arena::new(8);
+        let mut allocator = FreeListAllocator::new(arena.hhdm_offset());
+        allocator.add_usable_memory(arena.page(0), 4).unwrap();
+        allocator.deallocate(arena.page(5), 1);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "is not within the usable memory")]
+    fn free_past_the_end_of_usable_memory() {
+        let arena = Arena::new(8);
+        let mut allocator = FreeListAllocator::new(arena.hhdm_offset());
+        allocator.add_usable_memory(arena.page(0), 4).unwrap();
+        allocator.deallocate(arena.page(2), 4);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "is not a page aligned range")]
+    fn misaligned_free() {
+        let arena = Arena::new(4);
+        let mut allocator = allocator(&arena);
+        allocator.deallocate(PhysicalAddr::new(arena.page(1).addr() + 8), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "is not apart from the free region")]
+    fn verify_finds_uncoalesced_regions() {
+        let arena = Arena::new(4);
+        let mut allocator = allocator(&arena);
+        allocator.deallocate(arena.page(0), 1);
+        allocator.deallocate(arena.page(2), 1);
+
+        // Corrupt the list the way a lost merge would.
+        let mut head = allocator.head.unwrap();
+        unsafe { head.as_mut().num_pages = 2 };
+
+        allocator.verify();
+    }
+}
@@ -0,0 +1,182 @@
+//! Model based tests of the free list.
+//!
+//! Random sequences of allocations and deallocations run against both the allocator and a bitmap
+//! of free pages, which is simple enough to be obviously right. After every step, the free list
+//! has to be intact and describe exactly the pages the bitmap has free.
+//!
+//! This also runs under Miri with `just miri`, which checks the pointer juggling of the list. The
+//! addresses of the allocator are plain integers, so Miri has to allow integer to pointer casts
+//! with `-Zmiri-permissive-provenance`.
+
+use std::vec::Vec;
+
+use proptest::prelude::*;
+use proptest::test_runner::Config;
+
+use super::*;
+use crate::arena::Arena;
+
+const NUM_PAGES: usize = 64;
+
+/// The largest allocation, which is large enough to often not fit.
+const MAX_ALLOCATION: usize = 12;
+
+#[derive(Clone, Debug)]
+enum Op {
+    Allocate(usize),
+    /// Frees one of the live allocations, picked by this index modulo their number.
+    Deallocate(usize),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        (1..=MAX_ALLOCATION).prop_map(Op::Allocate),
+        any::<usize>().prop_map(Op::Deallocate),
+    ]
+}
+
+/// The pages that are free at first, in the order they are handed to the allocator one by one.
+fn initial_pages() -> impl Strategy<Value = Vec<usize>> {
+    proptest::sample::subsequence((0..NUM_PAGES).collect::<Vec<_>>(), 0..=NUM_PAGES).prop_shuffle()
+}
+
+fn config() -> Config {
+    Config {
+        // Miri is several orders of magnitude slower.
+        cases: if cfg!(miri) { 4 } else { 512 },
+        // Miri does not allow writing the regression file.
+        failure_persistence: None,
+        ..Config::default()
+    }
+}
+
+struct Model {
+    free: [bool; NUM_PAGES],
+}
+
+impl Model {
+    /// The length of the longest run of free pages.
+    fn longest_run(&self) -> usize {
+        self.free
+            .split(|&free| !free)
+            .map(|run| run.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Whether there are `num_pages` free pages in a row.
+    fn fits(&self, num_pages: usize) -> bool {
+        self.free
+            .windows(num_pages)
+            .any(|window| window.iter().all(|&free| free))
+    }
+}
+
+/// Checks the links of the list and that its regions are sorted, apart, within the arena and
+/// exactly the free pages of `model`.
+fn check(allocator: &FreeListAllocator, arena: &Arena, model: &Model) {
+    let mut prev = None;
+    let mut cursor = allocator.head;
+    let mut free = [false; NUM_PAGES];
+    let mut last_end = None;
+
+    while let Some(node) = cursor {
+        let node_ref = unsafe { node.as_ref() };
+        assert_eq!(node_ref.prev, prev, "broken back link");
+        assert!(node_ref.num_pages > 0, "empty region");
+
+        let start = arena.index(node_ref.base);
+        let end = start + node_ref.num_pages;
+        assert!(end <= NUM_PAGES, "region past the arena");
+
+        if let Some(last_end) = last_end {
+            assert!(start >= last_end, "regions out of order or overlapping");
+            assert!(start != last_end, "adjacent regions were not coalesced");
+        }
+
+        for page in &mut free[start..end] {
+            *page = true;
+        }
+
+        last_end = Some(end);
+        prev = Some(node);
+        cursor = node_ref.next;
+    }
+
+    assert_eq!(free, model.free, "the free list lost or gained pages");
+    assert_eq!(
+        allocator.free_pages(),
+        model.free.iter().filter(|&&free| free).count()
+    );
+    assert_eq!(allocator.largest_free_region(), model.longest_run());
+    allocator.verify();
+}
+
+proptest! {
+    #![proptest_config(config())]
+
+    #[test]
+    fn matches_the_model(initial in initial_pages(), ops in proptest::collection::vec(op(), 1..64)) {
+        let arena = Arena::new(NUM_PAGES);
+        let mut allocator = FreeListAllocator::new(arena.hhdm_offset());
+        allocator
+            .add_usable_memory(arena.page(0), NUM_PAGES)
+            .unwrap();
+        let mut model = Model { free: [false; NUM_PAGES] };
+
+        for &index in &initial {
+            allocator.deallocate(arena.page(index), 1);
+            model.free[index] = true;
+            check(&allocator, &arena, &model);
+        }
+
+        let mut live = Vec::new();
+
+        for op in ops {
+            match op {
+                Op::Allocate(num_pages) => match allocator.allocate(num_pages) {
+                    Ok(addr) => {
+                        let start = arena.index(addr);
+                        prop_assert!(start + num_pages <= NUM_PAGES);
+
+                        for page in &mut model.free[start..start + num_pages] {
+                            prop_assert!(*page, "allocated a page that is in use");
+                            *page = false;
+                        }
+
+                        live.push((addr, num_pages));
+                    }
+                    Err(AllocError) => {
+                        prop_assert!(!model.fits(num_pages), "failed although {} pages fit", num_pages);
+                    }
+                },
+                Op::Deallocate(index) => {
+                    if live.is_empty() {
+                        continue;
+                    }
+
+                    let (addr, num_pages) = live.swap_remove(index % live.len());
+                    allocator.deallocate(addr, num_pages);
+
+                    let start = arena.index(addr);
+                    for page in &mut model.free[start..start + num_pages] {
+                        *page = true;
+                    }
+                }
+            }
+
+            check(&allocator, &arena, &model);
+        }
+
+        // Everything comes back together once it is all freed.
+        for (addr, num_pages) in live {
+            allocator.deallocate(addr, num_pages);
+            let start = arena.index(addr);
+            for page in &mut model.free[start..start + num_pages] {
+                *page = true;
+            }
+        }
+
+        check(&allocator, &arena, &model);
+    }
+}
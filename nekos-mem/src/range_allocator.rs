@@ -1,12 +1,8 @@
 use arrayvec::ArrayVec;
 
-use crate::arch::PAGE_SIZE;
-use crate::misc;
-
 use core::ptr::NonNull;
 
-use super::VirtualMemoryFlags;
-use super::addr::VirtualAddr;
+use crate::{PAGE_SIZE, VirtualAddr, VirtualMemoryFlags};
 
 /// A `Range` corresponds to an region in the virtual memory address space.
 #[derive(Clone, Copy)]
@@ -29,7 +25,7 @@ const NUM_RANGE: usize =
     (PAGE_SIZE as usize - size_of::<NonNull<RangeObject>>() - size_of::<usize>())
         / size_of::<Range>();
 
-misc::const_assert!(size_of::<RangeObject>() <= PAGE_SIZE as usize);
+const _: () = assert!(size_of::<RangeObject>() <= PAGE_SIZE as usize);
 
 pub struct RangeAllocator {
     objects: Option<NonNull<RangeObject>>,
@@ -59,6 +55,12 @@ impl RangeAllocator {
 }
 
 impl RangeObject {
+    /// Writes an empty range object at `addr`.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be aligned for `RangeObject` and point to a page of writable memory that
+    /// nothing else uses.
     pub unsafe fn from_addr(addr: VirtualAddr) -> NonNull<RangeObject> {
         let addr = addr.as_mut_ptr::<RangeObject>();
         debug_assert!(addr.is_aligned());
@@ -73,3 +75,42 @@ impl RangeObject {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arena::Arena;
+
+    #[test]
+    fn range_objects_fit_in_a_page() {
+        const { assert!(NUM_RANGE > 0) };
+        assert!(size_of::<RangeObject>() <= PAGE_SIZE as usize);
+    }
+
+    #[test]
+    fn new_allocator_is_empty() {
+        let allocator = RangeAllocator::new(VirtualAddr::new(0x1000_0000));
+        assert!(allocator.objects.is_none());
+        assert!(allocator.tail().is_none());
+    }
+
+    #[test]
+    fn finds_the_last_object() {
+        let arena = Arena::new(3);
+        let object = |index| {
+            let addr = arena.page(index).as_virtual_by_offset(arena.hhdm_offset());
+            unsafe { RangeObject::from_addr(addr) }
+        };
+
+        let (mut first, mut second, third) = (object(0), object(1), object(2));
+        unsafe {
+            assert!(first.as_ref().objects.is_empty());
+            first.as_mut().next = Some(second);
+            second.as_mut().next = Some(third);
+        }
+
+        let mut allocator = RangeAllocator::new(VirtualAddr::new(0x1000_0000));
+        allocator.objects = Some(first);
+        assert_eq!(allocator.tail(), Some(third));
+    }
+}
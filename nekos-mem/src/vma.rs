@@ -0,0 +1,539 @@
+//! The virtual memory areas of an address space.
+//!
+//! An area is a page aligned range of user memory that was mapped as a whole, with the same flags
+//! and the same backing throughout. Its pages are only populated once they are touched, so the
+//! areas, not the page table, are what tells a page fault whether the access was legal.
+//!
+//! Areas never overlap. Mapping over existing areas cuts them, and neighbours that are alike are
+//! merged, so that a range mapped piece by piece ends up as one area.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::{PAGE_SIZE, VirtualAddr, VirtualMemoryFlags};
+
+/// The flags that `protect` changes, the others describe the area itself.
+const PROTECTION: VirtualMemoryFlags = VirtualMemoryFlags::Writeable
+    .union(VirtualMemoryFlags::Executable)
+    .union(VirtualMemoryFlags::NoAccess);
+
+/// What the pages of an area are filled with when they are populated.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Backing<F> {
+    /// Zeroed memory.
+    Anonymous,
+    /// The contents of `file` from `offset` on, which is the offset of the start of the area.
+    File { file: F, offset: u64 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Area<F> {
+    pub start: VirtualAddr,
+    pub end: VirtualAddr,
+    pub flags: VirtualMemoryFlags,
+    pub backing: Backing<F>,
+}
+
+impl<F: Clone + PartialEq> Area<F> {
+    pub fn new(
+        start: VirtualAddr,
+        end: VirtualAddr,
+        flags: VirtualMemoryFlags,
+        backing: Backing<F>,
+    ) -> Self {
+        assert!(start.is_aligned_with(PAGE_SIZE) && end.is_aligned_with(PAGE_SIZE));
+        assert!(start < end, "Empty area at {}", start);
+
+        Self {
+            start,
+            end,
+            flags,
+            backing,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.end.addr() - self.start.addr()
+    }
+
+    pub fn contains(&self, addr: VirtualAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// The file and the offset in it that the page at `page` is filled from, if any.
+    pub fn file_offset(&self, page: VirtualAddr) -> Option<(&F, u64)> {
+        match &self.backing {
+            Backing::Anonymous => None,
+            Backing::File { file, offset } => {
+                Some((file, offset + (page.addr() - self.start.addr())))
+            }
+        }
+    }
+
+    /// Cuts the area at `at`, keeping the lower part and returning the upper one.
+    fn split_off(&mut self, at: u64) -> Area<F> {
+        debug_assert!(self.start.addr() < at && at < self.end.addr());
+
+        let backing = match &self.backing {
+            Backing::Anonymous => Backing::Anonymous,
+            Backing::File { file, offset } => Backing::File {
+                file: file.clone(),
+                offset: offset + (at - self.start.addr()),
+            },
+        };
+
+        let upper = Area {
+            start: VirtualAddr::new(at),
+            end: self.end,
+            flags: self.flags,
+            backing,
+        };
+
+        self.end = VirtualAddr::new(at);
+        upper
+    }
+
+    /// Whether `next`, which starts where this area ends, could be part of this area.
+    fn can_merge(&self, next: &Area<F>) -> bool {
+        if self.end != next.start || self.flags != next.flags {
+            return false;
+        }
+
+        match (&self.backing, &next.backing) {
+            (Backing::Anonymous, Backing::Anonymous) => true,
+            (
+                Backing::File { file, offset },
+                Backing::File {
+                    file: next_file,
+                    offset: next_offset,
+                },
+            ) => file == next_file && offset + self.size() == *next_offset,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum VmaError {
+    /// Part of the range is not covered by any area.
+    NotMapped,
+}
+
+/// The areas of an address space, ordered by address.
+#[derive(Clone)]
+pub struct VmaTree<F> {
+    /// The areas by their start address.
+    areas: BTreeMap<u64, Area<F>>,
+}
+
+impl<F: Clone + PartialEq> VmaTree<F> {
+    pub const fn new() -> Self {
+        Self {
+            areas: BTreeMap::new(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Area<F>> {
+        self.areas.values()
+    }
+
+    /// The area containing `addr`.
+    pub fn find(&self, addr: VirtualAddr) -> Option<&Area<F>> {
+        self.areas
+            .range(..=addr.addr())
+            .next_back()
+            .map(|(_, area)| area)
+            .filter(|area| area.contains(addr))
+    }
+
+    /// Whether no area overlaps `[start, end)`.
+    pub fn is_free(&self, start: VirtualAddr, end: VirtualAddr) -> bool {
+        let before = self.areas.range(..start.addr()).next_back();
+        let overlapping = self.areas.range(start.addr()..end.addr()).next();
+
+        before.is_none_or(|(_, area)| area.end <= start) && overlapping.is_none()
+    }
+
+    /// The lowest page aligned address from `above` on where `size` bytes fit before `limit`.
+    pub fn find_free(
+        &self,
+        size: u64,
+        above: VirtualAddr,
+        limit: VirtualAddr,
+    ) -> Option<VirtualAddr> {
+        let mut candidate = above.addr();
+
+        // An area reaching past `above` pushes the candidate up as well.
+        let before = self.areas.range(..candidate).next_back();
+        if let Some((_, area)) = before {
+            candidate = candidate.max(area.end.addr());
+        }
+
+        for area in self.areas.range(candidate..).map(|(_, area)| area) {
+            if candidate + size <= area.start.addr() {
+                break;
+            }
+
+            candidate = area.end.addr();
+        }
+
+        (candidate.checked_add(size)? <= limit.addr()).then_some(VirtualAddr::new(candidate))
+    }
+
+    /// Adds `area`, replacing whatever overlaps it, and merges it with its neighbours.
+    pub fn insert(&mut self, area: Area<F>) {
+        let (start, end) = (area.start.addr(), area.end.addr());
+
+        self.remove(area.start, area.end);
+        self.areas.insert(start, area);
+
+        self.merge(start);
+        self.merge(end);
+    }
+
+    /// Removes `[start, end)` from the areas, cutting those that reach outside of it, and returns
+    /// what was removed.
+    pub fn remove(&mut self, start: VirtualAddr, end: VirtualAddr) -> Vec<Area<F>> {
+        self.split(start.addr());
+        self.split(end.addr());
+
+        let starts: Vec<u64> = self
+            .areas
+            .range(start.addr()..end.addr())
+            .map(|(&start, _)| start)
+            .collect();
+
+        starts
+            .into_iter()
+            .filter_map(|start| self.areas.remove(&start))
+            .collect()
+    }
+
+    /// Replaces the protection flags of the areas in `[start, end)` with those in `flags`,
+    /// cutting the areas that reach outside of it. Fails without changing anything if part of the
+    /// range is not mapped.
+    pub fn protect(
+        &mut self,
+        start: VirtualAddr,
+        end: VirtualAddr,
+        flags: VirtualMemoryFlags,
+    ) -> Result<(), VmaError> {
+        if !self.is_covered(start, end) {
+            return Err(VmaError::NotMapped);
+        }
+
+        self.split(start.addr());
+        self.split(end.addr());
+
+        let starts: Vec<u64> = self
+            .areas
+            .range_mut(start.addr()..end.addr())
+            .map(|(&start, area)| {
+                area.flags = area.flags.difference(PROTECTION) | flags.intersection(PROTECTION);
+                start
+            })
+            .collect();
+
+        for start in starts {
+            self.merge(start);
+        }
+        self.merge(end.addr());
+
+        Ok(())
+    }
+
+    /// Grows the area above `addr` down to the page of `addr`, if it is marked `GrowsDown`, no
+    /// other area is in the way and it ends up no larger than `max_size`. Returns whether it did.
+    pub fn grow_down(&mut self, addr: VirtualAddr, max_size: u64) -> bool {
+        let page = addr.addr() & !(PAGE_SIZE - 1);
+
+        let Some((&start, area)) = self.areas.range(page..).next() else {
+            return false;
+        };
+
+        let grows_down = area.flags.contains(VirtualMemoryFlags::GrowsDown);
+        if !grows_down || area.end.addr() - page > max_size || !self.is_free(addr, area.start) {
+            return false;
+        }
+
+        let mut area = self.areas.remove(&start).unwrap();
+        if let Backing::File { offset, .. } = &mut area.backing {
+            let Some(lower) = offset.checked_sub(start - page) else {
+                self.areas.insert(start, area);
+                return false;
+            };
+            *offset = lower;
+        }
+
+        area.start = VirtualAddr::new(page);
+        self.areas.insert(page, area);
+        self.merge(page);
+
+        true
+    }
+
+    /// Whether `[start, end)` is covered by areas without gaps.
+    fn is_covered(&self, start: VirtualAddr, end: VirtualAddr) -> bool {
+        let mut covered = start;
+
+        while covered < end {
+            match self.find(covered) {
+                Some(area) => covered = area.end,
+                None => return false,
+            }
+        }
+
+        true
+    }
+
+    /// Cuts the area containing `at` in two, unless `at` is already a boundary.
+    fn split(&mut self, at: u64) {
+        let Some((_, area)) = self.areas.range_mut(..at).next_back() else {
+            return;
+        };
+
+        if area.end.addr() <= at {
+            return;
+        }
+
+        let upper = area.split_off(at);
+        self.areas.insert(at, upper);
+    }
+
+    /// Merges the area ending at `at` with the one starting there, if they are alike.
+    fn merge(&mut self, at: u64) {
+        let Some(next) = self.areas.get(&at) else {
+            return;
+        };
+
+        let Some((_, previous)) = self.areas.range(..at).next_back() else {
+            return;
+        };
+
+        if !previous.can_merge(next) {
+            return;
+        }
+
+        let next = self.areas.remove(&at).unwrap();
+        let (_, previous) = self.areas.range_mut(..at).next_back().unwrap();
+        previous.end = next.end;
+    }
+}
+
+impl<F: Clone + PartialEq> Default for VmaTree<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec;
+
+    /// Files are told apart by a number in the tests.
+    type Tree = VmaTree<u32>;
+
+    const RW: VirtualMemoryFlags = VirtualMemoryFlags::Writeable;
+    const RX: VirtualMemoryFlags = VirtualMemoryFlags::Executable;
+
+    fn page(index: u64) -> VirtualAddr {
+        VirtualAddr::new(0x1000_0000 + index * PAGE_SIZE)
+    }
+
+    fn anonymous(start: u64, end: u64, flags: VirtualMemoryFlags) -> Area<u32> {
+        Area::new(page(start), page(end), flags, Backing::Anonymous)
+    }
+
+    fn file(start: u64, end: u64, file: u32, offset: u64) -> Area<u32> {
+        Area::new(
+            page(start),
+            page(end),
+            VirtualMemoryFlags::empty(),
+            Backing::File { file, offset },
+        )
+    }
+
+    fn ranges(tree: &Tree) -> Vec<(VirtualAddr, VirtualAddr)> {
+        tree.iter().map(|area| (area.start, area.end)).collect()
+    }
+
+    #[test]
+    fn finds_the_area_containing_an_address() {
+        let mut tree = Tree::new();
+        tree.insert(anonymous(2, 4, RW));
+
+        assert!(tree.find(page(1)).is_none());
+        assert_eq!(tree.find(page(2)).unwrap().start, page(2));
+        assert_eq!(
+            tree.find(VirtualAddr::new(page(4).addr() - 1)).unwrap().end,
+            page(4)
+        );
+        assert!(tree.find(page(4)).is_none());
+    }
+
+    #[test]
+    fn merges_alike_neighbours() {
+        let mut tree = Tree::new();
+        tree.insert(anonymous(0, 2, RW));
+        tree.insert(anonymous(4, 6, RW));
+        tree.insert(anonymous(2, 4, RW));
+
+        assert_eq!(ranges(&tree), vec![(page(0), page(6))]);
+    }
+
+    #[test]
+    fn keeps_different_neighbours_apart() {
+        let mut tree = Tree::new();
+        tree.insert(anonymous(0, 2, RW));
+        tree.insert(anonymous(2, 4, RX));
+        tree.insert(file(4, 6, 1, 0));
+        tree.insert(file(6, 8, 2, 2 * PAGE_SIZE));
+
+        assert_eq!(tree.iter().count(), 4);
+    }
+
+    #[test]
+    fn merges_file_areas_only_if_the_offsets_continue() {
+        let mut tree = Tree::new();
+        tree.insert(file(0, 2, 1, 0));
+        tree.insert(file(2, 4, 1, 2 * PAGE_SIZE));
+        tree.insert(file(4, 6, 1, 0));
+
+        assert_eq!(ranges(&tree), vec![(page(0), page(4)), (page(4), page(6))]);
+    }
+
+    #[test]
+    fn insert_replaces_overlapping_areas() {
+        let mut tree = Tree::new();
+        tree.insert(anonymous(0, 8, RW));
+        tree.insert(anonymous(2, 4, RX));
+
+        assert_eq!(
+            ranges(&tree),
+            vec![(page(0), page(2)), (page(2), page(4)), (page(4), page(8))]
+        );
+        assert_eq!(tree.find(page(3)).unwrap().flags, RX);
+    }
+
+    #[test]
+    fn remove_cuts_areas_at_the_edges() {
+        let mut tree = Tree::new();
+        tree.insert(file(0, 8, 1, 0));
+        tree.insert(anonymous(10, 12, RW));
+
+        let removed = tree.remove(page(2), page(11));
+        assert_eq!(removed.len(), 2);
+        assert_eq!(removed[0].file_offset(page(2)), Some((&1, 2 * PAGE_SIZE)));
+
+        assert_eq!(
+            ranges(&tree),
+            vec![(page(0), page(2)), (page(11), page(12))]
+        );
+    }
+
+    #[test]
+    fn remove_keeps_file_offsets_of_the_upper_part() {
+        let mut tree = Tree::new();
+        tree.insert(file(0, 8, 1, 0));
+        tree.remove(page(2), page(3));
+
+        let upper = tree.find(page(3)).unwrap();
+        assert_eq!(upper.file_offset(page(3)), Some((&1, 3 * PAGE_SIZE)));
+    }
+
+    #[test]
+    fn protect_splits_and_merges_again() {
+        let mut tree = Tree::new();
+        tree.insert(anonymous(0, 8, RW));
+
+        tree.protect(page(2), page(4), VirtualMemoryFlags::empty())
+            .unwrap();
+        assert_eq!(tree.iter().count(), 3);
+        assert_eq!(
+            tree.find(page(2)).unwrap().flags,
+            VirtualMemoryFlags::empty()
+        );
+
+        tree.protect(page(2), page(4), RW).unwrap();
+        assert_eq!(ranges(&tree), vec![(page(0), page(8))]);
+    }
+
+    #[test]
+    fn protect_keeps_the_other_flags() {
+        let mut tree = Tree::new();
+        let flags = RW | VirtualMemoryFlags::Shared;
+        tree.insert(anonymous(0, 2, flags));
+
+        tree.protect(page(0), page(2), RX).unwrap();
+        assert_eq!(
+            tree.find(page(0)).unwrap().flags,
+            RX | VirtualMemoryFlags::Shared
+        );
+    }
+
+    #[test]
+    fn protect_takes_away_all_access() {
+        let mut tree = Tree::new();
+        tree.insert(anonymous(0, 2, RW));
+
+        tree.protect(page(0), page(2), VirtualMemoryFlags::NoAccess)
+            .unwrap();
+        assert_eq!(
+            tree.find(page(0)).unwrap().flags,
+            VirtualMemoryFlags::NoAccess
+        );
+
+        tree.protect(page(0), page(2), RW).unwrap();
+        assert_eq!(tree.find(page(0)).unwrap().flags, RW);
+    }
+
+    #[test]
+    fn protect_fails_on_gaps() {
+        let mut tree = Tree::new();
+        tree.insert(anonymous(0, 2, RW));
+        tree.insert(anonymous(3, 5, RW));
+
+        assert!(tree.protect(page(1), page(4), RX).is_err());
+        assert_eq!(tree.iter().count(), 2);
+        assert_eq!(tree.find(page(1)).unwrap().flags, RW);
+    }
+
+    #[test]
+    fn finds_free_space() {
+        let mut tree = Tree::new();
+        tree.insert(anonymous(0, 2, RW));
+        tree.insert(anonymous(3, 5, RX));
+
+        assert_eq!(tree.find_free(PAGE_SIZE, page(0), page(100)), Some(page(2)));
+        assert_eq!(
+            tree.find_free(2 * PAGE_SIZE, page(0), page(100)),
+            Some(page(5))
+        );
+        assert_eq!(tree.find_free(PAGE_SIZE, page(4), page(100)), Some(page(5)));
+        assert_eq!(tree.find_free(2 * PAGE_SIZE, page(0), page(6)), None);
+
+        assert!(tree.is_free(page(2), page(3)));
+        assert!(!tree.is_free(page(1), page(3)));
+        assert!(!tree.is_free(page(2), page(4)));
+    }
+
+    #[test]
+    fn stacks_grow_down() {
+        let mut tree = Tree::new();
+        tree.insert(anonymous(0, 1, RW));
+        tree.insert(anonymous(8, 10, RW | VirtualMemoryFlags::GrowsDown));
+
+        let addr = VirtualAddr::new(page(6).addr() + 8);
+        assert!(tree.grow_down(addr, 4 * PAGE_SIZE));
+        assert_eq!(tree.find(addr).unwrap().start, page(6));
+
+        // Too far below the end of the stack.
+        assert!(!tree.grow_down(page(5), 4 * PAGE_SIZE));
+        assert!(tree.grow_down(page(5), 8 * PAGE_SIZE));
+
+        // Other areas do not grow.
+        tree.insert(anonymous(20, 21, RW));
+        assert!(!tree.grow_down(page(19), 8 * PAGE_SIZE));
+    }
+}
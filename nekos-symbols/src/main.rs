@@ -0,0 +1,192 @@
+//! Writes the symbol table of the kernel into its own `.symbols` section.
+//!
+//! The kernel can only be linked once its code is final, so its function names are added after
+//! the fact: this reads the ELF symbol table of the linked kernel, demangles the names of all
+//! functions and patches them into the space the kernel reserved for them, where backtraces look
+//! them up.
+//!
+//! The table is little endian and starts with the magic `NKSY` and the number of entries. The
+//! entries follow sorted by address, each an address (u64), a size (u32) and the offset of its
+//! name (u32) from the start of the table. A name is stored as its length (u16) and UTF-8 bytes,
+//! entries with the same name share it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::process::ExitCode;
+use std::{env, fs};
+
+const MAGIC: &[u8; 4] = b"NKSY";
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+
+struct Section {
+    name: u32,
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: u32,
+}
+
+fn main() -> ExitCode {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("Usage: nekos-symbols <kernel>");
+        return ExitCode::FAILURE;
+    };
+
+    match patch(&path) {
+        Ok(count) => {
+            println!("Wrote {count} symbols to {path}");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{path}: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn patch(path: &str) -> Result<usize, String> {
+    let mut elf = fs::read(path).map_err(|err| err.to_string())?;
+
+    if elf.get(..6) != Some(b"\x7fELF\x02\x01") {
+        return Err("not a little endian ELF64 file".into());
+    }
+
+    let sections = sections(&elf)?;
+    let names = sections
+        .get(u16_at(&elf, 0x3E)? as usize)
+        .ok_or("missing section name table")?;
+
+    let find = |wanted: &str| {
+        sections
+            .iter()
+            .find(|section| cstr_at(&elf, names.offset + section.name as usize) == Some(wanted))
+    };
+
+    let target = find(".symbols").ok_or("no .symbols section, is this the kernel?")?;
+    let symtab = sections
+        .iter()
+        .find(|section| section.kind == SHT_SYMTAB)
+        .ok_or("no symbol table")?;
+    let strtab = sections
+        .get(symtab.link as usize)
+        .ok_or("missing symbol name table")?;
+
+    // Aliases share an address, keep the first name for each.
+    let mut functions = BTreeMap::new();
+
+    for index in 0..symtab.size / 24 {
+        let symbol = symtab.offset + index * 24;
+        let info = *elf.get(symbol + 4).ok_or("truncated symbol table")?;
+        let address = u64_at(&elf, symbol + 8)?;
+        let size = u64_at(&elf, symbol + 16)?;
+
+        if info & 0xF != STT_FUNC || address == 0 {
+            continue;
+        }
+
+        let name = cstr_at(&elf, strtab.offset + u32_at(&elf, symbol)? as usize)
+            .ok_or("invalid symbol name")?;
+        let name = format!("{:#}", rustc_demangle::demangle(name));
+
+        functions
+            .entry(address)
+            .or_insert((size.min(u32::MAX as u64) as u32, name));
+    }
+
+    let table = encode(&functions);
+    if table.len() > target.size {
+        return Err(format!(
+            "the symbol table takes {} bytes, but .symbols only has {}",
+            table.len(),
+            target.size
+        ));
+    }
+
+    elf[target.offset..target.offset + target.size].fill(0);
+    elf[target.offset..target.offset + table.len()].copy_from_slice(&table);
+
+    fs::write(path, elf).map_err(|err| err.to_string())?;
+    Ok(functions.len())
+}
+
+fn encode(functions: &BTreeMap<u64, (u32, String)>) -> Vec<u8> {
+    // Generic functions show up once per instantiation under the same name, which is only stored
+    // once.
+    let mut names = Vec::new();
+    let mut name_offsets = HashMap::new();
+    let names_start = 8 + functions.len() * 16;
+
+    let mut table = Vec::new();
+    table.extend_from_slice(MAGIC);
+    table.extend_from_slice(&(functions.len() as u32).to_le_bytes());
+
+    for (address, (size, name)) in functions {
+        let name = &name[..name_len(name)];
+        let name_offset = *name_offsets.entry(name).or_insert_with(|| {
+            let offset = names_start + names.len();
+            names.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            names.extend_from_slice(name.as_bytes());
+            offset
+        });
+
+        table.extend_from_slice(&address.to_le_bytes());
+        table.extend_from_slice(&size.to_le_bytes());
+        table.extend_from_slice(&(name_offset as u32).to_le_bytes());
+    }
+
+    table.extend_from_slice(&names);
+    table
+}
+
+/// Cuts very long names, like those of deeply nested closures, to what their length field holds.
+fn name_len(name: &str) -> usize {
+    let mut len = name.len().min(u16::MAX as usize);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    len
+}
+
+fn sections(elf: &[u8]) -> Result<Vec<Section>, String> {
+    let offset = u64_at(elf, 0x28)? as usize;
+    let entry_size = u16_at(elf, 0x3A)? as usize;
+    let count = u16_at(elf, 0x3C)? as usize;
+
+    (0..count)
+        .map(|index| {
+            let header = offset + index * entry_size;
+            Ok(Section {
+                name: u32_at(elf, header)?,
+                kind: u32_at(elf, header + 4)?,
+                offset: u64_at(elf, header + 24)? as usize,
+                size: u64_at(elf, header + 32)? as usize,
+                link: u32_at(elf, header + 40)?,
+            })
+        })
+        .collect()
+}
+
+fn bytes_at<const N: usize>(elf: &[u8], offset: usize) -> Result<[u8; N], String> {
+    elf.get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("truncated file at offset {offset:#x}"))
+}
+
+fn u16_at(elf: &[u8], offset: usize) -> Result<u16, String> {
+    bytes_at(elf, offset).map(u16::from_le_bytes)
+}
+
+fn u32_at(elf: &[u8], offset: usize) -> Result<u32, String> {
+    bytes_at(elf, offset).map(u32::from_le_bytes)
+}
+
+fn u64_at(elf: &[u8], offset: usize) -> Result<u64, String> {
+    bytes_at(elf, offset).map(u64::from_le_bytes)
+}
+
+fn cstr_at(elf: &[u8], offset: usize) -> Option<&str> {
+    let bytes = elf.get(offset..)?;
+    let len = bytes.iter().position(|&byte| byte == 0)?;
+    std::str::from_utf8(&bytes[..len]).ok()
+}